@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use connlib_model::{DnsQueryLogEntry, DnsQueryStrategy, ResourceId};
+use serde::{Serialize, Serializer, ser::Error as _};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use crate::json_lines::JsonLinesWriter;
+
+/// Once the DNS query log exceeds this size, we rotate it.
+const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// We only keep the previous DNS query log around, at `<path>.1`.
+const MAX_ROTATED_FILES: usize = 1;
+
+/// Writes [`DnsQueryLogEntry`]s as JSON lines to a file.
+///
+/// The file IO happens on a dedicated thread so it never blocks the caller's event-loop.
+pub struct DnsQueryLogWriter {
+    inner: JsonLinesWriter<Line>,
+}
+
+impl DnsQueryLogWriter {
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        Ok(Self {
+            inner: JsonLinesWriter::new(
+                "DNS query log",
+                path.into(),
+                MAX_FILE_SIZE,
+                MAX_ROTATED_FILES,
+            )?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    /// Queues `entry` for writing, dropping it if the writer thread is falling behind.
+    ///
+    /// Fails if a previous write to the file failed.
+    pub fn write(&mut self, entry: DnsQueryLogEntry) -> Result<()> {
+        self.inner.write(Line(entry))
+    }
+}
+
+/// A single line of the DNS query log.
+struct Line(DnsQueryLogEntry);
+
+#[derive(Serialize)]
+struct LineRef<'a> {
+    timestamp: String,
+    domain: &'a str,
+    qtype: &'a str,
+    strategy: DnsQueryStrategy,
+    resource: Option<ResourceId>,
+    response_code: Option<&'a str>,
+    latency_ms: u128,
+    upstream: Option<&'a str>,
+}
+
+impl Serialize for Line {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let entry = &self.0;
+
+        LineRef {
+            timestamp: OffsetDateTime::from(entry.timestamp)
+                .format(&Rfc3339)
+                .map_err(S::Error::custom)?,
+            domain: &entry.domain,
+            qtype: &entry.qtype,
+            strategy: entry.strategy,
+            resource: entry.resource,
+            response_code: entry.response_code.as_deref(),
+            latency_ms: entry.latency.as_millis(),
+            upstream: entry.upstream.as_deref(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    #[test]
+    fn serializes_entries_as_json() {
+        let line = Line(DnsQueryLogEntry {
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(1000),
+            domain: "foo.internal".to_owned(),
+            qtype: "A".to_owned(),
+            strategy: DnsQueryStrategy::RecurseSite,
+            resource: Some(ResourceId::from_u128(1)),
+            response_code: Some("NXDOMAIN".to_owned()),
+            latency: Duration::from_millis(42),
+            upstream: Some("100.100.111.1:53".to_owned()),
+        });
+
+        assert_eq!(
+            serde_json::to_value(&line).unwrap(),
+            serde_json::json!({
+                "timestamp": "1970-01-01T00:16:40Z",
+                "domain": "foo.internal",
+                "qtype": "A",
+                "strategy": "recurse_site",
+                "resource": "00000000-0000-0000-0000-000000000001",
+                "response_code": "NXDOMAIN",
+                "latency_ms": 42,
+                "upstream": "100.100.111.1:53",
+            })
+        );
+    }
+}
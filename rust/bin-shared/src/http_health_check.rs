@@ -1,33 +1,79 @@
+use anyhow::{Context as _, Result};
 use axum::Router;
-use axum::http::StatusCode;
+use axum::http::{StatusCode, header};
+use axum::response::IntoResponse;
 use axum::routing::get;
+use opentelemetry_prometheus::PrometheusExporter;
+use prometheus::{Encoder as _, Registry, TextEncoder};
 use std::net::SocketAddr;
 
 /// Runs an HTTP server that responds to `GET /healthz` with 200 OK or 400 BAD REQUEST, depending on the return value of `is_healthy`.
+///
+/// If a [`Registry`] is given, its metrics are served in the Prometheus text format at `GET /metrics`.
 pub async fn serve(
     addr: impl Into<SocketAddr>,
     is_healthy: impl Fn() -> bool + Clone + Send + Sync + 'static,
+    metrics: Option<Registry>,
 ) -> std::io::Result<()> {
     let addr = addr.into();
 
-    let service = Router::new()
-        .route(
-            "/healthz",
-            get(move || async move {
-                if is_healthy() {
-                    StatusCode::OK
-                } else {
-                    StatusCode::BAD_REQUEST
-                }
-            }),
-        )
-        .into_make_service();
-
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, service).await?;
+    let mut router = Router::new().route(
+        "/healthz",
+        get(move || async move {
+            if is_healthy() {
+                StatusCode::OK
+            } else {
+                StatusCode::BAD_REQUEST
+            }
+        }),
+    );
+
+    if let Some(registry) = metrics {
+        router = router.route(
+            "/metrics",
+            get(move || async move { render_metrics(&registry) }),
+        );
+    }
+
+    axum::serve(
+        tokio::net::TcpListener::bind(addr).await?,
+        router.into_make_service(),
+    )
+    .await?;
 
     Ok(())
 }
 
+/// Creates a [`Registry`] together with a metric reader that exports all OpenTelemetry metrics into it.
+///
+/// The reader needs to be registered with the global `SdkMeterProvider`.
+pub fn prometheus_registry() -> Result<(Registry, PrometheusExporter)> {
+    let registry = Registry::new();
+    let exporter = opentelemetry_prometheus::exporter()
+        .with_registry(registry.clone())
+        .build()
+        .context("Failed to build Prometheus exporter")?;
+
+    Ok((registry, exporter))
+}
+
+fn render_metrics(registry: &Registry) -> impl IntoResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+
+    if let Err(e) = encoder.encode(&registry.gather(), &mut buffer) {
+        tracing::debug!("Failed to encode metrics: {e}");
+
+        return (StatusCode::INTERNAL_SERVER_ERROR, Vec::new()).into_response();
+    }
+
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_owned())],
+        buffer,
+    )
+        .into_response()
+}
+
 #[derive(clap::Args, Debug, Clone)]
 pub struct HealthCheckArgs {
     /// The address of the local interface where we should serve our health-check endpoint.
@@ -35,4 +81,10 @@ pub struct HealthCheckArgs {
     /// The actual health-check endpoint will be at `http://<health_check_addr>/healthz`.
     #[arg(long, env, hide = true, default_value = "0.0.0.0:8080")]
     pub health_check_addr: SocketAddr,
+
+    /// The address of the local interface where we should serve our metrics, disabled by default.
+    ///
+    /// Metrics are served in the Prometheus format at `http://<metrics_addr>/metrics`.
+    #[arg(long, env)]
+    pub metrics_addr: Option<SocketAddr>,
 }
@@ -0,0 +1,259 @@
+//! Writes JSON lines to a size-bounded, rotating file without blocking the caller.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write as _},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread::JoinHandle,
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use serde::Serialize;
+
+/// How many items we queue for the writer thread before we start dropping them.
+const QUEUE_SIZE: usize = 10_000;
+
+/// Writes items as JSON lines to a [`RotatingFile`] on a dedicated thread.
+///
+/// If that thread can't keep up, items are dropped.
+pub struct JsonLinesWriter<T> {
+    name: &'static str,
+    path: PathBuf,
+    tx: mpsc::SyncSender<T>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl<T> JsonLinesWriter<T>
+where
+    T: Serialize + Send + 'static,
+{
+    /// Opens the file at `path` and spawns the writer thread, named after `name`.
+    ///
+    /// Once the file exceeds `max_file_size`, it is moved to `<path>.1`, shifting previous rotations by one.
+    /// At most `max_rotated_files` of those are kept.
+    pub fn new(
+        name: &'static str,
+        path: PathBuf,
+        max_file_size: u64,
+        max_rotated_files: usize,
+    ) -> Result<Self> {
+        let mut file = RotatingFile::open(path.clone(), max_file_size, max_rotated_files)?;
+        let (tx, rx) = mpsc::sync_channel::<T>(QUEUE_SIZE);
+
+        let thread = std::thread::Builder::new()
+            .name(name.to_owned())
+            .spawn(move || {
+                for item in rx {
+                    let mut line = serde_json::to_vec(&item)?;
+                    line.push(b'\n');
+
+                    file.write(&line)?;
+                }
+
+                Ok(())
+            })
+            .with_context(|| format!("Failed to spawn {name} thread"))?;
+
+        Ok(Self {
+            name,
+            path,
+            tx,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Queues `item` for writing, dropping it if the writer thread is falling behind.
+    ///
+    /// Fails if a previous write to the file failed.
+    pub fn write(&mut self, item: T) -> Result<()> {
+        match self.tx.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(mpsc::TrySendError::Full(_)) => {
+                tracing::debug!("Dropping {} entry", self.name);
+
+                Ok(())
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => Err(self.join()),
+        }
+    }
+
+    fn join(&mut self) -> anyhow::Error {
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => e,
+            Some(Err(_)) => anyhow!("{} thread panicked", self.name),
+            Some(Ok(Ok(()))) | None => anyhow!("{} thread has stopped", self.name),
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    max_file_size: u64,
+    max_rotated_files: usize,
+
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_file_size: u64, max_rotated_files: usize) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create `{}`", parent.display()))?;
+        }
+
+        let (writer, size) = open(&path)?;
+
+        Ok(Self {
+            path,
+            max_file_size,
+            max_rotated_files,
+            writer,
+            size,
+        })
+    }
+
+    fn write(&mut self, line: &[u8]) -> Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_file_size {
+            self.rotate()
+                .with_context(|| format!("Failed to rotate `{}`", self.path.display()))?;
+        }
+
+        self.writer
+            .write_all(line)
+            .and_then(|()| self.writer.flush())
+            .with_context(|| format!("Failed to write to `{}`", self.path.display()))?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// Shifts all rotated files by one, dropping the oldest, and opens a new active file.
+    fn rotate(&mut self) -> Result<()> {
+        self.writer.flush()?;
+
+        for n in (1..self.max_rotated_files).rev() {
+            match fs::rename(rotated_path(&self.path, n), rotated_path(&self.path, n + 1)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if self.max_rotated_files > 0 {
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        (self.writer, self.size) = open(&self.path)?;
+
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> Result<(BufWriter<File>, u64)> {
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open `{}`", path.display()))?;
+    let size = file.metadata()?.len();
+
+    Ok((BufWriter::new(file), size))
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.to_owned().into_os_string();
+    rotated.push(format!(".{n}"));
+
+    rotated.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn writes_items_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.jsonl");
+        let mut file = RotatingFile::open(path.clone(), 1024, 1).unwrap();
+
+        file.write(b"{\"n\":1}\n").unwrap();
+        file.write(b"{\"n\":2}\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"n\":1}\n{\"n\":2}\n");
+    }
+
+    #[test]
+    fn rotates_file_once_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.jsonl");
+        fs::write(&path, vec![b'x'; 1024]).unwrap();
+        let mut file = RotatingFile::open(path.clone(), 1024, 1).unwrap();
+
+        file.write(b"{}\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}\n");
+        assert_eq!(
+            fs::read(dir.path().join("log.jsonl.1")).unwrap().len(),
+            1024
+        );
+    }
+
+    #[test]
+    fn keeps_at_most_max_rotated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.jsonl");
+        let mut file = RotatingFile::open(path.clone(), 4, 2).unwrap();
+
+        for _ in 0..4 {
+            file.write(b"{}\n").unwrap();
+        }
+
+        assert!(dir.path().join("log.jsonl.1").exists());
+        assert!(dir.path().join("log.jsonl.2").exists());
+        assert!(!dir.path().join("log.jsonl.3").exists());
+    }
+
+    #[test]
+    fn fails_to_open_file_in_unwritable_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let not_a_dir = dir.path().join("file");
+        fs::write(&not_a_dir, "").unwrap();
+
+        let error = JsonLinesWriter::<()>::new("test", not_a_dir.join("log.jsonl"), 1024, 1)
+            .err()
+            .unwrap();
+
+        assert!(error.to_string().starts_with("Failed to create"));
+    }
+
+    #[test]
+    fn writes_items_on_dedicated_thread() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.jsonl");
+        let mut writer = JsonLinesWriter::new("test", path.clone(), 1024, 1).unwrap();
+
+        writer.write(42).unwrap();
+
+        let content = std::iter::repeat_with(|| {
+            std::thread::sleep(Duration::from_millis(10));
+
+            fs::read_to_string(&path).unwrap()
+        })
+        .take(100)
+        .find(|content| !content.is_empty())
+        .unwrap();
+
+        assert_eq!(content, "42\n");
+    }
+}
@@ -1,6 +1,9 @@
 #![cfg_attr(test, allow(clippy::unwrap_used))]
 
+pub mod dns_query_log;
 pub mod http_health_check;
+pub mod ipc_codec;
+pub mod json_lines;
 
 mod dns_control;
 mod network_changes;
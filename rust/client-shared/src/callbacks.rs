@@ -1,4 +1,4 @@
-use connlib_model::ResourceView;
+use connlib_model::{DnsQueryLogEntry, ResourceView};
 use dns_types::DomainName;
 use ip_network::{Ipv4Network, Ipv6Network};
 use std::{
@@ -32,6 +32,9 @@ pub trait Callbacks: Clone + Send + Sync {
     /// or if all Resources for a user are disabled by policy.
     fn on_update_resources(&self, _: Vec<ResourceView>) {}
 
+    /// Called for every DNS query that connlib handled whilst the DNS query log is enabled.
+    fn on_dns_query(&self, _: DnsQueryLogEntry) {}
+
     /// Called when the tunnel is disconnected.
     fn on_disconnect(&self, _: DisconnectError) {}
 }
@@ -113,6 +116,14 @@ where
         });
     }
 
+    fn on_dns_query(&self, entry: DnsQueryLogEntry) {
+        let callbacks = self.inner.clone();
+
+        self.threadpool.spawn(move || {
+            callbacks.on_dns_query(entry);
+        });
+    }
+
     fn on_disconnect(&self, error: DisconnectError) {
         let callbacks = self.inner.clone();
 
@@ -147,13 +158,29 @@ pub enum ConnlibMsg {
 #[derive(Clone)]
 pub struct ChannelCallbackHandler {
     cb_tx: mpsc::Sender<ConnlibMsg>,
+    dns_query_tx: mpsc::Sender<DnsQueryLogEntry>,
 }
 
 impl ChannelCallbackHandler {
-    pub fn new() -> (Self, mpsc::Receiver<ConnlibMsg>) {
+    /// Returns the handler together with the receivers for [`ConnlibMsg`]s and DNS query log entries.
+    ///
+    /// DNS query log entries use their own channel so a burst of DNS queries can never crowd out the other callbacks.
+    pub fn new() -> (
+        Self,
+        mpsc::Receiver<ConnlibMsg>,
+        mpsc::Receiver<DnsQueryLogEntry>,
+    ) {
         let (cb_tx, cb_rx) = mpsc::channel(1_000);
+        let (dns_query_tx, dns_query_rx) = mpsc::channel(1_000);
 
-        (Self { cb_tx }, cb_rx)
+        (
+            Self {
+                cb_tx,
+                dns_query_tx,
+            },
+            cb_rx,
+            dns_query_rx,
+        )
     }
 }
 
@@ -194,6 +221,13 @@ impl Callbacks for ChannelCallbackHandler {
             .try_send(ConnlibMsg::OnUpdateResources(resources))
             .expect("Should be able to send OnUpdateResources");
     }
+
+    fn on_dns_query(&self, entry: DnsQueryLogEntry) {
+        // The DNS query log is best-effort, drop entries rather than panicking if we can't keep up.
+        if self.dns_query_tx.try_send(entry).is_err() {
+            tracing::debug!("Dropping DNS query log entry");
+        }
+    }
 }
 
 #[cfg(test)]
@@ -211,6 +245,30 @@ mod tests {
         assert!(disconnect_error.to_string().contains("401 Unauthorized")); // Apple client relies on this.
     }
 
+    #[test]
+    fn dns_query_burst_does_not_crowd_out_other_callbacks() {
+        let (callbacks, mut cb_rx, _dns_query_rx) = ChannelCallbackHandler::new();
+
+        for _ in 0..2_000 {
+            callbacks.on_dns_query(DnsQueryLogEntry {
+                timestamp: std::time::SystemTime::UNIX_EPOCH,
+                domain: "foo.internal".to_owned(),
+                qtype: "A".to_owned(),
+                strategy: connlib_model::DnsQueryStrategy::RecurseSite,
+                resource: None,
+                response_code: None,
+                latency: std::time::Duration::ZERO,
+                upstream: None,
+            });
+        }
+        callbacks.on_update_resources(Vec::new());
+
+        assert!(matches!(
+            cb_rx.try_recv(),
+            Ok(ConnlibMsg::OnUpdateResources(_))
+        ));
+    }
+
     // Make sure it's okay to store a bunch of these to mitigate #5880
     #[test]
     fn callback_msg_size() {
@@ -1,14 +1,14 @@
 use crate::{PHOENIX_TOPIC, callbacks::Callbacks};
 use anyhow::{Context as _, Result};
-use connlib_model::{PublicKey, ResourceId};
+use connlib_model::{ConnectionView, PublicKey, ResourceId};
 use firezone_tunnel::messages::RelaysPresence;
 use firezone_tunnel::messages::client::{
     EgressMessages, FailReason, FlowCreated, FlowCreationFailed, GatewayIceCandidates,
     GatewaysIceCandidates, IngressMessages, InitClient,
 };
-use firezone_tunnel::{ClientTunnel, IpConfig};
+use firezone_tunnel::{ClientTunnel, DnsOverrides, IpConfig};
 use phoenix_channel::{ErrorReply, OutboundRequestId, PhoenixChannel, PublicKeyParam};
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 use std::{
     collections::BTreeSet,
     io,
@@ -29,8 +29,11 @@ pub struct Eventloop<C: Callbacks> {
 pub enum Command {
     Reset,
     SetDns(Vec<IpAddr>),
+    SetDnsOverrides(DnsOverrides),
+    SetDnsQueryLog(bool),
     SetTun(Box<dyn Tun>),
     SetDisabledResources(BTreeSet<ResourceId>),
+    Connections(tokio::sync::oneshot::Sender<Vec<ConnectionView>>),
 }
 
 impl<C: Callbacks> Eventloop<C> {
@@ -64,6 +67,20 @@ where
 
                     continue;
                 }
+                Poll::Ready(Some(Command::SetDnsOverrides(overrides))) => {
+                    self.tunnel.state_mut().set_dns_overrides(overrides);
+
+                    continue;
+                }
+                Poll::Ready(Some(Command::SetDnsQueryLog(enabled))) => {
+                    self.tunnel.state_mut().set_dns_query_log(
+                        enabled,
+                        Instant::now(),
+                        SystemTime::now(),
+                    );
+
+                    continue;
+                }
                 Poll::Ready(Some(Command::SetDisabledResources(resources))) => {
                     self.tunnel.state_mut().set_disabled_resources(resources);
                     continue;
@@ -72,6 +89,10 @@ where
                     self.tunnel.set_tun(tun);
                     continue;
                 }
+                Poll::Ready(Some(Command::Connections(tx))) => {
+                    let _ = tx.send(self.tunnel.state_mut().connections(Instant::now()));
+                    continue;
+                }
                 Poll::Ready(Some(Command::Reset)) => {
                     self.tunnel.reset();
                     self.portal
@@ -180,6 +201,9 @@ where
                     Vec::from_iter(config.ipv6_routes),
                 );
             }
+            firezone_tunnel::ClientEvent::DnsQueryLogged(entry) => {
+                self.callbacks.on_dns_query(entry)
+            }
         }
     }
 
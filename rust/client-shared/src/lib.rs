@@ -4,10 +4,11 @@ use callbacks::BackgroundCallbacks;
 pub use callbacks::{Callbacks, ChannelCallbackHandler, ConnlibMsg, DisconnectError};
 pub use connlib_model::StaticSecret;
 pub use eventloop::Eventloop;
+pub use firezone_tunnel::DnsOverrides;
 pub use firezone_tunnel::messages::client::{IngressMessages, ResourceDescription};
 
 use anyhow::{Context, Result};
-use connlib_model::ResourceId;
+use connlib_model::{ConnectionView, ResourceId};
 use eventloop::Command;
 use firezone_tunnel::ClientTunnel;
 use phoenix_channel::{PhoenixChannel, PublicKeyParam};
@@ -95,12 +96,37 @@ impl Session {
         let _ = self.channel.send(Command::SetDns(new_dns));
     }
 
+    /// Sets the user-defined DNS records and forwarding rules for this [`Session`].
+    ///
+    /// Overrides only apply to domains that are not a DNS resource.
+    pub fn set_dns_overrides(&self, overrides: DnsOverrides) {
+        let _ = self.channel.send(Command::SetDnsOverrides(overrides));
+    }
+
+    /// Enables or disables the DNS query log for this [`Session`].
+    ///
+    /// Whilst enabled, every DNS query handled by connlib is reported via [`Callbacks::on_dns_query`].
+    pub fn set_dns_query_log(&self, enabled: bool) {
+        let _ = self.channel.send(Command::SetDnsQueryLog(enabled));
+    }
+
     pub fn set_disabled_resources(&self, disabled_resources: BTreeSet<ResourceId>) {
         let _ = self
             .channel
             .send(Command::SetDisabledResources(disabled_resources));
     }
 
+    /// Describes how we are connected to each Gateway.
+    pub async fn connections(&self) -> Result<Vec<ConnectionView>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+
+        self.channel
+            .send(Command::Connections(tx))
+            .context("connlib is not running")?;
+
+        rx.await.context("connlib is not running")
+    }
+
     /// Sets a new [`Tun`] device handle.
     pub fn set_tun(&self, new_tun: Box<dyn Tun>) {
         let _ = self.channel.send(Command::SetTun(new_tun));
@@ -0,0 +1,1649 @@
+//! DNSSEC validation as per RFC 4033, RFC 4034, RFC 4035 and RFC 5155.
+//!
+//! This module only verifies the RRsets and proofs of non-existence it is given.
+//! Fetching the DS and DNSKEY records along the chain of trust is up to the caller.
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use domain::base::{ToName, iana::Class, rdata::ComposeRecordData as _};
+use ring::digest;
+
+use crate::{DomainName, Record, RecordType, ResponseCode};
+
+/// The DS records of the root zone's key-signing keys, see <https://data.iana.org/root-anchors/root-anchors.xml>.
+const ROOT_TRUST_ANCHORS: [(u16, u8, u8, [u8; 32]); 2] = [
+    (
+        20326, // KSK-2017
+        8,
+        2,
+        hex_literal::hex!("E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D"),
+    ),
+    (
+        38696, // KSK-2024
+        8,
+        2,
+        hex_literal::hex!("683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16"),
+    ),
+];
+
+/// NSEC3 records with more iterations than this are treated as insecure, see RFC 9276.
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+
+const NSEC3_HASH_SHA1: u8 = 1;
+const NSEC3_FLAG_OPT_OUT: u8 = 0x01;
+
+const DNSKEY_FLAG_ZONE: u16 = 0x0100;
+const DNSKEY_FLAG_REVOKE: u16 = 0x0080;
+const DNSKEY_PROTOCOL: u8 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("No supported algorithm or digest type")]
+    UnsupportedAlgorithm,
+    #[error("RRset is not signed")]
+    MissingSignature,
+    #[error("RRSIG does not match the RRset or its zone")]
+    SignatureMismatch,
+    #[error("RRSIG signer is not authoritative for the RRset")]
+    SignerNotAuthoritative,
+    #[error("RRSIG is expired or not yet valid")]
+    SignatureExpired,
+    #[error("No DNSKEY matches the RRSIG")]
+    NoMatchingKey,
+    #[error("Invalid signature")]
+    InvalidSignature,
+    #[error("No DNSKEY matches a trusted DS record")]
+    UntrustedKeys,
+    #[error("Missing proof of non-existence")]
+    MissingDenial,
+    #[error("Proof of non-existence does not hold")]
+    InvalidDenial,
+}
+
+/// The DS records of the root zone we trust.
+pub fn root_trust_anchors() -> Vec<Ds> {
+    ROOT_TRUST_ANCHORS
+        .iter()
+        .map(|(key_tag, algorithm, digest_type, digest)| Ds {
+            key_tag: *key_tag,
+            algorithm: *algorithm,
+            digest_type: *digest_type,
+            digest: digest.to_vec(),
+        })
+        .collect()
+}
+
+pub fn root() -> DomainName {
+    from_labels([])
+}
+
+/// Converts a name into its canonical form, i.e. all lowercase.
+pub fn canonical(name: &impl ToName) -> DomainName {
+    let mut wire = Vec::new();
+    name.compose_canonical(&mut wire)
+        .expect("Vec-backed builder never fails");
+
+    DomainName::from_octets(wire).expect("composed name is valid")
+}
+
+/// All ancestors of the given name including itself, starting from the root.
+pub fn ancestors(name: &DomainName) -> Vec<DomainName> {
+    let labels = labels(name.as_slice());
+
+    (0..=labels.len())
+        .map(|n| from_labels(labels[labels.len() - n..].iter().copied()))
+        .collect()
+}
+
+/// A set of records with the same owner, class and type, together with the RRSIGs covering them.
+#[derive(Debug, Clone)]
+pub struct Rrset {
+    owner: DomainName,
+    rtype: RecordType,
+    class: Class,
+    /// The smallest TTL of the records in this RRset.
+    ttl: u32,
+    /// The RDATA of each record in canonical form, sorted.
+    rdata: Vec<Vec<u8>>,
+    signatures: Vec<Rrsig>,
+}
+
+/// Groups the given records into [`Rrset`]s, attaching the RRSIGs that cover them.
+pub fn rrsets<'a>(records: impl IntoIterator<Item = Record<'a>>) -> Vec<Rrset> {
+    let mut rrsets = BTreeMap::<(Vec<u8>, u16), Rrset>::new();
+    let mut signatures = Vec::new();
+
+    for record in records {
+        let owner = canonical(record.owner());
+        let mut rdata = Vec::new();
+        record
+            .data()
+            .compose_canonical_rdata(&mut rdata)
+            .expect("Vec-backed builder never fails");
+
+        if record.rtype() == RecordType::RRSIG {
+            if let Some(rrsig) = Rrsig::parse(&rdata) {
+                signatures.push((owner, rrsig));
+            }
+
+            continue;
+        }
+
+        let ttl = record.ttl().as_secs();
+        let rrset = rrsets
+            .entry((owner.as_slice().to_vec(), record.rtype().to_int()))
+            .or_insert_with(|| Rrset {
+                owner,
+                rtype: record.rtype(),
+                class: record.class(),
+                ttl,
+                rdata: Vec::new(),
+                signatures: Vec::new(),
+            });
+
+        rrset.ttl = rrset.ttl.min(ttl);
+        rrset.rdata.push(rdata);
+    }
+
+    for (owner, rrsig) in signatures {
+        if let Some(rrset) =
+            rrsets.get_mut(&(owner.as_slice().to_vec(), rrsig.type_covered.to_int()))
+        {
+            rrset.signatures.push(rrsig);
+        }
+    }
+
+    rrsets
+        .into_values()
+        .map(|mut rrset| {
+            rrset.rdata.sort();
+            rrset.rdata.dedup();
+
+            rrset
+        })
+        .collect()
+}
+
+impl Rrset {
+    pub fn owner(&self) -> &DomainName {
+        &self.owner
+    }
+
+    pub fn rtype(&self) -> RecordType {
+        self.rtype
+    }
+
+    pub fn is_signed(&self) -> bool {
+        !self.signatures.is_empty()
+    }
+
+    /// The zone that signed this RRset.
+    pub fn signer(&self) -> Option<&DomainName> {
+        Some(&self.signatures.first()?.signer)
+    }
+
+    /// The name whose zone must have signed this RRset.
+    ///
+    /// That is the owner, except for RRsets synthesized from a wildcard and those on the parent side of a zone cut (DS, NSEC and NSEC3).
+    /// Fails if the RRset is signed by a zone that is not an ancestor of its owner.
+    pub fn authority(&self) -> Result<DomainName, Error> {
+        let Some(rrsig) = self.signatures.first() else {
+            return Ok(self.owner.clone());
+        };
+
+        if !is_subdomain(&self.owner, &rrsig.signer) {
+            return Err(Error::SignerNotAuthoritative);
+        }
+
+        let ancestors = ancestors(&self.owner);
+
+        let authority = match self.rtype {
+            RecordType::DS => ancestors.iter().rev().nth(1),
+            RecordType::NSEC | RecordType::NSEC3 => Some(&rrsig.signer),
+            _ => ancestors.get(usize::from(rrsig.labels)),
+        };
+
+        Ok(authority.unwrap_or(&self.owner).clone())
+    }
+
+    /// The DS records contained in this RRset.
+    pub fn ds(&self) -> Vec<Ds> {
+        if self.rtype != RecordType::DS {
+            return Vec::new();
+        }
+
+        self.rdata
+            .iter()
+            .filter_map(|rdata| Ds::parse(rdata))
+            .collect()
+    }
+
+    /// The target of this CNAME RRset.
+    pub fn cname_target(&self) -> Option<DomainName> {
+        if self.rtype != RecordType::CNAME {
+            return None;
+        }
+
+        DomainName::from_octets(self.rdata.first()?.clone()).ok()
+    }
+
+    /// The target of this DNAME RRset.
+    pub fn dname_target(&self) -> Option<DomainName> {
+        if self.rtype != RecordType::DNAME {
+            return None;
+        }
+
+        DomainName::from_octets(self.rdata.first()?.clone()).ok()
+    }
+
+    /// For how long this RRset may be cached, see RFC 4035, Section 5.3.3.
+    ///
+    /// This is the smallest of the records' TTL, the original TTL of the signatures and the time until the signatures expire.
+    pub fn ttl(&self, now: SystemTime) -> Duration {
+        let now = unix_timestamp(now);
+
+        let ttl = self
+            .signatures
+            .iter()
+            .map(|rrsig| {
+                let remaining = if serial_le(now, rrsig.expiration) {
+                    rrsig.expiration.wrapping_sub(now)
+                } else {
+                    0
+                };
+
+                rrsig.original_ttl.min(remaining)
+            })
+            .fold(self.ttl, u32::min);
+
+        Duration::from_secs(ttl.into())
+    }
+
+    /// Verifies that this RRset is signed by one of the given keys.
+    ///
+    /// If the RRset was synthesized from a wildcard, returns the "next closer" name whose non-existence needs to be proven as well.
+    pub fn verify(&self, keys: &ZoneKeys, now: SystemTime) -> Result<Option<DomainName>, Error> {
+        let mut error = Error::MissingSignature;
+
+        for rrsig in &self.signatures {
+            match self.verify_rrsig(rrsig, keys, now) {
+                Ok(next_closer) => return Ok(next_closer),
+                Err(e) => error = e,
+            }
+        }
+
+        Err(error)
+    }
+
+    fn verify_rrsig(
+        &self,
+        rrsig: &Rrsig,
+        keys: &ZoneKeys,
+        now: SystemTime,
+    ) -> Result<Option<DomainName>, Error> {
+        if rrsig.type_covered != self.rtype
+            || rrsig.signer != keys.zone
+            || !is_subdomain(&self.owner, &keys.zone)
+        {
+            return Err(Error::SignatureMismatch);
+        }
+
+        if !rrsig.is_valid_at(now) {
+            return Err(Error::SignatureExpired);
+        }
+
+        let owner_labels = labels(self.owner.as_slice());
+        let label_count = rrsig_label_count(&owner_labels);
+        let signed_labels = usize::from(rrsig.labels);
+
+        let (owner, next_closer) = match signed_labels.cmp(&label_count) {
+            Ordering::Equal => (self.owner.clone(), None),
+            Ordering::Less => {
+                let suffix = &owner_labels[owner_labels.len() - signed_labels..];
+                let wildcard =
+                    from_labels(std::iter::once(b"*".as_slice()).chain(suffix.iter().copied()));
+                let next_closer = from_labels(
+                    owner_labels[owner_labels.len() - signed_labels - 1..]
+                        .iter()
+                        .copied(),
+                );
+
+                (wildcard, Some(next_closer))
+            }
+            Ordering::Greater => return Err(Error::SignatureMismatch),
+        };
+
+        let signed_data = self.signed_data(rrsig, &owner);
+        let mut error = Error::NoMatchingKey;
+
+        for key in keys
+            .keys
+            .iter()
+            .filter(|k| k.key_tag == rrsig.key_tag && k.algorithm == rrsig.algorithm)
+        {
+            match verify_signature(
+                key.algorithm,
+                &key.public_key,
+                &signed_data,
+                &rrsig.signature,
+            ) {
+                Ok(()) => return Ok(next_closer),
+                Err(e) => error = e,
+            }
+        }
+
+        Err(error)
+    }
+
+    /// The data covered by an RRSIG, see RFC 4034, Section 3.1.8.1.
+    fn signed_data(&self, rrsig: &Rrsig, owner: &DomainName) -> Vec<u8> {
+        let mut data = rrsig.signed_prefix.clone();
+
+        for rdata in &self.rdata {
+            data.extend_from_slice(owner.as_slice());
+            data.extend_from_slice(&self.rtype.to_int().to_be_bytes());
+            data.extend_from_slice(&self.class.to_int().to_be_bytes());
+            data.extend_from_slice(&rrsig.original_ttl.to_be_bytes());
+            data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            data.extend_from_slice(rdata);
+        }
+
+        data
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rrsig {
+    type_covered: RecordType,
+    algorithm: u8,
+    labels: u8,
+    original_ttl: u32,
+    expiration: u32,
+    inception: u32,
+    key_tag: u16,
+    signer: DomainName,
+    /// The RDATA without the signature, which is part of the signed data.
+    signed_prefix: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Rrsig {
+    fn parse(rdata: &[u8]) -> Option<Self> {
+        let mut reader = Reader(rdata);
+
+        let type_covered = RecordType::from_int(reader.u16()?);
+        let algorithm = reader.u8()?;
+        let labels = reader.u8()?;
+        let original_ttl = reader.u32()?;
+        let expiration = reader.u32()?;
+        let inception = reader.u32()?;
+        let key_tag = reader.u16()?;
+        let signer = canonical(&reader.name()?);
+        let signature = reader.0.to_vec();
+
+        Some(Self {
+            type_covered,
+            algorithm,
+            labels,
+            original_ttl,
+            expiration,
+            inception,
+            key_tag,
+            signer,
+            signed_prefix: rdata[..rdata.len() - signature.len()].to_vec(),
+            signature,
+        })
+    }
+
+    /// Whether `now` lies within the validity period, using serial number arithmetic as per RFC 4034, Section 3.1.5.
+    fn is_valid_at(&self, now: SystemTime) -> bool {
+        let now = unix_timestamp(now);
+
+        serial_le(self.inception, now) && serial_le(now, self.expiration)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ds {
+    key_tag: u16,
+    algorithm: u8,
+    digest_type: u8,
+    digest: Vec<u8>,
+}
+
+/// Parses a DS record from the presentation format of its RDATA, e.g. `20326 8 2 E06D44B8...`.
+impl FromStr for Ds {
+    type Err = ParseDsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+
+        let key_tag = parts
+            .next()
+            .ok_or(ParseDsError)?
+            .parse()
+            .map_err(|_| ParseDsError)?;
+        let algorithm = parts
+            .next()
+            .ok_or(ParseDsError)?
+            .parse()
+            .map_err(|_| ParseDsError)?;
+        let digest_type = parts
+            .next()
+            .ok_or(ParseDsError)?
+            .parse()
+            .map_err(|_| ParseDsError)?;
+        let digest = data_encoding::HEXUPPER_PERMISSIVE
+            .decode(parts.collect::<String>().as_bytes())
+            .map_err(|_| ParseDsError)?;
+
+        if digest.is_empty() {
+            return Err(ParseDsError);
+        }
+
+        Ok(Self {
+            key_tag,
+            algorithm,
+            digest_type,
+            digest,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid DS record, expected `<key tag> <algorithm> <digest type> <digest>`")]
+pub struct ParseDsError;
+
+impl Ds {
+    fn parse(rdata: &[u8]) -> Option<Self> {
+        let mut reader = Reader(rdata);
+
+        Some(Self {
+            key_tag: reader.u16()?,
+            algorithm: reader.u8()?,
+            digest_type: reader.u8()?,
+            digest: reader.0.to_vec(),
+        })
+    }
+
+    fn is_supported(&self) -> bool {
+        is_supported_algorithm(self.algorithm) && digest_algorithm(self.digest_type).is_some()
+    }
+
+    fn matches(&self, owner: &DomainName, key: &Dnskey) -> bool {
+        let Some(algorithm) = digest_algorithm(self.digest_type) else {
+            return false;
+        };
+
+        if self.key_tag != key.key_tag || self.algorithm != key.algorithm {
+            return false;
+        }
+
+        let mut context = digest::Context::new(algorithm);
+        context.update(owner.as_slice());
+        context.update(&key.rdata);
+
+        context.finish().as_ref() == self.digest
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Dnskey {
+    flags: u16,
+    protocol: u8,
+    algorithm: u8,
+    public_key: Vec<u8>,
+    key_tag: u16,
+    rdata: Vec<u8>,
+}
+
+impl Dnskey {
+    fn parse(rdata: &[u8]) -> Option<Self> {
+        let mut reader = Reader(rdata);
+
+        Some(Self {
+            flags: reader.u16()?,
+            protocol: reader.u8()?,
+            algorithm: reader.u8()?,
+            public_key: reader.0.to_vec(),
+            key_tag: key_tag(rdata),
+            rdata: rdata.to_vec(),
+        })
+    }
+
+    fn is_usable(&self) -> bool {
+        self.flags & DNSKEY_FLAG_ZONE != 0
+            && self.flags & DNSKEY_FLAG_REVOKE == 0
+            && self.protocol == DNSKEY_PROTOCOL
+    }
+}
+
+/// The authenticated DNSKEYs of a zone.
+#[derive(Debug, Clone)]
+pub struct ZoneKeys {
+    zone: DomainName,
+    keys: Vec<Dnskey>,
+}
+
+impl ZoneKeys {
+    /// Authenticates a zone's DNSKEY RRset with the DS records of its parent.
+    ///
+    /// Returns [`Error::UnsupportedAlgorithm`] if none of the DS records use an algorithm we support, in which case the zone is to be treated as insecure.
+    pub fn new(dnskeys: &Rrset, ds: &[Ds], now: SystemTime) -> Result<Self, Error> {
+        let supported_ds = ds.iter().filter(|ds| ds.is_supported()).collect::<Vec<_>>();
+
+        if supported_ds.is_empty() {
+            return Err(Error::UnsupportedAlgorithm);
+        }
+
+        let keys = dnskeys
+            .rdata
+            .iter()
+            .filter_map(|rdata| Dnskey::parse(rdata))
+            .filter(|key| key.is_usable())
+            .collect::<Vec<_>>();
+
+        let is_trusted = keys
+            .iter()
+            .filter(|key| {
+                supported_ds
+                    .iter()
+                    .any(|ds| ds.matches(&dnskeys.owner, key))
+            })
+            .any(|key| {
+                let entry_point = ZoneKeys {
+                    zone: dnskeys.owner.clone(),
+                    keys: vec![key.clone()],
+                };
+
+                dnskeys.verify(&entry_point, now).is_ok()
+            });
+
+        if !is_trusted {
+            return Err(Error::UntrustedKeys);
+        }
+
+        Ok(Self {
+            zone: dnskeys.owner.clone(),
+            keys,
+        })
+    }
+
+    pub fn zone(&self) -> &DomainName {
+        &self.zone
+    }
+}
+
+/// What a proof of non-existence established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denial {
+    /// The name or type does not exist.
+    ///
+    /// `delegation` is set if the name exists as an unsigned delegation to a child zone.
+    Secure { delegation: bool },
+    /// The name may exist in an unsigned delegation (NSEC3 opt-out), or the proof is too expensive to check.
+    Insecure,
+}
+
+/// Checks that the NSEC or NSEC3 records in the given (already verified) RRsets prove the negative response for `qname` and `qtype`.
+pub fn prove_denial(
+    qname: &DomainName,
+    qtype: RecordType,
+    response_code: ResponseCode,
+    rrsets: &[Rrset],
+) -> Result<Denial, Error> {
+    let nsecs = nsec_records(rrsets);
+
+    if !nsecs.is_empty() {
+        return prove_denial_nsec(qname, qtype, response_code, &nsecs);
+    }
+
+    let nsec3s = nsec3_records(rrsets);
+
+    if !nsec3s.is_empty() {
+        return prove_denial_nsec3(qname, qtype, response_code, &nsec3s);
+    }
+
+    Err(Error::MissingDenial)
+}
+
+/// Checks that the given (already verified) RRsets prove that `next_closer` does not exist, i.e. that a wildcard was expanded correctly.
+pub fn prove_wildcard_expansion(
+    next_closer: &DomainName,
+    rrsets: &[Rrset],
+) -> Result<Denial, Error> {
+    if nsec_records(rrsets)
+        .iter()
+        .any(|nsec| nsec.covers(next_closer))
+    {
+        return Ok(Denial::Secure { delegation: false });
+    }
+
+    let nsec3s = nsec3_records(rrsets);
+    let Some(params) = nsec3s.first().map(Nsec3::params) else {
+        return Err(Error::MissingDenial);
+    };
+
+    if params.iterations > MAX_NSEC3_ITERATIONS {
+        return Ok(Denial::Insecure);
+    }
+
+    let hash = params.hash(next_closer);
+
+    match nsec3s.iter().find(|nsec3| nsec3.covers(&hash)) {
+        Some(nsec3) if nsec3.is_opt_out() => Ok(Denial::Insecure),
+        Some(_) => Ok(Denial::Secure { delegation: false }),
+        None => Err(Error::InvalidDenial),
+    }
+}
+
+#[derive(Debug)]
+struct Nsec {
+    owner: DomainName,
+    next: DomainName,
+    types: TypeBitmap,
+}
+
+impl Nsec {
+    fn parse(owner: &DomainName, rdata: &[u8]) -> Option<Self> {
+        let mut reader = Reader(rdata);
+
+        Some(Self {
+            owner: owner.clone(),
+            next: canonical(&reader.name()?),
+            types: TypeBitmap(reader.0.to_vec()),
+        })
+    }
+
+    fn covers(&self, name: &DomainName) -> bool {
+        let is_last = canonical_cmp(&self.next, &self.owner) != Ordering::Greater;
+
+        canonical_cmp(&self.owner, name) == Ordering::Less
+            && (is_last || canonical_cmp(name, &self.next) == Ordering::Less)
+    }
+
+    /// The closest encloser of a name covered by this NSEC, see RFC 4035, Section 5.4.
+    fn closest_encloser(&self, name: &DomainName) -> DomainName {
+        let name_labels = labels(name.as_slice());
+        let common = common_suffix(&name_labels, &labels(self.owner.as_slice()))
+            .max(common_suffix(&name_labels, &labels(self.next.as_slice())));
+
+        from_labels(name_labels[name_labels.len() - common..].iter().copied())
+    }
+}
+
+fn nsec_records(rrsets: &[Rrset]) -> Vec<Nsec> {
+    rrsets
+        .iter()
+        .filter(|rrset| rrset.rtype == RecordType::NSEC)
+        .flat_map(|rrset| {
+            rrset
+                .rdata
+                .iter()
+                .filter_map(|rdata| Nsec::parse(&rrset.owner, rdata))
+        })
+        .collect()
+}
+
+fn prove_denial_nsec(
+    qname: &DomainName,
+    qtype: RecordType,
+    response_code: ResponseCode,
+    nsecs: &[Nsec],
+) -> Result<Denial, Error> {
+    let Some(covering) = nsecs.iter().find(|nsec| nsec.covers(qname)) else {
+        // NODATA: The name exists but not with the queried type.
+        let nsec = nsecs
+            .iter()
+            .find(|nsec| &nsec.owner == qname)
+            .ok_or(Error::InvalidDenial)?;
+
+        if response_code != ResponseCode::NOERROR || !nsec.types.denies(qtype) {
+            return Err(Error::InvalidDenial);
+        }
+
+        return Ok(Denial::Secure {
+            delegation: nsec.types.is_delegation(),
+        });
+    };
+
+    let wildcard = wildcard_of(&covering.closest_encloser(qname));
+
+    if response_code == ResponseCode::NXDOMAIN {
+        if !nsecs.iter().any(|nsec| nsec.covers(&wildcard)) {
+            return Err(Error::InvalidDenial);
+        }
+
+        return Ok(Denial::Secure { delegation: false });
+    }
+
+    // Empty non-terminal: The name exists because a name below it exists.
+    if is_subdomain(&covering.next, qname) {
+        return Ok(Denial::Secure { delegation: false });
+    }
+
+    // Wildcard NODATA: The name is synthesized from a wildcard that doesn't have the queried type.
+    if nsecs
+        .iter()
+        .any(|nsec| nsec.owner == wildcard && nsec.types.denies(qtype))
+    {
+        return Ok(Denial::Secure { delegation: false });
+    }
+
+    Err(Error::InvalidDenial)
+}
+
+#[derive(Debug)]
+struct Nsec3 {
+    owner_hash: Vec<u8>,
+    hash_algorithm: u8,
+    flags: u8,
+    iterations: u16,
+    salt: Vec<u8>,
+    next_hash: Vec<u8>,
+    types: TypeBitmap,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Nsec3Params {
+    hash_algorithm: u8,
+    iterations: u16,
+    salt: Vec<u8>,
+}
+
+impl Nsec3 {
+    fn parse(owner: &DomainName, rdata: &[u8]) -> Option<Self> {
+        let owner_hash = data_encoding::BASE32HEX_NOPAD
+            .decode(&labels(owner.as_slice()).first()?.to_ascii_uppercase())
+            .ok()?;
+
+        let mut reader = Reader(rdata);
+        let hash_algorithm = reader.u8()?;
+        let flags = reader.u8()?;
+        let iterations = reader.u16()?;
+        let salt_len = reader.u8()?;
+        let salt = reader.bytes(salt_len.into())?.to_vec();
+        let hash_len = reader.u8()?;
+        let next_hash = reader.bytes(hash_len.into())?.to_vec();
+
+        Some(Self {
+            owner_hash,
+            hash_algorithm,
+            flags,
+            iterations,
+            salt,
+            next_hash,
+            types: TypeBitmap(reader.0.to_vec()),
+        })
+    }
+
+    fn params(&self) -> Nsec3Params {
+        Nsec3Params {
+            hash_algorithm: self.hash_algorithm,
+            iterations: self.iterations,
+            salt: self.salt.clone(),
+        }
+    }
+
+    fn covers(&self, hash: &[u8]) -> bool {
+        if self.owner_hash < self.next_hash {
+            self.owner_hash.as_slice() < hash && hash < self.next_hash.as_slice()
+        } else {
+            self.owner_hash.as_slice() < hash || hash < self.next_hash.as_slice()
+        }
+    }
+
+    fn is_opt_out(&self) -> bool {
+        self.flags & NSEC3_FLAG_OPT_OUT != 0
+    }
+}
+
+impl Nsec3Params {
+    /// The iterated, salted SHA-1 hash of a name, see RFC 5155, Section 5.
+    fn hash(&self, name: &DomainName) -> Vec<u8> {
+        let mut hash = sha1(&[canonical(name).as_slice(), &self.salt]);
+
+        for _ in 0..self.iterations {
+            hash = sha1(&[&hash, &self.salt]);
+        }
+
+        hash
+    }
+}
+
+fn nsec3_records(rrsets: &[Rrset]) -> Vec<Nsec3> {
+    rrsets
+        .iter()
+        .filter(|rrset| rrset.rtype == RecordType::NSEC3)
+        .flat_map(|rrset| {
+            rrset
+                .rdata
+                .iter()
+                .filter_map(|rdata| Nsec3::parse(&rrset.owner, rdata))
+        })
+        .filter(|nsec3| nsec3.hash_algorithm == NSEC3_HASH_SHA1)
+        .collect()
+}
+
+fn prove_denial_nsec3(
+    qname: &DomainName,
+    qtype: RecordType,
+    response_code: ResponseCode,
+    nsec3s: &[Nsec3],
+) -> Result<Denial, Error> {
+    let Some(params) = nsec3s.first().map(Nsec3::params) else {
+        return Err(Error::UnsupportedAlgorithm);
+    };
+
+    if params.iterations > MAX_NSEC3_ITERATIONS {
+        return Ok(Denial::Insecure);
+    }
+
+    let nsec3s = nsec3s
+        .iter()
+        .filter(|nsec3| nsec3.params() == params)
+        .collect::<Vec<_>>();
+    let matching = |name: &DomainName| {
+        let hash = params.hash(name);
+
+        nsec3s.iter().find(move |nsec3| nsec3.owner_hash == hash)
+    };
+    let covering = |name: &DomainName| {
+        let hash = params.hash(name);
+
+        nsec3s.iter().find(move |nsec3| nsec3.covers(&hash))
+    };
+
+    if response_code == ResponseCode::NOERROR {
+        if let Some(nsec3) = matching(qname) {
+            if !nsec3.types.denies(qtype) {
+                return Err(Error::InvalidDenial);
+            }
+
+            return Ok(Denial::Secure {
+                delegation: nsec3.types.is_delegation(),
+            });
+        }
+    }
+
+    // Closest encloser proof, see RFC 5155, Section 8.3.
+    let qname_labels = labels(qname.as_slice());
+    let (closest_encloser, next_closer_cover) = (0..qname_labels.len())
+        .rev()
+        .find_map(|n| {
+            let ancestor = from_labels(qname_labels[qname_labels.len() - n..].iter().copied());
+            matching(&ancestor)?;
+
+            let next_closer =
+                from_labels(qname_labels[qname_labels.len() - n - 1..].iter().copied());
+            let covering = covering(&next_closer)?;
+
+            Some((ancestor, covering))
+        })
+        .ok_or(Error::InvalidDenial)?;
+    let wildcard = wildcard_of(&closest_encloser);
+
+    if response_code == ResponseCode::NXDOMAIN {
+        covering(&wildcard).ok_or(Error::InvalidDenial)?;
+    } else if !(qtype == RecordType::DS && next_closer_cover.is_opt_out())
+        && !matching(&wildcard).is_some_and(|nsec3| nsec3.types.denies(qtype))
+    {
+        return Err(Error::InvalidDenial);
+    }
+
+    if next_closer_cover.is_opt_out() {
+        return Ok(Denial::Insecure);
+    }
+
+    Ok(Denial::Secure { delegation: false })
+}
+
+/// The types present at a name, as encoded in NSEC and NSEC3 records.
+#[derive(Debug)]
+struct TypeBitmap(Vec<u8>);
+
+impl TypeBitmap {
+    fn contains(&self, rtype: RecordType) -> bool {
+        let [window, bit] = rtype.to_int().to_be_bytes();
+        let mut rest = self.0.as_slice();
+
+        while let [current, len, tail @ ..] = rest {
+            let Some((bitmap, tail)) = tail.split_at_checked(usize::from(*len)) else {
+                return false;
+            };
+
+            if *current == window {
+                return bitmap
+                    .get(usize::from(bit / 8))
+                    .is_some_and(|byte| byte & (0x80 >> (bit % 8)) != 0);
+            }
+
+            rest = tail;
+        }
+
+        false
+    }
+
+    /// Whether a record with these types proves the absence of `qtype`.
+    fn denies(&self, qtype: RecordType) -> bool {
+        !self.contains(qtype) && !self.contains(RecordType::CNAME)
+    }
+
+    /// Whether these are the types of a delegation to a child zone.
+    fn is_delegation(&self) -> bool {
+        self.contains(RecordType::NS) && !self.contains(RecordType::SOA)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (bytes, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    /// Reads an uncompressed name in wire format.
+    fn name(&mut self) -> Option<DomainName> {
+        let mut len = 0;
+
+        loop {
+            let label_len = usize::from(*self.0.get(len)?);
+            len += 1 + label_len;
+
+            if label_len == 0 {
+                break;
+            }
+        }
+
+        DomainName::from_octets(self.bytes(len)?.to_vec()).ok()
+    }
+}
+
+fn verify_signature(
+    algorithm: u8,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    match algorithm {
+        5 | 7 => verify_rsa(
+            &ring::signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY,
+            public_key,
+            message,
+            signature,
+        ),
+        8 => verify_rsa(
+            &ring::signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+            public_key,
+            message,
+            signature,
+        ),
+        10 => verify_rsa(
+            &ring::signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY,
+            public_key,
+            message,
+            signature,
+        ),
+        13 => verify_ecdsa(
+            &ring::signature::ECDSA_P256_SHA256_FIXED,
+            public_key,
+            message,
+            signature,
+        ),
+        14 => verify_ecdsa(
+            &ring::signature::ECDSA_P384_SHA384_FIXED,
+            public_key,
+            message,
+            signature,
+        ),
+        15 => ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key)
+            .verify(message, signature)
+            .map_err(|_| Error::InvalidSignature),
+        _ => Err(Error::UnsupportedAlgorithm),
+    }
+}
+
+/// Verifies an RSA signature, with the public key encoded as per RFC 3110, Section 2.
+fn verify_rsa(
+    parameters: &'static ring::signature::RsaParameters,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    let mut reader = Reader(public_key);
+
+    let exponent_len = match reader.u8().ok_or(Error::InvalidSignature)? {
+        0 => usize::from(reader.u16().ok_or(Error::InvalidSignature)?),
+        len => usize::from(len),
+    };
+    let e = reader.bytes(exponent_len).ok_or(Error::InvalidSignature)?;
+    let n = reader.0;
+
+    ring::signature::RsaPublicKeyComponents { n, e }
+        .verify(parameters, message, signature)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+/// Verifies an ECDSA signature, with the public key encoded as per RFC 6605, Section 4.
+fn verify_ecdsa(
+    algorithm: &'static ring::signature::EcdsaVerificationAlgorithm,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    let uncompressed = [&[0x04], public_key].concat();
+
+    ring::signature::UnparsedPublicKey::new(algorithm, uncompressed)
+        .verify(message, signature)
+        .map_err(|_| Error::InvalidSignature)
+}
+
+fn is_supported_algorithm(algorithm: u8) -> bool {
+    matches!(algorithm, 5 | 7 | 8 | 10 | 13 | 14 | 15)
+}
+
+fn digest_algorithm(digest_type: u8) -> Option<&'static digest::Algorithm> {
+    match digest_type {
+        1 => Some(&digest::SHA1_FOR_LEGACY_USE_ONLY),
+        2 => Some(&digest::SHA256),
+        4 => Some(&digest::SHA384),
+        _ => None,
+    }
+}
+
+fn sha1(parts: &[&[u8]]) -> Vec<u8> {
+    let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+
+    for part in parts {
+        context.update(part);
+    }
+
+    context.finish().as_ref().to_vec()
+}
+
+/// The key tag of a DNSKEY, see RFC 4034, Appendix B.
+fn key_tag(rdata: &[u8]) -> u16 {
+    let mut acc = rdata
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| {
+            if i % 2 == 0 {
+                u64::from(*byte) << 8
+            } else {
+                u64::from(*byte)
+            }
+        })
+        .sum::<u64>();
+    acc += (acc >> 16) & 0xFFFF;
+
+    (acc & 0xFFFF) as u16
+}
+
+/// Compares `a` and `b` with serial number arithmetic, see RFC 1982.
+fn serial_le(a: u32, b: u32) -> bool {
+    b.wrapping_sub(a) < 1 << 31
+}
+
+/// The labels of a name in wire format, excluding the root label.
+fn labels(wire: &[u8]) -> Vec<&[u8]> {
+    let mut labels = Vec::new();
+    let mut rest = wire;
+
+    while let Some((&len, tail)) = rest.split_first() {
+        let Some((label, tail)) = tail.split_at_checked(usize::from(len)) else {
+            break;
+        };
+
+        if label.is_empty() {
+            break;
+        }
+
+        labels.push(label);
+        rest = tail;
+    }
+
+    labels
+}
+
+fn from_labels<'a>(labels: impl IntoIterator<Item = &'a [u8]>) -> DomainName {
+    let mut wire = Vec::new();
+
+    for label in labels {
+        wire.push(label.len() as u8);
+        wire.extend_from_slice(label);
+    }
+    wire.push(0);
+
+    DomainName::from_octets(wire).expect("labels of a valid name form a valid name")
+}
+
+/// Substitutes the `owner` suffix of `name` with `target`, as a DNAME does, see RFC 6672, Section 2.2.
+///
+/// Returns `None` if `name` is not below `owner` or the result would be too long.
+pub fn substitute_dname(
+    name: &DomainName,
+    owner: &DomainName,
+    target: &DomainName,
+) -> Option<DomainName> {
+    let name_labels = labels(name.as_slice());
+    let owner_labels = labels(owner.as_slice());
+
+    if name_labels.len() <= owner_labels.len() || !is_subdomain(name, owner) {
+        return None;
+    }
+
+    let prefix = &name_labels[..name_labels.len() - owner_labels.len()];
+
+    let mut wire = Vec::new();
+    for label in prefix {
+        wire.push(label.len() as u8);
+        wire.extend_from_slice(label);
+    }
+    wire.extend_from_slice(canonical(target).as_slice());
+
+    DomainName::from_octets(wire).ok()
+}
+
+fn wildcard_of(name: &DomainName) -> DomainName {
+    from_labels(std::iter::once(b"*".as_slice()).chain(labels(name.as_slice())))
+}
+
+/// The number of labels as counted by RRSIGs, i.e. without a leading wildcard label.
+fn rrsig_label_count(labels: &[&[u8]]) -> usize {
+    labels.len() - usize::from(labels.first() == Some(&b"*".as_slice()))
+}
+
+fn common_suffix(a: &[&[u8]], b: &[&[u8]]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+fn unix_timestamp(now: SystemTime) -> u32 {
+    now.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or_default()
+}
+
+/// Whether `name` is equal to or below `parent`.
+pub fn is_subdomain(name: &DomainName, parent: &DomainName) -> bool {
+    let name = labels(name.as_slice());
+    let parent = labels(parent.as_slice());
+
+    common_suffix(&name, &parent) == parent.len()
+}
+
+/// Compares two canonical names in canonical DNS name order, see RFC 4034, Section 6.1.
+fn canonical_cmp(a: &DomainName, b: &DomainName) -> Ordering {
+    labels(a.as_slice())
+        .iter()
+        .rev()
+        .cmp(labels(b.as_slice()).iter().rev())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ring::signature::{Ed25519KeyPair, KeyPair as _};
+
+    use super::*;
+
+    #[test]
+    fn verifies_signed_rrset() {
+        let key = signing_key();
+        let keys = zone_keys(&key);
+        let rrset = signed(&key, a_rrset("www.example.com", [1, 2, 3, 4]), NOW);
+
+        assert_eq!(rrset.verify(&keys, now()).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_tampered_rrset() {
+        let key = signing_key();
+        let keys = zone_keys(&key);
+        let mut rrset = signed(&key, a_rrset("www.example.com", [1, 2, 3, 4]), NOW);
+
+        rrset.rdata = vec![vec![6, 6, 6, 6]];
+
+        assert!(matches!(
+            rrset.verify(&keys, now()),
+            Err(Error::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_expired_signature() {
+        let key = signing_key();
+        let keys = zone_keys(&key);
+        let rrset = signed(&key, a_rrset("www.example.com", [1, 2, 3, 4]), NOW);
+
+        assert!(matches!(
+            rrset.verify(&keys, now() + Duration::from_secs(2 * 86400)),
+            Err(Error::SignatureExpired)
+        ));
+    }
+
+    #[test]
+    fn rejects_signature_of_other_zone() {
+        let key = signing_key();
+        let keys = zone_keys(&key);
+        let rrset = signed(&key, a_rrset("www.example.org", [1, 2, 3, 4]), NOW);
+
+        assert!(matches!(
+            rrset.verify(&keys, now()),
+            Err(Error::SignatureMismatch)
+        ));
+    }
+
+    #[test]
+    fn reports_next_closer_name_of_wildcard_expansion() {
+        let key = signing_key();
+        let keys = zone_keys(&key);
+        let mut rrset = signed(&key, a_rrset("*.example.com", [1, 2, 3, 4]), NOW);
+        rrset.owner = name("foo.bar.example.com");
+
+        assert_eq!(
+            rrset.verify(&keys, now()).unwrap(),
+            Some(name("bar.example.com"))
+        );
+    }
+
+    #[test]
+    fn rejects_signer_outside_of_owner_zone() {
+        let key = signing_key();
+        let rrset = signed_by(
+            &key,
+            "example.org",
+            a_rrset("www.example.com", [1, 2, 3, 4]),
+            NOW,
+        );
+
+        assert!(matches!(
+            rrset.authority(),
+            Err(Error::SignerNotAuthoritative)
+        ));
+    }
+
+    #[test]
+    fn authority_is_owner_of_regular_rrset() {
+        let key = signing_key();
+        let rrset = signed(&key, a_rrset("www.example.com", [1, 2, 3, 4]), NOW);
+
+        assert_eq!(rrset.authority().unwrap(), name("www.example.com"));
+    }
+
+    #[test]
+    fn authority_of_wildcard_expansion_is_wildcard_parent() {
+        let key = signing_key();
+        let mut rrset = signed(&key, a_rrset("*.example.com", [1, 2, 3, 4]), NOW);
+        rrset.owner = name("foo.bar.example.com");
+
+        assert_eq!(rrset.authority().unwrap(), name("example.com"));
+    }
+
+    #[test]
+    fn authority_of_ds_is_parent() {
+        let key = signing_key();
+        let mut rrset = a_rrset("sub.example.com", [1, 2, 3, 4]);
+        rrset.rtype = RecordType::DS;
+        let rrset = signed(&key, rrset, NOW);
+
+        assert_eq!(rrset.authority().unwrap(), name("example.com"));
+    }
+
+    #[test]
+    fn authenticates_dnskeys_with_ds() {
+        let key = signing_key();
+        let dnskeys = signed(&key, dnskey_rrset(&key), NOW);
+        let ds = ds_for(&dnskeys);
+
+        let keys = ZoneKeys::new(&dnskeys, &[ds.clone()], now()).unwrap();
+
+        assert_eq!(keys.zone(), &name("example.com"));
+
+        let wrong_ds = Ds {
+            digest: vec![0; 32],
+            ..ds
+        };
+
+        assert!(matches!(
+            ZoneKeys::new(&dnskeys, &[wrong_ds], now()),
+            Err(Error::UntrustedKeys)
+        ));
+    }
+
+    #[test]
+    fn ds_with_unsupported_algorithm_is_reported() {
+        let key = signing_key();
+        let dnskeys = signed(&key, dnskey_rrset(&key), NOW);
+        let ds = Ds {
+            algorithm: 3, // DSA
+            ..ds_for(&dnskeys)
+        };
+
+        assert!(matches!(
+            ZoneKeys::new(&dnskeys, &[ds], now()),
+            Err(Error::UnsupportedAlgorithm)
+        ));
+    }
+
+    #[test]
+    fn computes_nsec3_hash() {
+        // Test vector from RFC 5155, Appendix A.
+        let params = Nsec3Params {
+            hash_algorithm: NSEC3_HASH_SHA1,
+            iterations: 12,
+            salt: hex_literal::hex!("AABBCCDD").to_vec(),
+        };
+
+        let hash = params.hash(&name("example"));
+
+        assert_eq!(
+            data_encoding::BASE32HEX_NOPAD
+                .encode(&hash)
+                .to_ascii_lowercase(),
+            "0p9mhaveqvm6t7vbl5lop2u3t2rp3tom"
+        );
+    }
+
+    #[test]
+    fn nsec_proves_nxdomain() {
+        let rrsets = [
+            nsec_rrset("example.com", "a.example.com", &[RecordType::SOA]),
+            nsec_rrset("b.example.com", "d.example.com", &[RecordType::A]),
+        ];
+
+        assert_eq!(
+            prove_denial(
+                &name("c.example.com"),
+                RecordType::A,
+                ResponseCode::NXDOMAIN,
+                &rrsets
+            )
+            .unwrap(),
+            Denial::Secure { delegation: false }
+        );
+    }
+
+    #[test]
+    fn nsec_without_wildcard_proof_is_rejected() {
+        let rrsets = [nsec_rrset(
+            "b.example.com",
+            "d.example.com",
+            &[RecordType::A],
+        )];
+
+        assert!(matches!(
+            prove_denial(
+                &name("c.example.com"),
+                RecordType::A,
+                ResponseCode::NXDOMAIN,
+                &rrsets
+            ),
+            Err(Error::InvalidDenial)
+        ));
+    }
+
+    #[test]
+    fn nsec_proves_insecure_delegation() {
+        let rrsets = [nsec_rrset(
+            "sub.example.com",
+            "www.example.com",
+            &[RecordType::NS, RecordType::RRSIG, RecordType::NSEC],
+        )];
+
+        assert_eq!(
+            prove_denial(
+                &name("sub.example.com"),
+                RecordType::DS,
+                ResponseCode::NOERROR,
+                &rrsets
+            )
+            .unwrap(),
+            Denial::Secure { delegation: true }
+        );
+    }
+
+    #[test]
+    fn nsec_with_queried_type_is_rejected() {
+        let rrsets = [nsec_rrset(
+            "www.example.com",
+            "zzz.example.com",
+            &[RecordType::A, RecordType::TXT],
+        )];
+
+        assert!(matches!(
+            prove_denial(
+                &name("www.example.com"),
+                RecordType::TXT,
+                ResponseCode::NOERROR,
+                &rrsets
+            ),
+            Err(Error::InvalidDenial)
+        ));
+    }
+
+    #[test]
+    fn parses_type_bitmap() {
+        let types = type_bitmap(&[RecordType::A, RecordType::NS, RecordType::CAA]);
+
+        assert!(types.contains(RecordType::A));
+        assert!(types.contains(RecordType::NS));
+        assert!(types.contains(RecordType::CAA));
+        assert!(!types.contains(RecordType::AAAA));
+        assert!(!types.contains(RecordType::DS));
+    }
+
+    #[test]
+    fn ttl_is_capped_by_signature() {
+        let key = signing_key();
+        let mut rrset = a_rrset("www.example.com", [1, 2, 3, 4]);
+        rrset.ttl = 86400;
+        let rrset = signed(&key, rrset, NOW);
+
+        assert_eq!(rrset.ttl(now()), Duration::from_secs(3600));
+        assert_eq!(
+            rrset.ttl(now() + Duration::from_secs(86400 - 60)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            rrset.ttl(now() + Duration::from_secs(2 * 86400)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn substitutes_dname() {
+        assert_eq!(
+            substitute_dname(
+                &name("www.example.com"),
+                &name("example.com"),
+                &name("example.net")
+            ),
+            Some(name("www.example.net"))
+        );
+        assert_eq!(
+            substitute_dname(
+                &name("example.com"),
+                &name("example.com"),
+                &name("example.net")
+            ),
+            None
+        );
+        assert_eq!(
+            substitute_dname(
+                &name("www.example.org"),
+                &name("example.com"),
+                &name("example.net")
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_ds_presentation_format() {
+        let ds = "20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D"
+            .parse::<Ds>()
+            .unwrap();
+
+        assert_eq!(ds, root_trust_anchors()[0]);
+        assert!("20326 8 2".parse::<Ds>().is_err());
+        assert!("20326 8 2 XYZ".parse::<Ds>().is_err());
+    }
+
+    /// 2024-01-01T00:00:00Z
+    const NOW: u32 = 1_704_067_200;
+
+    fn now() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(NOW.into())
+    }
+
+    fn name(name: &str) -> DomainName {
+        canonical(&DomainName::vec_from_str(name).unwrap())
+    }
+
+    fn signing_key() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[1; 32]).unwrap()
+    }
+
+    fn dnskey_rdata(key: &Ed25519KeyPair) -> Vec<u8> {
+        let mut rdata = Vec::new();
+        rdata.extend_from_slice(&257_u16.to_be_bytes()); // Zone key + secure entry point.
+        rdata.push(DNSKEY_PROTOCOL);
+        rdata.push(15);
+        rdata.extend_from_slice(key.public_key().as_ref());
+
+        rdata
+    }
+
+    fn dnskey_rrset(key: &Ed25519KeyPair) -> Rrset {
+        Rrset {
+            owner: name("example.com"),
+            rtype: RecordType::DNSKEY,
+            class: Class::IN,
+            ttl: 3600,
+            rdata: vec![dnskey_rdata(key)],
+            signatures: Vec::new(),
+        }
+    }
+
+    fn zone_keys(key: &Ed25519KeyPair) -> ZoneKeys {
+        ZoneKeys {
+            zone: name("example.com"),
+            keys: vec![Dnskey::parse(&dnskey_rdata(key)).unwrap()],
+        }
+    }
+
+    fn ds_for(dnskeys: &Rrset) -> Ds {
+        let key = Dnskey::parse(&dnskeys.rdata[0]).unwrap();
+
+        let mut context = digest::Context::new(&digest::SHA256);
+        context.update(dnskeys.owner.as_slice());
+        context.update(&key.rdata);
+
+        Ds {
+            key_tag: key.key_tag,
+            algorithm: key.algorithm,
+            digest_type: 2,
+            digest: context.finish().as_ref().to_vec(),
+        }
+    }
+
+    fn a_rrset(owner: &str, ip: [u8; 4]) -> Rrset {
+        Rrset {
+            owner: name(owner),
+            rtype: RecordType::A,
+            class: Class::IN,
+            ttl: 3600,
+            rdata: vec![ip.to_vec()],
+            signatures: Vec::new(),
+        }
+    }
+
+    fn nsec_rrset(owner: &str, next: &str, types: &[RecordType]) -> Rrset {
+        let mut rdata = name(next).as_slice().to_vec();
+        rdata.extend_from_slice(&type_bitmap(types).0);
+
+        Rrset {
+            owner: name(owner),
+            rtype: RecordType::NSEC,
+            class: Class::IN,
+            ttl: 3600,
+            rdata: vec![rdata],
+            signatures: Vec::new(),
+        }
+    }
+
+    fn type_bitmap(types: &[RecordType]) -> TypeBitmap {
+        let mut windows = BTreeMap::<u8, Vec<u8>>::new();
+
+        for rtype in types {
+            let [window, bit] = rtype.to_int().to_be_bytes();
+            let bitmap = windows.entry(window).or_default();
+            let index = usize::from(bit / 8);
+
+            if bitmap.len() <= index {
+                bitmap.resize(index + 1, 0);
+            }
+
+            bitmap[index] |= 0x80 >> (bit % 8);
+        }
+
+        TypeBitmap(
+            windows
+                .into_iter()
+                .flat_map(|(window, bitmap)| [window, bitmap.len() as u8].into_iter().chain(bitmap))
+                .collect(),
+        )
+    }
+
+    fn signed(key: &Ed25519KeyPair, rrset: Rrset, now: u32) -> Rrset {
+        signed_by(key, "example.com", rrset, now)
+    }
+
+    fn signed_by(key: &Ed25519KeyPair, zone: &str, mut rrset: Rrset, now: u32) -> Rrset {
+        let zone = name(zone);
+        let owner_labels = labels(rrset.owner.as_slice());
+
+        let mut rdata = Vec::new();
+        rdata.extend_from_slice(&rrset.rtype.to_int().to_be_bytes());
+        rdata.push(15);
+        rdata.push(rrsig_label_count(&owner_labels) as u8);
+        rdata.extend_from_slice(&3600_u32.to_be_bytes());
+        rdata.extend_from_slice(&(now + 86400).to_be_bytes());
+        rdata.extend_from_slice(&(now - 86400).to_be_bytes());
+        rdata.extend_from_slice(&key_tag(&dnskey_rdata(key)).to_be_bytes());
+        rdata.extend_from_slice(zone.as_slice());
+
+        let unsigned = Rrsig::parse(&rdata).unwrap();
+        let signature = key.sign(&rrset.signed_data(&unsigned, &rrset.owner));
+        rdata.extend_from_slice(signature.as_ref());
+
+        rrset.signatures.push(Rrsig::parse(&rdata).unwrap());
+
+        rrset
+    }
+}
@@ -2,12 +2,15 @@
 
 use domain::{
     base::{
-        HeaderCounts, Message, MessageBuilder, ParsedName, Question, RecordSection,
-        message_builder::AnswerBuilder, name::FlattenInto,
+        HeaderCounts, Message, MessageBuilder, ParsedName, Question, RecordSection, ToName as _,
+        Ttl, message_builder::AnswerBuilder, name::FlattenInto,
     },
     dep::octseq::OctetsInto,
     rdata::AllRecordData,
 };
+use std::time::Duration;
+
+pub mod dnssec;
 
 pub mod prelude {
     // Re-export trait names so other crates can call the functions on them.
@@ -19,6 +22,9 @@ pub mod prelude {
 
 pub const MAX_NAME_LEN: usize = domain::base::Name::MAX_LEN;
 
+/// The UDP payload size we advertise via EDNS, as recommended by the DNS flag day 2020.
+const EDNS_UDP_PAYLOAD_SIZE: u16 = 1232;
+
 pub type RecordType = domain::base::iana::Rtype;
 
 pub type DomainNameRef<'a> = domain::base::Name<&'a [u8]>;
@@ -93,6 +99,41 @@ impl Query {
         self
     }
 
+    /// Re-creates this query with an EDNS record that sets the DO bit, asking for DNSSEC records in the response.
+    pub fn with_dnssec_ok(self) -> Self {
+        let mut builder = MessageBuilder::new_vec();
+        *builder.header_mut() = self.inner.header();
+
+        let mut question = builder.question();
+        question
+            .push(self.question())
+            .expect("Vec-backed message builder never fails");
+
+        let mut additional = question.additional();
+        additional
+            .opt(|opt| {
+                opt.set_udp_payload_size(EDNS_UDP_PAYLOAD_SIZE);
+                opt.set_dnssec_ok(true);
+
+                Ok(())
+            })
+            .expect("Vec-backed message builder never fails");
+
+        Self {
+            inner: additional.into_message(),
+        }
+    }
+
+    /// Whether this query asks for DNSSEC records via the DO bit.
+    pub fn dnssec_ok(&self) -> bool {
+        self.inner.opt().is_some_and(|opt| opt.dnssec_ok())
+    }
+
+    /// Whether this query disables DNSSEC validation via the CD bit.
+    pub fn checking_disabled(&self) -> bool {
+        self.inner.header().cd()
+    }
+
     pub fn id(&self) -> u16 {
         self.inner.header().id()
     }
@@ -134,6 +175,7 @@ impl TryFrom<&[u8]> for Response {
     }
 }
 
+#[derive(Clone)]
 pub struct Response {
     inner: Message<Vec<u8>>,
 }
@@ -210,6 +252,162 @@ impl Response {
         })
     }
 
+    /// Follows the CNAME records in the answer section, starting at the queried domain.
+    ///
+    /// Returns each link of the chain as `(owner, target, ttl)`, in order.
+    pub fn cname_chain(&self) -> Vec<(DomainName, DomainName, u32)> {
+        let cnames = self
+            .records()
+            .filter_map(|r| {
+                #[expect(clippy::wildcard_enum_match_arm)]
+                match r.data() {
+                    AllRecordData::Cname(cname) => Some((
+                        r.owner().to_name(),
+                        cname.cname().to_name(),
+                        r.ttl().as_secs(),
+                    )),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<(DomainName, DomainName, u32)>>();
+
+        let mut chain = Vec::new();
+        let mut name = self.domain();
+
+        // A chain can have at most as many links as there are CNAME records, this also protects us from loops.
+        for _ in 0..cnames.len() {
+            let Some(link) = cnames.iter().find(|(owner, _, _)| owner == &name) else {
+                break;
+            };
+
+            name = link.1.clone();
+            chain.push(link.clone());
+        }
+
+        chain
+    }
+
+    /// For how long this response may be cached.
+    ///
+    /// For positive responses, this is the smallest TTL of all answer records.
+    /// For negative responses (NXDOMAIN or NODATA), it is derived from the SOA record in the authority section as per RFC 2308.
+    /// Returns `None` if the response must not be cached.
+    pub fn ttl(&self) -> Option<Duration> {
+        if self.truncated() {
+            return None;
+        }
+
+        let response_code = self.response_code();
+
+        if response_code == ResponseCode::NOERROR {
+            if let Some(ttl) = self.records().map(|r| r.ttl().as_secs()).min() {
+                return Some(Duration::from_secs(ttl.into()));
+            }
+        } else if response_code != ResponseCode::NXDOMAIN {
+            return None;
+        }
+
+        let negative_ttl = self.authority_records().find_map(|r| {
+            #[expect(clippy::wildcard_enum_match_arm)]
+            match r.data() {
+                AllRecordData::Soa(soa) => Some(r.ttl().as_secs().min(soa.minimum().as_secs())),
+                _ => None,
+            }
+        })?;
+
+        Some(Duration::from_secs(negative_ttl.into()))
+    }
+
+    /// Re-creates this response as an answer to the given query, reducing the TTL of all records by `age`.
+    ///
+    /// The RA and AD bits as well as all sections are retained.
+    /// The OPT record is only retained if the query has one too and its TTL is left as is because it carries the EDNS flags.
+    pub fn aged_for(&self, query: &Query, age: Duration) -> Self {
+        let age = u32::try_from(age.as_secs()).unwrap_or(u32::MAX);
+        let age_record = |mut record: Record<'_>| {
+            record.set_ttl(Ttl::from_secs(record.ttl().as_secs().saturating_sub(age)));
+
+            record
+        };
+
+        let mut answer = MessageBuilder::new_vec()
+            .start_answer(&query.inner, self.response_code())
+            .expect("Vec-backed message builder never fails");
+        answer.header_mut().set_ra(self.inner.header().ra());
+        answer.header_mut().set_ad(self.inner.header().ad());
+
+        for record in self.records() {
+            answer
+                .push(age_record(record))
+                .expect("Vec-backed message builder never fails");
+        }
+
+        let mut authority = answer.authority();
+
+        for record in self.authority_records() {
+            authority
+                .push(age_record(record))
+                .expect("Vec-backed message builder never fails");
+        }
+
+        let mut additional = authority.additional();
+
+        for record in self.additional_records() {
+            let record = match record.rtype() {
+                RecordType::OPT if query.inner.opt().is_none() => continue,
+                RecordType::OPT => record,
+                _ => age_record(record),
+            };
+
+            additional
+                .push(record)
+                .expect("Vec-backed message builder never fails");
+        }
+
+        Self {
+            inner: additional.into_message(),
+        }
+    }
+
+    /// Re-creates this response as an answer to the given query after validating it with DNSSEC.
+    ///
+    /// Only the answer and authority sections are retained, without DNSSEC records unless the query asked for them.
+    /// The AD bit is set if the response is `authenticated`.
+    pub fn validated_for(&self, query: &Query, authenticated: bool) -> Self {
+        let is_wanted = |record: &Record<'_>| {
+            let rtype = record.rtype();
+
+            query.dnssec_ok()
+                || rtype == query.qtype()
+                || (rtype != RecordType::RRSIG
+                    && rtype != RecordType::NSEC
+                    && rtype != RecordType::NSEC3)
+        };
+
+        let mut answer = MessageBuilder::new_vec()
+            .start_answer(&query.inner, self.response_code())
+            .expect("Vec-backed message builder never fails");
+        answer.header_mut().set_ad(authenticated);
+
+        for record in self.records().filter(is_wanted) {
+            answer
+                .push(record)
+                .expect("Vec-backed message builder never fails");
+        }
+
+        let mut authority = answer.authority();
+
+        for record in self.authority_records().filter(is_wanted) {
+            authority
+                .push(record)
+                .expect("Vec-backed message builder never fails");
+        }
+
+        Self {
+            inner: authority.into_message(),
+        }
+    }
+
     /// Serializes this response into a byte slice.
     ///
     /// The `max_len` parameter specifies the maximum size of the payload.
@@ -249,10 +447,29 @@ impl Response {
     fn answer(&self) -> RecordSection<'_, Vec<u8>> {
         self.inner.answer().expect("verified in ctor")
     }
+
+    /// The records in the authority section, skipping any that we cannot parse.
+    pub fn authority_records(&self) -> impl Iterator<Item = Record<'_>> {
+        self.inner
+            .authority()
+            .into_iter()
+            .flatten()
+            .filter_map(|r| r.ok()?.into_any_record::<AllRecordData<_, _>>().ok())
+    }
+
+    /// The records in the additional section, skipping any that we cannot parse.
+    fn additional_records(&self) -> impl Iterator<Item = Record<'_>> {
+        self.inner
+            .additional()
+            .into_iter()
+            .flatten()
+            .filter_map(|r| r.ok()?.into_any_record::<AllRecordData<_, _>>().ok())
+    }
 }
 
 pub struct ResponseBuilder {
     inner: AnswerBuilder<Vec<u8>>,
+    authority: Vec<OwnedRecord>,
 }
 
 impl ResponseBuilder {
@@ -261,7 +478,10 @@ impl ResponseBuilder {
             .start_answer(&query.inner, code)
             .expect("Vec-backed message builder never fails");
 
-        Self { inner }
+        Self {
+            inner,
+            authority: Vec::new(),
+        }
     }
 
     pub fn with_records(mut self, records: impl IntoIterator<Item: Into<OwnedRecord>>) -> Self {
@@ -274,9 +494,26 @@ impl ResponseBuilder {
         self
     }
 
+    pub fn with_authority_records(
+        mut self,
+        records: impl IntoIterator<Item: Into<OwnedRecord>>,
+    ) -> Self {
+        self.authority.extend(records.into_iter().map(Into::into));
+
+        self
+    }
+
     pub fn build(self) -> Response {
+        let mut authority = self.inner.authority();
+
+        for record in self.authority {
+            authority
+                .push(record)
+                .expect("Vec-backed message builder never fails");
+        }
+
         Response {
-            inner: self.inner.into_message(),
+            inner: authority.into_message(),
         }
     }
 }
@@ -296,7 +533,14 @@ pub enum Error {
 pub mod records {
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-    use domain::rdata::{A, Aaaa, Ptr, Srv, Txt, rfc1035::TxtError};
+    use domain::rdata::{
+        A, Aaaa, Cname, Dname, Ptr, Soa, Srv, Txt,
+        rfc1035::TxtError,
+        svcb::{
+            Https, SvcParams, Svcb,
+            value::{Ipv4Hint, Ipv6Hint},
+        },
+    };
 
     use super::*;
 
@@ -323,14 +567,82 @@ pub mod records {
         Ok(OwnedRecordData::Txt(Txt::from_octets(content)?))
     }
 
+    pub fn soa(mname: DomainName, rname: DomainName, serial: u32, minimum: u32) -> OwnedRecordData {
+        OwnedRecordData::Soa(Soa::new(
+            mname,
+            rname,
+            serial.into(),
+            Ttl::from_secs(3600),
+            Ttl::from_secs(600),
+            Ttl::from_secs(86400),
+            Ttl::from_secs(minimum),
+        ))
+    }
+
     pub fn srv(priority: u16, weight: u16, port: u16, target: DomainName) -> OwnedRecordData {
         OwnedRecordData::Srv(Srv::new(priority, weight, port, target))
     }
+
+    pub fn cname(target: DomainName) -> OwnedRecordData {
+        OwnedRecordData::Cname(Cname::new(target))
+    }
+
+    pub fn dname(target: DomainName) -> OwnedRecordData {
+        OwnedRecordData::Dname(Dname::new(target))
+    }
+
+    /// An HTTPS record in service mode for the owner name itself, with the given IPs as `ipv4hint` and `ipv6hint`.
+    pub fn https(ips: impl IntoIterator<Item = IpAddr>) -> OwnedRecordData {
+        OwnedRecordData::Https(
+            Https::new(1, DomainName::root_vec(), ip_hints(ips))
+                .expect("IP hints are always shorter than 64k"),
+        )
+    }
+
+    /// An SVCB record in service mode for the owner name itself, with the given IPs as `ipv4hint` and `ipv6hint`.
+    pub fn svcb(ips: impl IntoIterator<Item = IpAddr>) -> OwnedRecordData {
+        OwnedRecordData::Svcb(
+            Svcb::new(1, DomainName::root_vec(), ip_hints(ips))
+                .expect("IP hints are always shorter than 64k"),
+        )
+    }
+
+    fn ip_hints(ips: impl IntoIterator<Item = IpAddr>) -> SvcParams<Vec<u8>> {
+        let (ipv4, ipv6) =
+            ips.into_iter()
+                .fold((Vec::new(), Vec::new()), |(mut ipv4, mut ipv6), ip| {
+                    match ip {
+                        IpAddr::V4(ip) => ipv4.push(ip),
+                        IpAddr::V6(ip) => ipv6.push(ip),
+                    }
+
+                    (ipv4, ipv6)
+                });
+
+        SvcParams::from_values(|params| {
+            // Parameters must be in ascending order of their keys, `ipv4hint` is 4 and `ipv6hint` is 6.
+            if !ipv4.is_empty() {
+                params.push(
+                    &Ipv4Hint::<Vec<u8>>::from_addrs(ipv4)
+                        .expect("Vec-backed value builder never fails"),
+                )?;
+            }
+            if !ipv6.is_empty() {
+                params.push(
+                    &Ipv6Hint::<Vec<u8>>::from_addrs(ipv6)
+                        .expect("Vec-backed value builder never fails"),
+                )?;
+            }
+
+            Ok(())
+        })
+        .expect("Vec-backed params builder never fails")
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::Ipv4Addr;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
     use super::*;
 
@@ -354,4 +666,159 @@ mod tests {
         assert_eq!(parsed_response.records().count(), 0);
         assert_eq!(parsed_response.domain(), domain);
     }
+
+    #[test]
+    fn ttl_of_positive_response_is_smallest_record_ttl() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+
+        let query = Query::new(domain.clone(), RecordType::A);
+        let response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([
+                (domain.clone(), 300, records::a(Ipv4Addr::new(1, 1, 1, 1))),
+                (domain.clone(), 60, records::a(Ipv4Addr::new(1, 0, 0, 1))),
+            ])
+            .build();
+
+        assert_eq!(response.ttl(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn ttl_of_negative_response_is_taken_from_soa() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+        let ns = DomainName::vec_from_str("ns.example.com").unwrap();
+
+        let query = Query::new(domain.clone(), RecordType::A);
+        let response = ResponseBuilder::for_query(&query, ResponseCode::NXDOMAIN)
+            .with_authority_records([(domain.clone(), 900, records::soa(ns.clone(), ns, 1, 120))])
+            .build();
+
+        assert_eq!(response.ttl(), Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn servfail_is_not_cacheable() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+
+        let response = Response::servfail(&Query::new(domain, RecordType::A));
+
+        assert_eq!(response.ttl(), None);
+    }
+
+    #[test]
+    fn follows_cname_chain_from_queried_domain() {
+        let domain = DomainName::vec_from_str("www.example.com").unwrap();
+        let cdn = DomainName::vec_from_str("example.cdn.net").unwrap();
+        let edge = DomainName::vec_from_str("edge.cdn.net").unwrap();
+
+        let query = Query::new(domain.clone(), RecordType::A);
+        let response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([
+                (cdn.clone(), 60, records::cname(edge.clone())),
+                (domain.clone(), 300, records::cname(cdn.clone())),
+                (edge.clone(), 60, records::a(Ipv4Addr::LOCALHOST)),
+            ])
+            .build();
+
+        assert_eq!(
+            response.cname_chain(),
+            vec![(domain, cdn.clone(), 300), (cdn, edge, 60)]
+        );
+    }
+
+    #[test]
+    fn https_record_has_ip_hints() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+
+        let query = Query::new(domain.clone(), RecordType::HTTPS);
+        let response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([(
+                domain,
+                300,
+                records::https([
+                    IpAddr::from(Ipv4Addr::LOCALHOST),
+                    IpAddr::from(Ipv6Addr::LOCALHOST),
+                ]),
+            )])
+            .build();
+
+        let bytes = response.into_bytes(1000);
+        let parsed_response = Response::parse(&bytes).unwrap();
+        let record = parsed_response.records().next().unwrap();
+
+        let AllRecordData::Https(https) = record.data() else {
+            panic!("Expected HTTPS record")
+        };
+        assert!(https.is_service());
+        assert_eq!(https.params().iter_all().count(), 2);
+    }
+
+    #[test]
+    fn aged_response_has_reduced_ttl_and_new_id() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+
+        let query = Query::new(domain.clone(), RecordType::A).with_id(1);
+        let response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([(domain.clone(), 300, records::a(Ipv4Addr::LOCALHOST))])
+            .build();
+
+        let aged = response.aged_for(&query.with_id(2), Duration::from_secs(100));
+
+        assert_eq!(aged.id(), 2);
+        assert_eq!(aged.domain(), domain);
+        assert_eq!(aged.ttl(), Some(Duration::from_secs(200)));
+    }
+
+    #[test]
+    fn aged_response_retains_flags_and_additional_section() {
+        let domain = DomainName::vec_from_str("example.com").unwrap();
+        let ns = DomainName::vec_from_str("ns.example.com").unwrap();
+
+        let query = Query::new(domain.clone(), RecordType::A).with_dnssec_ok();
+        let mut answer = MessageBuilder::new_vec()
+            .start_answer(&query.inner, ResponseCode::NOERROR)
+            .unwrap();
+        answer.header_mut().set_ra(true);
+        answer.header_mut().set_ad(true);
+        answer
+            .push(OwnedRecord::from((
+                domain.clone(),
+                300,
+                records::a(Ipv4Addr::LOCALHOST),
+            )))
+            .unwrap();
+        let mut additional = answer.authority().additional();
+        additional
+            .push(OwnedRecord::from((
+                ns.clone(),
+                300,
+                records::a(Ipv4Addr::LOCALHOST),
+            )))
+            .unwrap();
+        additional
+            .opt(|opt| {
+                opt.set_dnssec_ok(true);
+
+                Ok(())
+            })
+            .unwrap();
+        let response = Response {
+            inner: additional.into_message(),
+        };
+
+        let aged = response.aged_for(&query, Duration::from_secs(100));
+
+        assert!(aged.inner.header().ra());
+        assert!(aged.inner.header().ad());
+        assert!(aged.inner.opt().is_some_and(|opt| opt.dnssec_ok()));
+        let glue = aged
+            .additional_records()
+            .find(|r| r.owner().to_name::<Vec<u8>>() == ns)
+            .unwrap();
+        assert_eq!(glue.ttl().as_secs(), 200);
+
+        let aged = response.aged_for(&Query::new(domain, RecordType::A), Duration::from_secs(100));
+
+        assert!(aged.inner.opt().is_none());
+        assert_eq!(aged.additional_records().count(), 1);
+    }
 }
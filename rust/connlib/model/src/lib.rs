@@ -10,7 +10,8 @@ mod view;
 pub use boringtun::x25519::PublicKey;
 pub use boringtun::x25519::StaticSecret;
 pub use view::{
-    CidrResourceView, DnsResourceView, InternetResourceView, ResourceStatus, ResourceView,
+    CandidateType, CidrResourceView, ConnectionPathView, ConnectionView, DnsQueryLogEntry,
+    DnsQueryStrategy, DnsResourceView, InternetResourceView, ResourceStatus, ResourceView,
 };
 
 use serde::{Deserialize, Serialize};
@@ -3,9 +3,10 @@ use ip_network::IpNetwork;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::fmt::Debug;
+use std::time::{Duration, SystemTime};
 
-use crate::ResourceId;
 use crate::Site;
+use crate::{GatewayId, RelayId, ResourceId};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ResourceStatus {
@@ -154,6 +155,109 @@ impl Ord for ResourceView {
     }
 }
 
+/// How we are connected to a Gateway.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ConnectionView {
+    pub gateway_id: GatewayId,
+    /// The network path we send data over, `None` whilst the connection is being established.
+    pub path: Option<ConnectionPathView>,
+    /// Round-trip time to the Gateway in milliseconds.
+    pub rtt_ms: Option<u64>,
+    /// Estimated share of packets that get lost, between 0 and 1.
+    pub packet_loss: f32,
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub rx_packets: u64,
+    /// Seconds since the last completed WireGuard handshake.
+    pub secs_since_last_handshake: Option<u64>,
+}
+
+impl ConnectionView {
+    /// Whether we are connected to the Gateway via a relay.
+    pub fn is_relayed(&self) -> bool {
+        self.path.is_some_and(|p| {
+            p.local == CandidateType::Relayed || p.remote == CandidateType::Relayed
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionPathView {
+    /// The type of our candidate.
+    pub local: CandidateType,
+    /// The type of the Gateway's candidate.
+    pub remote: CandidateType,
+    /// The relay we use to reach the Gateway, if any.
+    pub relay_id: Option<RelayId>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateType {
+    Host,
+    ServerReflexive,
+    PeerReflexive,
+    Relayed,
+}
+
+impl fmt::Display for CandidateType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CandidateType::Host => write!(f, "host"),
+            CandidateType::ServerReflexive => write!(f, "srflx"),
+            CandidateType::PeerReflexive => write!(f, "prflx"),
+            CandidateType::Relayed => write!(f, "relay"),
+        }
+    }
+}
+
+/// A DNS query that was handled by connlib.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQueryLogEntry {
+    /// When we received the query.
+    pub timestamp: SystemTime,
+    pub domain: String,
+    pub qtype: String,
+    pub strategy: DnsQueryStrategy,
+    /// The DNS resource the domain matched, if any.
+    pub resource: Option<ResourceId>,
+    /// The response code we answered with, `None` if we never received a response from upstream.
+    pub response_code: Option<String>,
+    /// How long it took us to answer the query.
+    pub latency: Duration,
+    /// The DNS server we forwarded the query to, if any.
+    pub upstream: Option<String>,
+}
+
+/// How connlib resolved a DNS query.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsQueryStrategy {
+    /// Answered by connlib itself, i.e. for DNS resources or static overrides.
+    LocalResponse,
+    /// Answered from the cache of recursive responses.
+    Cache,
+    /// Forwarded to the system's or portal's upstream resolvers.
+    RecurseLocal,
+    /// Forwarded to the Gateway of the site that hosts the DNS resource.
+    RecurseSite,
+    /// Forwarded to the DNS server of a forwarding rule.
+    RecurseServer,
+}
+
+impl fmt::Display for DnsQueryStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DnsQueryStrategy::LocalResponse => write!(f, "local_response"),
+            DnsQueryStrategy::Cache => write!(f, "cache"),
+            DnsQueryStrategy::RecurseLocal => write!(f, "recurse_local"),
+            DnsQueryStrategy::RecurseSite => write!(f, "recurse_site"),
+            DnsQueryStrategy::RecurseServer => write!(f, "recurse_server"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
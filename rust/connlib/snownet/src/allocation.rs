@@ -1,6 +1,6 @@
 use crate::{
     backoff::{self, ExponentialBackoff},
-    node::{SessionId, Transmit},
+    node::{SessionId, Transmit, Transport},
 };
 use bufferpool::BufferPool;
 use bytecodec::{DecodeExt as _, EncodeExt as _};
@@ -45,6 +45,9 @@ const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
 /// It ain't much traffic and with a lower interval, these checks can also help in disconnecting from an unresponsive relay.
 const BINDING_INTERVAL: Duration = Duration::from_secs(25);
 
+/// How many RTT samples we need before we compare a relay's RTT to other relays.
+const MIN_RTT_SAMPLES: usize = 3;
+
 /// Represents a TURN allocation that refreshes itself.
 ///
 /// Allocations have a lifetime and need to be continuously refreshed to stay active.
@@ -63,6 +66,14 @@ pub struct Allocation {
     /// This ensures any NAT bindings stay alive even if the allocation is completely idle.
     active_socket: Option<ActiveSocket>,
 
+    /// The transport we use to talk to the relay.
+    ///
+    /// We always start with UDP.
+    /// If none of our BINDING requests are answered, UDP is likely blocked on this network and we retry via TCP.
+    /// If TCP doesn't work either, we retry via TLS on port 443 which is the most likely to be allowed by restrictive firewalls.
+    /// Suspending the allocation resets this so we prefer UDP again the next time we try to make an allocation.
+    transport: Transport,
+
     software: Software,
 
     /// If present, the IPv4 address the relay observed for us.
@@ -77,6 +88,11 @@ pub struct Allocation {
     /// When we received the allocation and how long it is valid.
     allocation_lifetime: Option<(Instant, Duration)>,
 
+    /// The smoothed RTT of the BINDING requests on our active socket.
+    rtt: Option<Duration>,
+    /// How many samples went into [`Allocation::rtt`].
+    num_rtt_samples: usize,
+
     buffered_transmits: VecDeque<Transmit>,
     events: VecDeque<Event>,
 
@@ -219,6 +235,7 @@ impl Allocation {
         let mut allocation = Self {
             server,
             active_socket: None,
+            transport: Transport::Udp,
             ip4_srflx_candidate: Default::default(),
             ip6_srflx_candidate: Default::default(),
             ip4_allocation: Default::default(),
@@ -233,6 +250,8 @@ impl Allocation {
                 nonce: Default::default(),
             }),
             allocation_lifetime: Default::default(),
+            rtt: Default::default(),
+            num_rtt_samples: 0,
             channel_bindings: Default::default(),
             buffered_channel_bindings: AllocRingBuffer::new(100),
             software: Software::new(format!("snownet; session={session_id}"))
@@ -266,6 +285,9 @@ impl Allocation {
             tracing::debug!("Attempting to make a new allocation");
 
             self.active_socket = None;
+            self.rtt = None;
+            self.num_rtt_samples = 0;
+            self.transport = Transport::Udp;
             self.send_binding_requests(now);
             return;
         }
@@ -496,13 +518,26 @@ impl Allocation {
 
         match message.method() {
             BINDING => {
+                // Only the RTT of our active socket is relevant, the other one lost the race.
+                let is_active_socket = self
+                    .active_socket
+                    .is_none_or(|active| active.addr == original_dst);
+
+                if is_active_socket && !backoff.was_retransmitted() {
+                    self.update_rtt(rtt);
+                }
+
                 // First, process the binding request itself.
                 let current_srflx_candidate = match original_dst {
                     SocketAddr::V4(_) => &mut self.ip4_srflx_candidate,
                     SocketAddr::V6(_) => &mut self.ip6_srflx_candidate,
                 };
 
-                let maybe_candidate = message.attributes().find_map(|a| srflx_candidate(local, a));
+                // Over TCP, the relay observes the address of our TCP connection which is useless for UDP hole-punching.
+                let maybe_candidate = message
+                    .attributes()
+                    .find_map(|a| srflx_candidate(local, a))
+                    .filter(|_| self.transport == Transport::Udp);
                 if update_candidate(maybe_candidate, current_srflx_candidate, &mut self.events) {
                     self.log_update(now);
                 }
@@ -709,6 +744,8 @@ impl Allocation {
                     .is_some_and(|s| s.same_ip_version_as(dst))
             {
                 self.active_socket = None; // The socket seems to no longer be reachable.
+                self.rtt = None;
+                self.num_rtt_samples = 0;
                 self.invalidate_allocation();
             }
         }
@@ -717,6 +754,15 @@ impl Allocation {
             backoff.handle_timeout(now);
         }
 
+        if let Some(fallback) = fallback_transport(self.transport).filter(|_| {
+            !self.received_any_response() && self.sent_requests.is_empty() && self.has_credentials()
+        }) {
+            tracing::info!(transport = ?self.transport, ?fallback, "Relay did not respond, falling back to another transport");
+
+            self.transport = fallback;
+            self.send_binding_requests(now);
+        }
+
         if let Some(refresh_at) = self.refresh_allocation_at() {
             if (now >= refresh_at) && !self.refresh_in_flight() {
                 tracing::debug!("Allocation is due for a refresh");
@@ -846,6 +892,7 @@ impl Allocation {
 
         Some(EncodeOk {
             socket: active_socket,
+            transport: self.transport,
         })
     }
 
@@ -946,6 +993,29 @@ impl Allocation {
         self.server
     }
 
+    /// The smoothed round-trip time to the relay, if we have measured it yet.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// The smoothed round-trip time to the relay, once it is based on enough samples to compare relays by it.
+    pub fn settled_rtt(&self) -> Option<Duration> {
+        self.rtt.filter(|_| self.num_rtt_samples >= MIN_RTT_SAMPLES)
+    }
+
+    fn update_rtt(&mut self, sample: Duration) {
+        // Same smoothing factor as TCP's SRTT (RFC 6298).
+        let rtt = match self.rtt {
+            Some(srtt) => (srtt * 7 + sample) / 8,
+            None => sample,
+        };
+
+        tracing::trace!(?rtt, ?sample, "Updated RTT to relay");
+
+        self.rtt = Some(rtt);
+        self.num_rtt_samples += 1;
+    }
+
     pub fn ip4_socket(&self) -> Option<Socket> {
         let address = self.ip4_allocation.as_ref().map(|c| c.addr())?;
 
@@ -1090,6 +1160,7 @@ impl Allocation {
         self.buffered_transmits.push_back(Transmit {
             src: None,
             dst,
+            transport: self.transport,
             payload: self.buffer_pool.pull_initialised(&encode(message)),
         });
 
@@ -1128,6 +1199,7 @@ impl Allocation {
 
 pub struct EncodeOk {
     pub socket: SocketAddr,
+    pub transport: Transport,
 }
 
 impl ActiveSocket {
@@ -1213,6 +1285,15 @@ fn update_candidate(
     }
 }
 
+/// The transport to retry with if the relay doesn't respond via the given one.
+fn fallback_transport(transport: Transport) -> Option<Transport> {
+    match transport {
+        Transport::Udp => Some(Transport::Tcp),
+        Transport::Tcp => Some(Transport::Tls),
+        Transport::Tls => None,
+    }
+}
+
 fn make_binding_request(software: Software) -> Message<Attribute> {
     let mut message = Message::new(MessageClass::Request, BINDING, TransactionId::new(random()));
     message.add_attribute(software);
@@ -1953,7 +2034,16 @@ mod tests {
         let start = Instant::now();
         let mut allocation = Allocation::for_test_ip4(start);
 
-        let mut expected_backoffs = VecDeque::from(backoff::steps(start));
+        let udp_backoffs = backoff::steps(start);
+        let tcp_backoffs = backoff::steps(udp_backoffs[3]); // Once all UDP requests timed out, we retry via TCP.
+        let tls_backoffs = backoff::steps(tcp_backoffs[3]); // Once all TCP requests timed out, we retry via TLS.
+
+        let mut expected_backoffs = VecDeque::from_iter(
+            udp_backoffs
+                .into_iter()
+                .chain(tcp_backoffs)
+                .chain(tls_backoffs),
+        );
 
         loop {
             let Some(timeout) = allocation.poll_timeout() else {
@@ -1971,6 +2061,106 @@ mod tests {
         assert!(expected_backoffs.is_empty())
     }
 
+    #[test]
+    fn falls_back_to_tcp_after_udp_binding_requests_time_out() {
+        let start = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(start);
+
+        for timeout in backoff::steps(start) {
+            allocation.handle_timeout(timeout);
+        }
+
+        let transmits = iter::from_fn(|| allocation.poll_transmit()).collect::<Vec<_>>();
+        let (last, udp) = transmits.split_last().unwrap();
+
+        assert!(udp.iter().all(|t| t.transport == Transport::Udp));
+        assert_eq!(last.transport, Transport::Tcp);
+        assert_eq!(last.dst, RELAY_V4.into());
+        assert_eq!(decode(&last.payload).unwrap().unwrap().method(), BINDING);
+    }
+
+    #[test]
+    fn falls_back_to_tls_after_tcp_binding_requests_time_out() {
+        let start = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(start).with_tcp_fallback(start);
+        let tcp_start = backoff::steps(start)[3];
+
+        for timeout in backoff::steps(tcp_start) {
+            allocation.handle_timeout(timeout);
+        }
+
+        let transmits = iter::from_fn(|| allocation.poll_transmit()).collect::<Vec<_>>();
+        let (last, tcp) = transmits.split_last().unwrap();
+
+        assert!(tcp.iter().all(|t| t.transport == Transport::Tcp));
+        assert_eq!(last.transport, Transport::Tls);
+        assert_eq!(last.dst, RELAY_V4.into());
+        assert_eq!(decode(&last.payload).unwrap().unwrap().method(), BINDING);
+    }
+
+    #[test]
+    fn allocates_via_tcp_without_srflx_candidate() {
+        let start = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(start)
+            .with_tcp_fallback(start)
+            .with_binding_response(PEER1, start);
+
+        let allocate = allocation.poll_transmit().unwrap();
+        assert_eq!(allocate.transport, Transport::Tcp);
+
+        allocation.handle_test_input_ip4(
+            &allocate_response(
+                &decode(&allocate.payload).unwrap().unwrap(),
+                &[RELAY_ADDR_IP4],
+            ),
+            start,
+        );
+
+        assert_eq!(
+            allocation.poll_event(),
+            Some(Event::New(
+                Candidate::relayed(RELAY_ADDR_IP4, PEER1, Protocol::Udp).unwrap()
+            ))
+        );
+        assert_eq!(allocation.poll_event(), None);
+    }
+
+    #[test]
+    fn channel_data_is_encoded_for_tcp_after_fallback() {
+        let start = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(start)
+            .with_tcp_fallback(start)
+            .with_binding_response(PEER1, start)
+            .with_allocate_response(&[RELAY_ADDR_IP4], start);
+        allocation.bind_channel(PEER2_IP4, start);
+        let channel_bind_msg = allocation.next_message().unwrap();
+        allocation.handle_test_input_ip4(&encode(channel_bind_success(&channel_bind_msg)), start);
+
+        let mut buffer = channel_data_packet_buffer(b"foobar");
+        let encode_ok = allocation
+            .encode_channel_data_header(PEER2_IP4, &mut buffer, start)
+            .unwrap();
+
+        assert_eq!(encode_ok.transport, Transport::Tcp);
+    }
+
+    #[test]
+    fn refreshing_suspended_allocation_tries_udp_first() {
+        let start = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(start).with_tcp_fallback(start);
+
+        while let Some(timeout) = allocation.poll_timeout() {
+            allocation.handle_timeout(timeout);
+        }
+        let _ = iter::from_fn(|| allocation.poll_transmit()).collect::<Vec<_>>(); // Drain transmits.
+
+        allocation.refresh(start + Duration::from_secs(60));
+
+        let binding = allocation.poll_transmit().unwrap();
+        assert_eq!(binding.transport, Transport::Udp);
+        assert_eq!(decode(&binding.payload).unwrap().unwrap().method(), BINDING);
+    }
+
     #[test]
     fn given_no_ip6_allocation_does_not_attempt_to_bind_channel_to_ip6_address() {
         let mut allocation = Allocation::for_test_ip4(Instant::now())
@@ -2705,6 +2895,70 @@ mod tests {
         );
     }
 
+    #[test]
+    fn binding_responses_update_rtt() {
+        let start = Instant::now();
+        let mut now = start + Duration::from_millis(80);
+
+        let mut allocation = Allocation::for_test_ip4(start)
+            .with_binding_response(PEER1, now)
+            .with_allocate_response(&[RELAY_ADDR_IP4], now);
+
+        assert_eq!(allocation.rtt(), Some(Duration::from_millis(80)));
+
+        now += BINDING_INTERVAL;
+        allocation.handle_timeout(now);
+
+        let binding = allocation.next_message().unwrap();
+        allocation.handle_test_input_ip4(
+            &binding_response(&binding, PEER1),
+            now + Duration::from_millis(160),
+        );
+
+        assert_eq!(allocation.rtt(), Some(Duration::from_millis(90)));
+    }
+
+    #[test]
+    fn rtt_settles_after_min_samples() {
+        let start = Instant::now();
+        let mut now = start + Duration::from_millis(80);
+
+        let mut allocation = Allocation::for_test_ip4(start)
+            .with_binding_response(PEER1, now)
+            .with_allocate_response(&[RELAY_ADDR_IP4], now);
+
+        for _ in 1..MIN_RTT_SAMPLES {
+            assert_eq!(allocation.settled_rtt(), None);
+
+            now += BINDING_INTERVAL;
+            allocation.handle_timeout(now);
+
+            let binding = allocation.next_message().unwrap();
+            allocation.handle_test_input_ip4(
+                &binding_response(&binding, PEER1),
+                now + Duration::from_millis(80),
+            );
+        }
+
+        assert_eq!(allocation.settled_rtt(), Some(Duration::from_millis(80)));
+    }
+
+    #[test]
+    fn retransmitted_binding_requests_dont_update_rtt() {
+        let start = Instant::now();
+        let mut allocation = Allocation::for_test_ip4(start);
+
+        let binding = allocation.next_message().unwrap();
+
+        let timeout = allocation.poll_timeout().unwrap();
+        allocation.handle_timeout(timeout);
+        let _retransmit = allocation.next_message().unwrap();
+
+        allocation.handle_test_input_ip4(&binding_response(&binding, PEER1), timeout);
+
+        assert_eq!(allocation.rtt(), None);
+    }
+
     fn ch(peer: SocketAddr, now: Instant) -> Channel {
         Channel {
             peer,
@@ -2842,6 +3096,17 @@ mod tests {
             )
         }
 
+        /// Times out all UDP BINDING requests, leaving only the TCP one in the queue.
+        fn with_tcp_fallback(mut self, start: Instant) -> Self {
+            for timeout in backoff::steps(start) {
+                self.handle_timeout(timeout);
+            }
+            self.buffered_transmits
+                .retain(|t| t.transport == Transport::Tcp);
+
+            self
+        }
+
         fn with_binding_response(mut self, srflx_addr: SocketAddr, now: Instant) -> Self {
             let binding = self.next_message().unwrap();
             self.handle_test_input_ip4(&binding_response(&binding, srflx_addr), now);
@@ -8,6 +8,7 @@ pub struct ExponentialBackoff {
     start_time: Instant,
     next_trigger: Instant,
     interval: Duration,
+    retransmitted: bool,
 }
 
 impl ExponentialBackoff {
@@ -22,6 +23,7 @@ impl ExponentialBackoff {
 
         self.interval = Duration::from_secs_f32(self.interval.as_secs_f32() * MULTIPLIER);
         self.next_trigger += self.interval;
+        self.retransmitted = true;
     }
 
     pub(crate) fn next_trigger(&self) -> Instant {
@@ -39,6 +41,13 @@ impl ExponentialBackoff {
     pub(crate) fn start_time(&self) -> Instant {
         self.start_time
     }
+
+    /// Whether the request has been sent more than once.
+    ///
+    /// A response to a retransmitted request cannot be attributed to a particular send and is thus useless for RTT measurements.
+    pub(crate) fn was_retransmitted(&self) -> bool {
+        self.retransmitted
+    }
 }
 
 pub fn new(now: Instant, interval: Duration) -> ExponentialBackoff {
@@ -46,6 +55,7 @@ pub fn new(now: Instant, interval: Duration) -> ExponentialBackoff {
         interval,
         start_time: now,
         next_trigger: now + interval,
+        retransmitted: false,
     }
 }
 
@@ -0,0 +1,126 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// How much worse than the fastest relay a relay's RTT has to be for us to consider it degraded.
+const THRESHOLD: Duration = Duration::from_millis(100);
+
+/// For how long a relay's RTT has to stay above the [`THRESHOLD`] before we avoid it.
+///
+/// We measure the RTT to each relay every 25s, so this spans multiple samples and a single latency spike won't make us avoid a relay.
+const MIN_DURATION: Duration = Duration::from_secs(60);
+
+/// Tracks relays whose RTT has been significantly worse than that of the fastest relay for a sustained period.
+#[derive(Debug)]
+pub struct DegradedRelays<RId> {
+    /// Since when each relay's RTT has been above the [`THRESHOLD`].
+    since: BTreeMap<RId, Instant>,
+}
+
+impl<RId> Default for DegradedRelays<RId> {
+    fn default() -> Self {
+        Self {
+            since: BTreeMap::default(),
+        }
+    }
+}
+
+impl<RId> DegradedRelays<RId>
+where
+    RId: Copy + Ord,
+{
+    /// Updates the state from the current RTT of each relay.
+    ///
+    /// Relays that are not part of `rtts` are forgotten.
+    pub fn update(&mut self, rtts: &BTreeMap<RId, Duration>, now: Instant) {
+        let Some(fastest_rtt) = rtts.values().min().copied() else {
+            self.since.clear();
+            return;
+        };
+
+        self.since.retain(|rid, _| rtts.contains_key(rid));
+
+        for (rid, rtt) in rtts {
+            if *rtt > fastest_rtt + THRESHOLD {
+                self.since.entry(*rid).or_insert(now);
+            } else {
+                self.since.remove(rid);
+            }
+        }
+    }
+
+    /// Whether the relay's RTT has been above the [`THRESHOLD`] for at least [`MIN_DURATION`].
+    pub fn contains(&self, rid: &RId, now: Instant) -> bool {
+        self.since
+            .get(rid)
+            .is_some_and(|since| now.duration_since(*since) >= MIN_DURATION)
+    }
+
+    pub fn clear(&mut self) {
+        self.since.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relay_is_degraded_after_sustained_high_rtt() {
+        let mut now = Instant::now();
+        let mut relays = DegradedRelays::default();
+        let rtts = BTreeMap::from([(1, ms(20)), (2, ms(200))]);
+
+        relays.update(&rtts, now);
+        assert!(!relays.contains(&2, now));
+
+        now += MIN_DURATION;
+        relays.update(&rtts, now);
+        assert!(relays.contains(&2, now));
+        assert!(!relays.contains(&1, now));
+    }
+
+    #[test]
+    fn single_spike_does_not_degrade_relay() {
+        let mut now = Instant::now();
+        let mut relays = DegradedRelays::default();
+
+        relays.update(&BTreeMap::from([(1, ms(20)), (2, ms(200))]), now);
+
+        now += Duration::from_secs(1);
+        relays.update(&BTreeMap::from([(1, ms(20)), (2, ms(40))]), now);
+
+        now += MIN_DURATION;
+        relays.update(&BTreeMap::from([(1, ms(20)), (2, ms(200))]), now);
+        assert!(!relays.contains(&2, now));
+    }
+
+    #[test]
+    fn small_rtt_difference_does_not_degrade_relay() {
+        let mut now = Instant::now();
+        let mut relays = DegradedRelays::default();
+        let rtts = BTreeMap::from([(1, ms(20)), (2, ms(100))]);
+
+        relays.update(&rtts, now);
+        now += MIN_DURATION * 2;
+        relays.update(&rtts, now);
+
+        assert!(!relays.contains(&2, now));
+    }
+
+    #[test]
+    fn forgets_relays_without_rtt() {
+        let mut now = Instant::now();
+        let mut relays = DegradedRelays::default();
+
+        relays.update(&BTreeMap::from([(1, ms(20)), (2, ms(200))]), now);
+        relays.update(&BTreeMap::from([(1, ms(20))]), now);
+
+        now += MIN_DURATION;
+        relays.update(&BTreeMap::from([(1, ms(20)), (2, ms(200))]), now);
+        assert!(!relays.contains(&2, now));
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+}
@@ -7,6 +7,7 @@ mod allocation;
 mod backoff;
 mod candidate_set;
 mod channel_data;
+mod degraded_relays;
 mod index;
 mod node;
 mod stats;
@@ -16,10 +17,10 @@ pub use allocation::RelaySocket;
 #[allow(deprecated)] // Rust bug: `expect` doesn't seem to work on imports?
 pub use node::{Answer, Offer};
 pub use node::{
-    Client, ClientNode, Credentials, Error, Event, HANDSHAKE_TIMEOUT, NoTurnServers, Node, Server,
-    ServerNode, Transmit,
+    Client, ClientNode, Credentials, Error, Event, HANDSHAKE_TIMEOUT, NoTurnServers, Node,
+    RELAY_TLS_PORT, Server, ServerNode, Transmit, Transport,
 };
-pub use stats::{ConnectionStats, NodeStats};
+pub use stats::{CandidateType, ConnectionPath, ConnectionStats, NodeStats};
 
 pub fn is_wireguard(payload: &[u8]) -> bool {
     boringtun::noise::Tunn::parse_incoming_packet(payload).is_ok()
@@ -1,7 +1,8 @@
 use crate::allocation::{self, Allocation, RelaySocket, Socket};
 use crate::candidate_set::CandidateSet;
+use crate::degraded_relays::DegradedRelays;
 use crate::index::IndexLfsr;
-use crate::stats::{ConnectionStats, NodeStats};
+use crate::stats::{CandidateType, ConnectionPath, ConnectionStats, NodeStats};
 use crate::utils::{channel_data_packet_buffer, earliest};
 use boringtun::noise::errors::WireGuardError;
 use boringtun::noise::{Tunn, TunnResult};
@@ -40,6 +41,11 @@ const CANDIDATE_TIMEOUT: Duration = Duration::from_secs(10);
 /// How long we will at most wait for an [`Answer`] from the remote.
 pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(20);
 
+/// Relays whose RTT is at most this much worse than the fastest one are considered equally good.
+///
+/// Sampling among those spreads the load across nearby relays.
+const RELAY_RTT_TOLERANCE: Duration = Duration::from_millis(20);
+
 /// Manages a set of wireguard connections for a server.
 pub type ServerNode<TId, RId> = Node<Server, TId, RId>;
 /// Manages a set of wireguard connections for a client.
@@ -123,6 +129,8 @@ pub struct Node<T, TId, RId> {
     next_rate_limiter_reset: Option<Instant>,
 
     allocations: BTreeMap<RId, Allocation>,
+    /// Relays we avoid for new connections because their RTT has been much worse than that of the fastest relay.
+    degraded_relays: DegradedRelays<RId>,
 
     connections: Connections<TId, RId>,
     pending_events: VecDeque<Event<TId>>,
@@ -180,6 +188,7 @@ where
             next_rate_limiter_reset: None,
             pending_events: VecDeque::default(),
             allocations: Default::default(),
+            degraded_relays: Default::default(),
             connections: Default::default(),
             stats: Default::default(),
             buffer_pool: BufferPool::new(ip_packet::MAX_FZ_PAYLOAD, "snownet"),
@@ -199,6 +208,7 @@ where
     /// `snownet` cannot control which IP / port we are binding to, thus upper layers MUST ensure that a new IP / port is allocated after calling [`Node::reset`].
     pub fn reset(&mut self, now: Instant) {
         self.allocations.clear();
+        self.degraded_relays.clear();
 
         self.buffered_transmits.clear();
 
@@ -308,7 +318,12 @@ where
         })
     }
 
-    pub fn stats(&self) -> (NodeStats, impl Iterator<Item = (TId, ConnectionStats)> + '_) {
+    pub fn stats(
+        &self,
+    ) -> (
+        NodeStats,
+        impl Iterator<Item = (TId, ConnectionStats<RId>)> + '_,
+    ) {
         (self.stats, self.connections.stats())
     }
 
@@ -503,6 +518,7 @@ where
                 Ok(Some(Transmit {
                     src: Some(source),
                     dst: remote,
+                    transport: Transport::Udp,
                     payload: buffer,
                 }))
             }
@@ -523,6 +539,7 @@ where
                 Ok(Some(Transmit {
                     src: None,
                     dst: encode_ok.socket,
+                    transport: encode_ok.transport,
                     payload: buffer,
                 }))
             }
@@ -580,7 +597,13 @@ where
         self.allocations_drain_events();
 
         for (id, connection) in self.connections.iter_established_mut() {
-            connection.handle_timeout(id, now, &mut self.allocations, &mut self.buffered_transmits);
+            connection.handle_timeout(
+                id,
+                now,
+                &mut self.allocations,
+                &self.shared_candidates,
+                &mut self.buffered_transmits,
+            );
         }
 
         for (id, connection) in self.connections.initial.iter_mut() {
@@ -608,8 +631,20 @@ where
                 }
                 None => true,
             });
-        self.connections
-            .check_relays_available(&self.allocations, &mut self.rng);
+        self.degraded_relays.update(
+            &self
+                .allocations
+                .iter()
+                .filter_map(|(rid, a)| Some((*rid, a.settled_rtt()?)))
+                .collect(),
+            now,
+        );
+        self.connections.check_relays_available(
+            &self.allocations,
+            &self.degraded_relays,
+            now,
+            &mut self.rng,
+        );
         self.connections.gc(&mut self.pending_events);
     }
 
@@ -761,6 +796,8 @@ where
             ),
             next_wg_timer_update: now,
             stats: Default::default(),
+            pending_stun_requests: VecDeque::default(),
+            handshake_initiated_at: None,
             buffer: vec![0; ip_packet::MAX_FZ_PAYLOAD],
             intent_sent_at,
             signalling_completed_at: now,
@@ -869,7 +906,9 @@ where
             return ControlFlow::Continue(());
         };
 
-        for (_, agent, _span) in self.connections.agents_mut() {
+        let mut handled_by = None;
+
+        for (cid, agent, _span) in self.connections.agents_mut() {
             if agent.accepts_message(&message) {
                 agent.handle_packet(
                     now,
@@ -881,10 +920,23 @@ where
                     },
                 );
 
-                return ControlFlow::Break(Ok(()));
+                handled_by = Some(cid);
+                break;
             }
         }
 
+        if let Some(cid) = handled_by {
+            if let Some((conn, id)) = self
+                .connections
+                .get_established_mut(&cid)
+                .zip(stun_binding_response_id(packet))
+            {
+                conn.on_stun_response(id, now);
+            }
+
+            return ControlFlow::Break(Ok(()));
+        }
+
         tracing::trace!("Packet was a STUN message but no agent handled it. Already disconnected?");
 
         ControlFlow::Break(Ok(()))
@@ -968,14 +1020,9 @@ where
 
     /// Sample a relay to use for a new connection.
     fn sample_relay(&mut self) -> Result<RId, NoTurnServers> {
-        let rid = self
-            .allocations
-            .keys()
-            .copied()
-            .choose(&mut self.rng)
-            .ok_or(NoTurnServers {})?;
+        let rid = select_relay(&self.allocations, &mut self.rng).ok_or(NoTurnServers {})?;
 
-        tracing::debug!(%rid, "Sampled relay");
+        tracing::debug!(%rid, rtt = ?self.allocations.get(&rid).and_then(|a| a.rtt()), "Sampled relay");
 
         Ok(rid)
     }
@@ -1213,17 +1260,19 @@ where
     fn check_relays_available(
         &mut self,
         allocations: &BTreeMap<RId, Allocation>,
+        degraded_relays: &DegradedRelays<RId>,
+        now: Instant,
         rng: &mut impl Rng,
     ) {
         // For initial connections, we can just update the relay to be used.
         for (_, c) in self.iter_initial_mut() {
-            if allocations.contains_key(&c.relay) {
+            if allocations.contains_key(&c.relay) && !degraded_relays.contains(&c.relay, now) {
                 continue;
             }
 
             let _guard = c.span.enter();
 
-            let Some(new_rid) = allocations.keys().copied().choose(rng) else {
+            let Some(new_rid) = select_relay(allocations, rng) else {
                 continue;
             };
 
@@ -1262,16 +1311,21 @@ where
                 }
             };
 
-            if allocations.contains_key(relay) {
-                continue; // Our relay is still there, no problems.
+            if !allocations.contains_key(relay) {
+                tracing::info!("Connection failed (relay disconnected)");
+                c.state = ConnectionState::Failed;
+                continue;
             }
 
-            tracing::info!("Connection failed (relay disconnected)");
-            c.state = ConnectionState::Failed;
+            // Failing the connection makes the upper layer re-establish it via one of the fastest relays.
+            if degraded_relays.contains(relay, now) {
+                tracing::info!(rid = ?relay, "Connection failed (relay degraded)");
+                c.state = ConnectionState::Failed;
+            }
         }
     }
 
-    fn stats(&self) -> impl Iterator<Item = (TId, ConnectionStats)> + '_ {
+    fn stats(&self) -> impl Iterator<Item = (TId, ConnectionStats<RId>)> + '_ {
         self.established.iter().map(move |(id, c)| (*id, c.stats))
     }
 
@@ -1394,6 +1448,66 @@ fn add_local_candidate<TId>(
     })
 }
 
+/// Determines whether we send to `destination` from our host or our server-reflexive candidate.
+///
+/// Apart from relay candidates, the [`IceAgent`] only knows about our host candidates, so `source` is always a host socket.
+/// If we have discovered a server-reflexive candidate for it, the peer sees us on that address unless it is in our local network.
+fn local_candidate_type(
+    shared_candidates: &CandidateSet,
+    source: SocketAddr,
+    destination: SocketAddr,
+) -> CandidateType {
+    let is_behind_nat = shared_candidates.iter().any(|c| {
+        c.kind() == CandidateKind::ServerReflexive && c.base() == source && c.addr() != source
+    });
+
+    if is_behind_nat && !is_local_network(destination) {
+        return CandidateType::ServerReflexive;
+    }
+
+    CandidateType::Host
+}
+
+fn is_local_network(addr: SocketAddr) -> bool {
+    match addr {
+        SocketAddr::V4(addr) => {
+            let ip = addr.ip();
+
+            ip.is_private() || ip.is_link_local() || ip.is_loopback()
+        }
+        SocketAddr::V6(addr) => {
+            let ip = addr.ip();
+
+            ip.is_unique_local() || ip.is_unicast_link_local() || ip.is_loopback()
+        }
+    }
+}
+
+/// Selects a relay for a new connection, preferring the ones with the lowest RTT.
+///
+/// Until the RTT to any relay has settled, all of them are equally good.
+fn select_relay<RId>(allocations: &BTreeMap<RId, Allocation>, rng: &mut impl Rng) -> Option<RId>
+where
+    RId: Copy,
+{
+    let Some(fastest_rtt) = allocations
+        .values()
+        .filter_map(Allocation::settled_rtt)
+        .min()
+    else {
+        return allocations.keys().copied().choose(rng);
+    };
+
+    allocations
+        .iter()
+        .filter(|(_, a)| {
+            a.settled_rtt()
+                .is_some_and(|rtt| rtt <= fastest_rtt + RELAY_RTT_TOLERANCE)
+        })
+        .map(|(rid, _)| *rid)
+        .choose(rng)
+}
+
 fn invalidate_allocation_candidates<TId, RId>(
     connections: &mut Connections<TId, RId>,
     allocation: &Allocation,
@@ -1504,15 +1618,40 @@ pub struct Transmit {
     pub src: Option<SocketAddr>,
     /// The remote the packet should be sent to.
     pub dst: SocketAddr,
+    /// The transport the packet should be sent over.
+    ///
+    /// Only packets to relays are ever sent over [`Transport::Tcp`] or [`Transport::Tls`].
+    pub transport: Transport,
     /// The data that should be sent.
     pub payload: Buffer<Vec<u8>>,
 }
 
+/// The transport over which a [`Transmit`] should be sent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Transport {
+    #[default]
+    Udp,
+    /// A TCP stream to the relay, as per <https://www.rfc-editor.org/rfc/rfc8656#section-3.1>.
+    ///
+    /// The payload is always a STUN message or a channel-data message.
+    /// Framing them on the stream (i.e. padding channel-data messages) is up to the IO layer.
+    Tcp,
+    /// Like [`Transport::Tcp`] but wrapped in TLS and to port 443 of the relay's IP, see [`RELAY_TLS_PORT`].
+    ///
+    /// To a firewall, this looks like any other HTTPS connection.
+    /// The `dst` of the [`Transmit`] is still the relay's STUN socket because that is how we identify relays.
+    Tls,
+}
+
+/// The port on which relays accept TURN over TLS connections.
+pub const RELAY_TLS_PORT: u16 = 443;
+
 impl fmt::Debug for Transmit {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Transmit")
             .field("src", &self.src)
             .field("dst", &self.dst)
+            .field("transport", &self.transport)
             .field("len", &self.payload.len())
             .finish()
     }
@@ -1576,7 +1715,11 @@ struct Connection<RId> {
     /// Socket addresses from which we might receive data (even before we are connected).
     possible_sockets: BTreeSet<SocketAddr>,
 
-    stats: ConnectionStats,
+    stats: ConnectionStats<RId>,
+    /// STUN binding requests we sent to the peer and are waiting for a response to.
+    pending_stun_requests: VecDeque<(StunTransactionId, Instant)>,
+    /// When we last sent a WireGuard handshake initiation that hasn't been answered yet.
+    handshake_initiated_at: Option<Instant>,
     intent_sent_at: Instant,
     signalling_completed_at: Instant,
 
@@ -1793,6 +1936,7 @@ where
         cid: TId,
         now: Instant,
         allocations: &mut BTreeMap<RId, Allocation>,
+        shared_candidates: &CandidateSet,
         transmits: &mut VecDeque<Transmit>,
     ) where
         TId: Copy + Ord + fmt::Display,
@@ -1800,6 +1944,11 @@ where
     {
         self.agent.handle_timeout(now);
         self.state.handle_timeout(&mut self.agent, now);
+        self.expire_stun_requests(now);
+        self.stats.last_handshake_at = self
+            .tunnel
+            .time_since_last_handshake_at(now)
+            .and_then(|elapsed| now.checked_sub(elapsed));
 
         if self
             .candidate_timeout()
@@ -1841,11 +1990,24 @@ where
                     let source_relay = allocations.iter().find_map(|(relay, allocation)| {
                         allocation.has_socket(source).then_some(*relay)
                     });
-                    let dest_is_relay = self
+                    let dest_kind = self
                         .agent
                         .remote_candidates()
                         .iter()
-                        .any(|c| c.addr() == destination && c.kind() == CandidateKind::Relayed);
+                        .find(|c| c.addr() == destination)
+                        .map(|c| c.kind());
+                    let dest_is_relay = dest_kind == Some(CandidateKind::Relayed);
+
+                    self.stats.path = Some(ConnectionPath {
+                        local: if source_relay.is_some() {
+                            CandidateType::Relayed
+                        } else {
+                            local_candidate_type(shared_candidates, source, destination)
+                        },
+                        // Candidates we didn't get via signalling are discovered through STUN and thus peer-reflexive.
+                        remote: dest_kind.map_or(CandidateType::PeerReflexive, CandidateType::from),
+                        relay: source_relay,
+                    });
 
                     let remote_socket = match (source_relay, dest_is_relay) {
                         (None, false) => PeerSocket::PeerToPeer {
@@ -1938,6 +2100,10 @@ where
             let dst = transmit.destination;
             let stun_packet = transmit.contents;
 
+            if let Some(id) = stun_binding_request_id(&stun_packet) {
+                self.on_stun_request(id, now);
+            }
+
             // Check if `str0m` wants us to send from a "remote" socket, i.e. one that we allocated with a relay.
             let allocation = allocations
                 .iter_mut()
@@ -1950,6 +2116,7 @@ where
                 transmits.push_back(Transmit {
                     src: Some(source),
                     dst,
+                    transport: Transport::Udp,
                     payload: self.buffer_pool.pull_initialised(&Vec::from(stun_packet)),
                 });
                 continue;
@@ -1971,6 +2138,7 @@ where
             transmits.push_back(Transmit {
                 src: None,
                 dst: encode_ok.socket,
+                transport: encode_ok.transport,
                 payload: self.buffer_pool.pull_initialised(&data_channel_packet),
             });
         }
@@ -2004,6 +2172,10 @@ where
                 tracing::warn!(?e);
             }
             TunnResult::WriteToNetwork(b) => {
+                if is_handshake_initiation(b) {
+                    self.handshake_initiated_at = Some(now);
+                }
+
                 transmits.extend(make_owned_transmit(
                     peer_socket,
                     b,
@@ -2037,6 +2209,14 @@ where
             }
         };
 
+        // Without a session, `boringtun` queues the packet and initiates a handshake instead.
+        if is_handshake_initiation(&buffer[..len]) {
+            self.handshake_initiated_at = Some(now);
+        } else {
+            self.stats.tx_packets += 1;
+            self.stats.tx_bytes += packet.packet().len();
+        }
+
         Ok(Some(&buffer[..len]))
     }
 
@@ -2129,8 +2309,16 @@ where
             }
         };
 
+        if is_handshake_response(packet) && !matches!(control_flow, ControlFlow::Break(Err(_))) {
+            if let Some(initiated_at) = self.handshake_initiated_at.take() {
+                self.stats.handshake_rtt = Some(now.duration_since(initiated_at));
+            }
+        }
+
         if let ControlFlow::Continue(packet) = &control_flow {
             self.state.on_incoming(&mut self.agent, packet, now);
+            self.stats.rx_packets += 1;
+            self.stats.rx_bytes += packet.packet().len();
         }
 
         control_flow
@@ -2162,6 +2350,8 @@ where
             .socket()
             .expect("cannot force handshake while not connected");
 
+        self.handshake_initiated_at = Some(now);
+
         transmits.extend(make_owned_transmit(
             socket,
             bytes,
@@ -2186,6 +2376,91 @@ where
     fn is_idle(&self) -> bool {
         matches!(self.state, ConnectionState::Idle { .. })
     }
+
+    fn on_stun_request(&mut self, id: StunTransactionId, now: Instant) {
+        /// Bounds the memory we use for tracking requests, `str0m` never has this many in flight.
+        const MAX_PENDING_STUN_REQUESTS: usize = 64;
+
+        if self
+            .pending_stun_requests
+            .iter()
+            .any(|(pending, _)| *pending == id)
+        {
+            return; // Retransmission of an existing request.
+        }
+
+        if self.pending_stun_requests.len() >= MAX_PENDING_STUN_REQUESTS {
+            self.pending_stun_requests.pop_front();
+        }
+
+        self.pending_stun_requests.push_back((id, now));
+    }
+
+    fn on_stun_response(&mut self, id: StunTransactionId, now: Instant) {
+        let Some(index) = self
+            .pending_stun_requests
+            .iter()
+            .position(|(pending, _)| *pending == id)
+        else {
+            return;
+        };
+
+        let Some((_, sent_at)) = self.pending_stun_requests.remove(index) else {
+            return;
+        };
+
+        self.stats.on_stun_rtt(now.duration_since(sent_at));
+    }
+
+    fn expire_stun_requests(&mut self, now: Instant) {
+        /// After this time, we consider a STUN binding request to be lost.
+        const STUN_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+        while self
+            .pending_stun_requests
+            .front()
+            .is_some_and(|(_, sent_at)| now.duration_since(*sent_at) >= STUN_REQUEST_TIMEOUT)
+        {
+            self.pending_stun_requests.pop_front();
+            self.stats.on_stun_timeout();
+        }
+    }
+}
+
+type StunTransactionId = [u8; 12];
+
+/// Returns the transaction ID if the packet is a STUN binding request.
+fn stun_binding_request_id(packet: &[u8]) -> Option<StunTransactionId> {
+    stun_transaction_id(packet, STUN_BINDING_REQUEST)
+}
+
+/// Returns the transaction ID if the packet is a successful STUN binding response.
+fn stun_binding_response_id(packet: &[u8]) -> Option<StunTransactionId> {
+    stun_transaction_id(packet, STUN_BINDING_SUCCESS_RESPONSE)
+}
+
+const STUN_BINDING_REQUEST: [u8; 2] = [0x00, 0x01];
+const STUN_BINDING_SUCCESS_RESPONSE: [u8; 2] = [0x01, 0x01];
+const STUN_MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xA4, 0x42];
+
+fn stun_transaction_id(packet: &[u8], message_type: [u8; 2]) -> Option<StunTransactionId> {
+    match packet {
+        [t0, t1, _, _, c0, c1, c2, c3, id @ ..]
+            if [*t0, *t1] == message_type && [*c0, *c1, *c2, *c3] == STUN_MAGIC_COOKIE =>
+        {
+            id.get(..12)?.try_into().ok()
+        }
+        _ => None,
+    }
+}
+
+/// WireGuard messages start with their type as a little-endian `u32`.
+fn is_handshake_initiation(packet: &[u8]) -> bool {
+    packet.starts_with(&[1, 0, 0, 0])
+}
+
+fn is_handshake_response(packet: &[u8]) -> bool {
+    packet.starts_with(&[2, 0, 0, 0])
 }
 
 #[must_use]
@@ -2210,6 +2485,7 @@ where
         } => Transmit {
             src: Some(source),
             dst: remote,
+            transport: Transport::Udp,
             payload: buffer_pool.pull_initialised(message),
         },
         PeerSocket::RelayToPeer { relay, dest: peer }
@@ -2222,6 +2498,7 @@ where
             Transmit {
                 src: None,
                 dst: encode_ok.socket,
+                transport: encode_ok.transport,
                 payload: buffer_pool.pull_initialised(&channel_data),
             }
         }
@@ -2271,3 +2548,56 @@ impl fmt::Display for SessionId {
         write!(f, "{:X}", &self.0.hex())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const HOST: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 10), 52625));
+    const SRFLX: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 40000));
+    const PUBLIC_PEER: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(2, 2, 2, 2), 52625));
+    const LAN_PEER: SocketAddr =
+        SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), 52625));
+
+    #[test]
+    fn local_candidate_is_srflx_when_sending_through_nat() {
+        let candidates = candidates_behind_nat();
+
+        assert_eq!(
+            local_candidate_type(&candidates, HOST, PUBLIC_PEER),
+            CandidateType::ServerReflexive
+        );
+    }
+
+    #[test]
+    fn local_candidate_is_host_within_local_network() {
+        let candidates = candidates_behind_nat();
+
+        assert_eq!(
+            local_candidate_type(&candidates, HOST, LAN_PEER),
+            CandidateType::Host
+        );
+    }
+
+    #[test]
+    fn local_candidate_is_host_without_srflx_candidate() {
+        let mut candidates = CandidateSet::default();
+        candidates.insert(Candidate::host(HOST, Protocol::Udp).unwrap());
+
+        assert_eq!(
+            local_candidate_type(&candidates, HOST, PUBLIC_PEER),
+            CandidateType::Host
+        );
+    }
+
+    fn candidates_behind_nat() -> CandidateSet {
+        let mut candidates = CandidateSet::default();
+        candidates.insert(Candidate::host(HOST, Protocol::Udp).unwrap());
+        candidates.insert(Candidate::server_reflexive(SRFLX, HOST, Protocol::Udp).unwrap());
+
+        candidates
+    }
+}
@@ -1,4 +1,5 @@
 use std::ops::AddAssign;
+use std::time::{Duration, Instant};
 
 #[derive(Default, Debug, Clone, Copy)]
 pub struct NodeStats {
@@ -6,12 +7,107 @@ pub struct NodeStats {
     pub stun_bytes_to_relays: HumanBytes,
 }
 
-#[derive(Default, Debug, Clone, Copy)]
-pub struct ConnectionStats {
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionStats<RId> {
     /// How many bytes we sent as part of exchanging STUN messages to other peers directly.
     pub stun_bytes_to_peer_direct: HumanBytes,
     /// How many bytes we sent as part of exchanging STUN messages to other peers via relays.
     pub stun_bytes_to_peer_relayed: HumanBytes,
+
+    /// The candidate pair we send data over, `None` until ICE nominated one.
+    pub path: Option<ConnectionPath<RId>>,
+    /// Smoothed round-trip time of the STUN binding requests on this connection.
+    pub stun_rtt: Option<Duration>,
+    /// Round-trip time of the last WireGuard handshake we initiated.
+    pub handshake_rtt: Option<Duration>,
+    /// When the last WireGuard handshake completed.
+    pub last_handshake_at: Option<Instant>,
+    /// Estimated share of packets that get lost on this connection, between 0 and 1.
+    ///
+    /// Derived from the STUN binding requests that don't receive a response.
+    pub packet_loss: f32,
+
+    /// How many bytes of IP packets we sent through this connection.
+    pub tx_bytes: HumanBytes,
+    /// How many bytes of IP packets we received through this connection.
+    pub rx_bytes: HumanBytes,
+    pub tx_packets: usize,
+    pub rx_packets: usize,
+}
+
+impl<RId> Default for ConnectionStats<RId> {
+    fn default() -> Self {
+        Self {
+            stun_bytes_to_peer_direct: HumanBytes::default(),
+            stun_bytes_to_peer_relayed: HumanBytes::default(),
+            path: None,
+            stun_rtt: None,
+            handshake_rtt: None,
+            last_handshake_at: None,
+            packet_loss: 0.0,
+            tx_bytes: HumanBytes::default(),
+            rx_bytes: HumanBytes::default(),
+            tx_packets: 0,
+            rx_packets: 0,
+        }
+    }
+}
+
+impl<RId> ConnectionStats<RId> {
+    /// Whether we send data to the peer via a relay.
+    pub fn is_relayed(&self) -> bool {
+        self.path.as_ref().is_some_and(|p| {
+            p.local == CandidateType::Relayed || p.remote == CandidateType::Relayed
+        })
+    }
+
+    pub(crate) fn on_stun_rtt(&mut self, rtt: Duration) {
+        self.stun_rtt = Some(match self.stun_rtt {
+            Some(srtt) => (srtt * 7 + rtt) / 8, // As per RFC 6298.
+            None => rtt,
+        });
+        self.packet_loss *= 1.0 - LOSS_SMOOTHING;
+    }
+
+    pub(crate) fn on_stun_timeout(&mut self) {
+        self.packet_loss = self.packet_loss * (1.0 - LOSS_SMOOTHING) + LOSS_SMOOTHING;
+    }
+}
+
+/// How much weight a single STUN binding request has in the packet loss estimate.
+const LOSS_SMOOTHING: f32 = 0.1;
+
+/// The candidate pair of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionPath<RId> {
+    /// The type of our candidate.
+    ///
+    /// Host and server-reflexive candidates share the same socket.
+    /// We report [`CandidateType::ServerReflexive`] if the peer sees us on our server-reflexive address.
+    pub local: CandidateType,
+    /// The type of the remote's candidate.
+    pub remote: CandidateType,
+    /// The relay we use to send data to the peer, if any.
+    pub relay: Option<RId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidateType {
+    Host,
+    ServerReflexive,
+    PeerReflexive,
+    Relayed,
+}
+
+impl From<str0m::CandidateKind> for CandidateType {
+    fn from(kind: str0m::CandidateKind) -> Self {
+        match kind {
+            str0m::CandidateKind::Host => Self::Host,
+            str0m::CandidateKind::ServerReflexive => Self::ServerReflexive,
+            str0m::CandidateKind::PeerReflexive => Self::PeerReflexive,
+            str0m::CandidateKind::Relayed => Self::Relayed,
+        }
+    }
 }
 
 #[derive(Default, Clone, Copy)]
@@ -53,4 +149,20 @@ mod tests {
         assert_eq!(format!("{:?}", HumanBytes(1_000)), "1.00 kB");
         assert_eq!(format!("{:?}", HumanBytes(12_500_000)), "12.50 MB");
     }
+
+    #[test]
+    fn packet_loss_converges() {
+        let mut stats = ConnectionStats::<()>::default();
+
+        for _ in 0..50 {
+            stats.on_stun_timeout();
+        }
+        assert!(stats.packet_loss > 0.99);
+
+        for _ in 0..50 {
+            stats.on_stun_rtt(Duration::from_millis(10));
+        }
+        assert!(stats.packet_loss < 0.01);
+        assert_eq!(stats.stun_rtt, Some(Duration::from_millis(10)));
+    }
 }
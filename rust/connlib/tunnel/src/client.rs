@@ -1,6 +1,10 @@
+mod dns_cache;
+mod dns_query_log;
 mod dns_resource_nat;
 mod resource;
 
+use dns_cache::DnsCache;
+use dns_query_log::DnsQueryLog;
 use dns_resource_nat::DnsResourceNat;
 use dns_types::ResponseCode;
 pub(crate) use resource::{CidrResource, Resource};
@@ -17,7 +21,10 @@ use crate::unique_packet_buffer::UniquePacketBuffer;
 use crate::{IPV4_TUNNEL, IPV6_TUNNEL, IpConfig, TunConfig, dns, is_peer, p2p_control};
 use anyhow::Context;
 use bimap::BiMap;
-use connlib_model::{GatewayId, PublicKey, RelayId, ResourceId, ResourceStatus, ResourceView};
+use connlib_model::{
+    CandidateType, ConnectionPathView, ConnectionView, DnsQueryStrategy, GatewayId, PublicKey,
+    RelayId, ResourceId, ResourceStatus, ResourceView,
+};
 use connlib_model::{Site, SiteId};
 use firezone_logging::{err_with_src, telemetry_event, unwrap_or_debug, unwrap_or_warn};
 use ip_network::{IpNetwork, Ipv4Network, Ipv6Network};
@@ -34,7 +41,7 @@ use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::num::NonZeroUsize;
 use std::ops::ControlFlow;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 use std::{io, iter};
 
 pub(crate) const IPV4_RESOURCES: Ipv4Network =
@@ -120,6 +127,8 @@ pub struct ClientState {
     dns_mapping: BiMap<IpAddr, DnsServer>,
     /// UDP DNS queries that had their destination IP mangled to redirect them to another DNS resolver through the tunnel.
     udp_dns_sockets_by_upstream_and_query_id: ExpiringMap<(SocketAddr, u16), SocketAddr>,
+    /// UDP DNS queries that we forward via the host to a server from the [`DnsOverrides`](crate::DnsOverrides), mapped to the DNS sentinel they were sent to.
+    udp_dns_sentinels_by_server_and_query_id: ExpiringMap<(SocketAddr, u16), IpAddr>,
     /// Manages internal dns records and emits forwarding event when not internally handled
     stub_resolver: StubResolver,
 
@@ -129,6 +138,11 @@ pub struct ClientState {
     /// Resources that have been disabled by the UI
     disabled_resources: BTreeSet<ResourceId>,
 
+    /// Caches responses to DNS queries that we recursively resolve via the host.
+    dns_cache: DnsCache,
+    /// Opt-in log of the DNS queries we handle.
+    dns_query_log: DnsQueryLog,
+
     tcp_dns_client: dns_over_tcp::Client,
     tcp_dns_server: dns_over_tcp::Server,
     /// Tracks the TCP stream (i.e. socket-pair) on which we received a TCP DNS query by the ID of the recursive DNS query we issued.
@@ -201,6 +215,7 @@ impl ClientState {
             sites_status: Default::default(),
             gateways_site: Default::default(),
             udp_dns_sockets_by_upstream_and_query_id: Default::default(),
+            udp_dns_sentinels_by_server_and_query_id: Default::default(),
             stub_resolver: Default::default(),
             disabled_resources: Default::default(),
             buffered_transmits: Default::default(),
@@ -208,6 +223,8 @@ impl ClientState {
             recently_connected_gateways: LruCache::new(MAX_REMEMBERED_GATEWAYS),
             upstream_dns: Default::default(),
             buffered_dns_queries: Default::default(),
+            dns_cache: Default::default(),
+            dns_query_log: Default::default(),
             tcp_dns_client: dns_over_tcp::Client::new(now, seed),
             tcp_dns_server: dns_over_tcp::Server::new(now),
             tcp_dns_streams_by_upstream_and_query_id: Default::default(),
@@ -278,6 +295,38 @@ impl ClientState {
         self.node.public_key()
     }
 
+    pub(crate) fn connection_stats(
+        &self,
+    ) -> impl Iterator<Item = (GatewayId, snownet::ConnectionStats<RelayId>)> + '_ {
+        self.node.stats().1
+    }
+
+    /// Describes how we are connected to each Gateway.
+    pub fn connections(&self, now: Instant) -> Vec<ConnectionView> {
+        self.connection_stats()
+            .map(|(gateway_id, stats)| ConnectionView {
+                gateway_id,
+                path: stats.path.map(|path| ConnectionPathView {
+                    local: candidate_type(path.local),
+                    remote: candidate_type(path.remote),
+                    relay_id: path.relay,
+                }),
+                rtt_ms: stats
+                    .stun_rtt
+                    .or(stats.handshake_rtt)
+                    .map(|rtt| rtt.as_millis() as u64),
+                packet_loss: stats.packet_loss,
+                tx_bytes: stats.tx_bytes.0 as u64,
+                rx_bytes: stats.rx_bytes.0 as u64,
+                tx_packets: stats.tx_packets as u64,
+                rx_packets: stats.rx_packets as u64,
+                secs_since_last_handshake: stats
+                    .last_handshake_at
+                    .map(|at| now.duration_since(at).as_secs()),
+            })
+            .collect()
+    }
+
     /// Updates the NAT for all domains resolved by the stub resolver on the corresponding gateway.
     ///
     /// In order to route traffic for DNS resources, the designated gateway needs to set up NAT from
@@ -448,21 +497,30 @@ impl ClientState {
         let packet = maybe_mangle_dns_response_from_upstream_dns_server(
             packet,
             &mut self.udp_dns_sockets_by_upstream_and_query_id,
+            &mut self.dns_query_log,
+            now,
         );
 
         Some(packet)
     }
 
-    pub(crate) fn handle_dns_response(&mut self, response: dns::RecursiveResponse) {
+    pub(crate) fn handle_dns_response(&mut self, response: dns::RecursiveResponse, now: Instant) {
         let qid = response.query.id();
         let server = response.server;
         let domain = response.query.domain();
 
-        let _span = tracing::debug_span!("handle_dns_response", %qid, %server, %domain).entered();
+        let _span = tracing::debug_span!("handle_dns_response", %qid, ?server, %domain).entered();
 
-        match (response.transport, response.message) {
+        let message = response
+            .message
+            .map(|message| self.flatten_cname_chain(&response.query, message, now));
+
+        match (response.transport, message) {
             (dns::Transport::Udp { .. }, Err(e)) if e.kind() == io::ErrorKind::TimedOut => {
-                tracing::debug!("Recursive UDP DNS query timed out")
+                tracing::debug!("Recursive UDP DNS query timed out");
+
+                self.dns_query_log
+                    .log_response(server.address(), qid, None, now);
             }
             (dns::Transport::Udp { source }, result) => {
                 let message = result
@@ -472,6 +530,8 @@ impl ClientState {
                         if message.truncated() {
                             tracing::debug!("Upstream DNS server had to truncate response");
                         }
+
+                        self.dns_cache.insert(&response.query, message, now);
                     })
                     .unwrap_or_else(|e| {
                         telemetry_event!("Recursive UDP DNS query failed: {}", err_with_src(&e));
@@ -479,15 +539,20 @@ impl ClientState {
                         dns_types::Response::servfail(&response.query)
                     });
 
+                self.dns_query_log
+                    .log_response(server.address(), qid, Some(&message), now);
+
                 unwrap_or_warn!(
-                    self.try_queue_udp_dns_response(server, source, message),
+                    self.try_queue_udp_dns_response(&server, source, message),
                     "Failed to queue UDP DNS response: {}"
                 );
             }
             (dns::Transport::Tcp { local, remote }, result) => {
                 let message = result
-                    .inspect(|_| {
+                    .inspect(|message| {
                         tracing::trace!("Received recursive TCP DNS response");
+
+                        self.dns_cache.insert(&response.query, message, now);
                     })
                     .unwrap_or_else(|e| {
                         telemetry_event!("Recursive TCP DNS query failed: {}", err_with_src(&e));
@@ -495,6 +560,9 @@ impl ClientState {
                         dns_types::Response::servfail(&response.query)
                     });
 
+                self.dns_query_log
+                    .log_response(server.address(), qid, Some(&message), now);
+
                 unwrap_or_warn!(
                     self.tcp_dns_server.send_message(local, remote, message),
                     "Failed to send TCP DNS response: {}"
@@ -503,6 +571,24 @@ impl ClientState {
         }
     }
 
+    /// Rewrites upstream responses whose CNAME chain leads to a DNS resource to use our proxy IPs instead.
+    fn flatten_cname_chain(
+        &mut self,
+        query: &dns_types::Query,
+        message: dns_types::Response,
+        now: Instant,
+    ) -> dns_types::Response {
+        let Some((flattened, domain)) = self.stub_resolver.flatten_cname_chain(query, &message)
+        else {
+            return message;
+        };
+
+        self.dns_resource_nat.recreate(domain);
+        self.update_dns_resource_nat(now, iter::empty());
+
+        flattened
+    }
+
     fn encapsulate(&mut self, mut packet: IpPacket, now: Instant) -> Option<snownet::Transmit> {
         let dst = packet.destination();
 
@@ -555,14 +641,21 @@ impl ClientState {
 
     fn try_queue_udp_dns_response(
         &mut self,
-        from: SocketAddr,
+        from: &DnsServer,
         dst: SocketAddr,
         message: dns_types::Response,
     ) -> anyhow::Result<()> {
-        let saddr = *self
-            .dns_mapping
-            .get_by_right(&DnsServer::from(from))
-            .context("Unknown DNS server")?;
+        // Queries forwarded to a server from the DNS overrides must be answered from the sentinel they were sent to, even if that server is also an upstream.
+        let saddr = match self
+            .udp_dns_sentinels_by_server_and_query_id
+            .remove(&(from.address(), message.id()))
+        {
+            Some(sentinel) => sentinel,
+            None => *self
+                .dns_mapping
+                .get_by_right(from)
+                .context("Unknown DNS server")?,
+        };
 
         let ip_packet = ip_packet::make::udp_packet(
             saddr,
@@ -696,8 +789,13 @@ impl ClientState {
             let gateway = self.peers.get(&gateway_id).context("Unknown peer")?; // If this error happens we have a bug: We just inserted it above.
 
             let upstream = gateway.tun_dns_server_endpoint(packet.destination());
-            let packet =
-                self.mangle_udp_dns_query_to_new_upstream_through_tunnel(upstream, now, packet);
+            let packet = self.mangle_udp_dns_query_to_new_upstream_through_tunnel(
+                upstream,
+                DnsQueryStrategy::RecurseSite,
+                Some(resource_id),
+                now,
+                packet,
+            );
 
             encapsulate_and_buffer(
                 packet,
@@ -719,7 +817,13 @@ impl ClientState {
                 }
             };
 
-            self.forward_tcp_dns_query_to_new_upstream_via_tunnel(server, query);
+            self.forward_tcp_dns_query_to_new_upstream_via_tunnel(
+                server,
+                DnsQueryStrategy::RecurseSite,
+                Some(resource_id),
+                query,
+                now,
+            );
         }
 
         Ok(Ok(()))
@@ -732,19 +836,28 @@ impl ClientState {
     /// For DNS queries to IPs that are a CIDR resources we want to mangle and forward to the gateway that handles that resource.
     ///
     /// We only want to do this if the upstream DNS server is set by the portal, otherwise, the server might be a local IP.
-    fn should_forward_dns_query_to_gateway(&self, dns_server: IpAddr) -> bool {
+    /// Encrypted queries cannot be mangled and are thus always sent from the host.
+    fn should_forward_dns_query_to_gateway(&self, dns_server: &DnsServer) -> bool {
         if !self.is_upstream_set_by_the_portal() {
             return false;
         }
+        if dns_server.is_encrypted() {
+            return false;
+        }
         if self.internet_resource.is_some() {
             return true;
         }
 
         self.active_cidr_resources
-            .longest_match(dns_server)
+            .longest_match(dns_server.ip())
             .is_some()
     }
 
+    /// Servers from the [`DnsOverrides`](crate::DnsOverrides) are only reached through the tunnel if they are part of a CIDR resource.
+    fn is_cidr_resource(&self, ip: IpAddr) -> bool {
+        self.active_cidr_resources.longest_match(ip).is_some()
+    }
+
     /// Handles UDP & TCP packets targeted at our stub resolver.
     fn try_handle_dns(&mut self, packet: IpPacket, now: Instant) -> ControlFlow<(), IpPacket> {
         let dst = packet.destination();
@@ -754,7 +867,7 @@ impl ClientState {
             return ControlFlow::Break(());
         }
 
-        let Some(upstream) = self.dns_mapping.get_by_left(&dst).map(|s| s.address()) else {
+        let Some(upstream) = self.dns_mapping.get_by_left(&dst).cloned() else {
             return ControlFlow::Continue(packet); // Not for our DNS resolver.
         };
 
@@ -853,6 +966,36 @@ impl ClientState {
             .set_listen_addresses::<NUM_CONCURRENT_TCP_DNS_CLIENTS>(sentinel_sockets);
     }
 
+    pub fn set_dns_overrides(&mut self, overrides: crate::DnsOverrides) {
+        tracing::debug!(?overrides, "Received DNS overrides");
+
+        self.stub_resolver.set_overrides(overrides);
+        self.dns_cache.clear();
+    }
+
+    /// Enables or disables the DNS query log.
+    ///
+    /// Whilst enabled, every DNS query we handle is reported as a [`ClientEvent::DnsQueryLogged`].
+    /// `wall_clock` is the current system time, used to timestamp the entries.
+    pub fn set_dns_query_log(&mut self, enabled: bool, now: Instant, wall_clock: SystemTime) {
+        tracing::debug!(%enabled, "Setting DNS query log");
+
+        if enabled {
+            self.dns_query_log.enable(now, wall_clock);
+        } else {
+            self.dns_query_log.disable();
+        }
+    }
+
+    /// The DNS resource a query is attributed to in the DNS query log.
+    fn dns_query_log_resource(&self, query: &dns_types::Query) -> Option<ResourceId> {
+        if !self.dns_query_log.is_enabled() {
+            return None;
+        }
+
+        self.stub_resolver.match_resource(&query.domain())
+    }
+
     pub fn set_disabled_resources(&mut self, new_disabled_resources: BTreeSet<ResourceId>) {
         let current_disabled_resources = self.disabled_resources.clone();
 
@@ -947,6 +1090,7 @@ impl ClientState {
         tracing::debug!(servers = ?new_dns, "Received system-defined DNS servers");
 
         self.system_resolvers = new_dns;
+        self.dns_cache.clear();
 
         self.update_dns_mapping()
     }
@@ -990,6 +1134,7 @@ impl ClientState {
         self.maybe_update_tun_config(new_tun_config);
 
         self.upstream_dns = config.upstream_dns;
+        self.dns_cache.clear();
         self.update_dns_mapping();
     }
 
@@ -1002,6 +1147,8 @@ impl ClientState {
     pub fn poll_timeout(&mut self) -> Option<Instant> {
         iter::empty()
             .chain(self.udp_dns_sockets_by_upstream_and_query_id.poll_timeout())
+            .chain(self.udp_dns_sentinels_by_server_and_query_id.poll_timeout())
+            .chain(self.dns_query_log.poll_timeout())
             .chain(self.tcp_dns_client.poll_timeout())
             .chain(self.tcp_dns_server.poll_timeout())
             .chain(self.node.poll_timeout())
@@ -1014,6 +1161,9 @@ impl ClientState {
 
         self.udp_dns_sockets_by_upstream_and_query_id
             .handle_timeout(now);
+        self.udp_dns_sentinels_by_server_and_query_id
+            .handle_timeout(now);
+        self.dns_query_log.handle_timeout(now);
 
         self.advance_dns_tcp_sockets(now);
     }
@@ -1056,14 +1206,17 @@ impl ClientState {
                     continue;
                 };
 
-                self.handle_dns_response(dns::RecursiveResponse {
-                    server,
-                    query: query_result.query,
-                    message: query_result
-                        .result
-                        .map_err(|e| io::Error::other(format!("{e:#}"))),
-                    transport: dns::Transport::Tcp { local, remote },
-                });
+                self.handle_dns_response(
+                    dns::RecursiveResponse {
+                        server: server.into(),
+                        query: query_result.query,
+                        message: query_result
+                            .result
+                            .map_err(|e| io::Error::other(format!("{e:#}"))),
+                        transport: dns::Transport::Tcp { local, remote },
+                    },
+                    now,
+                );
                 continue;
             }
 
@@ -1073,7 +1226,7 @@ impl ClientState {
 
     fn handle_udp_dns_query(
         &mut self,
-        upstream: SocketAddr,
+        upstream: DnsServer,
         packet: IpPacket,
         now: Instant,
     ) -> ControlFlow<(), IpPacket> {
@@ -1106,25 +1259,101 @@ impl ClientState {
                 self.dns_resource_nat.recreate(message.domain());
                 self.update_dns_resource_nat(now, iter::empty());
 
+                let resource = self.dns_query_log_resource(&message);
+                self.dns_query_log.log_answered(
+                    &message,
+                    &response,
+                    DnsQueryStrategy::LocalResponse,
+                    resource,
+                    now,
+                );
+
                 unwrap_or_debug!(
-                    self.try_queue_udp_dns_response(upstream, source, response),
+                    self.try_queue_udp_dns_response(&upstream, source, response),
                     "Failed to queue UDP DNS response: {}"
                 );
             }
             dns::ResolveStrategy::RecurseLocal => {
-                if self.should_forward_dns_query_to_gateway(upstream.ip()) {
-                    let packet = self
-                        .mangle_udp_dns_query_to_new_upstream_through_tunnel(upstream, now, packet);
+                let resource = self.dns_query_log_resource(&message);
+
+                if self.should_forward_dns_query_to_gateway(&upstream) {
+                    let packet = self.mangle_udp_dns_query_to_new_upstream_through_tunnel(
+                        upstream.address(),
+                        DnsQueryStrategy::RecurseLocal,
+                        resource,
+                        now,
+                        packet,
+                    );
 
                     return ControlFlow::Continue(packet);
                 }
+
+                if let Some(response) = self.dns_cache.get(&message, now) {
+                    self.dns_query_log.log_answered(
+                        &message,
+                        &response,
+                        DnsQueryStrategy::Cache,
+                        resource,
+                        now,
+                    );
+
+                    unwrap_or_debug!(
+                        self.try_queue_udp_dns_response(&upstream, source, response),
+                        "Failed to queue UDP DNS response: {}"
+                    );
+
+                    return ControlFlow::Break(());
+                }
+
                 let query_id = message.id();
 
-                tracing::trace!(server = %upstream, %query_id, "Forwarding UDP DNS query directly via host");
+                tracing::trace!(server = ?upstream, %query_id, "Forwarding UDP DNS query directly via host");
 
+                self.dns_query_log.log_forwarded(
+                    &message,
+                    upstream.address(),
+                    format_args!("{upstream:?}"),
+                    DnsQueryStrategy::RecurseLocal,
+                    resource,
+                    now,
+                );
                 self.buffered_dns_queries
                     .push_back(dns::RecursiveQuery::via_udp(source, upstream, message));
             }
+            dns::ResolveStrategy::RecurseServer(server) => {
+                if self.is_cidr_resource(server.ip()) {
+                    let packet = self.mangle_udp_dns_query_to_new_upstream_through_tunnel(
+                        server,
+                        DnsQueryStrategy::RecurseServer,
+                        None,
+                        now,
+                        packet,
+                    );
+
+                    return ControlFlow::Continue(packet);
+                }
+
+                let query_id = message.id();
+
+                tracing::trace!(%server, %query_id, "Forwarding UDP DNS query directly via host");
+
+                self.dns_query_log.log_forwarded(
+                    &message,
+                    server,
+                    server,
+                    DnsQueryStrategy::RecurseServer,
+                    None,
+                    now,
+                );
+
+                self.udp_dns_sentinels_by_server_and_query_id.insert(
+                    (server, query_id),
+                    packet.destination(),
+                    now + IDS_EXPIRE,
+                );
+                self.buffered_dns_queries
+                    .push_back(dns::RecursiveQuery::via_udp(source, server.into(), message));
+            }
             dns::ResolveStrategy::RecurseSite(resource) => {
                 let Some(gateway) =
                     peer_by_resource_mut(&self.resources_gateways, &mut self.peers, resource)
@@ -1139,8 +1368,13 @@ impl ClientState {
 
                 let upstream = gateway.tun_dns_server_endpoint(packet.destination());
 
-                let packet =
-                    self.mangle_udp_dns_query_to_new_upstream_through_tunnel(upstream, now, packet);
+                let packet = self.mangle_udp_dns_query_to_new_upstream_through_tunnel(
+                    upstream,
+                    DnsQueryStrategy::RecurseSite,
+                    Some(resource),
+                    now,
+                    packet,
+                );
 
                 return ControlFlow::Continue(packet);
             }
@@ -1183,6 +1417,15 @@ impl ClientState {
                 self.dns_resource_nat.recreate(message.domain());
                 self.update_dns_resource_nat(now, iter::empty());
 
+                let resource = self.dns_query_log_resource(&message);
+                self.dns_query_log.log_answered(
+                    &message,
+                    &response,
+                    DnsQueryStrategy::LocalResponse,
+                    resource,
+                    now,
+                );
+
                 let maybe_packet = ip_packet::make::udp_packet(
                     packet.destination(),
                     packet.source(),
@@ -1200,7 +1443,7 @@ impl ClientState {
             dns::ResolveStrategy::RecurseLocal => {
                 tracing::trace!("LLMNR queries are not forwarded to upstream resolvers");
             }
-            dns::ResolveStrategy::RecurseSite(_) => {
+            dns::ResolveStrategy::RecurseSite(_) | dns::ResolveStrategy::RecurseServer(_) => {
                 tracing::trace!("LLMNR queries are not forwarded to upstream resolvers");
             }
         }
@@ -1209,6 +1452,8 @@ impl ClientState {
     fn mangle_udp_dns_query_to_new_upstream_through_tunnel(
         &mut self,
         upstream: SocketAddr,
+        strategy: DnsQueryStrategy,
+        resource: Option<ResourceId>,
         now: Instant,
         mut packet: IpPacket,
     ) -> IpPacket {
@@ -1218,9 +1463,12 @@ impl ClientState {
             .expect("to be a valid UDP packet at this point");
 
         let dst_port = datagram.destination_port();
-        let query_id = dns_types::Query::parse(datagram.payload())
-            .expect("to be a valid DNS query at this point")
-            .id();
+        let query = dns_types::Query::parse(datagram.payload())
+            .expect("to be a valid DNS query at this point");
+        let query_id = query.id();
+
+        self.dns_query_log
+            .log_forwarded(&query, upstream, upstream, strategy, resource, now);
 
         let connlib_dns_server = SocketAddr::new(dst_ip, dst_port);
 
@@ -1246,17 +1494,25 @@ impl ClientState {
     fn handle_tcp_dns_query(&mut self, query: dns_over_tcp::Query, now: Instant) {
         let query_id = query.message.id();
 
-        let Some(upstream) = self.dns_mapping.get_by_left(&query.local.ip()) else {
+        let Some(upstream) = self.dns_mapping.get_by_left(&query.local.ip()).cloned() else {
             // This is highly-unlikely but might be possible if our DNS mapping changes whilst the TCP DNS server is processing a request.
             return;
         };
-        let server = upstream.address();
 
         match self.stub_resolver.handle(&query.message) {
             dns::ResolveStrategy::LocalResponse(response) => {
                 self.dns_resource_nat.recreate(query.message.domain());
                 self.update_dns_resource_nat(now, iter::empty());
 
+                let resource = self.dns_query_log_resource(&query.message);
+                self.dns_query_log.log_answered(
+                    &query.message,
+                    &response,
+                    DnsQueryStrategy::LocalResponse,
+                    resource,
+                    now,
+                );
+
                 unwrap_or_debug!(
                     self.tcp_dns_server
                         .send_message(query.local, query.remote, response),
@@ -1264,19 +1520,85 @@ impl ClientState {
                 );
             }
             dns::ResolveStrategy::RecurseLocal => {
-                if self.should_forward_dns_query_to_gateway(server.ip()) {
-                    self.forward_tcp_dns_query_to_new_upstream_via_tunnel(server, query);
+                let resource = self.dns_query_log_resource(&query.message);
+
+                if self.should_forward_dns_query_to_gateway(&upstream) {
+                    self.forward_tcp_dns_query_to_new_upstream_via_tunnel(
+                        upstream.address(),
+                        DnsQueryStrategy::RecurseLocal,
+                        resource,
+                        query,
+                        now,
+                    );
 
                     return;
                 }
 
-                tracing::trace!(%server, %query_id, "Forwarding TCP DNS query");
+                if let Some(response) = self.dns_cache.get(&query.message, now) {
+                    self.dns_query_log.log_answered(
+                        &query.message,
+                        &response,
+                        DnsQueryStrategy::Cache,
+                        resource,
+                        now,
+                    );
+
+                    unwrap_or_debug!(
+                        self.tcp_dns_server
+                            .send_message(query.local, query.remote, response),
+                        "Failed to send TCP DNS response: {}"
+                    );
+
+                    return;
+                }
+
+                tracing::trace!(server = ?upstream, %query_id, "Forwarding TCP DNS query");
 
+                self.dns_query_log.log_forwarded(
+                    &query.message,
+                    upstream.address(),
+                    format_args!("{upstream:?}"),
+                    DnsQueryStrategy::RecurseLocal,
+                    resource,
+                    now,
+                );
                 self.buffered_dns_queries
                     .push_back(dns::RecursiveQuery::via_tcp(
                         query.local,
                         query.remote,
+                        upstream,
+                        query.message,
+                    ));
+            }
+            dns::ResolveStrategy::RecurseServer(server) => {
+                if self.is_cidr_resource(server.ip()) {
+                    self.forward_tcp_dns_query_to_new_upstream_via_tunnel(
                         server,
+                        DnsQueryStrategy::RecurseServer,
+                        None,
+                        query,
+                        now,
+                    );
+
+                    return;
+                }
+
+                tracing::trace!(%server, %query_id, "Forwarding TCP DNS query");
+
+                self.dns_query_log.log_forwarded(
+                    &query.message,
+                    server,
+                    server,
+                    DnsQueryStrategy::RecurseServer,
+                    None,
+                    now,
+                );
+
+                self.buffered_dns_queries
+                    .push_back(dns::RecursiveQuery::via_tcp(
+                        query.local,
+                        query.remote,
+                        server.into(),
                         query.message,
                     ));
             }
@@ -1294,7 +1616,13 @@ impl ClientState {
 
                 let server = gateway.tun_dns_server_endpoint(query.local.ip());
 
-                self.forward_tcp_dns_query_to_new_upstream_via_tunnel(server, query);
+                self.forward_tcp_dns_query_to_new_upstream_via_tunnel(
+                    server,
+                    DnsQueryStrategy::RecurseSite,
+                    Some(resource),
+                    query,
+                    now,
+                );
             }
         };
     }
@@ -1302,10 +1630,16 @@ impl ClientState {
     fn forward_tcp_dns_query_to_new_upstream_via_tunnel(
         &mut self,
         server: SocketAddr,
+        strategy: DnsQueryStrategy,
+        resource: Option<ResourceId>,
         query: dns_over_tcp::Query,
+        now: Instant,
     ) {
         let query_id = query.message.id();
 
+        self.dns_query_log
+            .log_forwarded(&query.message, server, server, strategy, resource, now);
+
         match self
             .tcp_dns_client
             .send_query(server, query.message.clone())
@@ -1483,7 +1817,11 @@ impl ClientState {
     }
 
     pub(crate) fn poll_event(&mut self) -> Option<ClientEvent> {
-        self.buffered_events.pop_front()
+        self.buffered_events.pop_front().or_else(|| {
+            self.dns_query_log
+                .poll_entry()
+                .map(ClientEvent::DnsQueryLogged)
+        })
     }
 
     pub(crate) fn reset(&mut self, now: Instant) {
@@ -1496,6 +1834,7 @@ impl ClientState {
 
         self.recently_connected_gateways.clear(); // Ensure we don't have sticky gateways when we roam.
         self.dns_resource_nat.clear(); // Clear all state related to DNS resource NATs.
+        self.dns_cache.clear(); // Cached responses may be specific to the network we were on.
         self.drain_node_events();
 
         // Resetting the client will trigger a failed `QueryResult` for each one that is in-progress.
@@ -1579,7 +1918,10 @@ impl ClientState {
         }
 
         let activated = match &new_resource {
-            Resource::Dns(dns) => self.stub_resolver.add_resource(dns.id, dns.address.clone()),
+            Resource::Dns(dns) => {
+                self.stub_resolver
+                    .add_resource(dns.id, dns.address.clone(), dns.records)
+            }
             Resource::Cidr(cidr) => {
                 let existing = self.active_cidr_resources.exact_match(cidr.address);
 
@@ -1752,6 +2094,15 @@ impl ClientState {
     }
 }
 
+fn candidate_type(candidate: snownet::CandidateType) -> CandidateType {
+    match candidate {
+        snownet::CandidateType::Host => CandidateType::Host,
+        snownet::CandidateType::ServerReflexive => CandidateType::ServerReflexive,
+        snownet::CandidateType::PeerReflexive => CandidateType::PeerReflexive,
+        snownet::CandidateType::Relayed => CandidateType::Relayed,
+    }
+}
+
 fn is_llmnr(dst: IpAddr) -> bool {
     match dst {
         IpAddr::V4(ip) => ip == LLMNR_IPV4,
@@ -1901,6 +2252,8 @@ fn is_definitely_not_a_resource(ip: IpAddr) -> bool {
 fn maybe_mangle_dns_response_from_upstream_dns_server(
     mut packet: IpPacket,
     udp_dns_sockets_by_upstream_and_query_id: &mut ExpiringMap<(SocketAddr, u16), SocketAddr>,
+    dns_query_log: &mut DnsQueryLog,
+    now: Instant,
 ) -> IpPacket {
     let src_ip = packet.source();
 
@@ -1923,6 +2276,8 @@ fn maybe_mangle_dns_response_from_upstream_dns_server(
 
     tracing::trace!(server = %src_ip, query_id = %message.id(), domain = %message.domain(), "Received UDP DNS response via tunnel");
 
+    dns_query_log.log_response(src_socket, message.id(), Some(&message), now);
+
     packet.set_src(original_dst.ip());
     packet
         .as_udp_mut()
@@ -2059,6 +2414,43 @@ mod tests {
         )
     }
 
+    #[test]
+    fn answers_forwarded_udp_dns_query_from_its_sentinel_if_server_is_also_upstream() {
+        let mut client_state = ClientState::for_test();
+        let server = dns("1.1.1.1:53");
+        client_state.dns_mapping = sentinel_dns_mapping(&[server.clone()], vec![]);
+        let upstream_sentinel = *client_state.dns_mapping.get_by_right(&server).unwrap();
+        let forward_sentinel = ip("100.100.111.254");
+        assert_ne!(upstream_sentinel, forward_sentinel);
+
+        let client = "100.64.0.1:5353".parse().unwrap();
+        let query = dns_types::Query::new("example.com".parse().unwrap(), dns_types::RecordType::A)
+            .with_id(42);
+        client_state
+            .udp_dns_sentinels_by_server_and_query_id
+            .insert(
+                (server.address(), query.id()),
+                forward_sentinel,
+                Instant::now() + IDS_EXPIRE,
+            );
+
+        client_state
+            .try_queue_udp_dns_response(&server, client, dns_types::Response::no_error(&query))
+            .unwrap();
+        client_state
+            .try_queue_udp_dns_response(&server, client, dns_types::Response::no_error(&query))
+            .unwrap();
+
+        assert_eq!(
+            client_state.poll_packets().unwrap().source(),
+            forward_sentinel
+        );
+        assert_eq!(
+            client_state.poll_packets().unwrap().source(),
+            upstream_sentinel
+        );
+    }
+
     impl ClientState {
         pub fn for_test() -> ClientState {
             ClientState::new(rand::random(), Instant::now())
@@ -0,0 +1,210 @@
+use std::{
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use dns_types::{DomainName, Query, RecordType, Response, ResponseCode};
+use lru::LruCache;
+use opentelemetry::metrics::Counter;
+
+use crate::otel;
+
+/// How many responses we will at most cache.
+const CAPACITY: NonZeroUsize = NonZeroUsize::new(1000).expect("1000 > 0");
+
+/// Upper bound for how long we cache positive responses, regardless of their TTL.
+const MAX_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Upper bound for how long we cache negative responses (NXDOMAIN / NODATA), see RFC 2308.
+const MAX_NEGATIVE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// A TTL-respecting cache for responses of recursive DNS queries.
+///
+/// Only responses from upstream resolvers that we contact directly from the host end up in here.
+/// Queries for DNS resources are answered by the stub resolver and never hit this cache.
+pub struct DnsCache {
+    inner: LruCache<Key, Entry>,
+
+    lookups: Counter<u64>,
+}
+
+/// Queries with different DO or CD bits get different responses, hence they are cached separately.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    domain: DomainName,
+    qtype: RecordType,
+    dnssec_ok: bool,
+    checking_disabled: bool,
+}
+
+impl Key {
+    fn new(query: &Query) -> Self {
+        Self {
+            domain: query.domain(),
+            qtype: query.qtype(),
+            dnssec_ok: query.dnssec_ok(),
+            checking_disabled: query.checking_disabled(),
+        }
+    }
+}
+
+struct Entry {
+    response: Response,
+    inserted_at: Instant,
+    expires_at: Instant,
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self {
+            inner: LruCache::new(CAPACITY),
+            lookups: otel::metrics::dns_cache_lookups(),
+        }
+    }
+}
+
+impl DnsCache {
+    /// Looks up a cached response for the given query.
+    ///
+    /// The returned response carries the ID of the query and its TTLs are reduced by the time it spent in the cache.
+    pub fn get(&mut self, query: &Query, now: Instant) -> Option<Response> {
+        let key = Key::new(query);
+
+        let Some(entry) = self.inner.get(&key) else {
+            self.lookups.add(1, &[otel::attr::dns_cache_miss()]);
+
+            return None;
+        };
+
+        if entry.expires_at <= now {
+            self.inner.pop(&key);
+            self.lookups.add(1, &[otel::attr::dns_cache_miss()]);
+
+            return None;
+        }
+
+        let response = entry
+            .response
+            .aged_for(query, now.duration_since(entry.inserted_at));
+
+        self.lookups.add(1, &[otel::attr::dns_cache_hit()]);
+        tracing::trace!(domain = %key.domain, qtype = %key.qtype, "Answering DNS query from cache");
+
+        Some(response)
+    }
+
+    /// Caches the given response to `query` if it is cacheable.
+    pub fn insert(&mut self, query: &Query, response: &Response, now: Instant) {
+        let Some(ttl) = response.ttl() else {
+            return;
+        };
+
+        let max_ttl = if response.response_code() == ResponseCode::NOERROR
+            && response.records().next().is_some()
+        {
+            MAX_TTL
+        } else {
+            MAX_NEGATIVE_TTL
+        };
+        let ttl = ttl.min(max_ttl);
+
+        if ttl.is_zero() {
+            return;
+        }
+
+        self.inner.put(
+            Key::new(query),
+            Entry {
+                response: response.clone(),
+                inserted_at: now,
+                expires_at: now + ttl,
+            },
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.inner.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use dns_types::{ResponseBuilder, records};
+
+    use super::*;
+
+    #[test]
+    fn cached_response_expires_after_ttl() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let query = a_query("example.com");
+
+        cache.insert(&query, &a_response(&query, 60), now);
+
+        assert!(cache.get(&query, now + Duration::from_secs(59)).is_some());
+        assert!(cache.get(&query, now + Duration::from_secs(60)).is_none());
+    }
+
+    #[test]
+    fn cached_response_answers_new_query_id() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let query = a_query("example.com");
+
+        cache.insert(&query, &a_response(&query, 60), now);
+
+        let response = cache
+            .get(&query.with_id(42), now + Duration::from_secs(10))
+            .unwrap();
+
+        assert_eq!(response.id(), 42);
+        assert_eq!(response.ttl(), Some(Duration::from_secs(50)));
+    }
+
+    #[test]
+    fn does_not_cache_servfail() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let query = a_query("example.com");
+
+        cache.insert(&query, &Response::servfail(&query), now);
+
+        assert!(cache.get(&query, now).is_none());
+    }
+
+    #[test]
+    fn clear_removes_all_entries() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let query = a_query("example.com");
+
+        cache.insert(&query, &a_response(&query, 60), now);
+        cache.clear();
+
+        assert!(cache.get(&query, now).is_none());
+    }
+
+    #[test]
+    fn caches_responses_separately_by_dnssec_bits() {
+        let mut cache = DnsCache::default();
+        let now = Instant::now();
+        let query = a_query("example.com");
+
+        cache.insert(&query, &a_response(&query, 60), now);
+
+        assert!(cache.get(&query.clone().with_dnssec_ok(), now).is_none());
+        assert!(cache.get(&query, now).is_some());
+    }
+
+    fn a_query(domain: &str) -> Query {
+        Query::new(DomainName::vec_from_str(domain).unwrap(), RecordType::A)
+    }
+
+    fn a_response(query: &Query, ttl: u32) -> Response {
+        ResponseBuilder::for_query(query, ResponseCode::NOERROR)
+            .with_records([(query.domain(), ttl, records::a(Ipv4Addr::LOCALHOST))])
+            .build()
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use connlib_model::{DnsQueryLogEntry, DnsQueryStrategy, ResourceId};
+use dns_types::{Query, Response};
+
+/// How many queries we will at most track whilst waiting for their response.
+const MAX_PENDING: usize = 1000;
+
+/// How many entries we will at most buffer until they are consumed.
+const MAX_BUFFERED: usize = 1000;
+
+/// After how long we give up waiting for the response to a forwarded query.
+const PENDING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An opt-in, bounded log of the DNS queries handled by connlib.
+///
+/// Queries we answer ourselves are logged immediately.
+/// Forwarded queries are tracked by upstream server and query ID until their response arrives or they time out.
+#[derive(Default)]
+pub struct DnsQueryLog {
+    /// The wall-clock time that corresponds to the [`Instant`], set whilst the log is enabled.
+    clock: Option<(Instant, SystemTime)>,
+
+    pending: BTreeMap<(SocketAddr, u16), Pending>,
+    entries: VecDeque<DnsQueryLogEntry>,
+}
+
+struct Pending {
+    received_at: Instant,
+    domain: String,
+    qtype: String,
+    strategy: DnsQueryStrategy,
+    resource: Option<ResourceId>,
+    upstream: String,
+}
+
+impl DnsQueryLog {
+    pub fn enable(&mut self, now: Instant, wall_clock: SystemTime) {
+        self.clock = Some((now, wall_clock));
+    }
+
+    pub fn disable(&mut self) {
+        self.clock = None;
+        self.pending.clear();
+        self.entries.clear();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.clock.is_some()
+    }
+
+    /// Logs a query that we answered without contacting any upstream server.
+    pub fn log_answered(
+        &mut self,
+        query: &Query,
+        response: &Response,
+        strategy: DnsQueryStrategy,
+        resource: Option<ResourceId>,
+        now: Instant,
+    ) {
+        let Some(timestamp) = self.timestamp(now) else {
+            return;
+        };
+
+        self.push(DnsQueryLogEntry {
+            timestamp,
+            domain: query.domain().to_string(),
+            qtype: query.qtype().to_string(),
+            strategy,
+            resource,
+            response_code: Some(response.response_code().to_string()),
+            latency: Duration::ZERO,
+            upstream: None,
+        });
+    }
+
+    /// Starts tracking a query that we forwarded to the given upstream server.
+    pub fn log_forwarded(
+        &mut self,
+        query: &Query,
+        server: SocketAddr,
+        upstream: impl ToString,
+        strategy: DnsQueryStrategy,
+        resource: Option<ResourceId>,
+        now: Instant,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        if self.pending.len() >= MAX_PENDING {
+            tracing::debug!(domain = %query.domain(), "Too many pending DNS queries, not logging query");
+            return;
+        }
+
+        self.pending.insert(
+            (server, query.id()),
+            Pending {
+                received_at: now,
+                domain: query.domain().to_string(),
+                qtype: query.qtype().to_string(),
+                strategy,
+                resource,
+                upstream: upstream.to_string(),
+            },
+        );
+    }
+
+    /// Completes the entry of a forwarded query, `None` meaning that we didn't receive a response.
+    pub fn log_response(
+        &mut self,
+        server: SocketAddr,
+        query_id: u16,
+        response: Option<&Response>,
+        now: Instant,
+    ) {
+        let Some(pending) = self.pending.remove(&(server, query_id)) else {
+            return;
+        };
+
+        self.complete(
+            pending,
+            response.map(|r| r.response_code().to_string()),
+            now,
+        );
+    }
+
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|p| p.received_at + PENDING_TIMEOUT)
+            .min()
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        let (expired, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition::<BTreeMap<_, _>, _>(|(_, p)| p.received_at + PENDING_TIMEOUT <= now);
+        self.pending = pending;
+
+        for pending in expired.into_values() {
+            self.complete(pending, None, now);
+        }
+    }
+
+    pub fn poll_entry(&mut self) -> Option<DnsQueryLogEntry> {
+        self.entries.pop_front()
+    }
+
+    fn complete(&mut self, pending: Pending, response_code: Option<String>, now: Instant) {
+        let Some(timestamp) = self.timestamp(pending.received_at) else {
+            return;
+        };
+
+        self.push(DnsQueryLogEntry {
+            timestamp,
+            domain: pending.domain,
+            qtype: pending.qtype,
+            strategy: pending.strategy,
+            resource: pending.resource,
+            response_code,
+            latency: now.duration_since(pending.received_at),
+            upstream: Some(pending.upstream),
+        });
+    }
+
+    fn push(&mut self, entry: DnsQueryLogEntry) {
+        if self.entries.len() >= MAX_BUFFERED {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+    }
+
+    fn timestamp(&self, instant: Instant) -> Option<SystemTime> {
+        let (anchor, wall_clock) = self.clock?;
+
+        Some(match instant.checked_duration_since(anchor) {
+            Some(elapsed) => wall_clock + elapsed,
+            None => wall_clock - anchor.duration_since(instant),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use dns_types::{DomainName, RecordType, ResponseCode};
+
+    use super::*;
+
+    #[test]
+    fn does_not_log_when_disabled() {
+        let mut log = DnsQueryLog::default();
+        let now = Instant::now();
+        let query = a_query("example.com");
+
+        log.log_answered(
+            &query,
+            &Response::no_error(&query),
+            DnsQueryStrategy::LocalResponse,
+            None,
+            now,
+        );
+        log.log_forwarded(
+            &query,
+            SERVER,
+            SERVER,
+            DnsQueryStrategy::RecurseLocal,
+            None,
+            now,
+        );
+
+        assert!(log.poll_entry().is_none());
+        assert!(log.poll_timeout().is_none());
+    }
+
+    #[test]
+    fn forwarded_query_is_logged_with_latency() {
+        let mut log = DnsQueryLog::default();
+        let now = Instant::now();
+        let wall_clock = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let query = a_query("example.com");
+        let rid = ResourceId::from_u128(1);
+
+        log.enable(now, wall_clock);
+        log.log_forwarded(
+            &query,
+            SERVER,
+            SERVER,
+            DnsQueryStrategy::RecurseSite,
+            Some(rid),
+            now + Duration::from_secs(1),
+        );
+        log.log_response(
+            SERVER,
+            query.id(),
+            Some(&Response::nxdomain(&query)),
+            now + Duration::from_millis(1500),
+        );
+
+        let entry = log.poll_entry().unwrap();
+
+        assert_eq!(entry.timestamp, wall_clock + Duration::from_secs(1));
+        assert_eq!(entry.domain, "example.com");
+        assert_eq!(entry.qtype, "A");
+        assert_eq!(entry.strategy, DnsQueryStrategy::RecurseSite);
+        assert_eq!(entry.resource, Some(rid));
+        assert_eq!(
+            entry.response_code,
+            Some(ResponseCode::NXDOMAIN.to_string())
+        );
+        assert_eq!(entry.latency, Duration::from_millis(500));
+        assert_eq!(entry.upstream, Some(SERVER.to_string()));
+    }
+
+    #[test]
+    fn unanswered_query_is_logged_after_timeout() {
+        let mut log = DnsQueryLog::default();
+        let now = Instant::now();
+        let query = a_query("example.com");
+
+        log.enable(now, SystemTime::now());
+        log.log_forwarded(
+            &query,
+            SERVER,
+            SERVER,
+            DnsQueryStrategy::RecurseLocal,
+            None,
+            now,
+        );
+
+        assert_eq!(log.poll_timeout(), Some(now + PENDING_TIMEOUT));
+
+        log.handle_timeout(now + PENDING_TIMEOUT);
+
+        let entry = log.poll_entry().unwrap();
+
+        assert_eq!(entry.response_code, None);
+        assert_eq!(entry.latency, PENDING_TIMEOUT);
+        assert!(log.poll_timeout().is_none());
+    }
+
+    #[test]
+    fn buffered_entries_are_bounded() {
+        let mut log = DnsQueryLog::default();
+        let now = Instant::now();
+        let query = a_query("example.com");
+        let response = Response::no_error(&query);
+
+        log.enable(now, SystemTime::now());
+
+        for _ in 0..(MAX_BUFFERED + 10) {
+            log.log_answered(
+                &query,
+                &response,
+                DnsQueryStrategy::LocalResponse,
+                None,
+                now,
+            );
+        }
+
+        assert_eq!(
+            std::iter::from_fn(|| log.poll_entry()).count(),
+            MAX_BUFFERED
+        );
+    }
+
+    const SERVER: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 53);
+
+    fn a_query(domain: &str) -> Query {
+        Query::new(DomainName::vec_from_str(domain).unwrap(), RecordType::A)
+    }
+}
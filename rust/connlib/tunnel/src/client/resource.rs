@@ -10,6 +10,7 @@ use ip_network::{IpNetwork, Ipv4Network, Ipv6Network};
 use itertools::Itertools as _;
 use serde::Deserialize;
 
+use crate::dns::RecordSettings;
 use crate::messages::client::{
     ResourceDescription, ResourceDescriptionCidr, ResourceDescriptionDns,
     ResourceDescriptionInternet,
@@ -35,6 +36,9 @@ pub struct DnsResource {
 
     pub address_description: Option<String>,
     pub sites: Vec<Site>,
+
+    /// How we synthesize A / AAAA records for this resource.
+    pub records: RecordSettings,
 }
 
 /// Description of a resource that maps to a CIDR.
@@ -249,6 +253,12 @@ impl DnsResource {
             name: resource.name,
             address_description: resource.address_description,
             sites: resource.sites,
+            records: RecordSettings::new(
+                resource.ttl,
+                resource.ipv4_proxy_ips,
+                resource.ipv6_proxy_ips,
+                resource.ip_stack,
+            ),
         }
     }
 
@@ -1,4 +1,7 @@
 use crate::client::IpProvider;
+use crate::messages::DnsServer;
+use crate::messages::client::IpStack;
+use crate::p2p_control::dns_resource_nat::MAX_PROXY_IPS_PER_FAMILY;
 use anyhow::Result;
 use connlib_model::ResourceId;
 use dns_types::{
@@ -11,11 +14,22 @@ use pattern::{Candidate, Pattern};
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, btree_map},
     net::SocketAddr,
 };
+use trie::PatternTrie;
+
+pub use overrides::DnsOverrides;
+
+mod overrides;
+mod trie;
 
 const DNS_TTL: u32 = 1;
+/// The longest TTL we allow for records synthesized for DNS resources.
+///
+/// Clients refresh the DNS resource NAT on the Gateway whenever they re-query a domain, so we don't want them to cache records forever.
+const MAX_DNS_TTL: u32 = 3600;
+const DEFAULT_PROXY_IPS_PER_FAMILY: usize = 4;
 const REVERSE_DNS_ADDRESS_END: &str = "arpa";
 const REVERSE_DNS_ADDRESS_V4: &str = "in-addr";
 const REVERSE_DNS_ADDRESS_V6: &str = "ip6";
@@ -38,14 +52,77 @@ pub struct StubResolver {
     ips_to_fqdn: HashMap<IpAddr, (dns_types::DomainName, ResourceId)>,
     ip_provider: IpProvider,
     /// All DNS resources we know about, indexed by the glob pattern they match against.
-    dns_resources: BTreeMap<Pattern, ResourceId>,
+    dns_resources: PatternTrie,
+    /// How to synthesize records for each DNS resource.
+    record_settings: HashMap<ResourceId, RecordSettings>,
+    /// User-defined records and forwarding rules for domains that aren't DNS resources.
+    overrides: DnsOverrides,
     search_domain: Option<DomainName>,
 }
 
+/// How we synthesize the A / AAAA records for the domains of a DNS resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordSettings {
+    /// TTL of the records in seconds.
+    pub ttl: u32,
+    /// How many IPv4 proxy IPs we assign to each domain.
+    ///
+    /// Zero means we don't answer A queries.
+    pub ipv4_proxy_ips: usize,
+    /// How many IPv6 proxy IPs we assign to each domain.
+    ///
+    /// Zero means we don't answer AAAA queries.
+    pub ipv6_proxy_ips: usize,
+}
+
+impl RecordSettings {
+    /// Constructs the settings from what the portal sent us, falling back to the defaults for anything it didn't specify.
+    pub fn new(
+        ttl: Option<u32>,
+        ipv4_proxy_ips: Option<u8>,
+        ipv6_proxy_ips: Option<u8>,
+        ip_stack: IpStack,
+    ) -> Self {
+        let proxy_ips = |n: Option<u8>| {
+            n.map_or(DEFAULT_PROXY_IPS_PER_FAMILY, usize::from)
+                .clamp(1, MAX_PROXY_IPS_PER_FAMILY)
+        };
+
+        let (ipv4_proxy_ips, ipv6_proxy_ips) = match ip_stack {
+            IpStack::Dual => (proxy_ips(ipv4_proxy_ips), proxy_ips(ipv6_proxy_ips)),
+            IpStack::Ipv4Only => (proxy_ips(ipv4_proxy_ips), 0),
+            IpStack::Ipv6Only => (0, proxy_ips(ipv6_proxy_ips)),
+        };
+
+        Self {
+            ttl: ttl.unwrap_or(DNS_TTL).clamp(1, MAX_DNS_TTL),
+            ipv4_proxy_ips,
+            ipv6_proxy_ips,
+        }
+    }
+
+    fn is_satisfied_by(&self, ips: &[IpAddr]) -> bool {
+        let num_ipv4 = ips.iter().filter(|ip| ip.is_ipv4()).count();
+        let num_ipv6 = ips.iter().filter(|ip| ip.is_ipv6()).count();
+
+        num_ipv4 == self.ipv4_proxy_ips && num_ipv6 == self.ipv6_proxy_ips
+    }
+}
+
+impl Default for RecordSettings {
+    fn default() -> Self {
+        Self {
+            ttl: DNS_TTL,
+            ipv4_proxy_ips: DEFAULT_PROXY_IPS_PER_FAMILY,
+            ipv6_proxy_ips: DEFAULT_PROXY_IPS_PER_FAMILY,
+        }
+    }
+}
+
 /// A query that needs to be forwarded to an upstream DNS server for resolution.
 #[derive(Debug)]
 pub(crate) struct RecursiveQuery {
-    pub server: SocketAddr,
+    pub server: DnsServer,
     pub message: dns_types::Query,
     pub transport: Transport,
 }
@@ -53,7 +130,7 @@ pub(crate) struct RecursiveQuery {
 /// A response to a [`RecursiveQuery`].
 #[derive(Debug)]
 pub(crate) struct RecursiveResponse {
-    pub server: SocketAddr,
+    pub server: DnsServer,
     pub query: dns_types::Query,
     pub message: io::Result<dns_types::Response>,
     pub transport: Transport,
@@ -62,7 +139,7 @@ pub(crate) struct RecursiveResponse {
 impl RecursiveQuery {
     pub(crate) fn via_udp(
         source: SocketAddr,
-        server: SocketAddr,
+        server: DnsServer,
         message: dns_types::Query,
     ) -> Self {
         Self {
@@ -75,7 +152,7 @@ impl RecursiveQuery {
     pub(crate) fn via_tcp(
         local: SocketAddr,
         remote: SocketAddr,
-        server: SocketAddr,
+        server: DnsServer,
         message: dns_types::Query,
     ) -> Self {
         Self {
@@ -107,6 +184,8 @@ pub(crate) enum ResolveStrategy {
     RecurseLocal,
     /// The query is for a DNS resource but for a type that we don't intercept (i.e. SRV, TXT, ...), forward it to the site that hosts the DNS resource and resolve it there.
     RecurseSite(ResourceId),
+    /// The query matches a forwarding rule of the [`DnsOverrides`], forward it to the given server.
+    RecurseServer(SocketAddr),
 }
 
 impl Default for StubResolver {
@@ -116,6 +195,8 @@ impl Default for StubResolver {
             ips_to_fqdn: Default::default(),
             ip_provider: IpProvider::for_resources(),
             dns_resources: Default::default(),
+            record_settings: Default::default(),
+            overrides: Default::default(),
             search_domain: Default::default(),
         }
     }
@@ -141,7 +222,12 @@ impl StubResolver {
             .map(|((domain, resource), ips)| (domain, resource, ips))
     }
 
-    pub(crate) fn add_resource(&mut self, id: ResourceId, pattern: String) -> bool {
+    pub(crate) fn add_resource(
+        &mut self,
+        id: ResourceId,
+        pattern: String,
+        settings: RecordSettings,
+    ) -> bool {
         let parsed_pattern = match Pattern::new(&pattern) {
             Ok(p) => p,
             Err(e) => {
@@ -151,12 +237,18 @@ impl StubResolver {
         };
 
         let existing = self.dns_resources.insert(parsed_pattern, id);
+        self.record_settings.insert(id, settings);
 
         existing.is_none()
     }
 
     pub(crate) fn remove_resource(&mut self, id: ResourceId) {
-        self.dns_resources.retain(|_, r| *r != id);
+        self.dns_resources.remove(id);
+        self.record_settings.remove(&id);
+    }
+
+    pub(crate) fn set_overrides(&mut self, overrides: DnsOverrides) {
+        self.overrides = overrides;
     }
 
     fn get_or_assign_a_records(
@@ -183,23 +275,67 @@ impl StubResolver {
             .collect_vec()
     }
 
+    /// Synthesizes an HTTPS or SVCB record that points clients to our proxy IPs via its `ipv4hint` and `ipv6hint`.
+    ///
+    /// Without these, clients that connect based on the service binding would use the IPs of the actual service and bypass the tunnel.
+    fn get_or_assign_service_binding_records(
+        &mut self,
+        qtype: RecordType,
+        fqdn: dns_types::DomainName,
+        resource_id: ResourceId,
+    ) -> Vec<OwnedRecordData> {
+        let ips = self.get_or_assign_ips(fqdn, resource_id);
+
+        let record = if qtype == RecordType::SVCB {
+            dns_types::records::svcb(ips)
+        } else {
+            dns_types::records::https(ips)
+        };
+
+        vec![record]
+    }
+
+    /// Synthesizes the records of a DNS resource for the given query type.
+    ///
+    /// For query types that aren't [`is_synthesized`], there are no records.
+    fn synthesize_records(
+        &mut self,
+        qtype: RecordType,
+        fqdn: dns_types::DomainName,
+        resource_id: ResourceId,
+    ) -> Vec<OwnedRecordData> {
+        match qtype {
+            RecordType::A => self.get_or_assign_a_records(fqdn, resource_id),
+            RecordType::AAAA => self.get_or_assign_aaaa_records(fqdn, resource_id),
+            RecordType::HTTPS | RecordType::SVCB => {
+                self.get_or_assign_service_binding_records(qtype, fqdn, resource_id)
+            }
+            _ => Vec::new(),
+        }
+    }
+
     fn get_or_assign_ips(
         &mut self,
         fqdn: dns_types::DomainName,
         resource_id: ResourceId,
     ) -> Vec<IpAddr> {
-        let ips = self
-            .fqdn_to_ips
-            .entry((fqdn.clone(), resource_id))
-            .or_insert_with(|| {
-                let mut ips = self.ip_provider.get_n_ipv4(4);
-                ips.extend_from_slice(&self.ip_provider.get_n_ipv6(4));
+        let settings = self.record_settings(resource_id);
+
+        // If the settings of the resource changed since we assigned the IPs, assign new ones.
+        // The old ones keep routing to the resource so existing connections are not interrupted.
+        let ips = match self.fqdn_to_ips.entry((fqdn.clone(), resource_id)) {
+            btree_map::Entry::Occupied(o) if settings.is_satisfied_by(o.get()) => o.get().clone(),
+            entry => {
+                let mut ips = self.ip_provider.get_n_ipv4(settings.ipv4_proxy_ips);
+                ips.extend_from_slice(&self.ip_provider.get_n_ipv6(settings.ipv6_proxy_ips));
 
                 tracing::debug!(domain = %fqdn, ?ips, "Assigning proxy IPs");
 
+                *entry.or_default() = ips.clone();
+
                 ips
-            })
-            .clone();
+            }
+        };
         for ip in &ips {
             self.ips_to_fqdn.insert(*ip, (fqdn.clone(), resource_id));
         }
@@ -207,24 +343,28 @@ impl StubResolver {
         ips
     }
 
+    fn record_settings(&self, resource_id: ResourceId) -> RecordSettings {
+        self.record_settings
+            .get(&resource_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
     /// Attempts to match the given domain against our list of possible patterns.
     ///
-    /// This performs a linear search and is thus O(N) and **must not** be called in the hot-path of packet routing.
-    fn match_resource_linear(&self, domain: &dns_types::DomainName) -> Option<ResourceId> {
-        let _span = telemetry_span!("match_resource_linear").entered();
+    /// This only tests the patterns that share a wildcard-free suffix with the domain.
+    /// It still evaluates glob patterns and thus **must not** be called in the hot-path of packet routing.
+    pub(crate) fn match_resource(&self, domain: &dns_types::DomainName) -> Option<ResourceId> {
+        let _span = telemetry_span!("match_resource").entered();
 
-        let name = Candidate::from_domain(domain);
+        if let Some((pattern, id)) = self.dns_resources.find(domain) {
+            tracing::trace!(%id, %pattern, %domain, "Matched resource");
 
-        for (pattern, id) in &self.dns_resources {
-            if pattern.matches(&name) {
-                tracing::trace!(%id, %pattern, %domain, "Matched resource");
-
-                return Some(*id);
-            }
+            return Some(id);
         }
 
         if tracing::enabled!(tracing::Level::TRACE) {
-            let patterns = self.dns_resources.keys().join(" | ");
+            let patterns = self.dns_resources.iter().map(|(p, _)| p).join(" | ");
             let patterns = format!("[{patterns}]");
 
             tracing::trace!(%domain, %patterns, "No resources matched");
@@ -254,15 +394,12 @@ impl StubResolver {
             return ResolveStrategy::LocalResponse(Response::nxdomain(query));
         }
 
-        // `match_resource` is `O(N)` which we deem fine for DNS queries.
-        let maybe_resource = self.match_resource_linear(&domain);
+        let maybe_resource = self.match_resource(&domain);
+        let ttl = maybe_resource.map_or(DNS_TTL, |r| self.record_settings(r).ttl);
 
         let records = match (qtype, maybe_resource) {
-            (RecordType::A, Some(resource)) => {
-                self.get_or_assign_a_records(domain.clone(), resource)
-            }
-            (RecordType::AAAA, Some(resource)) => {
-                self.get_or_assign_aaaa_records(domain.clone(), resource)
+            (qtype, Some(resource)) if is_synthesized(qtype) => {
+                self.synthesize_records(qtype, domain.clone(), resource)
             }
             (RecordType::SRV | RecordType::TXT, Some(resource)) => {
                 tracing::debug!(%qtype, %resource, "Forwarding query for DNS resource to corresponding site");
@@ -271,21 +408,104 @@ impl StubResolver {
             }
             (RecordType::PTR, _) => {
                 let Some(fqdn) = self.resource_address_name_by_reservse_dns(&domain) else {
-                    return ResolveStrategy::RecurseLocal;
+                    return self.handle_with_overrides(query);
                 };
 
                 vec![dns_types::records::ptr(fqdn)]
             }
-            (RecordType::HTTPS, Some(_)) => {
-                // We must intercept queries for the HTTPS record type to force the client to issue an A / AAAA query instead.
-                // Otherwise, the client won't use the IPs we issue for a particular domain and the traffic cannot be tunneled.
+            (_, Some(_)) => return ResolveStrategy::RecurseLocal,
+            (_, None) => return self.handle_with_overrides(query),
+        };
+
+        tracing::trace!(%qtype, %domain, records = ?records, "Forming DNS response");
+
+        let response = ResponseBuilder::for_query(query, ResponseCode::NOERROR)
+            .with_records(records.into_iter().map(|r| (domain.clone(), ttl, r)))
+            .build();
+
+        ResolveStrategy::LocalResponse(response)
+    }
+
+    /// Flattens the CNAME chain of an upstream response if it leads to one of our DNS resources.
+    ///
+    /// The chain is kept up to the first domain that is a DNS resource and completed with the records we synthesize for it.
+    /// Otherwise, the client would connect to the IPs the upstream resolver returned for the resource and bypass the tunnel.
+    ///
+    /// Returns the rewritten response together with the domain of the DNS resource.
+    pub(crate) fn flatten_cname_chain(
+        &mut self,
+        query: &Query,
+        response: &Response,
+    ) -> Option<(Response, DomainName)> {
+        let qtype = query.qtype();
+
+        if !is_synthesized(qtype) {
+            return None;
+        }
+
+        let chain = response.cname_chain();
+        let (position, domain, resource) =
+            chain
+                .iter()
+                .enumerate()
+                .find_map(|(position, (_, target, _))| {
+                    Some((position, target.clone(), self.match_resource(target)?))
+                })?;
+
+        let ttl = self.record_settings(resource).ttl;
+        let records = self.synthesize_records(qtype, domain.clone(), resource);
+
+        tracing::debug!(query = %query.domain(), %domain, %resource, "Flattening CNAME chain to DNS resource");
+
+        let cnames = chain
+            .into_iter()
+            .take(position + 1)
+            .map(|(owner, target, cname_ttl)| {
+                (owner, cname_ttl, dns_types::records::cname(target))
+            });
+        let records = records.into_iter().map(|r| (domain.clone(), ttl, r));
+
+        let response = ResponseBuilder::for_query(query, ResponseCode::NOERROR)
+            .with_records(cnames.chain(records))
+            .build();
+
+        Some((response, domain))
+    }
+
+    /// Resolves a query for a domain that is not a DNS resource using the [`DnsOverrides`].
+    ///
+    /// Static records take precedence over forwarding rules.
+    /// Anything that isn't covered by the overrides is resolved by the upstream resolvers.
+    fn handle_with_overrides(&self, query: &Query) -> ResolveStrategy {
+        let domain = query.domain();
+        let qtype = query.qtype();
+
+        let records = match (qtype, self.overrides.ips(&domain)) {
+            (RecordType::A, Some(ips)) => ips
+                .iter()
+                .copied()
+                .filter_map(get_v4)
+                .map(dns_types::records::a)
+                .collect_vec(),
+            (RecordType::AAAA, Some(ips)) => ips
+                .iter()
+                .copied()
+                .filter_map(get_v6)
+                .map(dns_types::records::aaaa)
+                .collect_vec(),
+            (RecordType::PTR, _) => {
+                let Some(fqdn) =
+                    reverse_dns_addr(&domain.to_string()).and_then(|ip| self.overrides.domain(ip))
+                else {
+                    return self.forward_or_recurse_local(&domain);
+                };
 
-                return ResolveStrategy::LocalResponse(Response::no_error(query));
+                vec![dns_types::records::ptr(fqdn.clone())]
             }
-            _ => return ResolveStrategy::RecurseLocal,
+            _ => return self.forward_or_recurse_local(&domain),
         };
 
-        tracing::trace!(%qtype, %domain, records = ?records, "Forming DNS response");
+        tracing::trace!(%qtype, %domain, records = ?records, "Answering DNS query from overrides");
 
         let response = ResponseBuilder::for_query(query, ResponseCode::NOERROR)
             .with_records(records.into_iter().map(|r| (domain.clone(), DNS_TTL, r)))
@@ -294,6 +514,17 @@ impl StubResolver {
         ResolveStrategy::LocalResponse(response)
     }
 
+    fn forward_or_recurse_local(&self, domain: &DomainName) -> ResolveStrategy {
+        match self.overrides.forward_server(domain) {
+            Some(server) => {
+                tracing::trace!(%domain, %server, "Forwarding DNS query according to overrides");
+
+                ResolveStrategy::RecurseServer(server)
+            }
+            None => ResolveStrategy::RecurseLocal,
+        }
+    }
+
     pub(crate) fn set_search_domain(&mut self, new_search_domain: Option<DomainName>) {
         if self.search_domain == new_search_domain {
             return;
@@ -305,6 +536,17 @@ impl StubResolver {
     }
 }
 
+/// Whether we synthesize the records for queries of this type for DNS resources.
+///
+/// Besides A and AAAA, we must also intercept HTTPS and SVCB queries.
+/// Otherwise, clients might connect to the IPs hinted at in the actual records and the traffic cannot be tunneled.
+fn is_synthesized(qtype: RecordType) -> bool {
+    matches!(
+        qtype,
+        RecordType::A | RecordType::AAAA | RecordType::HTTPS | RecordType::SVCB
+    )
+}
+
 pub fn is_subdomain(name: &dns_types::DomainName, resource: &str) -> bool {
     let pattern = match Pattern::new(resource) {
         Ok(p) => p,
@@ -370,7 +612,7 @@ mod pattern {
     use super::*;
     use std::{convert::Infallible, fmt, str::FromStr};
 
-    #[derive(Eq)]
+    #[derive(Clone, Eq)]
     pub struct Pattern {
         inner: glob::Pattern,
         original: String,
@@ -455,6 +697,16 @@ mod pattern {
             })
         }
 
+        /// The labels at the end of this pattern that don't contain any wildcards, from right to left.
+        ///
+        /// Any domain matching this pattern must end in these labels (ignoring case).
+        pub fn literal_suffix(&self) -> impl Iterator<Item = String> + '_ {
+            self.original
+                .rsplit('.')
+                .take_while(|label| !label.contains(['*', '?', '[']))
+                .map(|label| label.to_ascii_lowercase())
+        }
+
         /// Matches a [`Candidate`] against this [`Pattern`].
         ///
         /// Matching only requires a reference, thus allowing users to test a [`Candidate`] against multiple [`Pattern`]s.
@@ -539,6 +791,7 @@ mod pattern {
 mod tests {
     use super::*;
     use std::str::FromStr as _;
+    use std::time::Duration;
     use test_case::test_case;
 
     #[test]
@@ -641,11 +894,15 @@ mod tests {
         let wc = ResourceId::from_u128(0);
         let non_wc = ResourceId::from_u128(1);
 
-        resolver.add_resource(wc, "**.example.com".to_owned());
-        resolver.add_resource(non_wc, "foo.example.com".to_owned());
+        resolver.add_resource(wc, "**.example.com".to_owned(), RecordSettings::default());
+        resolver.add_resource(
+            non_wc,
+            "foo.example.com".to_owned(),
+            RecordSettings::default(),
+        );
 
         let resource_id = resolver
-            .match_resource_linear(&"foo.example.com".parse().unwrap())
+            .match_resource(&"foo.example.com".parse().unwrap())
             .unwrap();
 
         assert_eq!(resource_id, non_wc);
@@ -674,6 +931,222 @@ mod tests {
         assert_eq!(response.response_code(), ResponseCode::NXDOMAIN);
         assert_eq!(response.records().count(), 0);
     }
+
+    #[test]
+    fn answers_from_static_overrides() {
+        let mut resolver = StubResolver::default();
+        resolver.set_overrides(DnsOverrides::from_str("10.0.0.10 db.lab.local").unwrap());
+
+        let query = Query::new("db.lab.local".parse().unwrap(), RecordType::A);
+
+        let ResolveStrategy::LocalResponse(response) = resolver.handle(&query) else {
+            panic!("Unexpected result")
+        };
+
+        assert_eq!(response.response_code(), ResponseCode::NOERROR);
+        assert_eq!(response.records().count(), 1);
+    }
+
+    #[test]
+    fn forwards_according_to_overrides() {
+        let mut resolver = StubResolver::default();
+        resolver.set_overrides(DnsOverrides::from_str("forward *.lab.local 10.0.0.53").unwrap());
+
+        let query = Query::new("foo.lab.local".parse().unwrap(), RecordType::MX);
+
+        let ResolveStrategy::RecurseServer(server) = resolver.handle(&query) else {
+            panic!("Unexpected result")
+        };
+
+        assert_eq!(server, "10.0.0.53:53".parse().unwrap());
+    }
+
+    #[test]
+    fn prioritises_resources_over_overrides() {
+        let mut resolver = StubResolver::default();
+        resolver.add_resource(
+            ResourceId::from_u128(0),
+            "**.lab.local".to_owned(),
+            RecordSettings::default(),
+        );
+        resolver.set_overrides(
+            DnsOverrides::from_str("10.0.0.10 db.lab.local\nforward *.lab.local 10.0.0.53")
+                .unwrap(),
+        );
+
+        let a_query = Query::new("db.lab.local".parse().unwrap(), RecordType::A);
+        let mx_query = Query::new("db.lab.local".parse().unwrap(), RecordType::MX);
+
+        let ResolveStrategy::LocalResponse(response) = resolver.handle(&a_query) else {
+            panic!("Unexpected result")
+        };
+        assert!(
+            response
+                .records()
+                .all(|r| r.data().to_string() != "10.0.0.10")
+        );
+        assert!(matches!(
+            resolver.handle(&mx_query),
+            ResolveStrategy::RecurseLocal
+        ));
+    }
+
+    #[test]
+    fn synthesizes_records_according_to_resource_settings() {
+        let mut resolver = StubResolver::default();
+        resolver.add_resource(
+            ResourceId::from_u128(0),
+            "legacy.example.com".to_owned(),
+            RecordSettings::new(Some(300), Some(2), None, IpStack::Ipv4Only),
+        );
+
+        let a_query = Query::new("legacy.example.com".parse().unwrap(), RecordType::A);
+        let aaaa_query = Query::new("legacy.example.com".parse().unwrap(), RecordType::AAAA);
+
+        let ResolveStrategy::LocalResponse(a_response) = resolver.handle(&a_query) else {
+            panic!("Unexpected result")
+        };
+        let ResolveStrategy::LocalResponse(aaaa_response) = resolver.handle(&aaaa_query) else {
+            panic!("Unexpected result")
+        };
+
+        assert_eq!(a_response.records().count(), 2);
+        assert_eq!(a_response.ttl(), Some(Duration::from_secs(300)));
+        assert_eq!(aaaa_response.response_code(), ResponseCode::NOERROR);
+        assert_eq!(aaaa_response.records().count(), 0);
+    }
+
+    #[test]
+    fn synthesizes_service_binding_records_for_dns_resources() {
+        let mut resolver = StubResolver::default();
+        resolver.add_resource(
+            ResourceId::from_u128(0),
+            "app.example.com".to_owned(),
+            RecordSettings::default(),
+        );
+
+        for qtype in [RecordType::HTTPS, RecordType::SVCB] {
+            let query = Query::new("app.example.com".parse().unwrap(), qtype);
+
+            let ResolveStrategy::LocalResponse(response) = resolver.handle(&query) else {
+                panic!("Unexpected result")
+            };
+
+            let records = response.records().collect::<Vec<_>>();
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].rtype(), qtype);
+        }
+    }
+
+    #[test]
+    fn flattens_cname_chain_to_dns_resource() {
+        let mut resolver = StubResolver::default();
+        resolver.add_resource(
+            ResourceId::from_u128(0),
+            "app.corp.internal".to_owned(),
+            RecordSettings::default(),
+        );
+
+        let www = DomainName::vec_from_str("www.example.com").unwrap();
+        let app = DomainName::vec_from_str("app.corp.internal").unwrap();
+
+        let query = Query::new(www.clone(), RecordType::A);
+        let upstream_response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([
+                (www, 300, dns_types::records::cname(app.clone())),
+                (
+                    app.clone(),
+                    300,
+                    dns_types::records::a(Ipv4Addr::new(10, 0, 0, 1)),
+                ),
+            ])
+            .build();
+
+        let (response, domain) = resolver
+            .flatten_cname_chain(&query, &upstream_response)
+            .unwrap();
+
+        let ResolveStrategy::LocalResponse(resource_response) =
+            resolver.handle(&Query::new(app.clone(), RecordType::A))
+        else {
+            panic!("Unexpected result")
+        };
+
+        assert_eq!(domain, app);
+        assert_eq!(response.cname_chain().len(), 1);
+        assert_eq!(
+            response
+                .records()
+                .filter(|r| r.rtype() == RecordType::A)
+                .map(|r| r.data().to_string())
+                .collect::<Vec<_>>(),
+            resource_response
+                .records()
+                .map(|r| r.data().to_string())
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn does_not_flatten_cname_chain_without_dns_resource() {
+        let mut resolver = StubResolver::default();
+
+        let www = DomainName::vec_from_str("www.example.com").unwrap();
+        let cdn = DomainName::vec_from_str("example.cdn.net").unwrap();
+
+        let query = Query::new(www.clone(), RecordType::A);
+        let upstream_response = ResponseBuilder::for_query(&query, ResponseCode::NOERROR)
+            .with_records([
+                (www, 300, dns_types::records::cname(cdn.clone())),
+                (cdn, 300, dns_types::records::a(Ipv4Addr::new(1, 1, 1, 1))),
+            ])
+            .build();
+
+        assert!(
+            resolver
+                .flatten_cname_chain(&query, &upstream_response)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn reassigns_proxy_ips_when_resource_settings_change() {
+        let mut resolver = StubResolver::default();
+        let resource = ResourceId::from_u128(0);
+        let domain = "foo.example.com".parse::<DomainName>().unwrap();
+
+        resolver.add_resource(
+            resource,
+            "foo.example.com".to_owned(),
+            RecordSettings::default(),
+        );
+        let ips = resolver.get_or_assign_ips(domain.clone(), resource);
+
+        resolver.add_resource(
+            resource,
+            "foo.example.com".to_owned(),
+            RecordSettings::new(None, None, Some(1), IpStack::Ipv6Only),
+        );
+        let new_ips = resolver.get_or_assign_ips(domain.clone(), resource);
+
+        assert_eq!(ips.len(), 8);
+        assert_eq!(new_ips.len(), 1);
+        assert!(new_ips[0].is_ipv6());
+        assert!(
+            ips.iter()
+                .all(|ip| resolver.resolve_resource_by_ip(ip).is_some()),
+            "Old proxy IPs should still route to the resource"
+        );
+    }
+
+    #[test]
+    fn clamps_resource_settings() {
+        let settings = RecordSettings::new(Some(0), Some(0), Some(u8::MAX), IpStack::Dual);
+
+        assert_eq!(settings.ttl, 1);
+        assert_eq!(settings.ipv4_proxy_ips, 1);
+        assert_eq!(settings.ipv6_proxy_ips, MAX_PROXY_IPS_PER_FAMILY);
+    }
 }
 
 #[cfg(feature = "divan")]
@@ -685,28 +1158,33 @@ mod benches {
     #[divan::bench(
         consts = [10, 100, 1_000, 10_000, 100_000]
     )]
-    fn match_domain_linear<const NUM_RES: u128>(bencher: divan::Bencher) {
+    fn match_domain<const NUM_RES: u128>(bencher: divan::Bencher) {
         bencher
             .with_inputs(|| {
                 let mut resolver = StubResolver::default();
                 let mut rng = rand::thread_rng();
 
                 for n in 0..NUM_RES {
-                    resolver.add_resource(ResourceId::from_u128(n), make_domain(&mut rng));
+                    resolver.add_resource(
+                        ResourceId::from_u128(n),
+                        make_domain(&mut rng),
+                        RecordSettings::default(),
+                    );
                 }
 
                 let needle = resolver
                     .dns_resources
-                    .keys()
+                    .iter()
                     .choose(&mut rng)
                     .unwrap()
+                    .0
                     .to_string();
 
                 let needle = dns_types::DomainName::vec_from_str(&needle).unwrap();
 
                 (resolver, needle)
             })
-            .bench_refs(|(resolver, needle)| resolver.match_resource_linear(needle).unwrap());
+            .bench_refs(|(resolver, needle)| resolver.match_resource(needle).unwrap());
     }
 
     fn make_domain(rng: &mut impl Rng) -> String {
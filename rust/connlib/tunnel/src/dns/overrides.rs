@@ -0,0 +1,208 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use anyhow::{Context as _, Result, bail};
+use dns_types::DomainName;
+
+use super::{DNS_PORT, pattern::Candidate, pattern::Pattern};
+
+/// User-defined DNS records and forwarding rules.
+///
+/// Overrides are parsed from a text file, one entry per line:
+///
+/// ```text
+/// # Static records, like `/etc/hosts`: an IP followed by one or more names.
+/// 10.0.0.10   db.lab.local db
+/// fd00::10    db.lab.local
+///
+/// # Forward queries matching a pattern to a specific DNS server (the port defaults to 53).
+/// forward *.lab.local 10.0.0.53
+/// forward corp.example.com 10.0.0.54:5353
+/// ```
+///
+/// Overrides only apply to domains that are not a DNS resource.
+#[derive(Debug, Default, Clone)]
+pub struct DnsOverrides {
+    records: BTreeMap<DomainName, Vec<IpAddr>>,
+    forwards: BTreeMap<Pattern, SocketAddr>,
+}
+
+impl DnsOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty() && self.forwards.is_empty()
+    }
+
+    /// The statically configured IPs for the given domain.
+    pub(crate) fn ips(&self, domain: &DomainName) -> Option<&[IpAddr]> {
+        self.records.get(domain).map(|ips| ips.as_slice())
+    }
+
+    /// The first domain that is statically configured with the given IP.
+    pub(crate) fn domain(&self, ip: IpAddr) -> Option<&DomainName> {
+        self.records
+            .iter()
+            .find_map(|(domain, ips)| ips.contains(&ip).then_some(domain))
+    }
+
+    /// The DNS server that queries for the given domain should be forwarded to.
+    pub(crate) fn forward_server(&self, domain: &DomainName) -> Option<SocketAddr> {
+        let candidate = Candidate::from_domain(domain);
+
+        self.forwards
+            .iter()
+            .find_map(|(pattern, server)| pattern.matches(&candidate).then_some(*server))
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        let mut tokens = line.split_whitespace();
+
+        let Some(first) = tokens.next() else {
+            return Ok(());
+        };
+
+        if first == "forward" {
+            let pattern = tokens.next().context("Missing domain pattern")?;
+            let server = tokens.next().context("Missing DNS server")?;
+
+            if let Some(extra) = tokens.next() {
+                bail!("Unexpected token `{extra}`");
+            }
+
+            let pattern = Pattern::new(pattern)
+                .with_context(|| format!("Invalid domain pattern `{pattern}`"))?;
+            let server = parse_server(server)?;
+
+            self.forwards.insert(pattern, server);
+
+            return Ok(());
+        }
+
+        let ip = first
+            .parse::<IpAddr>()
+            .with_context(|| format!("Expected an IP address or `forward` but got `{first}`"))?;
+
+        let mut has_name = false;
+
+        for name in tokens {
+            let domain = DomainName::vec_from_str(name)
+                .with_context(|| format!("Invalid domain name `{name}`"))?;
+
+            let ips = self.records.entry(domain).or_default();
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+
+            has_name = true;
+        }
+
+        if !has_name {
+            bail!("Missing domain name for `{ip}`");
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for DnsOverrides {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut overrides = Self::default();
+
+        for (index, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+
+            overrides
+                .parse_line(line)
+                .with_context(|| format!("Line {}", index + 1))?;
+        }
+
+        Ok(overrides)
+    }
+}
+
+fn parse_server(server: &str) -> Result<SocketAddr> {
+    if let Ok(socket) = server.parse::<SocketAddr>() {
+        return Ok(socket);
+    }
+
+    let ip = server
+        .parse::<IpAddr>()
+        .with_context(|| format!("Invalid DNS server `{server}`"))?;
+
+    Ok(SocketAddr::new(ip, DNS_PORT))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn parses_records_and_forwards() {
+        let overrides = DnsOverrides::from_str(
+            r#"
+            # Lab machines
+            10.0.0.10   db.lab.local db # Trailing comment
+            fd00::10    db.lab.local
+
+            forward *.lab.local 10.0.0.53
+            forward corp.example.com 10.0.0.54:5353
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            overrides.ips(&domain("db.lab.local")).unwrap(),
+            [
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 10)),
+                IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x10))
+            ]
+        );
+        assert_eq!(
+            overrides.ips(&domain("db")).unwrap(),
+            [IpAddr::V4(Ipv4Addr::new(10, 0, 0, 10))]
+        );
+        assert_eq!(
+            overrides.forward_server(&domain("foo.lab.local")),
+            Some("10.0.0.53:53".parse().unwrap())
+        );
+        assert_eq!(
+            overrides.forward_server(&domain("corp.example.com")),
+            Some("10.0.0.54:5353".parse().unwrap())
+        );
+        assert_eq!(overrides.forward_server(&domain("example.com")), None);
+    }
+
+    #[test]
+    fn finds_domain_by_ip() {
+        let overrides = DnsOverrides::from_str("10.0.0.10 db.lab.local").unwrap();
+
+        assert_eq!(
+            overrides.domain(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 10))),
+            Some(&domain("db.lab.local"))
+        );
+    }
+
+    #[test]
+    fn reports_line_of_invalid_entry() {
+        let error = DnsOverrides::from_str("10.0.0.10 db\nforward *.lab.local")
+            .unwrap_err()
+            .to_string();
+
+        assert_eq!(error, "Line 2");
+    }
+
+    #[test]
+    fn rejects_record_without_name() {
+        assert!(DnsOverrides::from_str("10.0.0.10").is_err());
+    }
+
+    fn domain(name: &str) -> DomainName {
+        DomainName::vec_from_str(name).unwrap()
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+
+use connlib_model::ResourceId;
+
+use super::pattern::{Candidate, Pattern};
+
+/// An index of domain [`Pattern`]s, organised as a trie of their labels in reverse order.
+///
+/// Each pattern is stored at the node of its longest wildcard-free suffix, i.e. `*.corp.example.com` lives under `com` -> `example` -> `corp`.
+/// To match a domain, we only need to walk down the trie along its labels and test the patterns we encounter on the way.
+/// The actual matching and the precedence between multiple matching patterns is still defined by [`Pattern`].
+#[derive(Default)]
+pub struct PatternTrie {
+    root: Node,
+}
+
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+    patterns: BTreeMap<Pattern, ResourceId>,
+}
+
+impl PatternTrie {
+    /// Inserts a new pattern, returning the [`ResourceId`] previously associated with it.
+    pub fn insert(&mut self, pattern: Pattern, id: ResourceId) -> Option<ResourceId> {
+        let node = pattern
+            .literal_suffix()
+            .fold(&mut self.root, |node, label| {
+                node.children.entry(label).or_default()
+            });
+
+        node.patterns.insert(pattern, id)
+    }
+
+    /// Removes all patterns associated with the given [`ResourceId`].
+    pub fn remove(&mut self, id: ResourceId) {
+        self.root.retain(id);
+    }
+
+    /// Finds the highest-priority pattern matching the given domain.
+    pub fn find(&self, domain: &dns_types::DomainName) -> Option<(&Pattern, ResourceId)> {
+        let candidate = Candidate::from_domain(domain);
+        let domain = domain.to_string().to_ascii_lowercase();
+
+        let mut labels = domain.rsplit('.');
+        let mut node = &self.root;
+        let mut best_match = None::<(&Pattern, ResourceId)>;
+
+        loop {
+            // Patterns within a node are sorted so the first match is the best one of this node.
+            if let Some((pattern, id)) = node.patterns.iter().find(|(p, _)| p.matches(&candidate)) {
+                if best_match.is_none_or(|(best, _)| pattern < best) {
+                    best_match = Some((pattern, *id));
+                }
+            }
+
+            let Some(child) = labels.next().and_then(|label| node.children.get(label)) else {
+                break;
+            };
+
+            node = child;
+        }
+
+        best_match
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Pattern, ResourceId)> {
+        let mut nodes = vec![&self.root];
+
+        std::iter::from_fn(move || {
+            let node = nodes.pop()?;
+            nodes.extend(node.children.values());
+
+            Some(node.patterns.iter().map(|(p, id)| (p, *id)))
+        })
+        .flatten()
+    }
+}
+
+impl Node {
+    fn retain(&mut self, id: ResourceId) {
+        self.patterns.retain(|_, r| *r != id);
+        self.children.retain(|_, child| {
+            child.retain(id);
+
+            !child.is_empty()
+        });
+    }
+
+    fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && self.children.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_most_specific_pattern_across_nodes() {
+        let mut trie = PatternTrie::default();
+        trie.insert(Pattern::new("**.example.com").unwrap(), rid(0));
+        trie.insert(Pattern::new("*.corp.example.com").unwrap(), rid(1));
+        trie.insert(Pattern::new("foo.corp.example.com").unwrap(), rid(2));
+
+        assert_eq!(find(&trie, "foo.corp.example.com"), Some(rid(2)));
+        assert_eq!(find(&trie, "bar.corp.example.com"), Some(rid(1)));
+        assert_eq!(find(&trie, "corp.example.com"), Some(rid(1)));
+        assert_eq!(find(&trie, "baz.example.com"), Some(rid(0)));
+        assert_eq!(find(&trie, "example.org"), None);
+    }
+
+    #[test]
+    fn matches_case_insensitive() {
+        let mut trie = PatternTrie::default();
+        trie.insert(Pattern::new("*.Example.com").unwrap(), rid(0));
+
+        assert_eq!(find(&trie, "FOO.example.COM"), Some(rid(0)));
+    }
+
+    #[test]
+    fn removing_resource_prunes_trie() {
+        let mut trie = PatternTrie::default();
+        trie.insert(Pattern::new("*.corp.example.com").unwrap(), rid(0));
+        trie.insert(Pattern::new("example.com").unwrap(), rid(1));
+
+        trie.remove(rid(0));
+
+        assert_eq!(find(&trie, "foo.corp.example.com"), None);
+        assert_eq!(trie.iter().count(), 1);
+        assert!(
+            !trie.root.children["com"].children["example"]
+                .children
+                .contains_key("corp")
+        );
+    }
+
+    fn find(trie: &PatternTrie, domain: &str) -> Option<ResourceId> {
+        let (_, id) = trie.find(&domain.parse().unwrap())?;
+
+        Some(id)
+    }
+
+    fn rid(id: u128) -> ResourceId {
+        ResourceId::from_u128(id)
+    }
+}
+
+#[cfg(all(test, feature = "proptest"))]
+mod proptests {
+    use super::*;
+    use proptest::{collection, prelude::*};
+
+    #[test_strategy::proptest]
+    fn trie_is_equivalent_to_linear_matching(
+        #[strategy(collection::vec(pattern(), 0..20))] patterns: Vec<String>,
+        #[strategy(domain())] domain: String,
+    ) {
+        let mut trie = PatternTrie::default();
+        let mut linear = BTreeMap::new();
+
+        for (id, pattern) in patterns.iter().enumerate() {
+            let Ok(parsed) = Pattern::new(pattern) else {
+                continue;
+            };
+
+            trie.insert(parsed, ResourceId::from_u128(id as u128));
+            linear.insert(
+                Pattern::new(pattern).unwrap(),
+                ResourceId::from_u128(id as u128),
+            );
+        }
+
+        let domain = domain.parse::<dns_types::DomainName>().unwrap();
+        let candidate = Candidate::from_domain(&domain);
+
+        let expected = linear
+            .iter()
+            .find(|(p, _)| p.matches(&candidate))
+            .map(|(_, id)| *id);
+        let actual = trie.find(&domain).map(|(_, id)| id);
+
+        prop_assert_eq!(actual, expected);
+    }
+
+    /// Patterns built from a tiny alphabet so that they frequently overlap with each other and the generated domains.
+    fn pattern() -> impl Strategy<Value = String> {
+        let label = prop_oneof![
+            Just("*".to_owned()),
+            Just("**".to_owned()),
+            "[aAb]{1,2}",
+            "[ab?]{1,2}",
+            "[ab]?\\*[ab]?",
+        ];
+
+        collection::vec(label, 1..5).prop_map(|labels| labels.join("."))
+    }
+
+    fn domain() -> impl Strategy<Value = String> {
+        collection::vec("[aAb]{1,2}", 1..5).prop_map(|labels| labels.join("."))
+    }
+}
@@ -1,13 +1,16 @@
-use crate::messages::gateway::ResourceDescription;
+mod dns_resource_nat_refresh;
+
+use crate::messages::gateway::{RateLimit, ResourceDescription};
 use crate::messages::{Answer, IceCredentials, ResolveRequest, SecretKey};
-use crate::peer::TranslateOutboundResult;
+use crate::peer::{FlowLog, ResourceRateLimiters, TranslateOutboundResult};
 use crate::utils::earliest;
-use crate::{GatewayEvent, IpConfig, p2p_control};
+use crate::{GatewayEvent, IpConfig, ResolvedIps, p2p_control};
 use crate::{peer::ClientOnGateway, peer_store::PeerStore};
 use anyhow::{Context, Result};
 use boringtun::x25519::PublicKey;
 use chrono::{DateTime, Utc};
 use connlib_model::{ClientId, RelayId, ResourceId};
+use dns_resource_nat_refresh::DnsResourceNatRefresh;
 use dns_types::DomainName;
 use ip_packet::{FzP2pControlSlice, IpPacket};
 use secrecy::{ExposeSecret as _, Secret};
@@ -21,6 +24,12 @@ pub const TUN_DNS_PORT: u16 = 53535;
 
 const EXPIRE_RESOURCES_INTERVAL: Duration = Duration::from_secs(1);
 
+/// How long we wait before re-resolving a DNS resource NAT if we don't know the TTL of its records, e.g. after a failed resolution.
+const DEFAULT_DNS_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long we wait at least before re-resolving a DNS resource NAT, regardless of the TTL of its records.
+const MIN_DNS_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
 /// A SANS-IO implementation of a gateway's functionality.
 ///
 /// Internally, this composes a [`snownet::ServerNode`] with firezone's policy engine around resources.
@@ -37,6 +46,20 @@ pub struct GatewayState {
 
     tun_ip_config: Option<IpConfig>,
 
+    /// Whether we account the flows of our clients and emit [`GatewayEvent::FlowLog`]s for them.
+    flow_logs: bool,
+    /// The flow logs of removed clients, flushed with the next call to [`GatewayState::handle_timeout`].
+    removed_flow_logs: Vec<FlowLog>,
+    /// The rate limit applied to the traffic of each client.
+    client_rate_limit: Option<RateLimit>,
+    /// The rate limits of the resources our clients access, shared across all clients.
+    resource_rate_limiters: ResourceRateLimiters,
+    /// Whether we validate the responses to our clients' DNS queries with DNSSEC.
+    dnssec_validation: bool,
+
+    /// When to next re-resolve the domain of each DNS resource NAT.
+    dns_resource_nat_refresh: DnsResourceNatRefresh,
+
     buffered_events: VecDeque<GatewayEvent>,
     buffered_transmits: VecDeque<Transmit>,
 }
@@ -67,9 +90,61 @@ impl GatewayState {
             buffered_events: VecDeque::default(),
             buffered_transmits: VecDeque::default(),
             tun_ip_config: None,
+            flow_logs: false,
+            removed_flow_logs: Vec::default(),
+            client_rate_limit: None,
+            resource_rate_limiters: ResourceRateLimiters::default(),
+            dnssec_validation: false,
+            dns_resource_nat_refresh: Default::default(),
+        }
+    }
+
+    /// Limits the traffic of each current and future client.
+    pub fn set_client_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.client_rate_limit = limit;
+
+        for peer in self.peers.iter_mut() {
+            peer.set_rate_limit(limit);
         }
     }
 
+    /// Enables flow logs for all current and future clients.
+    pub fn enable_flow_logs(&mut self) {
+        self.flow_logs = true;
+
+        for peer in self.peers.iter_mut() {
+            peer.enable_flow_log();
+        }
+    }
+
+    /// The domain patterns exempt from DNSSEC validation for the client with the given tunnel IP, `None` if DNSSEC validation is disabled.
+    ///
+    /// DNS resources typically live in private zones that aren't signed, which is why we only validate names outside of them.
+    pub(crate) fn dnssec_exemptions(&self, client: IpAddr) -> Option<Vec<String>> {
+        if !self.dnssec_validation {
+            return None;
+        }
+
+        let exemptions = self
+            .peers
+            .peer_by_ip(client)
+            .map(|peer| {
+                peer.dns_resource_addresses()
+                    .map(ToOwned::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(exemptions)
+    }
+
+    /// Validates the responses to DNS queries forwarded to us by clients with DNSSEC.
+    ///
+    /// Bogus responses are answered with SERVFAIL.
+    pub fn enable_dnssec_validation(&mut self) {
+        self.dnssec_validation = true;
+    }
+
     #[cfg(all(test, feature = "proptest"))]
     pub(crate) fn tunnel_ip_config(&self) -> Option<IpConfig> {
         self.tun_ip_config
@@ -79,6 +154,12 @@ impl GatewayState {
         self.node.public_key()
     }
 
+    pub(crate) fn connection_stats(
+        &self,
+    ) -> impl Iterator<Item = (ClientId, snownet::ConnectionStats<RelayId>)> + '_ {
+        self.node.stats().1
+    }
+
     /// Handles packets received on the TUN device.
     pub(crate) fn handle_tun_input(
         &mut self,
@@ -98,7 +179,7 @@ impl GatewayState {
         let cid = peer.id();
 
         let Some(packet) = peer
-            .translate_inbound(packet, now)
+            .translate_inbound(packet, &mut self.resource_rate_limiters, now)
             .context("Failed to translate inbound packet")?
         else {
             return Ok(None);
@@ -159,7 +240,7 @@ impl GatewayState {
         }
 
         match peer
-            .translate_outbound(packet, now)
+            .translate_outbound(packet, &mut self.resource_rate_limiters, now)
             .context("Failed to translate outbound packet")?
         {
             TranslateOutboundResult::Send(ip_packet) => Ok(Some(ip_packet)),
@@ -172,12 +253,12 @@ impl GatewayState {
 
                 Ok(None)
             }
-            TranslateOutboundResult::Filtered => Ok(None),
+            TranslateOutboundResult::Filtered | TranslateOutboundResult::RateLimited => Ok(None),
         }
     }
 
     pub fn cleanup_connection(&mut self, id: &ClientId) {
-        self.peers.remove(id);
+        self.remove_peer(id);
     }
 
     pub fn add_ice_candidate(&mut self, conn_id: ClientId, ice_candidate: String, now: Instant) {
@@ -201,7 +282,9 @@ impl GatewayState {
 
         peer.remove_resource(resource);
         if peer.is_emptied() {
-            self.peers.remove(client);
+            self.remove_peer(client);
+        } else {
+            self.prune_resource_rate_limiters();
         }
 
         tracing::debug!("Access removed");
@@ -211,6 +294,11 @@ impl GatewayState {
         for peer in self.peers.iter_mut() {
             peer.update_resource(&resource);
         }
+
+        if self.peers.iter().any(|p| p.has_resource(&resource.id())) {
+            self.resource_rate_limiters
+                .upsert(resource.id(), resource.rate_limit());
+        }
     }
 
     /// Accept a connection request from a client.
@@ -259,7 +347,7 @@ impl GatewayState {
             now,
         )?;
 
-        let result = self.allow_access(client_id, client_tun, expires_at, resource, None);
+        let result = self.allow_access(client_id, client_tun, expires_at, resource, None, now);
         debug_assert!(
             result.is_ok(),
             "`allow_access` should never fail without a `DnsResourceEntry`"
@@ -275,6 +363,7 @@ impl GatewayState {
         expires_at: Option<DateTime<Utc>>,
         resource: ResourceDescription,
         dns_resource_nat: Option<DnsResourceNatEntry>,
+        now: Instant,
     ) -> anyhow::Result<()> {
         let gateway_tun = self.tun_ip_config.context("TUN device not configured")?;
 
@@ -283,15 +372,27 @@ impl GatewayState {
             .entry(client)
             .or_insert_with(|| ClientOnGateway::new(client, client_tun, gateway_tun));
 
+        if self.flow_logs {
+            peer.enable_flow_log();
+        }
+        peer.set_rate_limit(self.client_rate_limit);
+
         peer.add_resource(resource.clone(), expires_at);
+        self.resource_rate_limiters
+            .upsert(resource.id(), resource.rate_limit());
 
         if let Some(entry) = dns_resource_nat {
             peer.setup_nat(
-                entry.domain,
+                entry.domain.clone(),
                 resource.id(),
                 BTreeSet::from_iter(entry.resolved_ips),
                 BTreeSet::from_iter(entry.proxy_ips),
             )?;
+
+            self.dns_resource_nat_refresh.schedule(
+                (client, resource.id(), entry.domain),
+                now + DEFAULT_DNS_REFRESH_INTERVAL,
+            );
         }
 
         self.peers.add_ip(&client, &client_tun.v4.into());
@@ -300,6 +401,7 @@ impl GatewayState {
         Ok(())
     }
 
+    /// The DNS resource NATs we have set up for our clients.
     pub fn handle_domain_resolved(
         &mut self,
         req: ResolveDnsRequest,
@@ -308,6 +410,8 @@ impl GatewayState {
     ) -> anyhow::Result<()> {
         use p2p_control::dns_resource_nat;
 
+        let key = (req.client, req.resource, req.domain.clone());
+
         let nat_status = resolve_result
             .and_then(|addresses| {
                 self.peers
@@ -328,6 +432,11 @@ impl GatewayState {
                 dns_resource_nat::NatStatus::Inactive
             });
 
+        if nat_status == dns_resource_nat::NatStatus::Active {
+            self.dns_resource_nat_refresh
+                .schedule(key, now + DEFAULT_DNS_REFRESH_INTERVAL);
+        }
+
         let packet = dns_resource_nat::domain_status(req.resource, req.domain, nat_status)?;
 
         let Some(transmit) = encrypt_packet(packet, req.client, &mut self.node, now)? else {
@@ -339,9 +448,100 @@ impl GatewayState {
         Ok(())
     }
 
+    /// Updates a DNS resource NAT in place after its domain has been re-resolved as requested by [`GatewayEvent::RefreshDns`].
+    ///
+    /// Only if the resolved IPs changed, we notify the client with a `DomainStatus` event.
+    /// Failed resolutions keep the existing NAT and are retried later.
+    pub fn handle_domain_refreshed(
+        &mut self,
+        req: ResolveDnsRequest,
+        resolve_result: Result<ResolvedIps, Arc<anyhow::Error>>,
+        now: Instant,
+    ) -> anyhow::Result<()> {
+        use p2p_control::dns_resource_nat;
+
+        let key = (req.client, req.resource, req.domain.clone());
+
+        let resolved = match resolve_result {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                tracing::debug!(domain = %req.domain, "Failed to re-resolve DNS resource: {e:#}");
+
+                self.dns_resource_nat_refresh
+                    .schedule(key, now + DEFAULT_DNS_REFRESH_INTERVAL);
+
+                return Ok(());
+            }
+        };
+
+        self.dns_resource_nat_refresh
+            .schedule(key, now + resolved.ttl.max(MIN_DNS_REFRESH_INTERVAL));
+
+        let changed = self
+            .peers
+            .get_mut(&req.client)
+            .context("Unknown peer")?
+            .refresh_nat(
+                req.domain.clone(),
+                req.resource,
+                BTreeSet::from_iter(resolved.ips),
+            )?;
+
+        if !changed {
+            return Ok(());
+        }
+
+        let packet = dns_resource_nat::domain_status(
+            req.resource,
+            req.domain,
+            dns_resource_nat::NatStatus::Active,
+        )?;
+
+        let Some(transmit) = encrypt_packet(packet, req.client, &mut self.node, now)? else {
+            return Ok(());
+        };
+
+        self.buffered_transmits.push_back(transmit);
+
+        Ok(())
+    }
+
+    /// Emits a [`GatewayEvent::RefreshDns`] for each DNS resource NAT whose records are due to be re-resolved.
+    ///
+    /// NATs that have been torn down in the meantime, e.g. because the client lost access to the resource, are forgotten.
+    fn refresh_dns_resource_nats(&mut self, now: Instant) {
+        while let Some(key) = self.dns_resource_nat_refresh.pop_due(now) {
+            let (client, resource, domain) = key.clone();
+
+            let Some(proxy_ips) = self
+                .peers
+                .get(&client)
+                .map(|peer| peer.dns_resource_nat_proxy_ips(resource, &domain))
+                .filter(|proxy_ips| !proxy_ips.is_empty())
+            else {
+                continue;
+            };
+
+            // Don't refresh again until the result is in, or we time out waiting for it.
+            self.dns_resource_nat_refresh
+                .schedule(key, now + DEFAULT_DNS_REFRESH_INTERVAL);
+
+            self.buffered_events
+                .push_back(GatewayEvent::RefreshDns(ResolveDnsRequest {
+                    domain,
+                    client,
+                    resource,
+                    proxy_ips,
+                }));
+        }
+    }
+
     pub fn poll_timeout(&mut self) -> Option<Instant> {
         // TODO: This should check when the next resource actually expires instead of doing it at a fixed interval.
-        earliest(self.next_expiry_resources_check, self.node.poll_timeout())
+        earliest(
+            earliest(self.next_expiry_resources_check, self.node.poll_timeout()),
+            self.dns_resource_nat_refresh.poll_timeout(),
+        )
     }
 
     pub fn handle_timeout(&mut self, now: Instant, utc_now: DateTime<Utc>) {
@@ -352,15 +552,59 @@ impl GatewayState {
             Some(next_expiry_resources_check) if now >= next_expiry_resources_check => {
                 self.peers.iter_mut().for_each(|p| {
                     p.expire_resources(utc_now);
-                    p.handle_timeout(now)
+                    p.handle_timeout(now);
+
+                    self.buffered_events.extend(
+                        p.handle_flow_log_timeout(now, utc_now)
+                            .into_iter()
+                            .map(GatewayEvent::FlowLog),
+                    );
                 });
-                self.peers.retain(|_, p| !p.is_emptied());
+
+                let emptied = self
+                    .peers
+                    .iter()
+                    .filter(|p| p.is_emptied())
+                    .map(|p| p.id())
+                    .collect::<Vec<_>>();
+                for id in emptied {
+                    self.remove_peer(&id);
+                }
+                self.prune_resource_rate_limiters();
 
                 self.next_expiry_resources_check = Some(now + EXPIRE_RESOURCES_INTERVAL);
             }
             None => self.next_expiry_resources_check = Some(now + EXPIRE_RESOURCES_INTERVAL),
             Some(_) => {}
         }
+
+        self.refresh_dns_resource_nats(now);
+
+        self.buffered_events.extend(
+            self.removed_flow_logs
+                .drain(..)
+                .flat_map(|log| log.flush(now, utc_now))
+                .map(GatewayEvent::FlowLog),
+        );
+    }
+
+    /// Removes a client, keeping its flow log around so the flows are still reported.
+    fn remove_peer(&mut self, id: &ClientId) {
+        let Some(mut peer) = self.peers.remove(id) else {
+            return;
+        };
+
+        self.removed_flow_logs.extend(peer.take_flow_log());
+        self.dns_resource_nat_refresh.remove_client(*id);
+        self.prune_resource_rate_limiters();
+    }
+
+    /// Removes the rate limiters of resources none of our clients have access to anymore.
+    fn prune_resource_rate_limiters(&mut self) {
+        let peers = &self.peers;
+
+        self.resource_rate_limiters
+            .retain(|id| peers.iter().any(|p| p.has_resource(id)));
     }
 
     fn drain_node_events(&mut self) {
@@ -370,7 +614,7 @@ impl GatewayState {
         while let Some(event) = self.node.poll_event() {
             match event {
                 snownet::Event::ConnectionFailed(id) | snownet::Event::ConnectionClosed(id) => {
-                    self.peers.remove(&id);
+                    self.remove_peer(&id);
                 }
                 snownet::Event::NewIceCandidate {
                     connection,
@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Instant;
+
+use connlib_model::{ClientId, ResourceId};
+use dns_types::DomainName;
+
+type Key = (ClientId, ResourceId, DomainName);
+
+/// When to next re-resolve the domain of each DNS resource NAT.
+///
+/// Deadlines are ordered by time so we only ever look at the NATs that are due.
+#[derive(Debug, Default)]
+pub struct DnsResourceNatRefresh {
+    deadlines: BTreeMap<Key, Instant>,
+    queue: BTreeSet<(Instant, Key)>,
+}
+
+impl DnsResourceNatRefresh {
+    /// Schedules the next refresh of the given NAT, replacing any previous deadline.
+    pub fn schedule(&mut self, key: Key, at: Instant) {
+        if let Some(previous) = self.deadlines.insert(key.clone(), at) {
+            self.queue.remove(&(previous, key.clone()));
+        }
+
+        self.queue.insert((at, key));
+    }
+
+    /// Forgets the deadlines of all NATs of the given client.
+    pub fn remove_client(&mut self, client: ClientId) {
+        self.deadlines.retain(|(c, _, _), _| *c != client);
+        self.queue.retain(|(_, (c, _, _))| *c != client);
+    }
+
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.queue.first().map(|(at, _)| *at)
+    }
+
+    /// Removes and returns the next NAT that is due to be refreshed.
+    pub fn pop_due(&mut self, now: Instant) -> Option<Key> {
+        let (at, _) = self.queue.first()?;
+
+        if *at > now {
+            return None;
+        }
+
+        let (_, key) = self.queue.pop_first()?;
+        self.deadlines.remove(&key);
+
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn pops_due_nats_in_order() {
+        let mut refresh = DnsResourceNatRefresh::default();
+        let now = Instant::now();
+
+        refresh.schedule(key(1), now + Duration::from_secs(10));
+        refresh.schedule(key(2), now + Duration::from_secs(5));
+
+        assert_eq!(refresh.poll_timeout(), Some(now + Duration::from_secs(5)));
+        assert_eq!(refresh.pop_due(now), None);
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(refresh.pop_due(later), Some(key(2)));
+        assert_eq!(refresh.pop_due(later), Some(key(1)));
+        assert_eq!(refresh.pop_due(later), None);
+    }
+
+    #[test]
+    fn rescheduling_replaces_previous_deadline() {
+        let mut refresh = DnsResourceNatRefresh::default();
+        let now = Instant::now();
+
+        refresh.schedule(key(1), now);
+        refresh.schedule(key(1), now + Duration::from_secs(30));
+
+        assert_eq!(refresh.poll_timeout(), Some(now + Duration::from_secs(30)));
+        assert_eq!(refresh.pop_due(now), None);
+    }
+
+    #[test]
+    fn forgets_nats_of_removed_client() {
+        let mut refresh = DnsResourceNatRefresh::default();
+        let now = Instant::now();
+
+        refresh.schedule(key(1), now);
+        refresh.remove_client(ClientId::from_u128(1));
+
+        assert_eq!(refresh.poll_timeout(), None);
+    }
+
+    fn key(client: u128) -> Key {
+        (
+            ClientId::from_u128(client),
+            ResourceId::from_u128(1),
+            DomainName::vec_from_str("example.com").unwrap(),
+        )
+    }
+}
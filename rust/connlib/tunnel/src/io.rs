@@ -1,10 +1,19 @@
+mod connection_pool;
+mod dnssec;
 mod gso_queue;
+mod https_dns;
 mod nameserver_set;
+mod relay_tcp;
+mod resolver;
 mod tcp_dns;
+mod tls;
+mod tls_dns;
 mod udp_dns;
 
+use crate::messages::{DnsServer, IpDnsServer};
 use crate::{device_channel::Device, dns, otel, sockets::Sockets};
 use anyhow::{Context as _, Result};
+use dns_types::DomainName;
 use firezone_logging::{telemetry_event, telemetry_span};
 use futures::FutureExt as _;
 use futures_bounded::FuturesTupleSet;
@@ -12,10 +21,12 @@ use gat_lending_iterator::LendingIterator;
 use gso_queue::GsoQueue;
 use ip_packet::{Ecn, IpPacket, MAX_FZ_PAYLOAD};
 use nameserver_set::NameserverSet;
+use relay_tcp::RelayStreams;
+pub use resolver::ResolvedIps;
 use socket_factory::{DatagramIn, SocketFactory, TcpSocket, UdpSocket};
 use std::{
     collections::{BTreeSet, VecDeque},
-    io,
+    future, io,
     net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6},
     pin::Pin,
     sync::Arc,
@@ -46,6 +57,9 @@ pub struct Io {
     sockets: Sockets,
     gso_queue: GsoQueue,
 
+    /// TCP connections to relays that we cannot reach via UDP.
+    relay_streams: RelayStreams,
+
     nameservers: NameserverSet,
     reval_nameserver_interval: tokio::time::Interval,
 
@@ -56,6 +70,12 @@ pub struct Io {
     udp_socket_factory: Arc<dyn SocketFactory<UdpSocket>>,
 
     dns_queries: FuturesTupleSet<io::Result<dns_types::Response>, DnsQueryMetaData>,
+    /// The chains of trust established while validating DNS responses with DNSSEC.
+    dnssec_cache: Arc<parking_lot::Mutex<dnssec::TrustCache>>,
+    /// Idle connections to DNS-over-TLS servers, reused for subsequent queries.
+    tls_dns_connections: Arc<parking_lot::Mutex<tls_dns::Pool>>,
+    /// Idle connections to DNS-over-HTTPS servers, reused for subsequent queries.
+    https_dns_connections: Arc<parking_lot::Mutex<https_dns::Pool>>,
 
     timeout: Option<Pin<Box<tokio::time::Sleep>>>,
 
@@ -67,7 +87,7 @@ pub struct Io {
 #[derive(Debug)]
 struct DnsQueryMetaData {
     query: dns_types::Query,
-    server: SocketAddr,
+    server: DnsServer,
     transport: dns::Transport,
 }
 
@@ -87,6 +107,7 @@ pub enum Input<D, I> {
     Timeout(Instant),
     Device(D),
     Network(I),
+    RelayTcp(relay_tcp::Frame),
     TcpDnsQuery(l4_tcp_dns_server::Query),
     UdpDnsQuery(l4_udp_dns_server::Query),
     DnsResponse(dns::RecursiveResponse),
@@ -117,9 +138,13 @@ impl Io {
                 udp_socket_factory.clone(),
             ),
             reval_nameserver_interval: tokio::time::interval(RE_EVALUATE_NAMESERVER_INTERVAL),
+            relay_streams: RelayStreams::new(tcp_socket_factory.clone()),
             tcp_socket_factory,
             udp_socket_factory,
             dns_queries: FuturesTupleSet::new(DNS_QUERY_TIMEOUT, 1000),
+            dnssec_cache: Default::default(),
+            tls_dns_connections: Default::default(),
+            https_dns_connections: Default::default(),
             gso_queue: GsoQueue::new(),
             tun: Device::new(),
             udp_dns_server: Default::default(),
@@ -145,6 +170,11 @@ impl Io {
         Ok(())
     }
 
+    /// Replaces the DS records of the root zone we trust when validating DNS responses with DNSSEC.
+    pub fn set_dnssec_trust_anchors(&mut self, anchors: Vec<dns_types::dnssec::Ds>) {
+        self.dnssec_cache = Arc::new(parking_lot::Mutex::new(dnssec::TrustCache::new(anchors)));
+    }
+
     pub fn poll_has_sockets(&mut self, cx: &mut Context<'_>) -> Poll<()> {
         self.sockets.poll_has_sockets(cx)
     }
@@ -153,6 +183,50 @@ impl Io {
         self.nameservers.fastest()
     }
 
+    /// Resolves the given domain, trying one nameserver after the other starting with the fastest one.
+    ///
+    /// `None` if we don't know any nameservers (yet).
+    pub fn resolve(
+        &self,
+        domain: DomainName,
+    ) -> Option<impl Future<Output = Result<ResolvedIps>> + use<>> {
+        let nameservers = self.nameservers.by_preference();
+
+        if nameservers.is_empty() {
+            return None;
+        }
+
+        let udp_socket_factory = self.udp_socket_factory.clone();
+        let tcp_socket_factory = self.tcp_socket_factory.clone();
+
+        Some(
+            async move {
+                let mut last_error = None;
+
+                for nameserver in nameservers {
+                    match resolver::resolve(
+                        udp_socket_factory.clone(),
+                        tcp_socket_factory.clone(),
+                        SocketAddr::new(nameserver, dns::DNS_PORT),
+                        domain.clone(),
+                    )
+                    .await
+                    {
+                        Ok(ips) => return Ok(ips),
+                        Err(e) => {
+                            tracing::debug!(%domain, %nameserver, "Failed to resolve DNS: {e:#}");
+
+                            last_error = Some(e);
+                        }
+                    }
+                }
+
+                Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No nameservers")))
+            }
+            .instrument(telemetry_span!("resolve_dns_resource")),
+        )
+    }
+
     pub fn poll<'b>(
         &mut self,
         cx: &mut Context<'_>,
@@ -182,6 +256,10 @@ impl Io {
             )));
         }
 
+        if let Poll::Ready(frame) = self.relay_streams.poll_recv(cx) {
+            return Poll::Ready(Ok(Input::RelayTcp(frame)));
+        }
+
         if let Poll::Ready(num_packets) =
             self.tun
                 .poll_read_many(cx, &mut buffers.ip, MAX_INBOUND_PACKET_BATCH)
@@ -324,7 +402,10 @@ impl Io {
     pub fn reset(&mut self) {
         self.sockets.rebind(self.udp_socket_factory.clone());
         self.gso_queue.clear();
+        self.relay_streams.clear();
         self.dns_queries = FuturesTupleSet::new(DNS_QUERY_TIMEOUT, 1000);
+        self.tls_dns_connections.lock().clear();
+        self.https_dns_connections.lock().clear();
         self.nameservers.evaluate();
     }
 
@@ -344,16 +425,28 @@ impl Io {
         &mut self,
         src: Option<SocketAddr>,
         dst: SocketAddr,
+        transport: snownet::Transport,
         payload: &[u8],
         ecn: Ecn,
     ) {
-        self.gso_queue.enqueue(src, dst, payload, ecn);
+        let transport_attr = match transport {
+            snownet::Transport::Udp => {
+                self.gso_queue.enqueue(src, dst, payload, ecn);
+
+                otel::attr::network_transport_udp()
+            }
+            snownet::Transport::Tcp | snownet::Transport::Tls => {
+                self.relay_streams.send(dst, transport, payload);
+
+                otel::attr::network_transport_tcp()
+            }
+        };
 
         self.packet_counter.add(
             1,
             &[
                 otel::attr::network_protocol_name(payload),
-                otel::attr::network_transport_udp(),
+                transport_attr,
                 otel::attr::network_io_direction_transmit(),
             ],
         );
@@ -362,16 +455,16 @@ impl Io {
     pub fn send_dns_query(&mut self, query: dns::RecursiveQuery) {
         let meta = DnsQueryMetaData {
             query: query.message.clone(),
-            server: query.server,
+            server: query.server.clone(),
             transport: query.transport,
         };
 
-        match query.transport {
-            dns::Transport::Udp { .. } => {
+        match (query.server, query.transport) {
+            (DnsServer::IpPort(IpDnsServer { address }), dns::Transport::Udp { .. }) => {
                 if self
                     .dns_queries
                     .try_push(
-                        udp_dns::send(self.udp_socket_factory.clone(), query.server, query.message)
+                        udp_dns::send(self.udp_socket_factory.clone(), address, query.message)
                             .instrument(telemetry_span!("recursive_udp_dns_query")),
                         meta,
                     )
@@ -380,11 +473,11 @@ impl Io {
                     tracing::debug!("Failed to queue UDP DNS query")
                 }
             }
-            dns::Transport::Tcp { .. } => {
+            (DnsServer::IpPort(IpDnsServer { address }), dns::Transport::Tcp { .. }) => {
                 if self
                     .dns_queries
                     .try_push(
-                        tcp_dns::send(self.tcp_socket_factory.clone(), query.server, query.message)
+                        tcp_dns::send(self.tcp_socket_factory.clone(), address, query.message)
                             .instrument(telemetry_span!("recursive_tcp_dns_query")),
                         meta,
                     )
@@ -393,6 +486,78 @@ impl Io {
                     tracing::debug!("Failed to queue TCP DNS query")
                 }
             }
+            (DnsServer::DnsOverTls(server), _) => {
+                if self
+                    .dns_queries
+                    .try_push(
+                        tls_dns::send(
+                            self.tcp_socket_factory.clone(),
+                            server,
+                            query.message,
+                            self.tls_dns_connections.clone(),
+                        )
+                        .instrument(telemetry_span!("recursive_tls_dns_query")),
+                        meta,
+                    )
+                    .is_err()
+                {
+                    tracing::debug!("Failed to queue DNS-over-TLS query")
+                }
+            }
+            (DnsServer::DnsOverHttps(server), _) => {
+                if self
+                    .dns_queries
+                    .try_push(
+                        https_dns::send(
+                            self.tcp_socket_factory.clone(),
+                            server,
+                            query.message,
+                            self.https_dns_connections.clone(),
+                        )
+                        .instrument(telemetry_span!("recursive_https_dns_query")),
+                        meta,
+                    )
+                    .is_err()
+                {
+                    tracing::debug!("Failed to queue DNS-over-HTTPS query")
+                }
+            }
+        }
+    }
+
+    /// Sends a DNS query and validates its response with DNSSEC.
+    ///
+    /// Records owned by names matching one of the `exempt` patterns are not validated.
+    pub fn send_validated_dns_query(&mut self, query: dns::RecursiveQuery, exempt: Vec<String>) {
+        let meta = DnsQueryMetaData {
+            query: query.message.clone(),
+            server: query.server.clone(),
+            transport: query.transport,
+        };
+
+        let result = match query.server {
+            DnsServer::IpPort(IpDnsServer { address }) => self.dns_queries.try_push(
+                dnssec::send(
+                    self.udp_socket_factory.clone(),
+                    self.tcp_socket_factory.clone(),
+                    address,
+                    query.message,
+                    exempt,
+                    self.dnssec_cache.clone(),
+                )
+                .instrument(telemetry_span!("recursive_dnssec_dns_query")),
+                meta,
+            ),
+            DnsServer::DnsOverTls(_) | DnsServer::DnsOverHttps(_) => self.dns_queries.try_push(
+                future::ready(Err(io::Error::other(
+                    "DNSSEC validation is only supported for plain DNS servers",
+                ))),
+                meta,
+            ),
+        };
+
+        if result.is_err() {
+            tracing::debug!("Failed to queue DNSSEC-validated DNS query")
         }
     }
 
@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+/// How many idle connections we keep around per server.
+const MAX_IDLE_CONNECTIONS: usize = 4;
+
+/// For how long we keep an idle connection around.
+///
+/// Servers close idle connections eventually, so reusing an old connection is likely to fail anyway.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Idle connections to DNS servers, indexed by the server they are connected to.
+///
+/// Connections are taken out of the pool for the duration of a query and put back once it succeeded.
+pub struct ConnectionPool<K, C> {
+    idle: HashMap<K, Vec<(C, Instant)>>,
+}
+
+impl<K, C> Default for ConnectionPool<K, C> {
+    fn default() -> Self {
+        Self {
+            idle: HashMap::default(),
+        }
+    }
+}
+
+impl<K, C> ConnectionPool<K, C>
+where
+    K: Hash + Eq,
+{
+    /// Takes the most recently used connection to `server` out of the pool, if any.
+    pub fn take(&mut self, server: &K, now: Instant) -> Option<C> {
+        let connections = self.idle.get_mut(server)?;
+        connections.retain(|(_, idle_since)| now.duration_since(*idle_since) < IDLE_TIMEOUT);
+
+        let connection = connections.pop();
+
+        if connections.is_empty() {
+            self.idle.remove(server);
+        }
+
+        connection.map(|(c, _)| c)
+    }
+
+    /// Puts a connection to `server` back into the pool, dropping it if there are enough idle connections already.
+    pub fn put(&mut self, server: K, connection: C, now: Instant) {
+        let connections = self.idle.entry(server).or_default();
+
+        if connections.len() >= MAX_IDLE_CONNECTIONS {
+            return;
+        }
+
+        connections.push((connection, now));
+    }
+
+    pub fn clear(&mut self) {
+        self.idle.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn returns_connection_put_back_into_pool() {
+        let mut pool = ConnectionPool::default();
+        let now = Instant::now();
+
+        pool.put("server", 1, now);
+
+        assert_eq!(pool.take(&"server", now), Some(1));
+        assert_eq!(pool.take(&"server", now), None);
+    }
+
+    #[test]
+    fn forgets_connections_after_idle_timeout() {
+        let mut pool = ConnectionPool::default();
+        let now = Instant::now();
+
+        pool.put("server", 1, now);
+
+        assert_eq!(pool.take(&"server", now + IDLE_TIMEOUT), None);
+    }
+
+    #[test]
+    fn keeps_at_most_max_idle_connections_per_server() {
+        let mut pool = ConnectionPool::default();
+        let now = Instant::now();
+
+        for connection in 0..MAX_IDLE_CONNECTIONS + 1 {
+            pool.put("server", connection, now);
+        }
+        pool.put("other", 42, now);
+
+        assert_eq!(
+            std::iter::from_fn(|| pool.take(&"server", now)).count(),
+            MAX_IDLE_CONNECTIONS
+        );
+        assert_eq!(pool.take(&"other", now), Some(42));
+    }
+}
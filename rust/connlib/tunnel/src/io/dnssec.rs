@@ -0,0 +1,612 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context as _, Result, bail};
+use dns_types::{
+    DomainName, Query, RecordType, Response, ResponseCode,
+    dnssec::{self, Denial, Ds, Rrset, ZoneKeys},
+};
+use parking_lot::Mutex;
+use socket_factory::{SocketFactory, TcpSocket, UdpSocket};
+
+use super::{tcp_dns, udp_dns};
+use crate::dns;
+
+/// How many queries we send at most to validate a single response.
+const MAX_QUERIES: usize = 32;
+
+/// How long we cache a chain of trust at most, regardless of the TTLs of its DS and DNSKEY records.
+const MAX_CHAIN_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How many chains of trust we cache at most.
+const MAX_CACHED_CHAINS: usize = 1000;
+
+/// Resolves the query via the given server and validates the response with DNSSEC.
+///
+/// Records owned by names matching one of the `exempt` patterns are not validated.
+/// If validation fails, the response is replaced with SERVFAIL.
+pub async fn send(
+    udp_socket_factory: Arc<dyn SocketFactory<UdpSocket>>,
+    tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
+    server: SocketAddr,
+    query: Query,
+    exempt: Vec<String>,
+    cache: Arc<Mutex<TrustCache>>,
+) -> io::Result<Response> {
+    tracing::trace!(target: "wire::dns::recursive::dnssec", %server, domain = %query.domain());
+
+    let mut validator = Validator {
+        udp_socket_factory,
+        tcp_socket_factory,
+        server,
+        now: SystemTime::now(),
+        cache,
+        num_queries: 0,
+    };
+
+    let response = validator.exchange(query.clone().with_dnssec_ok()).await?;
+
+    match validator.validate(&query, &response, &exempt).await {
+        Ok(Security::Secure) => Ok(response.validated_for(&query, true)),
+        Ok(Security::Insecure) => Ok(response.validated_for(&query, false)),
+        Err(e) => {
+            tracing::debug!(domain = %query.domain(), "DNSSEC validation failed: {e:#}");
+
+            Ok(Response::servfail(&query))
+        }
+    }
+}
+
+struct Validator {
+    udp_socket_factory: Arc<dyn SocketFactory<UdpSocket>>,
+    tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
+    server: SocketAddr,
+    now: SystemTime,
+
+    cache: Arc<Mutex<TrustCache>>,
+    num_queries: usize,
+}
+
+/// The trust anchors and the chains of trust we have established so far, shared across queries.
+#[derive(Debug)]
+pub struct TrustCache {
+    anchors: Vec<Ds>,
+    /// Indexed by the name the chain leads to.
+    chains: BTreeMap<DomainName, CachedChain>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedChain {
+    chain: Chain,
+    expires_at: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Security {
+    Secure,
+    Insecure,
+}
+
+impl TrustCache {
+    pub fn new(anchors: Vec<Ds>) -> Self {
+        Self {
+            anchors,
+            chains: BTreeMap::default(),
+        }
+    }
+
+    fn get(&self, name: &DomainName, now: SystemTime) -> Option<CachedChain> {
+        self.chains
+            .get(name)
+            .filter(|cached| cached.expires_at > now)
+            .cloned()
+    }
+
+    fn insert(&mut self, name: DomainName, chain: CachedChain, now: SystemTime) {
+        if self.chains.len() >= MAX_CACHED_CHAINS && !self.chains.contains_key(&name) {
+            self.chains.retain(|_, cached| cached.expires_at > now);
+        }
+
+        if self.chains.len() >= MAX_CACHED_CHAINS && !self.chains.contains_key(&name) {
+            return;
+        }
+
+        self.chains.insert(name, chain);
+    }
+}
+
+impl Default for TrustCache {
+    fn default() -> Self {
+        Self::new(dnssec::root_trust_anchors())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Chain {
+    /// The name is part of a signed zone with these keys.
+    Secure(ZoneKeys),
+    /// The name is part of, or below, an unsigned delegation.
+    Insecure,
+}
+
+impl Validator {
+    async fn validate(
+        &mut self,
+        query: &Query,
+        response: &Response,
+        exempt: &[String],
+    ) -> Result<Security> {
+        let response_code = response.response_code();
+
+        // Errors like SERVFAIL or REFUSED don't carry any data we could validate.
+        if response_code != ResponseCode::NOERROR && response_code != ResponseCode::NXDOMAIN {
+            return Ok(Security::Insecure);
+        }
+
+        let is_exempt = |name: &DomainName| {
+            exempt
+                .iter()
+                .any(|pattern| dns::is_subdomain(name, pattern))
+        };
+
+        let answer = dnssec::rrsets(response.records());
+        let denial = dnssec::rrsets(response.authority_records())
+            .into_iter()
+            .filter(|r| r.rtype() == RecordType::NSEC || r.rtype() == RecordType::NSEC3)
+            .collect::<Vec<_>>();
+
+        let mut security = Security::Secure;
+
+        for rrset in &answer {
+            if is_exempt(rrset.owner()) {
+                security = Security::Insecure;
+                continue;
+            }
+
+            if is_synthesized_from_dname(rrset, &answer) {
+                continue; // The DNAME is validated on its own.
+            }
+
+            match self.verify(rrset).await? {
+                Verified::Secure {
+                    next_closer: None, ..
+                } => {}
+                Verified::Secure {
+                    keys,
+                    next_closer: Some(next_closer),
+                } => {
+                    self.verify_denial(&denial, &keys)?;
+
+                    if dnssec::prove_wildcard_expansion(&next_closer, &denial)? == Denial::Insecure
+                    {
+                        security = Security::Insecure;
+                    }
+                }
+                Verified::Insecure => security = Security::Insecure,
+            }
+        }
+
+        // Follow the CNAME chain to the name the answer is actually for.
+        let mut name = dnssec::canonical(&query.domain());
+
+        for _ in 0..answer.len() {
+            let Some(target) = answer
+                .iter()
+                .find(|r| r.owner() == &name && r.rtype() == RecordType::CNAME)
+                .and_then(|r| r.cname_target())
+            else {
+                break;
+            };
+
+            name = target;
+        }
+
+        if query.qtype() == RecordType::CNAME
+            || answer
+                .iter()
+                .any(|r| r.owner() == &name && r.rtype() == query.qtype())
+        {
+            return Ok(security);
+        }
+
+        // The response denies the existence of the name or type, which needs to be proven as well.
+        if is_exempt(&name) {
+            return Ok(Security::Insecure);
+        }
+
+        // Only the zone of the denied name can prove its absence, regardless of who signed the proof.
+        let keys = match self.chain(&name).await? {
+            Chain::Insecure => return Ok(Security::Insecure),
+            Chain::Secure(keys) => keys,
+        };
+
+        if denial.is_empty() {
+            bail!("Missing proof of non-existence for {name}");
+        }
+
+        self.verify_denial(&denial, &keys)?;
+
+        match dnssec::prove_denial(&name, query.qtype(), response_code, &denial)? {
+            Denial::Secure { .. } => Ok(security),
+            Denial::Insecure => Ok(Security::Insecure),
+        }
+    }
+
+    async fn verify(&mut self, rrset: &Rrset) -> Result<Verified> {
+        let Some(signer) = rrset.signer() else {
+            return match self.chain(rrset.owner()).await? {
+                Chain::Insecure => Ok(Verified::Insecure),
+                Chain::Secure(_) => {
+                    bail!("Missing signature for {} {}", rrset.owner(), rrset.rtype())
+                }
+            };
+        };
+
+        // The signer is attacker-controlled: The zone the RRset belongs to decides whether it has to be secure.
+        // Only then do we check that this zone is also the one that signed it.
+        let authority = rrset
+            .authority()
+            .with_context(|| format!("{signer} cannot sign {} {}", rrset.owner(), rrset.rtype()))?;
+
+        match self.chain(&authority).await? {
+            Chain::Insecure => Ok(Verified::Insecure),
+            Chain::Secure(keys) => {
+                if keys.zone() != signer {
+                    bail!(
+                        "{} {} must be signed by {}, not {signer}",
+                        rrset.owner(),
+                        rrset.rtype(),
+                        keys.zone()
+                    );
+                }
+
+                let next_closer = rrset.verify(&keys, self.now).with_context(|| {
+                    format!("Failed to verify {} {}", rrset.owner(), rrset.rtype())
+                })?;
+
+                Ok(Verified::Secure { keys, next_closer })
+            }
+        }
+    }
+
+    /// Verifies that the proof of non-existence is signed by the zone of the given keys.
+    fn verify_denial(&self, denial: &[Rrset], keys: &ZoneKeys) -> Result<()> {
+        for rrset in denial {
+            rrset
+                .verify(keys, self.now)
+                .with_context(|| format!("Failed to verify {} {}", rrset.owner(), rrset.rtype()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Establishes the chain of trust from the root down to the zone of the given name.
+    async fn chain(&mut self, name: &DomainName) -> Result<Chain> {
+        let ancestors = dnssec::ancestors(name);
+
+        // Continue from the closest ancestor we already have a chain for.
+        let cached = {
+            let cache = self.cache.lock();
+
+            ancestors
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(i, ancestor)| Some((i + 1, cache.get(ancestor, self.now)?)))
+        };
+
+        let (start, mut cached) = match cached {
+            Some(cached) => cached,
+            None => (1, self.root_chain().await?),
+        };
+
+        for ancestor in &ancestors[start..] {
+            let Chain::Secure(keys) = &cached.chain else {
+                break;
+            };
+
+            let (chain, ttl) = self.delegate(keys, ancestor).await?;
+
+            // A chain is only valid as long as all of its links are.
+            cached = CachedChain {
+                chain,
+                expires_at: cached.expires_at.min(self.now + ttl.min(MAX_CHAIN_TTL)),
+            };
+            self.cache
+                .lock()
+                .insert(ancestor.clone(), cached.clone(), self.now);
+        }
+
+        Ok(cached.chain)
+    }
+
+    async fn root_chain(&mut self) -> Result<CachedChain> {
+        let root = dnssec::root();
+        let anchors = self.cache.lock().anchors.clone();
+        let dnskeys = self.dnskeys(&root).await?;
+        let keys = ZoneKeys::new(&dnskeys, &anchors, self.now)
+            .context("Failed to authenticate root keys")?;
+
+        let cached = CachedChain {
+            chain: Chain::Secure(keys),
+            expires_at: self.now + dnskeys.ttl(self.now).min(MAX_CHAIN_TTL),
+        };
+        self.cache.lock().insert(root, cached.clone(), self.now);
+
+        Ok(cached)
+    }
+
+    /// Follows the delegation from the zone of the given keys to `child`.
+    ///
+    /// Returns the chain to `child` and for how long it may be cached.
+    async fn delegate(&mut self, keys: &ZoneKeys, child: &DomainName) -> Result<(Chain, Duration)> {
+        let response = self
+            .exchange(Query::new(child.clone(), RecordType::DS).with_dnssec_ok())
+            .await?;
+
+        if let Some(ds) = dnssec::rrsets(response.records())
+            .into_iter()
+            .find(|r| r.owner() == child && r.rtype() == RecordType::DS)
+        {
+            ds.verify(keys, self.now)
+                .with_context(|| format!("Failed to verify DS of {child}"))?;
+
+            let dnskeys = self.dnskeys(child).await?;
+            let ttl = ds.ttl(self.now).min(dnskeys.ttl(self.now));
+
+            return match ZoneKeys::new(&dnskeys, &ds.ds(), self.now) {
+                Ok(keys) => Ok((Chain::Secure(keys), ttl)),
+                Err(dnssec::Error::UnsupportedAlgorithm) => Ok((Chain::Insecure, ttl)),
+                Err(e) => {
+                    Err(anyhow::Error::new(e).context(format!("Failed to authenticate {child}")))
+                }
+            };
+        }
+
+        let denial_rrsets = dnssec::rrsets(response.authority_records())
+            .into_iter()
+            .filter(|r| r.rtype() == RecordType::NSEC || r.rtype() == RecordType::NSEC3)
+            .collect::<Vec<_>>();
+
+        for rrset in &denial_rrsets {
+            rrset
+                .verify(keys, self.now)
+                .with_context(|| format!("Failed to verify {} {}", rrset.owner(), rrset.rtype()))?;
+        }
+
+        let denial = dnssec::prove_denial(
+            child,
+            RecordType::DS,
+            response.response_code(),
+            &denial_rrsets,
+        )
+        .with_context(|| format!("Failed to prove absence of DS for {child}"))?;
+
+        let ttl = denial_rrsets
+            .iter()
+            .map(|rrset| rrset.ttl(self.now))
+            .fold(MAX_CHAIN_TTL, Duration::min);
+
+        match denial {
+            Denial::Secure { delegation: false } => Ok((Chain::Secure(keys.clone()), ttl)),
+            Denial::Secure { delegation: true } | Denial::Insecure => Ok((Chain::Insecure, ttl)),
+        }
+    }
+
+    async fn dnskeys(&mut self, zone: &DomainName) -> Result<Rrset> {
+        let response = self
+            .exchange(Query::new(zone.clone(), RecordType::DNSKEY).with_dnssec_ok())
+            .await?;
+
+        dnssec::rrsets(response.records())
+            .into_iter()
+            .find(|r| r.owner() == zone && r.rtype() == RecordType::DNSKEY)
+            .with_context(|| format!("Missing DNSKEY for {zone}"))
+    }
+
+    async fn exchange(&mut self, query: Query) -> io::Result<Response> {
+        self.num_queries += 1;
+
+        if self.num_queries > MAX_QUERIES {
+            return Err(io::Error::other("Too many queries to validate response"));
+        }
+
+        let response =
+            udp_dns::send(self.udp_socket_factory.clone(), self.server, query.clone()).await?;
+
+        if !response.truncated() {
+            return Ok(response);
+        }
+
+        tcp_dns::send(self.tcp_socket_factory.clone(), self.server, query).await
+    }
+}
+
+#[derive(Debug)]
+enum Verified {
+    /// The RRset is signed by a zone we have a chain of trust to.
+    ///
+    /// `next_closer` is set if the RRset was synthesized from a wildcard.
+    Secure {
+        keys: ZoneKeys,
+        next_closer: Option<DomainName>,
+    },
+    /// The RRset is part of an unsigned zone.
+    Insecure,
+}
+
+/// Whether the given RRset is an unsigned CNAME synthesized from a DNAME in the same answer, see RFC 6672, Section 5.3.1.
+///
+/// The CNAME's target must be exactly what the DNAME substitutes its owner with.
+fn is_synthesized_from_dname(rrset: &Rrset, answer: &[Rrset]) -> bool {
+    if rrset.rtype() != RecordType::CNAME || rrset.is_signed() {
+        return false;
+    }
+
+    let Some(target) = rrset.cname_target() else {
+        return false;
+    };
+    let target = dnssec::canonical(&target);
+
+    answer.iter().any(|r| {
+        r.dname_target()
+            .and_then(|dname| dnssec::substitute_dname(rrset.owner(), r.owner(), &dname))
+            .is_some_and(|substituted| substituted == target)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use dns_types::{ResponseBuilder, records};
+
+    use super::*;
+
+    #[test]
+    fn cname_matching_dname_substitution_is_synthesized() {
+        let answer = answer([
+            (name("example.com"), records::dname(name("example.net"))),
+            (
+                name("www.example.com"),
+                records::cname(name("www.example.net")),
+            ),
+        ]);
+        let cname = find(&answer, RecordType::CNAME);
+
+        assert!(is_synthesized_from_dname(cname, &answer));
+    }
+
+    #[test]
+    fn cname_not_matching_dname_substitution_is_not_synthesized() {
+        let answer = answer([
+            (name("example.com"), records::dname(name("example.net"))),
+            (
+                name("www.example.com"),
+                records::cname(name("evil.example.org")),
+            ),
+        ]);
+        let cname = find(&answer, RecordType::CNAME);
+
+        assert!(!is_synthesized_from_dname(cname, &answer));
+    }
+
+    #[test]
+    fn cname_outside_of_dname_is_not_synthesized() {
+        let answer = answer([
+            (name("example.com"), records::dname(name("example.net"))),
+            (
+                name("www.example.org"),
+                records::cname(name("www.example.net")),
+            ),
+        ]);
+        let cname = find(&answer, RecordType::CNAME);
+
+        assert!(!is_synthesized_from_dname(cname, &answer));
+    }
+
+    #[test]
+    fn cached_chain_expires() {
+        let now = SystemTime::now();
+        let mut cache = TrustCache::default();
+
+        cache.insert(
+            name("example.com"),
+            CachedChain {
+                chain: Chain::Insecure,
+                expires_at: now + Duration::from_secs(60),
+            },
+            now,
+        );
+
+        assert!(cache.get(&name("example.com"), now).is_some());
+        assert!(
+            cache
+                .get(&name("example.com"), now + Duration::from_secs(60))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn full_cache_evicts_expired_chains() {
+        let now = SystemTime::now();
+        let mut cache = TrustCache::default();
+
+        for i in 0..MAX_CACHED_CHAINS {
+            cache.insert(
+                name(&format!("{i}.example.com")),
+                CachedChain {
+                    chain: Chain::Insecure,
+                    expires_at: now + Duration::from_secs(60),
+                },
+                now,
+            );
+        }
+
+        let later = now + Duration::from_secs(120);
+        cache.insert(
+            name("example.com"),
+            CachedChain {
+                chain: Chain::Insecure,
+                expires_at: later + Duration::from_secs(60),
+            },
+            later,
+        );
+
+        assert!(cache.get(&name("example.com"), later).is_some());
+        assert_eq!(cache.chains.len(), 1);
+    }
+
+    #[test]
+    fn full_cache_does_not_evict_valid_chains() {
+        let now = SystemTime::now();
+        let mut cache = TrustCache::default();
+
+        for i in 0..MAX_CACHED_CHAINS {
+            cache.insert(
+                name(&format!("{i}.example.com")),
+                CachedChain {
+                    chain: Chain::Insecure,
+                    expires_at: now + Duration::from_secs(60),
+                },
+                now,
+            );
+        }
+
+        cache.insert(
+            name("example.com"),
+            CachedChain {
+                chain: Chain::Insecure,
+                expires_at: now + Duration::from_secs(60),
+            },
+            now,
+        );
+
+        assert!(cache.get(&name("example.com"), now).is_none());
+        assert_eq!(cache.chains.len(), MAX_CACHED_CHAINS);
+    }
+
+    fn name(name: &str) -> DomainName {
+        dnssec::canonical(&DomainName::vec_from_str(name).unwrap())
+    }
+
+    fn answer<const N: usize>(
+        records: [(DomainName, dns_types::OwnedRecordData); N],
+    ) -> Vec<Rrset> {
+        let response = ResponseBuilder::for_query(
+            &Query::new(name("www.example.com"), RecordType::A),
+            ResponseCode::NOERROR,
+        )
+        .with_records(records.into_iter().map(|(owner, data)| (owner, 300, data)))
+        .build();
+
+        dnssec::rrsets(response.records())
+    }
+
+    fn find(answer: &[Rrset], rtype: RecordType) -> &Rrset {
+        answer.iter().find(|r| r.rtype() == rtype).unwrap()
+    }
+}
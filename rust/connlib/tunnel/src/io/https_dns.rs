@@ -0,0 +1,138 @@
+use std::{io, pin::Pin, sync::Arc, time::Instant};
+
+use bytes::Bytes;
+use futures::future::{self, Either};
+use http_body_util::{BodyExt as _, Full, Limited};
+use hyper::{StatusCode, client::conn::http1, header};
+use hyper_util::rt::TokioIo;
+use parking_lot::Mutex;
+use socket_factory::{SocketFactory, TcpSocket, TcpStream};
+use tokio_rustls::client::TlsStream;
+
+use crate::messages::HttpsDnsServer;
+
+use super::{connection_pool::ConnectionPool, tls};
+
+const ALPN: &[u8] = b"http/1.1";
+
+/// The media type of DNS messages in requests and responses, see RFC 8484.
+const DNS_MESSAGE: &str = "application/dns-message";
+
+pub type Pool = ConnectionPool<HttpsDnsServer, Connection>;
+
+/// An HTTP/1.1 connection to a DNS-over-HTTPS server.
+pub struct Connection {
+    sender: http1::SendRequest<Full<Bytes>>,
+    /// Needs to be polled for requests on the connection to make progress.
+    driver: Pin<Box<http1::Connection<TokioIo<TlsStream<TcpStream>>, Full<Bytes>>>>,
+}
+
+pub async fn send(
+    factory: Arc<dyn SocketFactory<TcpSocket>>,
+    server: HttpsDnsServer,
+    query: dns_types::Query,
+    pool: Arc<Mutex<Pool>>,
+) -> io::Result<dns_types::Response> {
+    tracing::trace!(target: "wire::dns::recursive::https", url = %server.url, address = %server.address, domain = %query.domain());
+
+    let url = &server.url;
+
+    if url.scheme() != "https" {
+        return Err(io::Error::other(format!("Not an HTTPS URL: {url}")));
+    }
+
+    let host = url
+        .host_str()
+        .ok_or_else(|| io::Error::other(format!("URL has no host: {url}")))?;
+
+    let query = Bytes::from(query.into_bytes());
+    let idle_connection = pool.lock().take(&server, Instant::now());
+
+    // The server may have closed an idle connection in the meantime, in which case we fall back to a new one.
+    if let Some(connection) = idle_connection.filter(|c| !c.sender.is_closed()) {
+        match exchange(&server, connection, query.clone()).await {
+            Ok((response, connection)) => {
+                pool.lock().put(server, connection, Instant::now());
+
+                return Ok(response);
+            }
+            Err(e) => {
+                tracing::debug!(url = %server.url, "Failed to reuse DNS-over-HTTPS connection: {e}")
+            }
+        }
+    }
+
+    let tls_stream = tls::connect(factory, server.socket(), host, ALPN).await?;
+
+    let (sender, driver) = http1::handshake(TokioIo::new(tls_stream))
+        .await
+        .map_err(io::Error::other)?;
+    let connection = Connection {
+        sender,
+        driver: Box::pin(driver),
+    };
+
+    let (response, connection) = exchange(&server, connection, query).await?;
+    pool.lock().put(server, connection, Instant::now());
+
+    Ok(response)
+}
+
+/// Sends `query` on the given connection, returning the connection once the response has been read in full.
+async fn exchange(
+    server: &HttpsDnsServer,
+    connection: Connection,
+    query: Bytes,
+) -> io::Result<(dns_types::Response, Connection)> {
+    let url = &server.url;
+    let Connection {
+        mut sender,
+        mut driver,
+    } = connection;
+
+    let request = hyper::Request::post(&url[url::Position::BeforePath..])
+        .header(
+            header::HOST,
+            &url[url::Position::BeforeHost..url::Position::AfterPort],
+        )
+        .header(header::CONTENT_TYPE, DNS_MESSAGE)
+        .header(header::ACCEPT, DNS_MESSAGE)
+        .body(Full::new(query))
+        .map_err(io::Error::other)?;
+
+    let exchange = async {
+        sender.ready().await.map_err(io::Error::other)?;
+
+        let response = sender
+            .send_request(request)
+            .await
+            .map_err(io::Error::other)?;
+
+        if response.status() != StatusCode::OK {
+            return Err(io::Error::other(format!(
+                "DNS-over-HTTPS server responded with {}",
+                response.status()
+            )));
+        }
+
+        // DNS messages are at most 64 KiB.
+        let body = Limited::new(response.into_body(), u16::MAX as usize)
+            .collect()
+            .await
+            .map_err(io::Error::other)?
+            .to_bytes();
+
+        dns_types::Response::parse(&body).map_err(io::Error::other)
+    };
+
+    // The connection needs to be polled for the exchange to make progress.
+    // It only completes on its own if the server closes it.
+    let result = match future::select(Box::pin(exchange), &mut driver).await {
+        Either::Left((result, _)) => result,
+        Either::Right((Ok(()), _)) => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+        Either::Right((Err(e), _)) => Err(io::Error::other(e)),
+    };
+    let response = result?;
+
+    Ok((response, Connection { sender, driver }))
+}
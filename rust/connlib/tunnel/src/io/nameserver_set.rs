@@ -98,6 +98,24 @@ impl NameserverSet {
         Some(*ns)
     }
 
+    /// All nameservers, the ones that responded ordered by their RTT first, followed by the remaining ones.
+    pub fn by_preference(&self) -> Vec<IpAddr> {
+        let mut nameservers = Vec::with_capacity(self.inner.len());
+
+        for ns in self
+            .nameserver_by_rtt
+            .values()
+            .chain(self.inner.iter())
+            .copied()
+        {
+            if !nameservers.contains(&ns) {
+                nameservers.push(ns);
+            }
+        }
+
+        nameservers
+    }
+
     pub fn poll(&mut self, cx: &mut Context<'_>) -> Poll<()> {
         if self.queries.is_empty() {
             return Poll::Ready(());
@@ -189,4 +207,32 @@ mod tests {
 
         assert!(set.fastest().is_none());
     }
+
+    #[test]
+    fn prefers_fastest_nameservers_then_remaining_ones() {
+        let mut set = NameserverSet::new(
+            BTreeSet::from([
+                Ipv4Addr::new(1, 1, 1, 1).into(),
+                Ipv4Addr::new(8, 8, 8, 8).into(),
+                Ipv4Addr::new(9, 9, 9, 9).into(),
+            ]),
+            Arc::new(socket_factory::tcp),
+            Arc::new(socket_factory::udp),
+        );
+        set.nameserver_by_rtt
+            .insert(Duration::from_millis(10), Ipv4Addr::new(9, 9, 9, 9).into());
+        set.nameserver_by_rtt
+            .insert(Duration::from_millis(20), Ipv4Addr::new(8, 8, 8, 8).into());
+        set.nameserver_by_rtt
+            .insert(Duration::from_millis(30), Ipv4Addr::new(9, 9, 9, 9).into());
+
+        assert_eq!(
+            set.by_preference(),
+            vec![
+                IpAddr::from(Ipv4Addr::new(9, 9, 9, 9)),
+                IpAddr::from(Ipv4Addr::new(8, 8, 8, 8)),
+                IpAddr::from(Ipv4Addr::new(1, 1, 1, 1)),
+            ]
+        );
+    }
 }
@@ -0,0 +1,331 @@
+//! TURN over TCP and TLS connections to relays.
+//!
+//! If a relay doesn't answer via UDP, `snownet` falls back to talking to it via TCP and eventually via TLS.
+//! The messages are the same (STUN and channel-data) but a stream doesn't preserve message boundaries.
+//! Channel-data messages are therefore padded to a multiple of 4 bytes, see <https://www.rfc-editor.org/rfc/rfc8656#section-12.5>.
+//! STUN messages are always a multiple of 4 bytes long.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf as _, BytesMut};
+use futures::{FutureExt as _, future::BoxFuture};
+use snownet::Transport;
+use socket_factory::{SocketFactory, TcpSocket};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::tls;
+
+/// How many bytes we buffer for a single relay before we start dropping packets.
+const MAX_OUTBOUND_BUFFER: usize = 1024 * 1024;
+
+/// After how long without sending anything we close the connection to a relay.
+///
+/// An allocation sends a BINDING request every 25s so connections are only idle for this long once their allocation is gone.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The connections to all relays that we talk to via TCP or TLS.
+///
+/// Connections are made lazily upon the first packet we send to a relay.
+/// If a connection fails or is idle for [`IDLE_TIMEOUT`], it is discarded and the next packet will make a new one.
+pub struct RelayStreams {
+    tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
+
+    connections: HashMap<SocketAddr, Connection>,
+    idle_check: tokio::time::Interval,
+}
+
+/// A STUN or channel-data message received from a relay.
+pub struct Frame {
+    pub local: SocketAddr,
+    pub from: SocketAddr,
+    pub packet: BytesMut,
+}
+
+struct Connection {
+    transport: Transport,
+    last_sent: Instant,
+    state: State,
+}
+
+enum State {
+    Connecting {
+        connect: BoxFuture<'static, io::Result<(Box<dyn Stream>, SocketAddr)>>,
+        outbound: BytesMut,
+    },
+    Connected {
+        stream: Box<dyn Stream>,
+        local: SocketAddr,
+        inbound: BytesMut,
+        outbound: BytesMut,
+    },
+}
+
+trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> Stream for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl RelayStreams {
+    pub fn new(tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>) -> Self {
+        Self {
+            tcp_socket_factory,
+            connections: Default::default(),
+            idle_check: tokio::time::interval(IDLE_TIMEOUT / 2),
+        }
+    }
+
+    /// Queues a STUN or channel-data message to be sent to the given relay via TCP or TLS.
+    ///
+    /// If we are already connected to the relay via the other transport, that connection is replaced.
+    pub fn send(&mut self, relay: SocketAddr, transport: Transport, payload: &[u8]) {
+        let now = Instant::now();
+
+        if self
+            .connections
+            .get(&relay)
+            .is_some_and(|c| c.transport != transport)
+        {
+            tracing::debug!(%relay, ?transport, "Switching transport of relay connection");
+
+            self.connections.remove(&relay);
+        }
+
+        let connection = self.connections.entry(relay).or_insert_with(|| {
+            tracing::debug!(%relay, ?transport, "Connecting to relay");
+
+            Connection {
+                transport,
+                last_sent: now,
+                state: State::Connecting {
+                    connect: connect(self.tcp_socket_factory.clone(), relay, transport).boxed(),
+                    outbound: BytesMut::new(),
+                },
+            }
+        });
+
+        let outbound = match &mut connection.state {
+            State::Connecting { outbound, .. } | State::Connected { outbound, .. } => outbound,
+        };
+
+        if outbound.len() + payload.len() > MAX_OUTBOUND_BUFFER {
+            tracing::debug!(%relay, "Dropping packet because relay connection is congested");
+            return;
+        }
+
+        outbound.extend_from_slice(payload);
+        outbound.resize(outbound.len().next_multiple_of(4), 0);
+        connection.last_sent = now;
+    }
+
+    /// Drives all connections, returning the next message received from a relay.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Frame> {
+        if self.idle_check.poll_tick(cx).is_ready() {
+            let now = Instant::now();
+
+            self.connections.retain(|relay, connection| {
+                let idle = now.duration_since(connection.last_sent) >= IDLE_TIMEOUT;
+
+                if idle {
+                    tracing::debug!(%relay, "Closing idle relay connection");
+                }
+
+                !idle
+            });
+        }
+
+        let mut failed = Vec::new();
+        let mut frame = None;
+
+        for (relay, connection) in self.connections.iter_mut() {
+            match connection.state.poll(cx) {
+                Poll::Ready(Ok(packet)) => {
+                    frame = Some((*relay, packet));
+                    break;
+                }
+                Poll::Ready(Err(e)) => {
+                    tracing::debug!(%relay, transport = ?connection.transport, "Connection to relay failed: {e}");
+                    failed.push(*relay);
+                }
+                Poll::Pending => {}
+            }
+        }
+
+        for relay in failed {
+            self.connections.remove(&relay);
+        }
+
+        let Some((from, (local, packet))) = frame else {
+            return Poll::Pending;
+        };
+
+        Poll::Ready(Frame {
+            local,
+            from,
+            packet,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.connections.clear();
+    }
+}
+
+impl State {
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(SocketAddr, BytesMut)>> {
+        if let State::Connecting { connect, outbound } = self {
+            let (stream, local) = match connect.poll_unpin(cx) {
+                Poll::Ready(result) => result?,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            *self = State::Connected {
+                stream,
+                local,
+                inbound: BytesMut::new(),
+                outbound: std::mem::take(outbound),
+            };
+        }
+
+        let State::Connected {
+            stream,
+            local,
+            inbound,
+            outbound,
+        } = self
+        else {
+            unreachable!("we just connected")
+        };
+
+        while !outbound.is_empty() {
+            match Pin::new(&mut *stream).poll_write(cx, outbound) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(num_written)) => outbound.advance(num_written),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => break,
+            }
+        }
+
+        if outbound.is_empty() {
+            // TLS buffers written data internally, so make sure it actually goes out.
+            if let Poll::Ready(Err(e)) = Pin::new(&mut *stream).poll_flush(cx) {
+                return Poll::Ready(Err(e));
+            }
+        }
+
+        loop {
+            if let Some(packet) = decode_frame(inbound)? {
+                return Poll::Ready(Ok((*local, packet)));
+            }
+
+            let mut buf = [0u8; 4096];
+            let mut read_buf = ReadBuf::new(&mut buf);
+
+            match Pin::new(&mut *stream).poll_read(cx, &mut read_buf) {
+                Poll::Ready(Ok(())) if read_buf.filled().is_empty() => {
+                    return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                }
+                Poll::Ready(Ok(())) => inbound.extend_from_slice(read_buf.filled()),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+async fn connect(
+    factory: Arc<dyn SocketFactory<TcpSocket>>,
+    relay: SocketAddr,
+    transport: Transport,
+) -> io::Result<(Box<dyn Stream>, SocketAddr)> {
+    match transport {
+        Transport::Tcp => {
+            let tcp_socket = factory(&relay)?;
+            let tcp_stream = tcp_socket.connect(relay).await?;
+            let local = tcp_stream.local_addr()?;
+
+            Ok((Box::new(tcp_stream), local))
+        }
+        Transport::Tls => {
+            let server = SocketAddr::new(relay.ip(), snownet::RELAY_TLS_PORT);
+            let tls_stream = tls::connect_unverified(factory, server).await?;
+            let local = tls_stream.get_ref().0.local_addr()?;
+
+            Ok((Box::new(tls_stream), local))
+        }
+        Transport::Udp => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Cannot make a stream connection via UDP",
+        )),
+    }
+}
+
+/// Splits the next complete message off the front of the buffer, stripping any padding.
+fn decode_frame(buffer: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+    let Some(header) = buffer.first_chunk::<4>() else {
+        return Ok(None);
+    };
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+
+    // The first two bits tell STUN messages (0b00) and channel-data messages (0b01) apart.
+    let message_len = match header[0] >> 6 {
+        0b00 => 20 + length,
+        0b01 => 4 + length,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Relay sent neither a STUN nor a channel-data message",
+            ));
+        }
+    };
+    let frame_len = message_len.next_multiple_of(4);
+
+    if buffer.len() < frame_len {
+        return Ok(None);
+    }
+
+    let mut frame = buffer.split_to(frame_len);
+    frame.truncate(message_len);
+
+    Ok(Some(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_padded_channel_data_messages() {
+        let mut buffer = BytesMut::from(
+            &[
+                // Channel-data with 3 bytes of padding.
+                0x40, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5, 0, 0, 0,
+                // Incomplete channel-data message.
+                0x40, 0x01, 0x00, 0x04, 6, 7,
+            ][..],
+        );
+
+        let frame = decode_frame(&mut buffer).unwrap().unwrap();
+        assert_eq!(&frame[..], [0x40, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5]);
+
+        assert!(decode_frame(&mut buffer).unwrap().is_none());
+
+        buffer.extend_from_slice(&[8, 9]);
+        let frame = decode_frame(&mut buffer).unwrap().unwrap();
+        assert_eq!(&frame[..], [0x40, 0x01, 0x00, 0x04, 6, 7, 8, 9]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_messages() {
+        let mut buffer = BytesMut::from(&[0x80, 0x00, 0x00, 0x00][..]);
+
+        assert!(decode_frame(&mut buffer).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use dns_types::{DomainName, Query, RecordData, RecordType, Response, ResponseCode};
+use socket_factory::{SocketFactory, TcpSocket, UdpSocket};
+
+use super::{tcp_dns, udp_dns};
+
+/// The IPs a domain resolved to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedIps {
+    pub ips: Vec<IpAddr>,
+    /// For how long the IPs may be cached, i.e. the smallest TTL of the records.
+    pub ttl: Duration,
+}
+
+/// Resolves the A and AAAA records of the given domain via the given nameserver.
+///
+/// Like `getaddrinfo`, this succeeds as long as one of the two queries succeeds.
+pub async fn resolve(
+    udp_socket_factory: Arc<dyn SocketFactory<UdpSocket>>,
+    tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
+    server: SocketAddr,
+    domain: DomainName,
+) -> Result<ResolvedIps> {
+    tracing::debug!(%domain, %server, "Resolving DNS");
+
+    let (a, aaaa) = futures::future::join(
+        exchange(
+            udp_socket_factory.clone(),
+            tcp_socket_factory.clone(),
+            server,
+            Query::new(domain.clone(), RecordType::A),
+        ),
+        exchange(
+            udp_socket_factory,
+            tcp_socket_factory,
+            server,
+            Query::new(domain.clone(), RecordType::AAAA),
+        ),
+    )
+    .await;
+
+    resolved_ips(&domain, a, aaaa)
+}
+
+/// Combines the responses of the A and AAAA queries for `domain`.
+fn resolved_ips(
+    domain: &DomainName,
+    a: Result<Response>,
+    aaaa: Result<Response>,
+) -> Result<ResolvedIps> {
+    let responses = match (a, aaaa) {
+        (Ok(a), Ok(aaaa)) => vec![a, aaaa],
+        (Ok(response), Err(e)) | (Err(e), Ok(response)) => {
+            tracing::debug!(%domain, "DNS query failed: {e:#}");
+
+            vec![response]
+        }
+        (Err(e), Err(_)) => return Err(e),
+    };
+
+    let ips = responses
+        .iter()
+        .flat_map(|r| r.records())
+        .filter_map(|record| {
+            #[expect(clippy::wildcard_enum_match_arm)]
+            match record.data() {
+                RecordData::A(a) => Some(IpAddr::from(a.addr())),
+                RecordData::Aaaa(aaaa) => Some(IpAddr::from(aaaa.addr())),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    anyhow::ensure!(!ips.is_empty(), "{domain} has no A or AAAA records");
+
+    let ttl = responses
+        .iter()
+        .filter_map(|r| r.ttl())
+        .min()
+        .unwrap_or_default();
+
+    Ok(ResolvedIps { ips, ttl })
+}
+
+async fn exchange(
+    udp_socket_factory: Arc<dyn SocketFactory<UdpSocket>>,
+    tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
+    server: SocketAddr,
+    query: Query,
+) -> Result<Response> {
+    let qtype = query.qtype();
+
+    let response = send(udp_socket_factory, tcp_socket_factory, server, query)
+        .await
+        .with_context(|| format!("Failed to send {qtype} query to {server}"))?;
+
+    anyhow::ensure!(
+        response.response_code() == ResponseCode::NOERROR,
+        "{qtype} query failed with {}",
+        response.response_code()
+    );
+
+    Ok(response)
+}
+
+async fn send(
+    udp_socket_factory: Arc<dyn SocketFactory<UdpSocket>>,
+    tcp_socket_factory: Arc<dyn SocketFactory<TcpSocket>>,
+    server: SocketAddr,
+    query: Query,
+) -> io::Result<Response> {
+    let response = udp_dns::send(udp_socket_factory, server, query.clone()).await?;
+
+    if !response.truncated() {
+        return Ok(response);
+    }
+
+    tcp_dns::send(tcp_socket_factory, server, query).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use dns_types::{ResponseBuilder, records};
+
+    use super::*;
+
+    #[test]
+    fn combines_a_and_aaaa_records() {
+        let domain = domain("example.com");
+
+        let resolved = resolved_ips(
+            &domain,
+            Ok(response(
+                &domain,
+                RecordType::A,
+                [(60, records::a(Ipv4Addr::LOCALHOST))],
+            )),
+            Ok(response(
+                &domain,
+                RecordType::AAAA,
+                [(60, records::aaaa(Ipv6Addr::LOCALHOST))],
+            )),
+        )
+        .unwrap();
+
+        assert_eq!(
+            resolved.ips,
+            vec![
+                IpAddr::from(Ipv4Addr::LOCALHOST),
+                IpAddr::from(Ipv6Addr::LOCALHOST)
+            ]
+        );
+    }
+
+    #[test]
+    fn uses_smallest_ttl() {
+        let domain = domain("example.com");
+
+        let resolved = resolved_ips(
+            &domain,
+            Ok(response(
+                &domain,
+                RecordType::A,
+                [(300, records::a(Ipv4Addr::LOCALHOST))],
+            )),
+            Ok(response(
+                &domain,
+                RecordType::AAAA,
+                [(30, records::aaaa(Ipv6Addr::LOCALHOST))],
+            )),
+        )
+        .unwrap();
+
+        assert_eq!(resolved.ttl, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn succeeds_if_one_query_fails() {
+        let domain = domain("example.com");
+
+        let resolved = resolved_ips(
+            &domain,
+            Ok(response(
+                &domain,
+                RecordType::A,
+                [(60, records::a(Ipv4Addr::LOCALHOST))],
+            )),
+            Err(anyhow::anyhow!("AAAA query failed")),
+        )
+        .unwrap();
+
+        assert_eq!(resolved.ips, vec![IpAddr::from(Ipv4Addr::LOCALHOST)]);
+        assert_eq!(resolved.ttl, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn fails_if_both_queries_fail() {
+        let domain = domain("example.com");
+
+        let result = resolved_ips(
+            &domain,
+            Err(anyhow::anyhow!("A query failed")),
+            Err(anyhow::anyhow!("AAAA query failed")),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn ignores_other_records() {
+        let domain = domain("example.com");
+        let target = self::domain("target.example.com");
+
+        let resolved = resolved_ips(
+            &domain,
+            Ok(ResponseBuilder::for_query(
+                &Query::new(domain.clone(), RecordType::A),
+                ResponseCode::NOERROR,
+            )
+            .with_records([
+                (domain.clone(), 60, records::cname(target.clone())),
+                (target, 60, records::a(Ipv4Addr::LOCALHOST)),
+            ])
+            .build()),
+            Ok(response(&domain, RecordType::AAAA, [])),
+        )
+        .unwrap();
+
+        assert_eq!(resolved.ips, vec![IpAddr::from(Ipv4Addr::LOCALHOST)]);
+    }
+
+    #[test]
+    fn fails_without_any_addresses() {
+        let domain = domain("example.com");
+
+        let result = resolved_ips(
+            &domain,
+            Ok(response(&domain, RecordType::A, [])),
+            Ok(response(&domain, RecordType::AAAA, [])),
+        );
+
+        assert!(result.is_err());
+    }
+
+    fn domain(name: &str) -> DomainName {
+        DomainName::vec_from_str(name).unwrap()
+    }
+
+    fn response<const N: usize>(
+        domain: &DomainName,
+        qtype: RecordType,
+        records: [(u32, dns_types::OwnedRecordData); N],
+    ) -> Response {
+        ResponseBuilder::for_query(&Query::new(domain.clone(), qtype), ResponseCode::NOERROR)
+            .with_records(
+                records
+                    .into_iter()
+                    .map(|(ttl, data)| (domain.clone(), ttl, data)),
+            )
+            .build()
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, LazyLock},
+};
+
+use rustls::{
+    DigitallySignedStruct, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::WebPkiSupportedAlgorithms,
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+use socket_factory::{SocketFactory, TcpSocket, TcpStream};
+use tokio_rustls::{TlsConnector, client::TlsStream};
+
+/// Loading the root certificates isn't free, so we only do it once.
+static ROOT_CERTIFICATES: LazyLock<Arc<rustls::RootCertStore>> = LazyLock::new(|| {
+    Arc::new(rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    })
+});
+
+/// Opens a TLS connection to `server`, validating its certificate against `server_name`.
+pub async fn connect(
+    factory: Arc<dyn SocketFactory<TcpSocket>>,
+    server: SocketAddr,
+    server_name: &str,
+    alpn: &[u8],
+) -> io::Result<TlsStream<TcpStream>> {
+    let server_name = ServerName::try_from(server_name.to_owned()).map_err(io::Error::other)?;
+
+    let mut config = rustls::ClientConfig::builder()
+        .with_root_certificates(ROOT_CERTIFICATES.clone())
+        .with_no_client_auth();
+    config.alpn_protocols = vec![alpn.to_vec()];
+
+    let tcp_socket = factory(&server)?;
+    let tcp_stream = tcp_socket.connect(server).await?;
+
+    TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp_stream)
+        .await
+}
+
+/// Opens a TLS connection to `server` without validating its certificate.
+///
+/// We only know relays by their IP address so there is no name to validate their certificate against.
+/// This is fine because we only use TLS to get through firewalls that block everything but HTTPS:
+/// The relay proves its identity via the MESSAGE-INTEGRITY of its STUN responses and the traffic we relay is encrypted with WireGuard.
+pub async fn connect_unverified(
+    factory: Arc<dyn SocketFactory<TcpSocket>>,
+    server: SocketAddr,
+) -> io::Result<TlsStream<TcpStream>> {
+    let config = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate::default()))
+        .with_no_client_auth();
+
+    let tcp_socket = factory(&server)?;
+    let tcp_stream = tcp_socket.connect(server).await?;
+
+    TlsConnector::from(Arc::new(config))
+        .connect(ServerName::IpAddress(server.ip().into()), tcp_stream)
+        .await
+}
+
+/// Accepts any certificate but still checks that the server owns the key of the certificate it presents.
+#[derive(Debug)]
+struct AnyCertificate {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl Default for AnyCertificate {
+    fn default() -> Self {
+        Self {
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        }
+    }
+}
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,46 @@
+use std::{io, sync::Arc, time::Instant};
+
+use parking_lot::Mutex;
+use socket_factory::{SocketFactory, TcpSocket, TcpStream};
+use tokio_rustls::client::TlsStream;
+
+use crate::messages::TlsDnsServer;
+
+use super::{connection_pool::ConnectionPool, tcp_dns, tls};
+
+/// The ALPN protocol identifier for DNS over TLS, see <https://www.iana.org/assignments/tls-extensiontype-values>.
+const ALPN: &[u8] = b"dot";
+
+pub type Pool = ConnectionPool<TlsDnsServer, TlsStream<TcpStream>>;
+
+pub async fn send(
+    factory: Arc<dyn SocketFactory<TcpSocket>>,
+    server: TlsDnsServer,
+    query: dns_types::Query,
+    pool: Arc<Mutex<Pool>>,
+) -> io::Result<dns_types::Response> {
+    tracing::trace!(target: "wire::dns::recursive::tls", server = %server.address, server_name = %server.server_name, domain = %query.domain());
+
+    let idle_stream = pool.lock().take(&server, Instant::now());
+
+    // The server may have closed an idle connection in the meantime, in which case we fall back to a new one.
+    if let Some(mut tls_stream) = idle_stream {
+        match tcp_dns::exchange(&mut tls_stream, query.clone()).await {
+            Ok(response) => {
+                pool.lock().put(server, tls_stream, Instant::now());
+
+                return Ok(response);
+            }
+            Err(e) => {
+                tracing::debug!(server = %server.address, "Failed to reuse DNS-over-TLS connection: {e}")
+            }
+        }
+    }
+
+    let mut tls_stream = tls::connect(factory, server.address, &server.server_name, ALPN).await?;
+
+    let response = tcp_dns::exchange(&mut tls_stream, query).await?;
+    pool.lock().put(server, tls_stream, Instant::now());
+
+    Ok(response)
+}
@@ -8,7 +8,7 @@
 use anyhow::Result;
 use bimap::BiMap;
 use chrono::Utc;
-use connlib_model::{ClientId, GatewayId, PublicKey, ResourceId, ResourceView};
+use connlib_model::{ClientId, DnsQueryLogEntry, GatewayId, PublicKey, ResourceId, ResourceView};
 use dns_types::DomainName;
 use gat_lending_iterator::LendingIterator;
 use io::{Buffers, Io};
@@ -69,7 +69,10 @@ pub type GatewayTunnel = Tunnel<GatewayState>;
 pub type ClientTunnel = Tunnel<ClientState>;
 
 pub use client::ClientState;
+pub use dns::DnsOverrides;
 pub use gateway::{DnsResourceNatEntry, GatewayState, ResolveDnsRequest};
+pub use io::ResolvedIps;
+pub use peer::{FlowProtocol, FlowRecord, FlowVerdict};
 pub use sockets::UdpSocketThreadStopped;
 pub use utils::turn;
 
@@ -88,6 +91,7 @@ pub struct Tunnel<TRoleState> {
     buffers: Buffers,
 
     packet_counter: opentelemetry::metrics::Counter<u64>,
+    connection_gauges: ConnectionGauges,
 }
 
 impl<TRoleState> Tunnel<TRoleState> {
@@ -125,6 +129,7 @@ impl ClientTunnel {
                 .u64_counter("system.network.packets")
                 .with_description("The number of packets processed.")
                 .build(),
+            connection_gauges: ConnectionGauges::default(),
         }
     }
 
@@ -151,8 +156,13 @@ impl ClientTunnel {
             }
 
             if let Some(trans) = self.role_state.poll_transmit() {
-                self.io
-                    .send_network(trans.src, trans.dst, &trans.payload, Ecn::NonEct);
+                self.io.send_network(
+                    trans.src,
+                    trans.dst,
+                    trans.transport,
+                    &trans.payload,
+                    Ecn::NonEct,
+                );
                 continue;
             }
 
@@ -168,6 +178,8 @@ impl ClientTunnel {
             match self.io.poll(cx, &mut self.buffers)? {
                 Poll::Ready(io::Input::Timeout(timeout)) => {
                     self.role_state.handle_timeout(timeout);
+                    self.connection_gauges
+                        .record(self.role_state.connection_stats());
                     continue;
                 }
                 Poll::Ready(io::Input::Device(packets)) => {
@@ -181,8 +193,13 @@ impl ClientTunnel {
                             continue;
                         };
 
-                        self.io
-                            .send_network(transmit.src, transmit.dst, &transmit.payload, ecn);
+                        self.io.send_network(
+                            transmit.src,
+                            transmit.dst,
+                            transmit.transport,
+                            &transmit.payload,
+                            ecn,
+                        );
                     }
 
                     continue;
@@ -216,9 +233,37 @@ impl ClientTunnel {
 
                     continue;
                 }
+                Poll::Ready(io::Input::RelayTcp(frame)) => {
+                    let now = Instant::now();
+
+                    self.packet_counter.add(
+                        1,
+                        &[
+                            otel::attr::network_protocol_name(&frame.packet),
+                            otel::attr::network_transport_tcp(),
+                            otel::attr::network_io_direction_receive(),
+                        ],
+                    );
+
+                    let Some(packet) = self.role_state.handle_network_input(
+                        frame.local,
+                        frame.from,
+                        &frame.packet,
+                        now,
+                    ) else {
+                        self.role_state.handle_timeout(now);
+                        continue;
+                    };
+
+                    self.io.send_tun(packet);
+
+                    continue;
+                }
                 Poll::Ready(io::Input::DnsResponse(packet)) => {
-                    self.role_state.handle_dns_response(packet);
-                    self.role_state.handle_timeout(Instant::now());
+                    let now = Instant::now();
+
+                    self.role_state.handle_dns_response(packet, now);
+                    self.role_state.handle_timeout(now);
                     continue;
                 }
                 Poll::Ready(io::Input::UdpDnsQuery(_) | io::Input::TcpDnsQuery(_)) => {
@@ -254,6 +299,7 @@ impl GatewayTunnel {
                 .u64_counter("system.network.packets")
                 .with_description("The number of packets processed.")
                 .build(),
+            connection_gauges: ConnectionGauges::default(),
         }
     }
 
@@ -261,6 +307,21 @@ impl GatewayTunnel {
         self.role_state.public_key()
     }
 
+    /// Replaces the root trust anchors used for DNSSEC validation.
+    pub fn set_dnssec_trust_anchors(&mut self, anchors: Vec<dns_types::dnssec::Ds>) {
+        self.io.set_dnssec_trust_anchors(anchors);
+    }
+
+    /// Resolves the domain of a DNS resource via the fastest of our nameservers.
+    ///
+    /// Returns `None` if we haven't determined the fastest nameserver yet.
+    pub fn resolve(
+        &self,
+        domain: DomainName,
+    ) -> Option<impl Future<Output = Result<ResolvedIps>> + use<>> {
+        self.io.resolve(domain)
+    }
+
     pub fn poll_next_event(&mut self, cx: &mut Context<'_>) -> Poll<Result<GatewayEvent>> {
         for _ in 0..MAX_EVENTLOOP_ITERS {
             ready!(self.io.poll_has_sockets(cx)); // Suspend everything if we don't have any sockets.
@@ -270,8 +331,13 @@ impl GatewayTunnel {
             }
 
             if let Some(trans) = self.role_state.poll_transmit() {
-                self.io
-                    .send_network(trans.src, trans.dst, &trans.payload, Ecn::NonEct);
+                self.io.send_network(
+                    trans.src,
+                    trans.dst,
+                    trans.transport,
+                    &trans.payload,
+                    Ecn::NonEct,
+                );
                 continue;
             }
 
@@ -300,6 +366,8 @@ impl GatewayTunnel {
                 }
                 Poll::Ready(io::Input::Timeout(timeout)) => {
                     self.role_state.handle_timeout(timeout, Utc::now());
+                    self.connection_gauges
+                        .record(self.role_state.connection_stats());
                     continue;
                 }
                 Poll::Ready(io::Input::Device(packets)) => {
@@ -313,8 +381,13 @@ impl GatewayTunnel {
                             continue;
                         };
 
-                        self.io
-                            .send_network(transmit.src, transmit.dst, &transmit.payload, ecn);
+                        self.io.send_network(
+                            transmit.src,
+                            transmit.dst,
+                            transmit.transport,
+                            &transmit.payload,
+                            ecn,
+                        );
                     }
 
                     continue;
@@ -350,6 +423,33 @@ impl GatewayTunnel {
 
                     continue;
                 }
+                Poll::Ready(io::Input::RelayTcp(frame)) => {
+                    let now = Instant::now();
+
+                    self.packet_counter.add(
+                        1,
+                        &[
+                            otel::attr::network_protocol_name(&frame.packet),
+                            otel::attr::network_transport_tcp(),
+                            otel::attr::network_io_direction_receive(),
+                        ],
+                    );
+
+                    let Some(packet) = self.role_state.handle_network_input(
+                        frame.local,
+                        frame.from,
+                        &frame.packet,
+                        now,
+                    )?
+                    else {
+                        self.role_state.handle_timeout(now, Utc::now());
+                        continue;
+                    };
+
+                    self.io.send_tun(packet);
+
+                    continue;
+                }
                 Poll::Ready(io::Input::UdpDnsQuery(query)) => {
                     let Some(nameserver) = self.io.fastest_nameserver() else {
                         tracing::warn!(query = ?query.message, "No nameserver available to handle UDP DNS query");
@@ -362,11 +462,17 @@ impl GatewayTunnel {
                         continue;
                     };
 
-                    self.io.send_dns_query(dns::RecursiveQuery::via_udp(
+                    let exemptions = self.role_state.dnssec_exemptions(query.source.ip());
+                    let query = dns::RecursiveQuery::via_udp(
                         query.source,
-                        SocketAddr::new(nameserver, dns::DNS_PORT),
+                        SocketAddr::new(nameserver, dns::DNS_PORT).into(),
                         query.message,
-                    ));
+                    );
+
+                    match exemptions {
+                        Some(exempt) => self.io.send_validated_dns_query(query, exempt),
+                        None => self.io.send_dns_query(query),
+                    }
                 }
                 Poll::Ready(io::Input::TcpDnsQuery(query)) => {
                     let Some(nameserver) = self.io.fastest_nameserver() else {
@@ -380,12 +486,18 @@ impl GatewayTunnel {
                         continue;
                     };
 
-                    self.io.send_dns_query(dns::RecursiveQuery::via_tcp(
+                    let exemptions = self.role_state.dnssec_exemptions(query.remote.ip());
+                    let query = dns::RecursiveQuery::via_tcp(
                         query.local,
                         query.remote,
-                        SocketAddr::new(nameserver, dns::DNS_PORT),
+                        SocketAddr::new(nameserver, dns::DNS_PORT).into(),
                         query.message,
-                    ));
+                    );
+
+                    match exemptions {
+                        Some(exempt) => self.io.send_validated_dns_query(query, exempt),
+                        None => self.io.send_dns_query(query),
+                    }
                 }
                 Poll::Pending => {}
             }
@@ -399,6 +511,52 @@ impl GatewayTunnel {
     }
 }
 
+/// Gauges aggregated across all connections, updated whenever the tunnel's timer fires.
+///
+/// We deliberately don't record per-connection series as those would never be removed.
+struct ConnectionGauges {
+    connections: opentelemetry::metrics::Gauge<u64>,
+    stun_bytes: opentelemetry::metrics::Gauge<u64>,
+}
+
+impl Default for ConnectionGauges {
+    fn default() -> Self {
+        Self {
+            connections: otel::metrics::connections(),
+            stun_bytes: otel::metrics::connection_stun_bytes(),
+        }
+    }
+}
+
+impl ConnectionGauges {
+    fn record<TId, RId>(&self, stats: impl Iterator<Item = (TId, snownet::ConnectionStats<RId>)>) {
+        let mut num_direct = 0;
+        let mut num_relayed = 0;
+        let mut stun_bytes_direct = 0;
+        let mut stun_bytes_relayed = 0;
+
+        for (_, stats) in stats {
+            match stats.path {
+                Some(_) if stats.is_relayed() => num_relayed += 1,
+                Some(_) => num_direct += 1,
+                None => {} // Still connecting.
+            }
+
+            stun_bytes_direct += stats.stun_bytes_to_peer_direct.0 as u64;
+            stun_bytes_relayed += stats.stun_bytes_to_peer_relayed.0 as u64;
+        }
+
+        self.connections
+            .record(num_direct, &[otel::attr::network_path_direct()]);
+        self.connections
+            .record(num_relayed, &[otel::attr::network_path_relayed()]);
+        self.stun_bytes
+            .record(stun_bytes_direct, &[otel::attr::network_path_direct()]);
+        self.stun_bytes
+            .record(stun_bytes_relayed, &[otel::attr::network_path_relayed()]);
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ClientEvent {
     AddedIceCandidates {
@@ -418,6 +576,8 @@ pub enum ClientEvent {
         resources: Vec<ResourceView>,
     },
     TunInterfaceUpdated(TunConfig),
+    /// A DNS query was handled whilst the DNS query log is enabled.
+    DnsQueryLogged(DnsQueryLogEntry),
 }
 
 #[derive(Clone, derive_more::Debug, PartialEq, Eq)]
@@ -464,6 +624,12 @@ pub enum GatewayEvent {
         candidates: BTreeSet<String>,
     },
     ResolveDns(ResolveDnsRequest),
+    /// The records of a domain we have set up a DNS resource NAT for expired and need to be re-resolved.
+    ///
+    /// Pass the result to [`GatewayState::handle_domain_refreshed`].
+    RefreshDns(ResolveDnsRequest),
+    /// A flow between a client and a resource finished or reached its reporting interval.
+    FlowLog(FlowRecord),
 }
 
 /// Adapter-struct to [`fmt::Display`] a [`BTreeSet`].
@@ -111,30 +111,47 @@ pub struct IceCredentials {
     pub password: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "protocol", rename_all = "snake_case")]
 pub enum DnsServer {
     IpPort(IpDnsServer),
+    DnsOverHttps(HttpsDnsServer),
+    DnsOverTls(TlsDnsServer),
 }
 
 impl fmt::Debug for DnsServer {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::IpPort(IpDnsServer { address }) => address.fmt(f),
+            Self::DnsOverHttps(HttpsDnsServer { url, address }) => write!(f, "{url} ({address})"),
+            Self::DnsOverTls(TlsDnsServer {
+                address,
+                server_name,
+            }) => write!(f, "tls://{server_name} ({address})"),
         }
     }
 }
 
 impl DnsServer {
     pub fn ip(&self) -> IpAddr {
-        match self {
-            DnsServer::IpPort(s) => s.address.ip(),
-        }
+        self.address().ip()
     }
 
     pub fn address(&self) -> SocketAddr {
         match self {
             DnsServer::IpPort(s) => s.address,
+            DnsServer::DnsOverHttps(s) => s.socket(),
+            DnsServer::DnsOverTls(s) => s.address,
+        }
+    }
+
+    /// Whether queries to this server are encrypted.
+    ///
+    /// Encrypted queries are always sent from the host because we can't mangle them into the tunnel.
+    pub fn is_encrypted(&self) -> bool {
+        match self {
+            DnsServer::IpPort(_) => false,
+            DnsServer::DnsOverHttps(_) | DnsServer::DnsOverTls(_) => true,
         }
     }
 }
@@ -155,6 +172,34 @@ pub struct IpDnsServer {
     pub address: SocketAddr,
 }
 
+/// A DNS-over-HTTPS server (RFC 8484).
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct HttpsDnsServer {
+    /// The URL to `POST` queries to, e.g. `https://cloudflare-dns.com/dns-query`.
+    pub url: url::Url,
+    /// The IP to connect to.
+    ///
+    /// Resolving the host of the URL would require DNS which we are about to take over.
+    pub address: IpAddr,
+}
+
+impl HttpsDnsServer {
+    pub fn socket(&self) -> SocketAddr {
+        SocketAddr::new(
+            self.address,
+            self.url.port_or_known_default().unwrap_or(443),
+        )
+    }
+}
+
+/// A DNS-over-TLS server (RFC 7858).
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
+pub struct TlsDnsServer {
+    pub address: SocketAddr,
+    /// The name to send as SNI and to validate the server's certificate against.
+    pub server_name: String,
+}
+
 /// Represents a wireguard interface configuration.
 ///
 /// Note that the ips are /32 for ipv4 and /128 for ipv6.
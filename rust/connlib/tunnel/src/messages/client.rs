@@ -24,6 +24,31 @@ pub struct ResourceDescriptionDns {
     pub address_description: Option<String>,
     #[serde(rename = "gateway_groups")]
     pub sites: Vec<Site>,
+
+    /// TTL in seconds of the A / AAAA records we synthesize for this resource.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    /// How many IPv4 proxy IPs we assign to each domain of this resource.
+    #[serde(default)]
+    pub ipv4_proxy_ips: Option<u8>,
+    /// How many IPv6 proxy IPs we assign to each domain of this resource.
+    #[serde(default)]
+    pub ipv6_proxy_ips: Option<u8>,
+    /// For which IP families we synthesize records.
+    #[serde(default)]
+    pub ip_stack: IpStack,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IpStack {
+    /// Only answer A queries, AAAA queries get an empty response.
+    Ipv4Only,
+    /// Only answer AAAA queries, A queries get an empty response.
+    Ipv6Only,
+    #[default]
+    #[serde(other)]
+    Dual,
 }
 
 /// Description of a resource that maps to a CIDR.
@@ -186,6 +211,8 @@ pub enum EgressMessages {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::messages::DnsServer;
+    use std::net::SocketAddr;
 
     #[test]
     fn can_deserialize_internet_resource() {
@@ -221,6 +248,43 @@ mod tests {
         serde_json::from_str::<Vec<ResourceDescription>>(resources).unwrap();
     }
 
+    #[test]
+    fn can_deserialize_dns_resource_with_record_settings() {
+        let resource = r#"{
+            "id": "03000143-e25e-45c7-aafb-144990e57dcd",
+            "name": "gitlab.mycorp.com",
+            "address": "gitlab.mycorp.com",
+            "address_description": "dns resource",
+            "gateway_groups": [{"name": "test", "id": "bf56f32d-7b2c-4f5d-a784-788977d014a4"}],
+            "ttl": 300,
+            "ipv4_proxy_ips": 2,
+            "ip_stack": "ipv4_only"
+        }"#;
+
+        let resource = serde_json::from_str::<ResourceDescriptionDns>(resource).unwrap();
+
+        assert_eq!(resource.ttl, Some(300));
+        assert_eq!(resource.ipv4_proxy_ips, Some(2));
+        assert_eq!(resource.ipv6_proxy_ips, None);
+        assert_eq!(resource.ip_stack, IpStack::Ipv4Only);
+    }
+
+    #[test]
+    fn unknown_ip_stack_falls_back_to_dual() {
+        let resource = r#"{
+            "id": "03000143-e25e-45c7-aafb-144990e57dcd",
+            "name": "gitlab.mycorp.com",
+            "address": "gitlab.mycorp.com",
+            "address_description": null,
+            "gateway_groups": [],
+            "ip_stack": "ipv5_only"
+        }"#;
+
+        let resource = serde_json::from_str::<ResourceDescriptionDns>(resource).unwrap();
+
+        assert_eq!(resource.ip_stack, IpStack::Dual);
+    }
+
     #[test]
     fn can_deserialize_unknown_resource() {
         let resources = r#"[
@@ -315,6 +379,56 @@ mod tests {
         assert!(matches!(message, IngressMessages::ConfigChanged(_)))
     }
 
+    #[test]
+    fn can_deserialize_encrypted_upstream_dns() {
+        let json = r#"
+        {
+            "event": "config_changed",
+            "ref": null,
+            "topic": "client",
+            "payload": {
+              "interface": {
+                "ipv6": "fd00:2021:1111::e:65ea",
+                "upstream_dns": [
+                  {
+                    "protocol": "dns_over_https",
+                    "url": "https://cloudflare-dns.com/dns-query",
+                    "address": "1.1.1.1"
+                  },
+                  {
+                    "protocol": "dns_over_tls",
+                    "address": "9.9.9.9:853",
+                    "server_name": "dns.quad9.net"
+                  }
+                ],
+                "ipv4": "100.67.138.25"
+              }
+            }
+          }
+        "#;
+
+        let IngressMessages::ConfigChanged(config) =
+            serde_json::from_str::<IngressMessages>(json).unwrap()
+        else {
+            panic!("Unexpected message")
+        };
+
+        let addresses = config
+            .interface
+            .upstream_dns
+            .iter()
+            .map(DnsServer::address)
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            addresses,
+            vec![
+                SocketAddr::from(([1, 1, 1, 1], 443)),
+                SocketAddr::from(([9, 9, 9, 9], 853))
+            ]
+        );
+    }
+
     #[test]
     fn can_deserialize_init_message() {
         let json = r#"{
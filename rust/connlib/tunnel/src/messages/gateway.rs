@@ -30,6 +30,11 @@ pub struct ResourceDescriptionDns {
     pub name: String,
 
     pub filters: Filters,
+    /// Only allow traffic that was initiated by the client.
+    #[serde(default)]
+    pub client_initiated_only: bool,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
 }
 
 /// Description of a resource that maps to a CIDR.
@@ -45,12 +50,19 @@ pub struct ResourceDescriptionCidr {
     pub name: String,
 
     pub filters: Filters,
+    /// Only allow traffic that was initiated by the client.
+    #[serde(default)]
+    pub client_initiated_only: bool,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
 }
 
 /// Description of an Internet resource.
 #[derive(Debug, Deserialize, Clone)]
 pub struct ResourceDescriptionInternet {
     pub id: ResourceId,
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -61,12 +73,88 @@ pub enum ResourceDescription {
     Internet(ResourceDescriptionInternet),
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "protocol", rename_all = "snake_case")]
 pub enum Filter {
     Udp(PortRange),
     Tcp(PortRange),
-    Icmp,
+    Icmp {
+        /// Restricts this filter to a subset of the resource's addresses.
+        #[serde(default)]
+        destination: Option<IpNetwork>,
+        /// The ICMP messages permitted by this filter.
+        ///
+        /// If not set, all ICMP messages are permitted.
+        #[serde(default)]
+        types: Option<BTreeSet<IcmpType>>,
+    },
+}
+
+/// An ICMP message type, covering both ICMPv4 and ICMPv6.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum IcmpType {
+    /// Echo request and reply, i.e. ping.
+    Echo,
+    /// Destination unreachable, except for "fragmentation needed".
+    DestinationUnreachable,
+    /// ICMPv6 "packet too big" and ICMPv4 "fragmentation needed".
+    PacketTooBig,
+    TimeExceeded,
+    ParameterProblem,
+}
+
+impl IcmpType {
+    pub const ALL: [IcmpType; 5] = [
+        IcmpType::Echo,
+        IcmpType::DestinationUnreachable,
+        IcmpType::PacketTooBig,
+        IcmpType::TimeExceeded,
+        IcmpType::ParameterProblem,
+    ];
+}
+
+/// A token-bucket rate limit, applied to each direction of traffic separately.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The sustained rate.
+    pub bytes_per_second: u64,
+    /// How many bytes may be sent in a single burst.
+    ///
+    /// Defaults to one second worth of traffic.
+    #[serde(default)]
+    pub burst_bytes: Option<u64>,
+    /// What to do with packets exceeding the limit.
+    #[serde(default)]
+    pub action: RateLimitAction,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAction {
+    #[default]
+    Drop,
+    /// Mark packets of ECN-capable transports as congested for up to one extra burst and drop all others.
+    MarkEcn,
+}
+
+impl RateLimit {
+    pub fn burst_bytes(&self) -> u64 {
+        self.burst_bytes.unwrap_or(self.bytes_per_second)
+    }
+}
+
+impl Filter {
+    /// The sub-prefix of the resource this filter applies to.
+    ///
+    /// `None` means the filter applies to the entire resource.
+    /// An IP set is expressed as several filters, one per address.
+    pub fn destination(&self) -> Option<IpNetwork> {
+        match self {
+            Filter::Udp(range) | Filter::Tcp(range) => range.destination,
+            Filter::Icmp { destination, .. } => *destination,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
@@ -77,6 +165,9 @@ pub struct PortRange {
     pub port_range_end: u16,
     #[serde(default = "min_port")]
     pub port_range_start: u16,
+    /// Restricts this filter to a subset of the resource's addresses.
+    #[serde(default)]
+    pub destination: Option<IpNetwork>,
 }
 
 // Note: these 2 functions are needed since serde doesn't yet support default_value
@@ -98,6 +189,14 @@ impl ResourceDescription {
         }
     }
 
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        match self {
+            ResourceDescription::Dns(r) => r.rate_limit,
+            ResourceDescription::Cidr(r) => r.rate_limit,
+            ResourceDescription::Internet(r) => r.rate_limit,
+        }
+    }
+
     pub fn filters(&self) -> Vec<Filter> {
         match self {
             ResourceDescription::Dns(r) => r.filters.clone(),
@@ -259,6 +358,7 @@ mod tests {
         let expected_filter = Filter::Udp(PortRange {
             port_range_start: 10,
             port_range_end: 20,
+            destination: None,
         });
 
         let actual_filter = serde_json::from_str(msg).unwrap();
@@ -272,6 +372,7 @@ mod tests {
         let expected_filter = Filter::Udp(PortRange {
             port_range_start: 0,
             port_range_end: u16::MAX,
+            destination: None,
         });
 
         let actual_filter = serde_json::from_str(msg).unwrap();
@@ -285,6 +386,7 @@ mod tests {
         let expected_filter = Filter::Tcp(PortRange {
             port_range_start: 10,
             port_range_end: 20,
+            destination: None,
         });
 
         let actual_filter = serde_json::from_str(msg).unwrap();
@@ -298,6 +400,21 @@ mod tests {
         let expected_filter = Filter::Tcp(PortRange {
             port_range_start: 0,
             port_range_end: u16::MAX,
+            destination: None,
+        });
+
+        let actual_filter = serde_json::from_str(msg).unwrap();
+
+        assert_eq!(expected_filter, actual_filter);
+    }
+
+    #[test]
+    fn can_deserialize_tcp_filter_with_destination() {
+        let msg = r#"{ "protocol": "tcp", "port_range_start": 22, "port_range_end": 22, "destination": "10.0.1.0/24" }"#;
+        let expected_filter = Filter::Tcp(PortRange {
+            port_range_start: 22,
+            port_range_end: 22,
+            destination: Some("10.0.1.0/24".parse().unwrap()),
         });
 
         let actual_filter = serde_json::from_str(msg).unwrap();
@@ -308,13 +425,50 @@ mod tests {
     #[test]
     fn can_deserialize_icmp_filter() {
         let msg = r#"{ "protocol": "icmp" }"#;
-        let expected_filter = Filter::Icmp;
+        let expected_filter = Filter::Icmp {
+            destination: None,
+            types: None,
+        };
 
         let actual_filter = serde_json::from_str(msg).unwrap();
 
         assert_eq!(expected_filter, actual_filter);
     }
 
+    #[test]
+    fn can_deserialize_icmp_filter_with_types() {
+        let msg = r#"{ "protocol": "icmp", "types": ["echo", "packet_too_big"] }"#;
+        let expected_filter = Filter::Icmp {
+            destination: None,
+            types: Some(BTreeSet::from([IcmpType::Echo, IcmpType::PacketTooBig])),
+        };
+
+        let actual_filter = serde_json::from_str(msg).unwrap();
+
+        assert_eq!(expected_filter, actual_filter);
+    }
+
+    #[test]
+    fn can_deserialize_resource_with_rate_limit() {
+        let msg = r#"{
+            "id": "73037362-715d-4a83-a749-f18eadd970e6",
+            "type": "cidr",
+            "address": "172.172.0.0/16",
+            "name": "172.172.0.0/16",
+            "filters": [],
+            "rate_limit": { "bytes_per_second": 1000000, "action": "mark_ecn" }
+        }"#;
+        let expected_rate_limit = RateLimit {
+            bytes_per_second: 1_000_000,
+            burst_bytes: None,
+            action: RateLimitAction::MarkEcn,
+        };
+
+        let resource = serde_json::from_str::<ResourceDescription>(msg).unwrap();
+
+        assert_eq!(resource.rate_limit(), Some(expected_rate_limit));
+    }
+
     #[test]
     fn can_deserialize_internet_resource() {
         let resources = r#"[
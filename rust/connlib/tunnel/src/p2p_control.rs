@@ -23,13 +23,18 @@ pub mod dns_resource_nat {
     use ip_packet::{FzP2pControlSlice, IpPacket};
     use std::net::IpAddr;
 
+    /// The maximum number of proxy IPs per address family that may be assigned to a domain.
+    ///
+    /// This bounds the size of the [`AssignedIps`] event.
+    pub const MAX_PROXY_IPS_PER_FAMILY: usize = 8;
+
     /// Construct a new [`AssignedIps`] event.
     pub fn assigned_ips(
         resource: ResourceId,
         domain: DomainName,
         proxy_ips: Vec<IpAddr>,
     ) -> Result<IpPacket> {
-        anyhow::ensure!(proxy_ips.len() == 8, "Expected 8 proxy IPs");
+        ensure_valid_proxy_ips(&proxy_ips)?;
 
         let payload = serde_json::to_vec(&AssignedIps {
             resource,
@@ -75,8 +80,11 @@ pub mod dns_resource_nat {
             "Control protocol packet is not a `dns_resource_nat::AssignedIp`s event"
         );
 
-        serde_json::from_slice::<AssignedIps>(packet.payload())
-            .context("Failed to deserialize `dns_resource_nat::AssignedIps`")
+        let assigned_ips = serde_json::from_slice::<AssignedIps>(packet.payload())
+            .context("Failed to deserialize `dns_resource_nat::AssignedIps`")?;
+        ensure_valid_proxy_ips(&assigned_ips.proxy_ips)?;
+
+        Ok(assigned_ips)
     }
 
     pub fn decode_domain_status(packet: FzP2pControlSlice) -> Result<DomainStatus> {
@@ -89,6 +97,19 @@ pub mod dns_resource_nat {
             .context("Failed to deserialize `dns_resource_nat::DomainStatus`")
     }
 
+    fn ensure_valid_proxy_ips(proxy_ips: &[IpAddr]) -> Result<()> {
+        let num_ipv4 = proxy_ips.iter().filter(|ip| ip.is_ipv4()).count();
+        let num_ipv6 = proxy_ips.iter().filter(|ip| ip.is_ipv6()).count();
+
+        anyhow::ensure!(!proxy_ips.is_empty(), "Expected at least 1 proxy IP");
+        anyhow::ensure!(
+            num_ipv4 <= MAX_PROXY_IPS_PER_FAMILY && num_ipv6 <= MAX_PROXY_IPS_PER_FAMILY,
+            "Expected at most {MAX_PROXY_IPS_PER_FAMILY} proxy IPs per address family"
+        );
+
+        Ok(())
+    }
+
     #[derive(serde::Serialize, serde::Deserialize)]
     pub struct AssignedIps {
         pub resource: ResourceId,
@@ -149,6 +170,56 @@ pub mod dns_resource_nat {
             assert_eq!(assigned_ips.proxy_ips, eight_proxy_ips())
         }
 
+        #[test]
+        fn max_payload_length_assigned_ips_with_max_proxy_ips() {
+            let assigned_ips = AssignedIps {
+                resource: ResourceId::from_u128(100),
+                domain: longest_domain_possible(),
+                proxy_ips: std::iter::repeat_n(
+                    IpAddr::V4(Ipv4Addr::BROADCAST),
+                    MAX_PROXY_IPS_PER_FAMILY,
+                )
+                .chain(std::iter::repeat_n(
+                    IpAddr::V6(Ipv6Addr::from(u128::MAX)),
+                    MAX_PROXY_IPS_PER_FAMILY,
+                ))
+                .collect(),
+            };
+
+            let serialized = serde_json::to_vec(&assigned_ips).unwrap();
+
+            assert!(serialized.len() <= ip_packet::MAX_IP_SIZE);
+        }
+
+        #[test]
+        fn assigned_ips_supports_single_address_family() {
+            let proxy_ips = vec![IpAddr::V4(Ipv4Addr::LOCALHOST); 2];
+
+            let packet = assigned_ips(
+                ResourceId::from_u128(101),
+                domain("example.com"),
+                proxy_ips.clone(),
+            )
+            .unwrap();
+
+            let slice = packet.as_fz_p2p_control().unwrap();
+            let assigned_ips = decode_assigned_ips(slice).unwrap();
+
+            assert_eq!(assigned_ips.proxy_ips, proxy_ips)
+        }
+
+        #[test]
+        fn assigned_ips_rejects_invalid_number_of_proxy_ips() {
+            let too_many = vec![IpAddr::V6(Ipv6Addr::LOCALHOST); MAX_PROXY_IPS_PER_FAMILY + 1];
+
+            assert!(
+                assigned_ips(ResourceId::from_u128(101), domain("example.com"), vec![]).is_err()
+            );
+            assert!(
+                assigned_ips(ResourceId::from_u128(101), domain("example.com"), too_many).is_err()
+            );
+        }
+
         #[test]
         fn domain_status_serde_roundtrip() {
             let packet = domain_status(
@@ -4,12 +4,15 @@ use std::net::{IpAddr, SocketAddr};
 use std::time::Instant;
 
 use crate::client::{IPV4_RESOURCES, IPV6_RESOURCES};
-use crate::messages::gateway::Filters;
 use crate::messages::gateway::ResourceDescription;
+use crate::messages::gateway::{Filters, RateLimit};
 use chrono::{DateTime, Utc};
 use connlib_model::{ClientId, GatewayId, ResourceId};
 use dns_types::DomainName;
 use filter_engine::FilterEngine;
+pub(crate) use flow_log::FlowLog;
+use flow_log::OutboundFlow;
+use flow_table::FlowTable;
 use ip_network::{IpNetwork, Ipv4Network, Ipv6Network};
 use ip_network_table::IpNetworkTable;
 use ip_packet::{IpPacket, Protocol, UnsupportedProtocol};
@@ -19,9 +22,16 @@ use crate::{GatewayEvent, IpConfig, otel};
 
 use anyhow::{Context, Result, bail};
 use nat_table::{NatTable, TranslateIncomingResult};
+pub(crate) use rate_limiter::ResourceRateLimiters;
+use rate_limiter::{Direction, RateLimiter};
 
 mod filter_engine;
+mod flow_log;
+mod flow_table;
 mod nat_table;
+mod rate_limiter;
+
+pub use flow_log::{FlowProtocol, FlowRecord, FlowVerdict};
 
 /// The state of one gateway on a client.
 pub(crate) struct GatewayOnClient {
@@ -74,6 +84,11 @@ pub struct ClientOnGateway {
     filters: IpNetworkTable<FilterEngine>,
     permanent_translations: BTreeMap<IpAddr, TranslationState>,
     nat_table: NatTable,
+    flow_table: FlowTable,
+    /// Per-flow accounting, only present if flow logs are enabled on this gateway.
+    flow_log: Option<FlowLog>,
+    /// Limits the entire traffic of this client.
+    rate_limiter: Option<RateLimiter>,
     buffered_events: VecDeque<GatewayEvent>,
 
     num_dropped_packets: opentelemetry::metrics::Counter<u64>,
@@ -93,6 +108,9 @@ impl ClientOnGateway {
             filters: IpNetworkTable::new(),
             permanent_translations: Default::default(),
             nat_table: Default::default(),
+            flow_table: Default::default(),
+            flow_log: None,
+            rate_limiter: None,
             buffered_events: Default::default(),
             internet_resource_enabled: false,
             num_dropped_packets: otel::metrics::network_packet_dropped(),
@@ -132,31 +150,18 @@ impl ClientOnGateway {
 
         anyhow::ensure!(crate::dns::is_subdomain(&name, address));
 
-        let mapped_ipv4 = mapped_ipv4(&resolved_ips);
-        let mapped_ipv6 = mapped_ipv6(&resolved_ips);
-
-        let ipv4_maps = proxy_ips
-            .iter()
-            .filter(|ip| ip.is_ipv4())
-            .zip(mapped_ipv4.iter().cycle().copied());
-
-        let ipv6_maps = proxy_ips
-            .iter()
-            .filter(|ip| ip.is_ipv6())
-            .zip(mapped_ipv6.iter().cycle().copied());
-
-        let ip_maps = ipv4_maps.chain(ipv6_maps);
-
-        for (proxy_ip, real_ip) in ip_maps {
+        for (proxy_ip, real_ip) in proxy_ip_mappings(&proxy_ips, &resolved_ips) {
             tracing::debug!(%name, %proxy_ip, %real_ip);
 
-            if self.nat_table.has_entry_for_inside(*proxy_ip) {
+            if self.nat_table.has_entry_for_inside(proxy_ip) {
                 tracing::debug!(%name, %proxy_ip, %real_ip, "Skipping DNS resource NAT entry because we have open NAT sessions for it");
                 continue;
             }
 
-            self.permanent_translations
-                .insert(*proxy_ip, TranslationState::new(resource_id, real_ip));
+            self.permanent_translations.insert(
+                proxy_ip,
+                TranslationState::new(resource_id, name.clone(), real_ip),
+            );
         }
 
         tracing::debug!(domain = %name, ?resolved_ips, ?proxy_ips, "Set up DNS resource NAT");
@@ -167,10 +172,64 @@ impl ClientOnGateway {
         Ok(())
     }
 
+    /// Updates the NAT for a domain in place after it has been re-resolved.
+    ///
+    /// Unlike [`ClientOnGateway::setup_nat`], this also updates proxy IPs with open NAT sessions.
+    /// The open sessions themselves continue to use the IPs they have been established with.
+    ///
+    /// Returns whether the resolved IPs changed.
+    #[tracing::instrument(level = "debug", skip_all, fields(cid = %self.id))]
+    pub(crate) fn refresh_nat(
+        &mut self,
+        name: DomainName,
+        resource_id: ResourceId,
+        resolved_ips: BTreeSet<IpAddr>,
+    ) -> Result<bool> {
+        let resource = self
+            .resources
+            .get_mut(&resource_id)
+            .context("Unknown resource")?;
+
+        let ResourceOnGateway::Dns { domains, .. } = resource else {
+            bail!("Cannot refresh NAT for non-DNS resource")
+        };
+
+        let current_ips = domains.entry(name.clone()).or_default();
+
+        if *current_ips == resolved_ips {
+            return Ok(false);
+        }
+
+        let proxy_ips = self
+            .permanent_translations
+            .iter()
+            .filter(|(_, state)| state.resource_id == resource_id && state.domain == name)
+            .map(|(proxy_ip, _)| *proxy_ip)
+            .collect::<BTreeSet<_>>();
+
+        for (proxy_ip, real_ip) in proxy_ip_mappings(&proxy_ips, &resolved_ips) {
+            self.permanent_translations.insert(
+                proxy_ip,
+                TranslationState::new(resource_id, name.clone(), real_ip),
+            );
+        }
+
+        tracing::debug!(domain = %name, old = ?current_ips, new = ?resolved_ips, "Refreshed DNS resource NAT");
+
+        *current_ips = resolved_ips;
+        self.recalculate_filters();
+
+        Ok(true)
+    }
+
     pub(crate) fn is_emptied(&self) -> bool {
         self.resources.is_empty()
     }
 
+    pub(crate) fn has_resource(&self, resource: &ResourceId) -> bool {
+        self.resources.contains_key(resource)
+    }
+
     pub(crate) fn expire_resources(&mut self, now: DateTime<Utc>) {
         let cid = self.id;
         let mut any_expired = false;
@@ -197,6 +256,37 @@ impl ClientOnGateway {
 
     pub(crate) fn handle_timeout(&mut self, now: Instant) {
         self.nat_table.handle_timeout(now);
+        self.flow_table.handle_timeout(now);
+    }
+
+    pub(crate) fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        if self.rate_limiter.as_ref().map(|l| l.limit()) == limit {
+            return;
+        }
+
+        self.rate_limiter = limit.map(RateLimiter::new);
+    }
+
+    pub(crate) fn enable_flow_log(&mut self) {
+        self.flow_log.get_or_insert_with(|| FlowLog::new(self.id));
+    }
+
+    /// Returns the records of all flows that finished or need to be reported in the meantime.
+    pub(crate) fn handle_flow_log_timeout(
+        &mut self,
+        now: Instant,
+        utc_now: DateTime<Utc>,
+    ) -> Vec<FlowRecord> {
+        let Some(flow_log) = self.flow_log.as_mut() else {
+            return Vec::new();
+        };
+
+        flow_log.handle_timeout(now, utc_now)
+    }
+
+    /// Takes the flow log of this client, e.g. to flush it before the client is removed.
+    pub(crate) fn take_flow_log(&mut self) -> Option<FlowLog> {
+        self.flow_log.take()
     }
 
     pub(crate) fn remove_resource(&mut self, resource: &ResourceId) {
@@ -252,7 +342,7 @@ impl ClientOnGateway {
                     r.ips()
                         .iter()
                         .any(|r_ip| network_contains_network(*r_ip, *ip))
-                        .then_some(r.filters())
+                        .then_some(r)
                 });
 
                 insert_filters(&mut self.filters, *ip, filters);
@@ -271,7 +361,7 @@ impl ClientOnGateway {
             insert_filters(
                 &mut self.filters,
                 IpNetwork::from(*addr),
-                iter::once(resource.filters()),
+                iter::once(resource),
             );
         }
     }
@@ -336,23 +426,53 @@ impl ClientOnGateway {
     pub fn translate_outbound(
         &mut self,
         packet: IpPacket,
+        resource_rate_limiters: &mut ResourceRateLimiters,
         now: Instant,
     ) -> anyhow::Result<TranslateOutboundResult> {
         // Filtering a packet is not an error.
         if let Err(e) = self.ensure_allowed_src_and_dst(&packet) {
             tracing::debug!(filtered_packet = ?packet, "{e:#}");
+            self.log_outbound_flow(OutboundFlow::new(&packet), FlowVerdict::Filtered, now);
+
             return Ok(TranslateOutboundResult::Filtered);
         }
 
+        let dst = packet.destination();
+        let Some(packet) = self.apply_rate_limits(
+            packet,
+            Direction::Outbound,
+            dst,
+            resource_rate_limiters,
+            now,
+        ) else {
+            return Ok(TranslateOutboundResult::RateLimited);
+        };
+
+        if self.is_client_initiated_only(packet.destination()) {
+            self.flow_table.on_outbound(&packet, now);
+        }
+
+        // The packet is translated below, so capture its flow before that.
+        let flow = self
+            .flow_log
+            .is_some()
+            .then(|| OutboundFlow::new(&packet))
+            .flatten();
+
         // Failing to transform is an error we want to know about further up.
         let result = self.transform_network_to_tun(packet, now)?;
 
+        if matches!(result, TranslateOutboundResult::Send(_)) {
+            self.log_outbound_flow(flow, FlowVerdict::Allowed, now);
+        }
+
         Ok(result)
     }
 
     pub fn translate_inbound(
         &mut self,
         packet: IpPacket,
+        resource_rate_limiters: &mut ResourceRateLimiters,
         now: Instant,
     ) -> anyhow::Result<Option<IpPacket>> {
         // Traffic from our own IP is allowed.
@@ -385,9 +505,99 @@ impl ClientOnGateway {
             return Ok(None);
         }
 
+        if self.is_client_initiated_only(packet.source()) {
+            if let Err(e) = self.flow_table.on_inbound(&packet, now) {
+                tracing::debug!(?packet, "{e}");
+
+                self.num_dropped_packets.add(
+                    1,
+                    &[
+                        otel::attr::network_type_for_packet(&packet),
+                        otel::attr::network_io_direction_receive(),
+                        otel::attr::error_type("UnsolicitedPacket"),
+                    ],
+                );
+
+                return Ok(None);
+            }
+        }
+
+        let src = packet.source();
+        let Some(packet) =
+            self.apply_rate_limits(packet, Direction::Inbound, src, resource_rate_limiters, now)
+        else {
+            return Ok(None);
+        };
+
+        if let Some(flow_log) = self.flow_log.as_mut() {
+            flow_log.on_inbound(&packet, now);
+        }
+
         Ok(Some(packet))
     }
 
+    /// Applies the rate limit of the client and the one of the resource behind `resource_ip`.
+    ///
+    /// Returns `None` if the packet has to be dropped.
+    fn apply_rate_limits(
+        &mut self,
+        packet: IpPacket,
+        direction: Direction,
+        resource_ip: IpAddr,
+        resource_rate_limiters: &mut ResourceRateLimiters,
+        now: Instant,
+    ) -> Option<IpPacket> {
+        let resource_rate_limiter = if resource_rate_limiters.is_empty() {
+            None
+        } else {
+            attribute_flow(
+                &self.resources,
+                &self.permanent_translations,
+                self.internet_resource_enabled,
+                resource_ip,
+            )
+            .0
+            .and_then(|id| resource_rate_limiters.get_mut(&id))
+        };
+
+        if self.rate_limiter.is_none() && resource_rate_limiter.is_none() {
+            return Some(packet);
+        }
+
+        let network_type = otel::attr::network_type_for_packet(&packet);
+        let Some(packet) = rate_limiter::apply(
+            [self.rate_limiter.as_mut(), resource_rate_limiter],
+            packet,
+            direction,
+            now,
+        ) else {
+            record_rate_limited(&self.num_dropped_packets, network_type, direction);
+            return None;
+        };
+
+        Some(packet)
+    }
+
+    fn log_outbound_flow(
+        &mut self,
+        flow: Option<OutboundFlow>,
+        verdict: FlowVerdict,
+        now: Instant,
+    ) {
+        let (Some(flow_log), Some(flow)) = (self.flow_log.as_mut(), flow) else {
+            return;
+        };
+
+        flow_log.on_outbound(flow, verdict, now, || {
+            attribute_flow(
+                &self.resources,
+                &self.permanent_translations,
+                self.internet_resource_enabled,
+                flow.destination(),
+            )
+        });
+    }
+
     fn transform_tun_to_network(
         &mut self,
         packet: IpPacket,
@@ -436,6 +646,33 @@ impl ClientOnGateway {
         Ok(Some(packet))
     }
 
+    /// The addresses (i.e. domain patterns) of all DNS resources this client has access to.
+    pub(crate) fn dns_resource_addresses(&self) -> impl Iterator<Item = &str> {
+        self.resources
+            .values()
+            .filter_map(|resource| match resource {
+                ResourceOnGateway::Dns { address, .. } => Some(address.as_str()),
+                ResourceOnGateway::Cidr { .. } | ResourceOnGateway::Internet { .. } => None,
+            })
+    }
+
+    /// The proxy IPs assigned to the domain of a DNS resource, empty if we don't have a NAT for it.
+    pub(crate) fn dns_resource_nat_proxy_ips(
+        &self,
+        resource: ResourceId,
+        domain: &DomainName,
+    ) -> Vec<IpAddr> {
+        if !self.is_allowed(resource) {
+            return Vec::new();
+        }
+
+        self.permanent_translations
+            .iter()
+            .filter(|(_, state)| state.resource_id == resource && &state.domain == domain)
+            .map(|(proxy_ip, _)| *proxy_ip)
+            .collect()
+    }
+
     pub(crate) fn is_allowed(&self, resource: ResourceId) -> bool {
         self.resources.contains_key(&resource)
     }
@@ -477,11 +714,30 @@ impl ClientOnGateway {
             .context("No filter")
             .context(NotAllowedResource(ip))?;
 
-        filter.apply(protocol).context(NotAllowedResource(ip))?;
+        // For DNS resources, filters restricted to a destination refer to the resolved IP, not the proxy IP.
+        let destination = self
+            .permanent_translations
+            .get(&ip)
+            .map_or(ip, |state| state.resolved_ip);
+
+        filter
+            .apply(destination, protocol)
+            .context(NotAllowedResource(ip))?;
 
         Ok(())
     }
 
+    /// Whether traffic to / from the given resource IP must be initiated by the client.
+    fn is_client_initiated_only(&self, ip: IpAddr) -> bool {
+        if self.internet_resource_enabled && !is_dns_addr(ip) {
+            return false;
+        }
+
+        self.filters
+            .longest_match(ip)
+            .is_some_and(|(_, filter)| filter.is_client_initiated_only())
+    }
+
     pub fn id(&self) -> ClientId {
         self.id
     }
@@ -492,6 +748,7 @@ pub enum TranslateOutboundResult {
     Send(IpPacket),
     DestinationUnreachable(IpPacket),
     Filtered,
+    RateLimited,
 }
 
 impl GatewayOnClient {
@@ -527,12 +784,14 @@ enum ResourceOnGateway {
     Cidr {
         network: IpNetwork,
         filters: Filters,
+        client_initiated_only: bool,
         expires_at: Option<DateTime<Utc>>,
     },
     Dns {
         address: String,
         domains: HashMap<DomainName, BTreeSet<IpAddr>>,
         filters: Filters,
+        client_initiated_only: bool,
         expires_at: Option<DateTime<Utc>>,
     },
     Internet {
@@ -546,12 +805,14 @@ impl ResourceOnGateway {
             ResourceDescription::Dns(r) => ResourceOnGateway::Dns {
                 domains: HashMap::default(),
                 filters: r.filters,
+                client_initiated_only: r.client_initiated_only,
                 address: r.address,
                 expires_at,
             },
             ResourceDescription::Cidr(r) => ResourceOnGateway::Cidr {
                 network: r.address,
                 filters: r.filters,
+                client_initiated_only: r.client_initiated_only,
                 expires_at,
             },
             ResourceDescription::Internet(_) => ResourceOnGateway::Internet { expires_at },
@@ -560,11 +821,27 @@ impl ResourceOnGateway {
 
     fn update(&mut self, resource: &ResourceDescription) {
         match (self, resource) {
-            (ResourceOnGateway::Cidr { filters, .. }, ResourceDescription::Cidr(new)) => {
+            (
+                ResourceOnGateway::Cidr {
+                    filters,
+                    client_initiated_only,
+                    ..
+                },
+                ResourceDescription::Cidr(new),
+            ) => {
                 *filters = new.filters.clone();
+                *client_initiated_only = new.client_initiated_only;
             }
-            (ResourceOnGateway::Dns { filters, .. }, ResourceDescription::Dns(new)) => {
+            (
+                ResourceOnGateway::Dns {
+                    filters,
+                    client_initiated_only,
+                    ..
+                },
+                ResourceDescription::Dns(new),
+            ) => {
                 *filters = new.filters.clone();
+                *client_initiated_only = new.client_initiated_only;
             }
             (ResourceOnGateway::Internet { .. }, ResourceDescription::Internet(_)) => {
                 // No-op.
@@ -602,6 +879,20 @@ impl ResourceOnGateway {
         }
     }
 
+    fn is_client_initiated_only(&self) -> bool {
+        match self {
+            ResourceOnGateway::Cidr {
+                client_initiated_only,
+                ..
+            } => *client_initiated_only,
+            ResourceOnGateway::Dns {
+                client_initiated_only,
+                ..
+            } => *client_initiated_only,
+            ResourceOnGateway::Internet { .. } => false,
+        }
+    }
+
     fn is_allowed(&self, now: &DateTime<Utc>) -> bool {
         let Some(expires_at) = self.expires_at() else {
             return true;
@@ -636,14 +927,17 @@ impl ResourceOnGateway {
 struct TranslationState {
     /// Which (DNS) resource we belong to.
     resource_id: ResourceId,
+    /// The domain the proxy IP has been assigned to.
+    domain: DomainName,
     /// The IP we have resolved for the domain.
     resolved_ip: IpAddr,
 }
 
 impl TranslationState {
-    fn new(resource_id: ResourceId, resolved_ip: IpAddr) -> Self {
+    fn new(resource_id: ResourceId, domain: DomainName, resolved_ip: IpAddr) -> Self {
         Self {
             resource_id,
+            domain,
             resolved_ip,
         }
     }
@@ -657,6 +951,29 @@ fn ipv6_addresses(ip: &BTreeSet<IpAddr>) -> BTreeSet<IpAddr> {
     ip.iter().filter(|ip| ip.is_ipv6()).copied().collect()
 }
 
+/// Maps each proxy IP to one of the resolved IPs, preferring resolved IPs of the same address family.
+fn proxy_ip_mappings(
+    proxy_ips: &BTreeSet<IpAddr>,
+    resolved_ips: &BTreeSet<IpAddr>,
+) -> Vec<(IpAddr, IpAddr)> {
+    let mapped_ipv4 = mapped_ipv4(resolved_ips);
+    let mapped_ipv6 = mapped_ipv6(resolved_ips);
+
+    let ipv4_maps = proxy_ips
+        .iter()
+        .copied()
+        .filter(|ip| ip.is_ipv4())
+        .zip(mapped_ipv4.into_iter().cycle());
+
+    let ipv6_maps = proxy_ips
+        .iter()
+        .copied()
+        .filter(|ip| ip.is_ipv6())
+        .zip(mapped_ipv6.into_iter().cycle());
+
+    ipv4_maps.chain(ipv6_maps).collect()
+}
+
 fn mapped_ipv4(ips: &BTreeSet<IpAddr>) -> BTreeSet<IpAddr> {
     if !ipv4_addresses(ips).is_empty() {
         ipv4_addresses(ips)
@@ -673,6 +990,62 @@ fn mapped_ipv6(ips: &BTreeSet<IpAddr>) -> BTreeSet<IpAddr> {
     }
 }
 
+/// Finds the resource and, for DNS resources, the resolved IP a flow to the given destination belongs to.
+fn attribute_flow(
+    resources: &HashMap<ResourceId, ResourceOnGateway>,
+    permanent_translations: &BTreeMap<IpAddr, TranslationState>,
+    internet_resource_enabled: bool,
+    dst: IpAddr,
+) -> (Option<ResourceId>, Option<IpAddr>) {
+    if let Some(state) = permanent_translations.get(&dst) {
+        return (Some(state.resource_id), Some(state.resolved_ip));
+    }
+
+    let cidr_resource = resources
+        .iter()
+        .filter_map(|(id, r)| match r {
+            ResourceOnGateway::Cidr { network, .. } if network.contains(dst) => {
+                Some((*id, network.netmask()))
+            }
+            ResourceOnGateway::Cidr { .. }
+            | ResourceOnGateway::Dns { .. }
+            | ResourceOnGateway::Internet { .. } => None,
+        })
+        .max_by_key(|(_, netmask)| *netmask)
+        .map(|(id, _)| id);
+
+    if let Some(id) = cidr_resource {
+        return (Some(id), None);
+    }
+
+    if !internet_resource_enabled {
+        return (None, None);
+    }
+
+    let internet_resource = resources
+        .iter()
+        .find_map(|(id, r)| r.is_internet_resource().then_some(*id));
+
+    (internet_resource, None)
+}
+
+fn record_rate_limited(
+    num_dropped_packets: &opentelemetry::metrics::Counter<u64>,
+    network_type: opentelemetry::KeyValue,
+    direction: Direction,
+) {
+    tracing::trace!(?direction, "Rate limit exceeded; dropping packet");
+
+    num_dropped_packets.add(
+        1,
+        &[
+            network_type,
+            otel::attr::network_io_direction_receive(),
+            otel::attr::error_type("RateLimited"),
+        ],
+    );
+}
+
 fn is_dns_addr(addr: IpAddr) -> bool {
     IpNetwork::from(IPV4_RESOURCES).contains(addr) || IpNetwork::from(IPV6_RESOURCES).contains(addr)
 }
@@ -680,9 +1053,12 @@ fn is_dns_addr(addr: IpAddr) -> bool {
 fn insert_filters<'a>(
     filter_store: &mut IpNetworkTable<FilterEngine>,
     ip: IpNetwork,
-    filters: impl Iterator<Item = &'a Filters> + Clone,
+    resources: impl Iterator<Item = &'a ResourceOnGateway> + Clone,
 ) {
-    let filter_engine = FilterEngine::with_filters(filters);
+    // Overlapping resources are merged permissively: unsolicited traffic is only dropped if all of them demand it.
+    let client_initiated_only = resources.clone().all(|r| r.is_client_initiated_only());
+    let filter_engine =
+        FilterEngine::with_filters(resources.map(|r| r.filters()), client_initiated_only);
 
     tracing::trace!(%ip, filters = ?filter_engine, "Installing new filters");
     filter_store.insert(ip, filter_engine);
@@ -692,14 +1068,17 @@ fn insert_filters<'a>(
 mod tests {
     use std::{
         collections::BTreeSet,
-        net::{Ipv4Addr, Ipv6Addr},
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
         time::{Duration, Instant},
     };
 
     use crate::{
         IpConfig,
-        messages::gateway::{Filter, PortRange, ResourceDescription, ResourceDescriptionCidr},
-        peer::{TranslateOutboundResult, nat_table},
+        messages::gateway::{
+            Filter, IcmpType, PortRange, RateLimit, RateLimitAction, ResourceDescription,
+            ResourceDescriptionCidr,
+        },
+        peer::{FlowVerdict, ResourceRateLimiters, TranslateOutboundResult, nat_table},
     };
     use chrono::Utc;
     use connlib_model::{ClientId, ResourceId};
@@ -721,7 +1100,10 @@ mod tests {
                 filters: vec![Filter::Tcp(PortRange {
                     port_range_start: 20,
                     port_range_end: 100,
+                    destination: None,
                 })],
+                client_initiated_only: false,
+                rate_limit: None,
             }),
             Some(then),
         );
@@ -733,7 +1115,10 @@ mod tests {
                 filters: vec![Filter::Udp(PortRange {
                     port_range_start: 20,
                     port_range_end: 100,
+                    destination: None,
                 })],
+                client_initiated_only: false,
+                rate_limit: None,
             }),
             Some(after_then),
         );
@@ -831,16 +1216,245 @@ mod tests {
         .unwrap();
 
         assert!(matches!(
-            peer.translate_outbound(request, Instant::now()).unwrap(),
+            peer.translate_outbound(
+                request,
+                &mut ResourceRateLimiters::default(),
+                Instant::now()
+            )
+            .unwrap(),
             crate::peer::TranslateOutboundResult::Send(_)
         ));
         assert!(
-            peer.translate_inbound(response, Instant::now())
+            peer.translate_inbound(
+                response,
+                &mut ResourceRateLimiters::default(),
+                Instant::now()
+            )
+            .unwrap()
+            .is_some()
+        );
+    }
+
+    #[test]
+    fn filters_can_be_restricted_to_destination_within_cidr_resource() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(
+            ResourceDescription::Cidr(ResourceDescriptionCidr {
+                id: resource_id(),
+                address: cidr_v4_resource().into(),
+                name: "cidr1".to_owned(),
+                filters: vec![
+                    Filter::Tcp(PortRange {
+                        port_range_start: 22,
+                        port_range_end: 22,
+                        destination: Some("10.0.0.0/28".parse().unwrap()),
+                    }),
+                    Filter::Tcp(PortRange {
+                        port_range_start: 443,
+                        port_range_end: 443,
+                        destination: None,
+                    }),
+                ],
+                client_initiated_only: false,
+                rate_limit: None,
+            }),
+            None,
+        );
+        let bastion = "10.0.0.1".parse::<Ipv4Addr>().unwrap();
+        let other = "10.0.0.100".parse::<Ipv4Addr>().unwrap();
+
+        for (dst, port, allowed) in [
+            (bastion, 22, true),
+            (bastion, 443, true),
+            (other, 22, false),
+            (other, 443, true),
+        ] {
+            let packet =
+                ip_packet::make::tcp_packet(client_tun_ipv4(), dst, 5401, port, vec![0; 8])
+                    .unwrap();
+
+            assert_eq!(
+                peer.ensure_allowed_resource(packet.destination(), packet.destination_protocol())
+                    .is_ok(),
+                allowed,
+                "{dst}:{port}"
+            );
+        }
+    }
+
+    #[test]
+    fn icmp_filters_only_permit_listed_types() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(
+            ResourceDescription::Cidr(ResourceDescriptionCidr {
+                id: resource_id(),
+                address: cidr_v4_resource().into(),
+                name: "cidr1".to_owned(),
+                filters: vec![Filter::Icmp {
+                    destination: None,
+                    types: Some(BTreeSet::from([IcmpType::DestinationUnreachable])),
+                }],
+                client_initiated_only: false,
+                rate_limit: None,
+            }),
+            None,
+        );
+        let resource_ip = cidr_v4_resource().hosts().next().unwrap();
+
+        let ping =
+            ip_packet::make::icmp_request_packet(client_tun_ipv4().into(), resource_ip, 1, 1, &[])
+                .unwrap();
+        let unreachable = ip_packet::make::icmp_dst_unreachable(
+            resource_ip,
+            Ipv6Addr::LOCALHOST,
+            &ip_packet::make::tcp_packet(client_tun_ipv4(), resource_ip, 5401, 80, vec![0; 8])
+                .unwrap(),
+        )
+        .unwrap();
+
+        assert!(
+            peer.ensure_allowed_resource(ping.destination(), ping.destination_protocol())
+                .is_err()
+        );
+        assert!(
+            peer.ensure_allowed_resource(unreachable.source(), unreachable.source_protocol())
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn client_initiated_only_resource_drops_unsolicited_packets() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(
+            ResourceDescription::Cidr(ResourceDescriptionCidr {
+                id: resource_id(),
+                address: cidr_v4_resource().into(),
+                name: "cidr1".to_owned(),
+                filters: vec![],
+                client_initiated_only: true,
+                rate_limit: None,
+            }),
+            None,
+        );
+        let resource_ip = cidr_v4_resource().hosts().next().unwrap();
+        let now = Instant::now();
+
+        let response =
+            ip_packet::make::udp_packet(resource_ip, client_tun_ipv4(), 53, 5401, vec![0; 8])
+                .unwrap();
+
+        assert!(
+            peer.translate_inbound(response.clone(), &mut ResourceRateLimiters::default(), now)
+                .unwrap()
+                .is_none()
+        );
+
+        let request =
+            ip_packet::make::udp_packet(client_tun_ipv4(), resource_ip, 5401, 53, vec![0; 8])
+                .unwrap();
+
+        assert!(matches!(
+            peer.translate_outbound(request, &mut ResourceRateLimiters::default(), now)
+                .unwrap(),
+            TranslateOutboundResult::Send(_)
+        ));
+        assert!(
+            peer.translate_inbound(response, &mut ResourceRateLimiters::default(), now)
                 .unwrap()
                 .is_some()
         );
     }
 
+    #[test]
+    fn resource_rate_limit_drops_excess_packets() {
+        let limit = RateLimit {
+            bytes_per_second: 100,
+            burst_bytes: None,
+            action: RateLimitAction::Drop,
+        };
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(
+            ResourceDescription::Cidr(ResourceDescriptionCidr {
+                id: resource_id(),
+                address: cidr_v4_resource().into(),
+                name: "cidr1".to_owned(),
+                filters: vec![],
+                client_initiated_only: false,
+                rate_limit: Some(limit),
+            }),
+            None,
+        );
+        let mut limiters = ResourceRateLimiters::default();
+        limiters.upsert(resource_id(), Some(limit));
+        let resource_ip = cidr_v4_resource().hosts().next().unwrap();
+        let now = Instant::now();
+
+        let request =
+            ip_packet::make::udp_packet(client_tun_ipv4(), resource_ip, 5401, 53, vec![0; 60])
+                .unwrap();
+
+        assert!(matches!(
+            peer.translate_outbound(request.clone(), &mut limiters, now)
+                .unwrap(),
+            TranslateOutboundResult::Send(_)
+        ));
+        assert!(matches!(
+            peer.translate_outbound(request.clone(), &mut limiters, now)
+                .unwrap(),
+            TranslateOutboundResult::RateLimited
+        ));
+        assert!(matches!(
+            peer.translate_outbound(request, &mut limiters, now + Duration::from_secs(1))
+                .unwrap(),
+            TranslateOutboundResult::Send(_)
+        ));
+    }
+
+    #[test]
+    fn resource_rate_limit_is_shared_by_clients() {
+        let limit = RateLimit {
+            bytes_per_second: 100,
+            burst_bytes: None,
+            action: RateLimitAction::Drop,
+        };
+        let resource = ResourceDescription::Cidr(ResourceDescriptionCidr {
+            id: resource_id(),
+            address: cidr_v4_resource().into(),
+            name: "cidr1".to_owned(),
+            filters: vec![],
+            client_initiated_only: false,
+            rate_limit: Some(limit),
+        });
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(resource.clone(), None);
+        let mut other_peer = ClientOnGateway::new(
+            "9d4b79f6-1db7-4cb3-a077-712102204d74".parse().unwrap(),
+            client_tun(),
+            gateway_tun(),
+        );
+        other_peer.add_resource(resource, None);
+        let mut limiters = ResourceRateLimiters::default();
+        limiters.upsert(resource_id(), Some(limit));
+        let resource_ip = cidr_v4_resource().hosts().next().unwrap();
+        let now = Instant::now();
+
+        let request =
+            ip_packet::make::udp_packet(client_tun_ipv4(), resource_ip, 5401, 53, vec![0; 60])
+                .unwrap();
+
+        assert!(matches!(
+            peer.translate_outbound(request.clone(), &mut limiters, now)
+                .unwrap(),
+            TranslateOutboundResult::Send(_)
+        ));
+        assert!(matches!(
+            other_peer
+                .translate_outbound(request, &mut limiters, now)
+                .unwrap(),
+            TranslateOutboundResult::RateLimited
+        ));
+    }
+
     #[test]
     fn dns_and_cidr_filters_dot_mix() {
         let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
@@ -865,7 +1479,10 @@ mod tests {
         )
         .unwrap();
 
-        assert!(peer.translate_outbound(pkt, Instant::now()).is_ok());
+        assert!(
+            peer.translate_outbound(pkt, &mut ResourceRateLimiters::default(), Instant::now())
+                .is_ok()
+        );
 
         let pkt = ip_packet::make::udp_packet(
             client_tun_ipv4(),
@@ -877,7 +1494,8 @@ mod tests {
         .unwrap();
 
         assert!(matches!(
-            peer.translate_outbound(pkt, Instant::now()).unwrap(),
+            peer.translate_outbound(pkt, &mut ResourceRateLimiters::default(), Instant::now())
+                .unwrap(),
             crate::peer::TranslateOutboundResult::Filtered
         ));
 
@@ -891,7 +1509,8 @@ mod tests {
         .unwrap();
 
         assert!(matches!(
-            peer.translate_outbound(pkt, Instant::now()).unwrap(),
+            peer.translate_outbound(pkt, &mut ResourceRateLimiters::default(), Instant::now())
+                .unwrap(),
             crate::peer::TranslateOutboundResult::Filtered
         ));
 
@@ -904,7 +1523,10 @@ mod tests {
         )
         .unwrap();
 
-        assert!(peer.translate_outbound(pkt, Instant::now()).is_ok());
+        assert!(
+            peer.translate_outbound(pkt, &mut ResourceRateLimiters::default(), Instant::now())
+                .is_ok()
+        );
     }
 
     #[test]
@@ -929,7 +1551,10 @@ mod tests {
         )
         .unwrap();
 
-        assert!(peer.translate_outbound(pkt, Instant::now()).is_ok());
+        assert!(
+            peer.translate_outbound(pkt, &mut ResourceRateLimiters::default(), Instant::now())
+                .is_ok()
+        );
 
         let pkt = ip_packet::make::udp_packet(
             client_tun_ipv4(),
@@ -941,7 +1566,8 @@ mod tests {
         .unwrap();
 
         assert!(matches!(
-            peer.translate_outbound(pkt, Instant::now()).unwrap(),
+            peer.translate_outbound(pkt, &mut ResourceRateLimiters::default(), Instant::now())
+                .unwrap(),
             crate::peer::TranslateOutboundResult::Filtered
         ));
 
@@ -954,7 +1580,10 @@ mod tests {
         )
         .unwrap();
 
-        assert!(peer.translate_outbound(pkt, Instant::now()).is_ok());
+        assert!(
+            peer.translate_outbound(pkt, &mut ResourceRateLimiters::default(), Instant::now())
+                .is_ok()
+        );
     }
 
     #[test]
@@ -983,7 +1612,7 @@ mod tests {
         let mut now = Instant::now();
 
         assert!(matches!(
-            peer.translate_outbound(request, now),
+            peer.translate_outbound(request, &mut ResourceRateLimiters::default(), now),
             Ok(TranslateOutboundResult::Send(_))
         ));
 
@@ -1000,7 +1629,10 @@ mod tests {
         peer.handle_timeout(now);
 
         assert!(
-            matches!(peer.translate_inbound(response, now), Ok(Some(_))),
+            matches!(
+                peer.translate_inbound(response, &mut ResourceRateLimiters::default(), now),
+                Ok(Some(_))
+            ),
             "After 30s remote should still be able to send a packet back"
         );
 
@@ -1017,11 +1649,52 @@ mod tests {
         peer.handle_timeout(now);
 
         assert!(
-            matches!(peer.translate_inbound(response, now), Ok(None)),
+            matches!(
+                peer.translate_inbound(response, &mut ResourceRateLimiters::default(), now),
+                Ok(None)
+            ),
             "After 1 minute of inactivity, NAT session should be freed"
         );
     }
 
+    #[test]
+    fn logs_forwarded_packets_of_dns_resource_as_allowed_flow_to_proxy_ip() {
+        let _guard = firezone_logging::test("trace");
+
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.enable_flow_log();
+        peer.add_resource(foo_dns_resource(), None);
+        peer.setup_nat(
+            foo_name().parse().unwrap(),
+            resource_id(),
+            BTreeSet::from([foo_real_ip1().into()]),
+            BTreeSet::from([foo_proxy_ip1().into()]),
+        )
+        .unwrap();
+
+        let request = ip_packet::make::udp_packet(
+            client_tun_ipv4(),
+            foo_proxy_ip1(),
+            1,
+            foo_allowed_port(),
+            vec![0, 0, 0, 0, 0, 0, 0, 0],
+        )
+        .unwrap();
+        let now = Instant::now();
+
+        assert!(matches!(
+            peer.translate_outbound(request, &mut ResourceRateLimiters::default(), now),
+            Ok(TranslateOutboundResult::Send(_))
+        ));
+
+        let records = peer.take_flow_log().unwrap().flush(now, Utc::now());
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].verdict, FlowVerdict::Allowed);
+        assert_eq!(records[0].resource_ip, IpAddr::from(foo_proxy_ip1()));
+        assert_eq!(records[0].tx_packets, 1);
+    }
+
     #[test]
     fn setting_up_dns_resource_nat_does_not_clear_existing_nat_session() {
         let _guard = firezone_logging::test("trace");
@@ -1047,7 +1720,9 @@ mod tests {
         )
         .unwrap();
 
-        let result = peer.translate_outbound(request.clone(), now).unwrap();
+        let result = peer
+            .translate_outbound(request.clone(), &mut ResourceRateLimiters::default(), now)
+            .unwrap();
 
         assert!(matches!(result, TranslateOutboundResult::Send(_)));
 
@@ -1059,7 +1734,9 @@ mod tests {
         )
         .unwrap();
 
-        let result = peer.translate_outbound(request, now).unwrap();
+        let result = peer
+            .translate_outbound(request, &mut ResourceRateLimiters::default(), now)
+            .unwrap();
 
         assert!(matches!(result, TranslateOutboundResult::Send(_)));
 
@@ -1072,11 +1749,112 @@ mod tests {
         )
         .unwrap();
 
-        let response = peer.translate_inbound(response, now).unwrap();
+        let response = peer
+            .translate_inbound(response, &mut ResourceRateLimiters::default(), now)
+            .unwrap();
 
         assert!(response.is_some());
     }
 
+    #[test]
+    fn lists_proxy_ips_of_dns_resource_nat_domain() {
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(foo_dns_resource(), None);
+        peer.setup_nat(
+            foo_name().parse().unwrap(),
+            resource_id(),
+            BTreeSet::from([foo_real_ip1().into()]),
+            BTreeSet::from([foo_proxy_ip1().into(), foo_proxy_ip2().into()]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            peer.dns_resource_nat_proxy_ips(resource_id(), &foo_name().parse().unwrap()),
+            vec![IpAddr::from(foo_proxy_ip1()), foo_proxy_ip2().into()]
+        );
+
+        peer.remove_resource(&resource_id());
+
+        assert!(
+            peer.dns_resource_nat_proxy_ips(resource_id(), &foo_name().parse().unwrap())
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn refreshing_nat_keeps_open_sessions_on_previous_ip() {
+        let _guard = firezone_logging::test("trace");
+
+        let mut peer = ClientOnGateway::new(client_id(), client_tun(), gateway_tun());
+        peer.add_resource(foo_dns_resource(), None);
+        peer.setup_nat(
+            foo_name().parse().unwrap(),
+            resource_id(),
+            BTreeSet::from([foo_real_ip1().into()]),
+            BTreeSet::from([foo_proxy_ip1().into()]),
+        )
+        .unwrap();
+
+        let now = Instant::now();
+        let request = |src_port| {
+            ip_packet::make::udp_packet(
+                client_tun_ipv4(),
+                foo_proxy_ip1(),
+                src_port,
+                foo_allowed_port(),
+                vec![0, 0, 0, 0, 0, 0, 0, 0],
+            )
+            .unwrap()
+        };
+
+        let Ok(TranslateOutboundResult::Send(packet)) =
+            peer.translate_outbound(request(1), &mut ResourceRateLimiters::default(), now)
+        else {
+            panic!("Expected packet to be sent");
+        };
+        assert_eq!(packet.destination(), IpAddr::from(foo_real_ip1()));
+
+        let changed = peer
+            .refresh_nat(
+                foo_name().parse().unwrap(),
+                resource_id(),
+                BTreeSet::from([foo_real_ip2().into()]),
+            )
+            .unwrap();
+        assert!(changed);
+
+        let Ok(TranslateOutboundResult::Send(packet)) =
+            peer.translate_outbound(request(1), &mut ResourceRateLimiters::default(), now)
+        else {
+            panic!("Expected packet to be sent");
+        };
+        assert_eq!(
+            packet.destination(),
+            IpAddr::from(foo_real_ip1()),
+            "Open session should stay on the previous IP"
+        );
+
+        let Ok(TranslateOutboundResult::Send(packet)) =
+            peer.translate_outbound(request(2), &mut ResourceRateLimiters::default(), now)
+        else {
+            panic!("Expected packet to be sent");
+        };
+        assert_eq!(
+            packet.destination(),
+            IpAddr::from(foo_real_ip2()),
+            "New session should use the re-resolved IP"
+        );
+
+        let changed = peer
+            .refresh_nat(
+                foo_name().parse().unwrap(),
+                resource_id(),
+                BTreeSet::from([foo_real_ip2().into()]),
+            )
+            .unwrap();
+        assert!(!changed);
+    }
+
     fn foo_dns_resource() -> crate::messages::gateway::ResourceDescription {
         crate::messages::gateway::ResourceDescription::Dns(
             crate::messages::gateway::ResourceDescriptionDns {
@@ -1086,7 +1864,10 @@ mod tests {
                 filters: vec![Filter::Udp(PortRange {
                     port_range_end: foo_allowed_port(),
                     port_range_start: foo_allowed_port(),
+                    destination: None,
                 })],
+                client_initiated_only: false,
+                rate_limit: None,
             },
         )
     }
@@ -1100,7 +1881,10 @@ mod tests {
                 filters: vec![Filter::Udp(PortRange {
                     port_range_end: bar_allowed_port(),
                     port_range_start: bar_allowed_port(),
+                    destination: None,
                 })],
+                client_initiated_only: false,
+                rate_limit: None,
             },
         )
     }
@@ -1109,6 +1893,7 @@ mod tests {
         crate::messages::gateway::ResourceDescription::Internet(
             crate::messages::gateway::ResourceDescriptionInternet {
                 id: "ed29c148-2acf-4ceb-8db5-d796c267163a".parse().unwrap(),
+                rate_limit: None,
             },
         )
     }
@@ -1299,6 +2084,8 @@ mod proptests {
                     address: resource_addr,
                     name: String::new(),
                     filters: filters.clone(),
+                    client_initiated_only: false,
+                    rate_limit: None,
                 }),
                 None,
             );
@@ -1359,6 +2146,8 @@ mod proptests {
                 address: resource_addr,
                 name: String::new(),
                 filters,
+                client_initiated_only: false,
+                rate_limit: None,
             }),
             None,
         );
@@ -1422,6 +2211,8 @@ mod proptests {
                 address: supernet(resource_addr).unwrap_or(resource_addr),
                 name: String::new(),
                 filters: filters_allowed,
+                client_initiated_only: false,
+                rate_limit: None,
             }),
             None,
         );
@@ -1432,6 +2223,8 @@ mod proptests {
                 address: resource_addr,
                 name: String::new(),
                 filters: filters_removed,
+                client_initiated_only: false,
+                rate_limit: None,
             }),
             None,
         );
@@ -1470,6 +2263,8 @@ mod proptests {
                             address,
                             name: String::new(),
                             filters,
+                            client_initiated_only: false,
+                            rate_limit: None,
                         }),
                         protocol,
                         host,
@@ -1529,7 +2324,12 @@ mod proptests {
                         let f = f.clone();
 
                         move |p| {
-                            (p != ProtocolKind::Icmp || !f.contains(&Filter::Icmp)).then_some(p)
+                            (p != ProtocolKind::Icmp
+                                || !f.contains(&Filter::Icmp {
+                                    destination: None,
+                                    types: None,
+                                }))
+                            .then_some(p)
                         }
                     })
                     .prop_filter("no gaps in port ranges", {
@@ -1581,21 +2381,26 @@ mod proptests {
             Filter::Udp(PortRange {
                 port_range_end,
                 port_range_start,
+                destination: None,
             }) => (port_range_start..=port_range_end)
                 .prop_map(|dport| Protocol::Udp { dport })
                 .boxed(),
             Filter::Tcp(PortRange {
                 port_range_end,
                 port_range_start,
+                destination: None,
             }) => (port_range_start..=port_range_end)
                 .prop_map(|dport| Protocol::Tcp { dport })
                 .boxed(),
-            Filter::Icmp => Just(Protocol::Icmp).boxed(),
+            Filter::Icmp { .. } => Just(Protocol::Icmp).boxed(),
         }
     }
 
     fn filters_in_gaps(filters: Filters) -> impl Strategy<Value = Filters> {
-        let contains_icmp_filter = filters.contains(&Filter::Icmp);
+        let contains_icmp_filter = filters.contains(&Filter::Icmp {
+            destination: None,
+            types: None,
+        });
 
         let ranges_without_tcp_filter = gaps(filters.clone(), ProtocolKind::Tcp);
         let tcp_filters = filter_from_vec(ranges_without_tcp_filter, ProtocolKind::Tcp);
@@ -1606,7 +2411,10 @@ mod proptests {
         let icmp_filter = if contains_icmp_filter {
             Just(vec![])
         } else {
-            Just(vec![Filter::Icmp])
+            Just(vec![Filter::Icmp {
+                destination: None,
+                types: None,
+            }])
         };
 
         (tcp_filters, udp_filters, icmp_filter)
@@ -1636,7 +2444,10 @@ mod proptests {
     fn filters() -> impl Strategy<Value = Filters> {
         collection::vec(
             prop_oneof![
-                Just(Filter::Icmp),
+                Just(Filter::Icmp {
+                    destination: None,
+                    types: None,
+                }),
                 port_range().prop_map(Filter::Udp),
                 port_range().prop_map(Filter::Tcp),
             ],
@@ -1649,6 +2460,7 @@ mod proptests {
             (s..=u16::MAX).prop_map(move |d| PortRange {
                 port_range_start: s,
                 port_range_end: d,
+                destination: None,
             })
         })
     }
@@ -1672,7 +2484,7 @@ mod proptests {
             match value {
                 Filter::Udp(_) => ProtocolKind::Udp,
                 Filter::Tcp(_) => ProtocolKind::Tcp,
-                Filter::Icmp => ProtocolKind::Icmp,
+                Filter::Icmp { .. } => ProtocolKind::Icmp,
             }
         }
     }
@@ -1698,12 +2510,17 @@ mod proptests {
                 ProtocolKind::Tcp => Filter::Tcp(PortRange {
                     port_range_start: *range.start(),
                     port_range_end: *range.end(),
+                    destination: None,
                 }),
                 ProtocolKind::Udp => Filter::Udp(PortRange {
                     port_range_start: *range.start(),
                     port_range_end: *range.end(),
+                    destination: None,
                 }),
-                ProtocolKind::Icmp => Filter::Icmp,
+                ProtocolKind::Icmp => Filter::Icmp {
+                    destination: None,
+                    types: None,
+                },
             }
         }
     }
@@ -1,19 +1,38 @@
-use ip_packet::{Protocol, UnsupportedProtocol};
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+
+use ip_network::{Ipv4Network, Ipv6Network};
+use ip_network_table::IpNetworkTable;
+use ip_packet::icmpv4::DestUnreachableHeader;
+use ip_packet::{Icmpv4Type, Icmpv6Type, Protocol, UnsupportedProtocol};
 use rangemap::RangeInclusiveSet;
 
-use crate::messages::gateway::{Filter, Filters};
+use crate::messages::gateway::{Filter, Filters, IcmpType};
+use crate::utils::network_contains_network;
+
+#[derive(Debug)]
+pub(crate) struct FilterEngine {
+    rules: Rules,
+    /// Whether traffic must be initiated by the client.
+    ///
+    /// If set, unsolicited packets from the resource towards the client are dropped.
+    client_initiated_only: bool,
+}
 
 #[derive(Debug)]
-pub(crate) enum FilterEngine {
+enum Rules {
     PermitAll,
-    PermitSome(AllowRules),
+    /// The rules that apply to a destination are found via longest-prefix match.
+    ///
+    /// The rules of each prefix already include the rules of all less-specific prefixes.
+    PermitSome(IpNetworkTable<AllowRules>),
 }
 
 #[derive(Debug)]
 pub(crate) struct AllowRules {
     udp: RangeInclusiveSet<u16>,
     tcp: RangeInclusiveSet<u16>,
-    icmp: bool,
+    icmp: BTreeSet<IcmpType>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -31,26 +50,65 @@ pub(crate) enum Filtered {
 impl FilterEngine {
     pub(crate) fn apply(
         &self,
+        destination: IpAddr,
         protocol: Result<Protocol, UnsupportedProtocol>,
     ) -> Result<(), Filtered> {
-        match self {
-            FilterEngine::PermitAll => Ok(()),
-            FilterEngine::PermitSome(filter_engine) => filter_engine.apply(protocol),
+        match &self.rules {
+            Rules::PermitAll => Ok(()),
+            Rules::PermitSome(allow_rules) => {
+                let (_, allow_rules) = allow_rules
+                    .longest_match(destination)
+                    .expect("always have rules for the default route");
+
+                allow_rules.apply(protocol)
+            }
         }
     }
 
+    pub(crate) fn is_client_initiated_only(&self) -> bool {
+        self.client_initiated_only
+    }
+
     pub(crate) fn with_filters<'a>(
         filters: impl Iterator<Item = &'a Filters> + Clone,
+        client_initiated_only: bool,
     ) -> FilterEngine {
+        FilterEngine {
+            rules: Rules::with_filters(filters),
+            client_initiated_only,
+        }
+    }
+}
+
+impl Rules {
+    fn with_filters<'a>(filters: impl Iterator<Item = &'a Filters> + Clone) -> Rules {
         // Empty filters means permit all
         if filters.clone().any(|f| f.is_empty()) {
             return Self::PermitAll;
         }
 
-        let mut allow_rules = AllowRules::new();
-        allow_rules.add_filters(filters.flatten());
+        let prefixes = filters
+            .clone()
+            .flatten()
+            .filter_map(|f| f.destination())
+            .chain([
+                Ipv4Network::DEFAULT_ROUTE.into(),
+                Ipv6Network::DEFAULT_ROUTE.into(),
+            ]);
+
+        let mut table = IpNetworkTable::new();
 
-        Self::PermitSome(allow_rules)
+        for prefix in prefixes {
+            let mut allow_rules = AllowRules::new();
+            allow_rules.add_filters(filters.clone().flatten().filter(|f| {
+                f.destination()
+                    .is_none_or(|destination| network_contains_network(destination, prefix))
+            }));
+
+            table.insert(prefix, allow_rules);
+        }
+
+        Self::PermitSome(table)
     }
 }
 
@@ -59,15 +117,29 @@ impl AllowRules {
         AllowRules {
             udp: RangeInclusiveSet::new(),
             tcp: RangeInclusiveSet::new(),
-            icmp: false,
+            icmp: BTreeSet::new(),
         }
     }
 
     fn apply(&self, protocol: Result<Protocol, UnsupportedProtocol>) -> Result<(), Filtered> {
-        match protocol? {
+        let protocol = match protocol {
+            Ok(protocol) => protocol,
+            Err(e) => {
+                // ICMP errors don't carry an identifier and therefore don't map to a `Protocol`.
+                let icmp_type = icmp_error_type(&e).ok_or(e)?;
+
+                if !self.icmp.contains(&icmp_type) {
+                    return Err(Filtered::Icmp);
+                }
+
+                return Ok(());
+            }
+        };
+
+        match protocol {
             Protocol::Tcp(port) if self.tcp.contains(&port) => Ok(()),
             Protocol::Udp(port) if self.udp.contains(&port) => Ok(()),
-            Protocol::Icmp(_) if self.icmp => Ok(()),
+            Protocol::Icmp(_) if self.icmp.contains(&IcmpType::Echo) => Ok(()),
             Protocol::Tcp(_) => Err(Filtered::Tcp),
             Protocol::Udp(_) => Err(Filtered::Udp),
             Protocol::Icmp(_) => Err(Filtered::Icmp),
@@ -85,10 +157,43 @@ impl AllowRules {
                     self.tcp
                         .insert(range.port_range_start..=range.port_range_end);
                 }
-                Filter::Icmp => {
-                    self.icmp = true;
+                Filter::Icmp { types: None, .. } => {
+                    self.icmp.extend(IcmpType::ALL);
+                }
+                Filter::Icmp {
+                    types: Some(types), ..
+                } => {
+                    self.icmp.extend(types);
                 }
             }
         }
     }
 }
+
+#[expect(
+    clippy::wildcard_enum_match_arm,
+    reason = "We only classify the ICMP errors that can be permitted by a filter"
+)]
+fn icmp_error_type(e: &UnsupportedProtocol) -> Option<IcmpType> {
+    let icmp_type = match e {
+        UnsupportedProtocol::UnsupportedIcmpv4Type(icmp_type) => match icmp_type {
+            Icmpv4Type::DestinationUnreachable(DestUnreachableHeader::FragmentationNeeded {
+                ..
+            }) => IcmpType::PacketTooBig,
+            Icmpv4Type::DestinationUnreachable(_) => IcmpType::DestinationUnreachable,
+            Icmpv4Type::TimeExceeded(_) => IcmpType::TimeExceeded,
+            Icmpv4Type::ParameterProblem(_) => IcmpType::ParameterProblem,
+            _ => return None,
+        },
+        UnsupportedProtocol::UnsupportedIcmpv6Type(icmp_type) => match icmp_type {
+            Icmpv6Type::DestinationUnreachable(_) => IcmpType::DestinationUnreachable,
+            Icmpv6Type::PacketTooBig { .. } => IcmpType::PacketTooBig,
+            Icmpv6Type::TimeExceeded(_) => IcmpType::TimeExceeded,
+            Icmpv6Type::ParameterProblem(_) => IcmpType::ParameterProblem,
+            _ => return None,
+        },
+        UnsupportedProtocol::UnsupportedIpPayload(_) => return None,
+    };
+
+    Some(icmp_type)
+}
@@ -0,0 +1,404 @@
+//! Per-flow accounting of the traffic between a client and its resources.
+use chrono::{DateTime, Utc};
+use connlib_model::{ClientId, ResourceId};
+use ip_packet::{IpPacket, Protocol};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// A flow is considered finished after this much time without any packets.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Long-lived flows are reported in intervals of this length.
+const ACTIVE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// How many flows we track per client at most.
+///
+/// Packets of further flows, e.g. of a client scanning a resource, are only counted until some flows finish.
+const MAX_FLOWS: usize = 10_000;
+
+/// Accounts packets and bytes per flow and turns them into [`FlowRecord`]s.
+///
+/// Like the NAT table, flows are keyed on the addresses as seen by the client.
+#[derive(Debug)]
+pub(crate) struct FlowLog {
+    client_id: ClientId,
+    flows: HashMap<FlowKey, ActiveFlow>,
+    /// Packets we didn't account for because we already track [`MAX_FLOWS`] flows.
+    num_dropped_packets: u64,
+}
+
+/// The flow a packet sent by the client belongs to.
+///
+/// Captured before the packet is translated, so we can account it once we know it is going to be sent.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OutboundFlow {
+    key: FlowKey,
+    len: u64,
+}
+
+impl OutboundFlow {
+    pub(crate) fn new(packet: &IpPacket) -> Option<Self> {
+        let (Ok(src), Ok(dst)) = (packet.source_protocol(), packet.destination_protocol()) else {
+            return None;
+        };
+
+        Some(Self {
+            key: FlowKey {
+                client: (src, packet.source()),
+                resource: (dst, packet.destination()),
+            },
+            len: packet.packet().len() as u64,
+        })
+    }
+
+    pub(crate) fn destination(&self) -> IpAddr {
+        self.key.resource.1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    client: (Protocol, IpAddr),
+    resource: (Protocol, IpAddr),
+}
+
+#[derive(Debug)]
+struct ActiveFlow {
+    resource_id: Option<ResourceId>,
+    translated_resource_ip: Option<IpAddr>,
+    verdict: FlowVerdict,
+
+    tx_packets: u64,
+    tx_bytes: u64,
+    rx_packets: u64,
+    rx_bytes: u64,
+
+    start: Instant,
+    last_seen: Instant,
+}
+
+/// A record of a single flow between a client and a resource.
+///
+/// `tx` refers to traffic from the client to the resource, `rx` to traffic from the resource to the client.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct FlowRecord {
+    pub client_id: ClientId,
+    /// The resource this flow was attributed to, if any.
+    pub resource_id: Option<ResourceId>,
+    pub protocol: FlowProtocol,
+    pub client_ip: IpAddr,
+    /// The source port or, for ICMP, the identifier.
+    pub client_port: u16,
+    /// The resource IP as addressed by the client, i.e. the proxy IP for DNS resources.
+    pub resource_ip: IpAddr,
+    /// The destination port or, for ICMP, the identifier.
+    pub resource_port: u16,
+    /// The IP we translated the proxy IP of a DNS resource to.
+    pub translated_resource_ip: Option<IpAddr>,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub verdict: FlowVerdict,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowVerdict {
+    Allowed,
+    Filtered,
+}
+
+impl FlowLog {
+    pub(crate) fn new(client_id: ClientId) -> Self {
+        Self {
+            client_id,
+            flows: HashMap::default(),
+            num_dropped_packets: 0,
+        }
+    }
+
+    /// Accounts a packet sent by the client.
+    ///
+    /// `attribution` is only invoked for new flows and returns the resource and translated IP of the flow.
+    pub(crate) fn on_outbound(
+        &mut self,
+        packet: OutboundFlow,
+        verdict: FlowVerdict,
+        now: Instant,
+        attribution: impl FnOnce() -> (Option<ResourceId>, Option<IpAddr>),
+    ) {
+        let num_flows = self.flows.len();
+
+        let flow = match self.flows.entry(packet.key) {
+            Entry::Occupied(occupied) => occupied.into_mut(),
+            Entry::Vacant(_) if num_flows >= MAX_FLOWS => {
+                self.num_dropped_packets += 1;
+                return;
+            }
+            Entry::Vacant(vacant) => {
+                let (resource_id, translated_resource_ip) = attribution();
+
+                vacant.insert(ActiveFlow {
+                    resource_id,
+                    translated_resource_ip,
+                    verdict,
+                    tx_packets: 0,
+                    tx_bytes: 0,
+                    rx_packets: 0,
+                    rx_bytes: 0,
+                    start: now,
+                    last_seen: now,
+                })
+            }
+        };
+
+        flow.tx_packets += 1;
+        flow.tx_bytes += packet.len;
+        flow.last_seen = now;
+    }
+
+    /// Accounts a packet sent by a resource to the client.
+    ///
+    /// Packets that don't belong to a flow initiated by the client are ignored.
+    pub(crate) fn on_inbound(&mut self, packet: &IpPacket, now: Instant) {
+        let (Ok(src), Ok(dst)) = (packet.source_protocol(), packet.destination_protocol()) else {
+            return;
+        };
+        let key = FlowKey {
+            client: (dst, packet.destination()),
+            resource: (src, packet.source()),
+        };
+
+        let Some(flow) = self.flows.get_mut(&key) else {
+            return;
+        };
+
+        flow.rx_packets += 1;
+        flow.rx_bytes += packet.packet().len() as u64;
+        flow.last_seen = now;
+    }
+
+    /// Returns records for all flows that either finished or have been active for longer than [`ACTIVE_TIMEOUT`].
+    pub(crate) fn handle_timeout(
+        &mut self,
+        now: Instant,
+        utc_now: DateTime<Utc>,
+    ) -> Vec<FlowRecord> {
+        let mut records = Vec::new();
+
+        self.report_dropped_packets();
+
+        self.flows.retain(|key, flow| {
+            let is_idle = now.duration_since(flow.last_seen) >= IDLE_TIMEOUT;
+            let is_long_lived = now.duration_since(flow.start) >= ACTIVE_TIMEOUT;
+
+            if !is_idle && !is_long_lived {
+                return true;
+            }
+
+            let end = if is_idle { flow.last_seen } else { now };
+            records.push(make_record(self.client_id, key, flow, end, now, utc_now));
+
+            if is_idle {
+                return false;
+            }
+
+            flow.tx_packets = 0;
+            flow.tx_bytes = 0;
+            flow.rx_packets = 0;
+            flow.rx_bytes = 0;
+            flow.start = now;
+
+            true
+        });
+
+        records
+    }
+
+    /// Returns records for all flows, e.g. because the client is going away.
+    pub(crate) fn flush(mut self, now: Instant, utc_now: DateTime<Utc>) -> Vec<FlowRecord> {
+        self.report_dropped_packets();
+
+        self.flows
+            .iter()
+            .map(|(key, flow)| make_record(self.client_id, key, flow, flow.last_seen, now, utc_now))
+            .collect()
+    }
+
+    fn report_dropped_packets(&mut self) {
+        if self.num_dropped_packets == 0 {
+            return;
+        }
+
+        tracing::warn!(client = %self.client_id, num_packets = %self.num_dropped_packets, "Flow log is full, did not account for some packets");
+
+        self.num_dropped_packets = 0;
+    }
+}
+
+fn make_record(
+    client_id: ClientId,
+    key: &FlowKey,
+    flow: &ActiveFlow,
+    end: Instant,
+    now: Instant,
+    utc_now: DateTime<Utc>,
+) -> FlowRecord {
+    let (client_proto, client_ip) = key.client;
+    let (resource_proto, resource_ip) = key.resource;
+
+    FlowRecord {
+        client_id,
+        resource_id: flow.resource_id,
+        protocol: match client_proto {
+            Protocol::Tcp(_) => FlowProtocol::Tcp,
+            Protocol::Udp(_) => FlowProtocol::Udp,
+            Protocol::Icmp(_) => FlowProtocol::Icmp,
+        },
+        client_ip,
+        client_port: client_proto.value(),
+        resource_ip,
+        resource_port: resource_proto.value(),
+        translated_resource_ip: flow.translated_resource_ip,
+        tx_packets: flow.tx_packets,
+        tx_bytes: flow.tx_bytes,
+        rx_packets: flow.rx_packets,
+        rx_bytes: flow.rx_bytes,
+        start: to_utc(flow.start, now, utc_now),
+        end: to_utc(end, now, utc_now),
+        verdict: flow.verdict,
+    }
+}
+
+fn to_utc(instant: Instant, now: Instant, utc_now: DateTime<Utc>) -> DateTime<Utc> {
+    let elapsed = chrono::Duration::from_std(now.duration_since(instant)).unwrap_or_default();
+
+    utc_now - elapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn reports_idle_flow_with_both_directions() {
+        let mut log = FlowLog::new(client_id());
+        let mut now = Instant::now();
+        let utc_now = Utc::now();
+
+        log.on_outbound(outbound(5000), FlowVerdict::Allowed, now, || {
+            (Some(resource_id()), None)
+        });
+        log.on_inbound(&udp(RESOURCE, CLIENT, 53, 5000), now);
+        log.on_inbound(&udp(RESOURCE, CLIENT, 53, 5000), now);
+
+        assert!(log.handle_timeout(now, utc_now).is_empty());
+
+        now += IDLE_TIMEOUT;
+        let records = log.handle_timeout(now, utc_now);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].resource_id, Some(resource_id()));
+        assert_eq!(records[0].protocol, FlowProtocol::Udp);
+        assert_eq!(records[0].client_port, 5000);
+        assert_eq!(records[0].resource_port, 53);
+        assert_eq!(records[0].tx_packets, 1);
+        assert_eq!(records[0].rx_packets, 2);
+        assert!(log.handle_timeout(now, utc_now).is_empty());
+    }
+
+    #[test]
+    fn reports_long_lived_flow_in_intervals() {
+        let mut log = FlowLog::new(client_id());
+        let start = Instant::now();
+        let utc_now = Utc::now();
+
+        let mut now = start;
+        while now < start + ACTIVE_TIMEOUT {
+            log.on_outbound(outbound(5000), FlowVerdict::Allowed, now, || {
+                (Some(resource_id()), None)
+            });
+            now += Duration::from_secs(10);
+        }
+
+        let records = log.handle_timeout(now, utc_now);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].tx_packets, 30);
+
+        log.on_outbound(outbound(5000), FlowVerdict::Allowed, now, || {
+            unreachable!("flow should still exist")
+        });
+    }
+
+    #[test]
+    fn flush_reports_active_flows() {
+        let mut log = FlowLog::new(client_id());
+        let now = Instant::now();
+        let utc_now = Utc::now();
+
+        log.on_outbound(outbound(5000), FlowVerdict::Allowed, now, || {
+            (Some(resource_id()), None)
+        });
+        log.on_outbound(outbound(5001), FlowVerdict::Filtered, now, || (None, None));
+
+        assert!(log.handle_timeout(now, utc_now).is_empty());
+
+        let records = log.flush(now, utc_now);
+
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.tx_packets == 1));
+    }
+
+    #[test]
+    fn does_not_track_more_than_max_flows() {
+        let mut log = FlowLog::new(client_id());
+        let now = Instant::now();
+
+        for sport in 0..MAX_FLOWS as u16 {
+            log.on_outbound(outbound(sport), FlowVerdict::Filtered, now, || (None, None));
+        }
+        log.on_outbound(outbound(u16::MAX), FlowVerdict::Filtered, now, || {
+            unreachable!("flow should not be tracked")
+        });
+
+        assert_eq!(log.flows.len(), MAX_FLOWS);
+        assert_eq!(log.num_dropped_packets, 1);
+
+        log.on_outbound(outbound(0), FlowVerdict::Filtered, now, || (None, None));
+
+        assert_eq!(log.num_dropped_packets, 1);
+    }
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 1);
+    const RESOURCE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn outbound(sport: u16) -> OutboundFlow {
+        OutboundFlow::new(&udp(CLIENT, RESOURCE, sport, 53)).unwrap()
+    }
+
+    fn udp(src: Ipv4Addr, dst: Ipv4Addr, sport: u16, dport: u16) -> IpPacket {
+        ip_packet::make::udp_packet(src, dst, sport, dport, vec![0; 8]).unwrap()
+    }
+
+    fn client_id() -> ClientId {
+        "9d4b79f6-1db7-4cb3-a077-712102204d73".parse().unwrap()
+    }
+
+    fn resource_id() -> ResourceId {
+        "ed29c148-2acf-4ceb-8db5-d796c2671631".parse().unwrap()
+    }
+}
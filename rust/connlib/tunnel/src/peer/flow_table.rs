@@ -0,0 +1,359 @@
+//! A connection-tracking table for resources that only allow client-initiated traffic.
+use ip_packet::{IpPacket, Protocol};
+use lru::LruCache;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+/// Tracks the flows initiated by a client towards its resources.
+///
+/// Every packet sent by the client creates or refreshes a flow.
+/// Packets sent by a resource are only allowed if they belong to such a flow.
+/// ICMP errors (e.g. "fragmentation needed" for path MTU discovery) belong to the flow of the packet they quote.
+///
+/// TCP flows follow the handshake and teardown of the connection to pick an appropriate timeout.
+/// UDP and ICMP are stateless, hence their flows are "pseudo-flows" that only live for as long as there is traffic.
+///
+/// All addresses and ports are as seen by the client, i.e. before any NAT is applied to the packet.
+///
+/// At most [`MAX_FLOWS`] are tracked, after which the least-recently used flow is evicted.
+#[derive(Debug)]
+pub(crate) struct FlowTable {
+    flows: LruCache<FlowKey, Flow>,
+}
+
+/// How many flows we track per client at most.
+const MAX_FLOWS: NonZeroUsize = NonZeroUsize::new(10_000).expect("10_000 > 0");
+
+/// How long we wait for the resource to answer a TCP SYN.
+const TCP_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Matches the default TCP keep-alive interval so idle connections with keep-alives enabled survive.
+const TCP_ESTABLISHED_TIMEOUT: Duration = Duration::from_secs(2 * 60 * 60);
+/// How long we keep a TCP flow around after a FIN has been seen, to allow for the teardown to complete.
+const TCP_CLOSING_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+/// Same as the TTL of the NAT table.
+const UDP_TIMEOUT: Duration = Duration::from_secs(60);
+const ICMP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    client: Protocol,
+    resource: (Protocol, IpAddr),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Flow {
+    state: FlowState,
+    last_seen: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlowState {
+    TcpSynSent,
+    TcpEstablished,
+    TcpClosing,
+    Udp,
+    Icmp,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Packet from resource does not belong to a client-initiated flow")]
+pub(crate) struct UnsolicitedPacket;
+
+impl Default for FlowTable {
+    fn default() -> Self {
+        Self {
+            flows: LruCache::new(MAX_FLOWS),
+        }
+    }
+}
+
+impl FlowTable {
+    pub(crate) fn handle_timeout(&mut self, now: Instant) {
+        let expired = self
+            .flows
+            .iter()
+            .filter(|(_, flow)| now.duration_since(flow.last_seen) >= flow.state.timeout())
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        for key in expired {
+            let Some(flow) = self.flows.pop(&key) else {
+                continue;
+            };
+
+            tracing::trace!(?key, state = ?flow.state, "Flow expired");
+        }
+    }
+
+    /// Records a packet sent by the client, creating a new flow if necessary.
+    pub(crate) fn on_outbound(&mut self, packet: &IpPacket, now: Instant) {
+        let (Ok(client), Ok(resource)) = (packet.source_protocol(), packet.destination_protocol())
+        else {
+            return;
+        };
+        let key = FlowKey {
+            client,
+            resource: (resource, packet.destination()),
+        };
+
+        let Some(tcp) = packet.as_tcp() else {
+            let state = match client {
+                Protocol::Udp(_) => FlowState::Udp,
+                Protocol::Icmp(_) => FlowState::Icmp,
+                Protocol::Tcp(_) => return, // Malformed TCP packet.
+            };
+
+            self.insert(
+                key,
+                Flow {
+                    state,
+                    last_seen: now,
+                },
+            );
+            return;
+        };
+
+        if tcp.rst() {
+            self.flows.pop(&key);
+            return;
+        }
+
+        let current = self.flows.peek(&key).map(|f| f.state);
+
+        let state = match current {
+            _ if tcp.fin() => FlowState::TcpClosing,
+            _ if tcp.syn() && !tcp.ack() => FlowState::TcpSynSent,
+            Some(state) => state,
+            None => FlowState::TcpEstablished, // Pick up connections that were established before we started tracking them.
+        };
+
+        if current.is_none() {
+            tracing::trace!(?key, ?state, "New flow");
+        }
+
+        self.insert(
+            key,
+            Flow {
+                state,
+                last_seen: now,
+            },
+        );
+    }
+
+    /// Checks whether a packet sent by a resource belongs to a flow initiated by the client.
+    pub(crate) fn on_inbound(
+        &mut self,
+        packet: &IpPacket,
+        now: Instant,
+    ) -> Result<(), UnsolicitedPacket> {
+        if let Ok(Some((failed_packet, _))) = packet.icmp_unreachable_destination() {
+            let key = FlowKey {
+                client: failed_packet.src_proto(),
+                resource: (failed_packet.dst_proto(), failed_packet.dst()),
+            };
+
+            // An ICMP error doesn't tell us anything about the state of the flow, it only has to belong to one.
+            if !self.flows.contains(&key) {
+                return Err(UnsolicitedPacket);
+            }
+
+            return Ok(());
+        }
+
+        let (Ok(client), Ok(resource)) = (packet.destination_protocol(), packet.source_protocol())
+        else {
+            return Err(UnsolicitedPacket);
+        };
+        let key = FlowKey {
+            client,
+            resource: (resource, packet.source()),
+        };
+
+        let flow = self.flows.get_mut(&key).ok_or(UnsolicitedPacket)?;
+        flow.last_seen = now;
+
+        let Some(tcp) = packet.as_tcp() else {
+            return Ok(());
+        };
+
+        if tcp.rst() {
+            self.flows.pop(&key);
+            return Ok(());
+        }
+
+        match flow.state {
+            _ if tcp.fin() => flow.state = FlowState::TcpClosing,
+            FlowState::TcpSynSent if tcp.ack() => flow.state = FlowState::TcpEstablished,
+            FlowState::TcpSynSent
+            | FlowState::TcpEstablished
+            | FlowState::TcpClosing
+            | FlowState::Udp
+            | FlowState::Icmp => {}
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, key: FlowKey, flow: Flow) {
+        match self.flows.push(key, flow) {
+            Some((evicted_key, evicted)) if evicted_key != key => {
+                tracing::debug!(key = ?evicted_key, state = ?evicted.state, "Flow table is full, evicted least-recently used flow");
+            }
+            Some(_) | None => {}
+        }
+    }
+}
+
+impl FlowState {
+    fn timeout(&self) -> Duration {
+        match self {
+            FlowState::TcpSynSent => TCP_HANDSHAKE_TIMEOUT,
+            FlowState::TcpEstablished => TCP_ESTABLISHED_TIMEOUT,
+            FlowState::TcpClosing => TCP_CLOSING_TIMEOUT,
+            FlowState::Udp => UDP_TIMEOUT,
+            FlowState::Icmp => ICMP_TIMEOUT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn allows_response_to_outbound_udp() {
+        let mut table = FlowTable::default();
+        let now = Instant::now();
+
+        table.on_outbound(&udp(CLIENT, RESOURCE, 5000, 53), now);
+
+        assert!(
+            table
+                .on_inbound(&udp(RESOURCE, CLIENT, 53, 5000), now)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn drops_unsolicited_udp() {
+        let mut table = FlowTable::default();
+        let now = Instant::now();
+
+        table.on_outbound(&udp(CLIENT, RESOURCE, 5000, 53), now);
+
+        assert!(
+            table
+                .on_inbound(&udp(RESOURCE, CLIENT, 53, 5001), now)
+                .is_err()
+        );
+        assert!(
+            table
+                .on_inbound(&udp(RESOURCE, CLIENT, 54, 5000), now)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn udp_flow_expires_without_traffic() {
+        let mut table = FlowTable::default();
+        let mut now = Instant::now();
+
+        table.on_outbound(&udp(CLIENT, RESOURCE, 5000, 53), now);
+
+        now += UDP_TIMEOUT;
+        table.handle_timeout(now);
+
+        assert!(
+            table
+                .on_inbound(&udp(RESOURCE, CLIENT, 53, 5000), now)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn established_tcp_flow_outlives_udp_timeout() {
+        let mut table = FlowTable::default();
+        let mut now = Instant::now();
+
+        table.on_outbound(&tcp(CLIENT, RESOURCE, 5000, 22), now);
+
+        now += UDP_TIMEOUT * 2;
+        table.handle_timeout(now);
+
+        assert!(
+            table
+                .on_inbound(&tcp(RESOURCE, CLIENT, 22, 5000), now)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn allows_icmp_error_for_outbound_tcp() {
+        let mut table = FlowTable::default();
+        let now = Instant::now();
+        let syn = tcp(CLIENT, RESOURCE, 5000, 22);
+
+        table.on_outbound(&syn, now);
+
+        let unreachable =
+            ip_packet::make::icmp_dst_unreachable(RESOURCE, Ipv6Addr::LOCALHOST, &syn).unwrap();
+
+        assert!(table.on_inbound(&unreachable, now).is_ok());
+    }
+
+    #[test]
+    fn drops_icmp_error_for_unknown_flow() {
+        let mut table = FlowTable::default();
+        let now = Instant::now();
+
+        table.on_outbound(&tcp(CLIENT, RESOURCE, 5000, 22), now);
+
+        let unreachable = ip_packet::make::icmp_dst_unreachable(
+            RESOURCE,
+            Ipv6Addr::LOCALHOST,
+            &tcp(CLIENT, RESOURCE, 5001, 22),
+        )
+        .unwrap();
+
+        assert!(table.on_inbound(&unreachable, now).is_err());
+    }
+
+    #[test]
+    fn evicts_least_recently_used_flow_when_full() {
+        let mut table = FlowTable::default();
+        let now = Instant::now();
+
+        for sport in 0..MAX_FLOWS.get() as u16 {
+            table.on_outbound(&udp(CLIENT, RESOURCE, sport, 53), now);
+        }
+        assert!(table.on_inbound(&udp(RESOURCE, CLIENT, 53, 0), now).is_ok());
+
+        table.on_outbound(&udp(CLIENT, RESOURCE, u16::MAX, 53), now);
+
+        assert_eq!(table.flows.len(), MAX_FLOWS.get());
+        assert!(table.on_inbound(&udp(RESOURCE, CLIENT, 53, 0), now).is_ok());
+        assert!(
+            table
+                .on_inbound(&udp(RESOURCE, CLIENT, 53, 1), now)
+                .is_err()
+        );
+        assert!(
+            table
+                .on_inbound(&udp(RESOURCE, CLIENT, 53, u16::MAX), now)
+                .is_ok()
+        );
+    }
+
+    const CLIENT: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 1);
+    const RESOURCE: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn udp(src: Ipv4Addr, dst: Ipv4Addr, sport: u16, dport: u16) -> IpPacket {
+        ip_packet::make::udp_packet(src, dst, sport, dport, vec![0; 8]).unwrap()
+    }
+
+    fn tcp(src: Ipv4Addr, dst: Ipv4Addr, sport: u16, dport: u16) -> IpPacket {
+        ip_packet::make::tcp_packet(src, dst, sport, dport, vec![0; 8]).unwrap()
+    }
+}
@@ -55,15 +55,12 @@ impl NatTable {
 
         let inside = (src, dst);
 
+        // Open sessions stick to the IP they have been established with, even if the domain has since been re-resolved to a different one.
         if let Some(outside) = self.table.get_by_left(&inside) {
-            if outside.1 == outside_dst {
-                tracing::trace!(?inside, ?outside, "Translating outgoing packet");
+            tracing::trace!(?inside, ?outside, %outside_dst, "Translating outgoing packet");
 
-                self.last_seen.insert(*outside, now);
-                return Ok(*outside);
-            }
-
-            tracing::trace!(?inside, ?outside, "Outgoing packet for expired translation");
+            self.last_seen.insert(*outside, now);
+            return Ok(*outside);
         }
 
         // Find the first available public port, starting from the port of the to-be-mapped packet.
@@ -268,6 +265,28 @@ mod tests {
         }
     }
 
+    #[test_strategy::proptest(ProptestConfig { max_local_rejects: 10_000, max_global_rejects: 10_000, ..ProptestConfig::default() })]
+    fn open_session_keeps_outside_dst(
+        #[strategy(udp_or_tcp_or_icmp_packet())] packet: IpPacket,
+        #[strategy(any::<IpAddr>())] outside_dst1: IpAddr,
+        #[strategy(any::<IpAddr>())] outside_dst2: IpAddr,
+    ) {
+        proptest::prop_assume!(packet.destination().is_ipv4() == outside_dst1.is_ipv4());
+        proptest::prop_assume!(packet.destination().is_ipv4() == outside_dst2.is_ipv4());
+
+        let now = Instant::now();
+        let mut table = NatTable::default();
+
+        let first = table
+            .translate_outgoing(&packet, outside_dst1, now)
+            .unwrap();
+        let second = table
+            .translate_outgoing(&packet, outside_dst2, now + Duration::from_secs(1))
+            .unwrap();
+
+        assert_eq!(first, second);
+    }
+
     #[test_strategy::proptest(ProptestConfig { max_local_rejects: 10_000, max_global_rejects: 10_000, ..ProptestConfig::default() })]
     fn can_handle_multiple_packets(
         #[strategy(udp_or_tcp_or_icmp_packet())] packet1: IpPacket,
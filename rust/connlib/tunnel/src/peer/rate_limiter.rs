@@ -0,0 +1,357 @@
+//! Token-bucket rate limiting of the traffic between a client and its resources.
+use crate::messages::gateway::{RateLimit, RateLimitAction};
+use connlib_model::ResourceId;
+use ip_packet::{Ecn, IpPacket};
+use std::collections::HashMap;
+use std::time::Instant;
+
+const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// Enforces a [`RateLimit`] on both directions of traffic independently.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+
+    /// Traffic sent by the client.
+    outbound: TokenBucket,
+    /// Traffic sent to the client.
+    inbound: TokenBucket,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Direction {
+    Outbound,
+    Inbound,
+}
+
+/// What to do with a packet, ordered from most to least permissive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verdict {
+    Pass,
+    /// Mark the packet with [`Ecn::Ce`].
+    Mark,
+    Drop,
+}
+
+/// The rate limiters of all resources, shared by all clients accessing them.
+#[derive(Debug, Default)]
+pub(crate) struct ResourceRateLimiters {
+    inner: HashMap<ResourceId, RateLimiter>,
+}
+
+impl ResourceRateLimiters {
+    /// Sets the limit of the given resource, keeping the limiter's state unless the limit changed.
+    pub(crate) fn upsert(&mut self, id: ResourceId, limit: Option<RateLimit>) {
+        let Some(limit) = limit else {
+            self.inner.remove(&id);
+            return;
+        };
+
+        if self.inner.get(&id).is_some_and(|l| l.limit == limit) {
+            return;
+        }
+
+        self.inner.insert(id, RateLimiter::new(limit));
+    }
+
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(&ResourceId) -> bool) {
+        self.inner.retain(|id, _| f(id));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub(crate) fn get_mut(&mut self, id: &ResourceId) -> Option<&mut RateLimiter> {
+        self.inner.get_mut(id)
+    }
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            outbound: TokenBucket::new(&limit),
+            inbound: TokenBucket::new(&limit),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    fn bucket(&mut self, direction: Direction) -> &mut TokenBucket {
+        match direction {
+            Direction::Outbound => &mut self.outbound,
+            Direction::Inbound => &mut self.inbound,
+        }
+    }
+
+    /// Checks the packet against the limit without consuming any tokens.
+    fn check(&mut self, packet: &IpPacket, direction: Direction, now: Instant) -> Verdict {
+        let limit = self.limit;
+        let cost = cost(packet);
+        let bucket = self.bucket(direction);
+
+        bucket.refill(&limit, now);
+
+        if bucket.credit >= cost {
+            return Verdict::Pass;
+        }
+
+        match limit.action {
+            RateLimitAction::Drop => Verdict::Drop,
+            RateLimitAction::MarkEcn if packet.ecn() == Ecn::NonEct => Verdict::Drop,
+            // Marked packets still consume tokens, so they can only exceed the limit by another burst.
+            RateLimitAction::MarkEcn if bucket.credit - cost >= -capacity(&limit) => Verdict::Mark,
+            RateLimitAction::MarkEcn => Verdict::Drop,
+        }
+    }
+
+    fn consume(&mut self, packet: &IpPacket, direction: Direction) {
+        self.bucket(direction).credit -= cost(packet);
+    }
+}
+
+/// Accounts the given packet against all given limiters.
+///
+/// Returns `None` if the packet exceeds any of the limits and must be dropped, in which case no tokens are consumed.
+/// With [`RateLimitAction::MarkEcn`], excess packets of ECN-capable transports are marked with [`Ecn::Ce`] instead, up to another burst worth of traffic.
+pub(crate) fn apply<const N: usize>(
+    mut limiters: [Option<&mut RateLimiter>; N],
+    packet: IpPacket,
+    direction: Direction,
+    now: Instant,
+) -> Option<IpPacket> {
+    let verdict = limiters
+        .iter_mut()
+        .flatten()
+        .map(|limiter| limiter.check(&packet, direction, now))
+        .max()
+        .unwrap_or(Verdict::Pass);
+
+    if verdict == Verdict::Drop {
+        return None;
+    }
+
+    for limiter in limiters.iter_mut().flatten() {
+        limiter.consume(&packet, direction);
+    }
+
+    match verdict {
+        Verdict::Pass => Some(packet),
+        Verdict::Mark => Some(packet.with_ecn_from_transport(Ecn::Ce)),
+        Verdict::Drop => None,
+    }
+}
+
+/// A token bucket that tracks its credit in byte-nanoseconds to avoid losing fractional refills.
+///
+/// The credit may become negative for packets that are marked instead of dropped.
+#[derive(Debug)]
+struct TokenBucket {
+    credit: i128,
+    last_refill: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            credit: capacity(limit),
+            last_refill: None,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = self
+            .last_refill
+            .map(|last| now.duration_since(last).as_nanos() as i128)
+            .unwrap_or_default();
+
+        self.credit = self
+            .credit
+            .saturating_add(elapsed.saturating_mul(limit.bytes_per_second as i128))
+            .min(capacity(limit));
+        self.last_refill = Some(now);
+    }
+}
+
+fn cost(packet: &IpPacket) -> i128 {
+    packet.packet().len() as i128 * NANOS_PER_SEC
+}
+
+fn capacity(limit: &RateLimit) -> i128 {
+    limit.burst_bytes() as i128 * NANOS_PER_SEC
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    #[test]
+    fn drops_packets_exceeding_burst() {
+        let mut limiter = RateLimiter::new(limit(1000, RateLimitAction::Drop));
+        let now = Instant::now();
+
+        assert!(apply([Some(&mut limiter)], udp(500), Direction::Outbound, now).is_some());
+        assert!(apply([Some(&mut limiter)], udp(500), Direction::Outbound, now).is_none());
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let mut limiter = RateLimiter::new(limit(1000, RateLimitAction::Drop));
+        let mut now = Instant::now();
+
+        assert!(apply([Some(&mut limiter)], udp(900), Direction::Outbound, now).is_some());
+        assert!(apply([Some(&mut limiter)], udp(900), Direction::Outbound, now).is_none());
+
+        now += Duration::from_secs(1);
+
+        assert!(apply([Some(&mut limiter)], udp(900), Direction::Outbound, now).is_some());
+    }
+
+    #[test]
+    fn directions_are_limited_independently() {
+        let mut limiter = RateLimiter::new(limit(1000, RateLimitAction::Drop));
+        let now = Instant::now();
+
+        assert!(apply([Some(&mut limiter)], udp(900), Direction::Outbound, now).is_some());
+        assert!(apply([Some(&mut limiter)], udp(900), Direction::Inbound, now).is_some());
+    }
+
+    #[test]
+    fn drops_without_consuming_if_any_limit_is_exceeded() {
+        let mut client = RateLimiter::new(limit(2000, RateLimitAction::Drop));
+        let mut resource = RateLimiter::new(limit(1000, RateLimitAction::Drop));
+        let now = Instant::now();
+
+        assert!(
+            apply(
+                [Some(&mut client), Some(&mut resource)],
+                udp(900),
+                Direction::Outbound,
+                now
+            )
+            .is_some()
+        );
+        assert!(
+            apply(
+                [Some(&mut client), Some(&mut resource)],
+                udp(900),
+                Direction::Outbound,
+                now
+            )
+            .is_none()
+        );
+
+        // The client still has the credit for the packet dropped by the resource's limit.
+        assert!(apply([Some(&mut client)], udp(1000), Direction::Outbound, now).is_some());
+    }
+
+    #[test]
+    fn marks_ecn_capable_packets_up_to_another_burst() {
+        let mut limiter = RateLimiter::new(limit(1000, RateLimitAction::MarkEcn));
+        let now = Instant::now();
+
+        let packet = apply(
+            [Some(&mut limiter)],
+            ect0_udp(1000),
+            Direction::Outbound,
+            now,
+        )
+        .unwrap();
+        assert_eq!(packet.ecn(), Ecn::Ect0);
+
+        let packet = apply(
+            [Some(&mut limiter)],
+            ect0_udp(1000),
+            Direction::Outbound,
+            now,
+        )
+        .unwrap();
+        assert_eq!(packet.ecn(), Ecn::Ce);
+
+        assert!(
+            apply(
+                [Some(&mut limiter)],
+                ect0_udp(1000),
+                Direction::Outbound,
+                now
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn mark_ecn_drops_packets_of_transports_without_ecn() {
+        let mut limiter = RateLimiter::new(limit(1000, RateLimitAction::MarkEcn));
+        let now = Instant::now();
+
+        assert!(apply([Some(&mut limiter)], udp(1000), Direction::Outbound, now).is_some());
+        assert!(apply([Some(&mut limiter)], udp(1000), Direction::Outbound, now).is_none());
+    }
+
+    #[test]
+    fn marked_packets_have_to_be_paid_back() {
+        let mut limiter = RateLimiter::new(limit(1000, RateLimitAction::MarkEcn));
+        let mut now = Instant::now();
+
+        assert!(
+            apply(
+                [Some(&mut limiter)],
+                ect0_udp(1000),
+                Direction::Outbound,
+                now
+            )
+            .is_some()
+        );
+        assert!(
+            apply(
+                [Some(&mut limiter)],
+                ect0_udp(1000),
+                Direction::Outbound,
+                now
+            )
+            .is_some()
+        );
+
+        now += Duration::from_secs(1);
+
+        let packet = apply(
+            [Some(&mut limiter)],
+            ect0_udp(1000),
+            Direction::Outbound,
+            now,
+        )
+        .unwrap();
+        assert_eq!(packet.ecn(), Ecn::Ce);
+    }
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(100, 64, 0, 1);
+    const DST: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    fn udp(size: usize) -> IpPacket {
+        ip_packet::make::udp_packet(SRC, DST, 5000, 53, vec![0; size - 28]).unwrap()
+    }
+
+    fn ect0_udp(size: usize) -> IpPacket {
+        let packet = udp(size);
+        let len = packet.packet().len();
+
+        let mut buf = ip_packet::IpPacketBuf::new();
+        buf.buf()[..len].copy_from_slice(packet.packet());
+        buf.buf()[1] |= Ecn::Ect0 as u8; // The ECN bits are the lowest two bits of the IPv4 TOS field.
+
+        IpPacket::new(buf, len).unwrap()
+    }
+
+    fn limit(bytes_per_second: u64, action: RateLimitAction) -> RateLimit {
+        RateLimit {
+            bytes_per_second,
+            burst_bytes: None,
+            action,
+        }
+    }
+}
@@ -92,7 +92,6 @@ where
         self.peer_by_id.get_mut(id)
     }
 
-    #[cfg(test)]
     pub(crate) fn peer_by_ip(&self, ip: IpAddr) -> Option<&P> {
         let (_, id) = self.id_by_ip.longest_match(ip)?;
         self.peer_by_id.get(id)
@@ -103,6 +102,10 @@ where
         self.peer_by_id.get_mut(id)
     }
 
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &P> {
+        self.peer_by_id.values()
+    }
+
     pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut P> {
         self.peer_by_id.values_mut()
     }
@@ -41,6 +41,7 @@ pub fn dns_resource(sites: impl Strategy<Value = Vec<Site>>) -> impl Strategy<Va
                 name,
                 sites,
                 address_description,
+                records: Default::default(),
             },
         )
 }
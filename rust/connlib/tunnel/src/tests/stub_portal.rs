@@ -174,6 +174,8 @@ impl StubPortal {
                     address: r.address,
                     name: r.name.clone(),
                     filters: Vec::new(),
+                    client_initiated_only: false,
+                    rate_limit: None,
                 },
             ))
         });
@@ -182,12 +184,15 @@ impl StubPortal {
                 id: r.id,
                 name: r.name.clone(),
                 filters: Vec::new(),
+                client_initiated_only: false,
+                rate_limit: None,
                 address: r.address.clone(),
             })
         });
         let internet_resource = Some(gateway::ResourceDescription::Internet(
             gateway::ResourceDescriptionInternet {
                 id: self.internet_resource.id,
+                rate_limit: None,
             },
         ));
 
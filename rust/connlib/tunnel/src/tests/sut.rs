@@ -15,7 +15,7 @@ use crate::tests::assertions::*;
 use crate::tests::flux_capacitor::FluxCapacitor;
 use crate::tests::transition::Transition;
 use crate::utils::earliest;
-use crate::{ClientEvent, GatewayEvent, dns, messages::Interface};
+use crate::{ClientEvent, GatewayEvent, ResolvedIps, dns, messages::Interface};
 use bufferpool::BufferPool;
 use connlib_model::{ClientId, GatewayId, PublicKey, RelayId};
 use dns_types::ResponseCode;
@@ -23,7 +23,7 @@ use dns_types::prelude::*;
 use rand::SeedableRng;
 use rand::distributions::DistString;
 use sha2::Digest;
-use snownet::Transmit;
+use snownet::{Transmit, Transport};
 use std::iter;
 use std::{
     collections::BTreeMap,
@@ -430,12 +430,15 @@ impl TunnelTest {
                 let response =
                     self.on_recursive_dns_query(&query.message, &ref_state.global_dns_records);
                 self.client.exec_mut(|c| {
-                    c.sut.handle_dns_response(dns::RecursiveResponse {
-                        server,
-                        query: query.message,
-                        message: Ok(response), // TODO: Vary this?
-                        transport,
-                    })
+                    c.sut.handle_dns_response(
+                        dns::RecursiveResponse {
+                            server,
+                            query: query.message,
+                            message: Ok(response), // TODO: Vary this?
+                            transport,
+                        },
+                        now,
+                    )
                 });
 
                 continue;
@@ -457,6 +460,7 @@ impl TunnelTest {
                             Transmit {
                                 src: Some(src),
                                 dst,
+                                transport: Transport::Udp,
                                 payload: self.buffer_pool.pull_initialised(&payload),
                             },
                             relay,
@@ -474,6 +478,7 @@ impl TunnelTest {
                     }
                     firezone_relay::Command::CreateChannelBinding { .. } => {}
                     firezone_relay::Command::DeleteChannelBinding { .. } => {}
+                    firezone_relay::Command::ForwardToPeer { .. } => {} // `snownet` only relays via channels.
                 }
 
                 continue 'outer;
@@ -787,6 +792,9 @@ impl TunnelTest {
                     c.search_domain = config.search_domain
                 });
             }
+            ClientEvent::DnsQueryLogged(_) => {
+                tracing::error!("The DNS query log is never enabled in tests");
+            }
         }
     }
 
@@ -919,5 +927,18 @@ fn on_gateway_event(
                     .unwrap()
             })
         }
+        GatewayEvent::RefreshDns(r) => {
+            let resolved_ips = ResolvedIps {
+                ips: global_dns_records.domain_ips_iter(r.domain()).collect(),
+                ttl: Duration::from_secs(300),
+            };
+
+            gateway.exec_mut(|g| {
+                g.sut
+                    .handle_domain_refreshed(r, Ok(resolved_ips), now)
+                    .unwrap()
+            })
+        }
+        GatewayEvent::FlowLog(_) => {}
     }
 }
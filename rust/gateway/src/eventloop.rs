@@ -1,3 +1,5 @@
+use crate::flow_log::FlowLogExporter;
+use crate::local_config::LocalConfigSource;
 use anyhow::{Context as _, Result};
 use boringtun::x25519::PublicKey;
 #[cfg(not(target_os = "windows"))]
@@ -12,6 +14,7 @@ use firezone_tunnel::messages::gateway::{
 use firezone_tunnel::messages::{ConnectionAccepted, GatewayResponse, Interface, RelaysPresence};
 use firezone_tunnel::{
     DnsResourceNatEntry, GatewayTunnel, IPV4_TUNNEL, IPV6_TUNNEL, IpConfig, ResolveDnsRequest,
+    ResolvedIps,
 };
 use phoenix_channel::{PhoenixChannel, PublicKeyParam};
 use std::collections::BTreeSet;
@@ -27,11 +30,17 @@ use tracing::Instrument;
 
 pub const PHOENIX_TOPIC: &str = "gateway";
 
-/// How long we allow a DNS resolution via `libc::get_addr_info`.
+/// How long we allow a DNS resolution, either via our upstream nameservers or `libc::get_addr_info`.
 const DNS_RESOLUTION_TIMEOUT: Duration = Duration::from_secs(10);
 
-/// Cache DNS responses for 30 seconds.
-const DNS_TTL: Duration = Duration::from_secs(30);
+/// Cache DNS responses resolved via `libc::get_addr_info` for 30 seconds as we don't know their TTL.
+const DEFAULT_DNS_TTL: Duration = Duration::from_secs(30);
+
+/// Cache DNS responses from our upstream nameservers for at least 5 seconds to not overwhelm them.
+const MIN_DNS_TTL: Duration = Duration::from_secs(5);
+
+/// Cache DNS responses from our upstream nameservers for at most 5 minutes, regardless of their TTL.
+const MAX_DNS_TTL: Duration = Duration::from_secs(300);
 
 // DNS resolution happens as part of every connection setup.
 // For a connection to succeed, DNS resolution must be less than `snownet`'s handshake timeout.
@@ -44,42 +53,58 @@ enum ResolveTrigger {
     RequestConnection(RequestConnection), // Deprecated
     AllowAccess(AllowAccess),             // Deprecated
     SetupNat(ResolveDnsRequest),
+    RefreshNat(ResolveDnsRequest),
 }
 
 pub struct Eventloop {
     tunnel: GatewayTunnel,
-    portal: PhoenixChannel<(), IngressMessages, (), PublicKeyParam>,
+    /// Our connection to the portal, absent if we are driven by a local config file instead.
+    portal: Option<PhoenixChannel<(), IngressMessages, (), PublicKeyParam>>,
+    local_config: Option<LocalConfigSource>,
     tun_device_manager: Arc<Mutex<TunDeviceManager>>,
 
     resolve_tasks:
-        futures_bounded::FuturesTupleSet<Result<Vec<IpAddr>, Arc<anyhow::Error>>, ResolveTrigger>,
-    dns_cache: moka::future::Cache<DomainName, Vec<IpAddr>>,
+        futures_bounded::FuturesTupleSet<Result<ResolvedIps, Arc<anyhow::Error>>, ResolveTrigger>,
+    dns_cache: moka::future::Cache<DomainName, CachedIps>,
 
     set_interface_tasks: futures_bounded::FuturesSet<Result<Interface>>,
 
+    flow_log: Option<FlowLogExporter>,
+
+    /// Whether to resolve DNS resources via our upstream nameservers instead of the system resolver.
+    resolve_via_upstreams: bool,
+
     logged_permission_denied: bool,
 }
 
 impl Eventloop {
     pub(crate) fn new(
         tunnel: GatewayTunnel,
-        mut portal: PhoenixChannel<(), IngressMessages, (), PublicKeyParam>,
+        mut portal: Option<PhoenixChannel<(), IngressMessages, (), PublicKeyParam>>,
+        local_config: Option<LocalConfigSource>,
         tun_device_manager: TunDeviceManager,
+        flow_log: Option<FlowLogExporter>,
+        resolve_via_upstreams: bool,
     ) -> Self {
-        portal.connect(PublicKeyParam(tunnel.public_key().to_bytes()));
+        if let Some(portal) = portal.as_mut() {
+            portal.connect(PublicKeyParam(tunnel.public_key().to_bytes()));
+        }
 
         Self {
             tunnel,
             portal,
+            local_config,
             tun_device_manager: Arc::new(Mutex::new(tun_device_manager)),
             resolve_tasks: futures_bounded::FuturesTupleSet::new(DNS_RESOLUTION_TIMEOUT, 1000),
             set_interface_tasks: futures_bounded::FuturesSet::new(Duration::from_secs(5), 10),
             logged_permission_denied: false,
+            flow_log,
+            resolve_via_upstreams,
             dns_cache: moka::future::Cache::builder()
                 .name("DNS queries")
-                .time_to_live(DNS_TTL)
-                .eviction_listener(|domain, ips, cause| {
-                    tracing::debug!(%domain, ?ips, ?cause, "DNS cache entry evicted");
+                .expire_after(RecordTtl)
+                .eviction_listener(|domain, cached: CachedIps, cause| {
+                    tracing::debug!(%domain, ips = ?cached.resolved.ips, ?cause, "DNS cache entry evicted");
                 })
                 .build(),
         }
@@ -147,17 +172,17 @@ impl Eventloop {
                 )
             }) {
                 Poll::Ready((result, ResolveTrigger::RequestConnection(req))) => {
-                    self.accept_connection(result, req);
+                    self.accept_connection(result.map(|r| r.ips), req);
                     continue;
                 }
                 Poll::Ready((result, ResolveTrigger::AllowAccess(req))) => {
-                    self.allow_access(result, req);
+                    self.allow_access(result.map(|r| r.ips), req);
                     continue;
                 }
                 Poll::Ready((result, ResolveTrigger::SetupNat(request))) => {
                     if let Err(e) = self.tunnel.state_mut().handle_domain_resolved(
                         request,
-                        result,
+                        result.map(|r| r.ips),
                         Instant::now(),
                     ) {
                         tracing::warn!("Failed to set DNS resource NAT: {e:#}");
@@ -165,6 +190,17 @@ impl Eventloop {
 
                     continue;
                 }
+                Poll::Ready((result, ResolveTrigger::RefreshNat(request))) => {
+                    if let Err(e) = self.tunnel.state_mut().handle_domain_refreshed(
+                        request,
+                        result,
+                        Instant::now(),
+                    ) {
+                        tracing::warn!("Failed to refresh DNS resource NAT: {e:#}");
+                    };
+
+                    continue;
+                }
                 Poll::Pending => {}
             }
 
@@ -194,14 +230,29 @@ impl Eventloop {
                 Poll::Pending => {}
             }
 
-            match self.portal.poll(cx) {
-                Poll::Ready(result) => {
-                    let event = result.context("Failed to login to portal")?;
-                    self.handle_portal_event(event);
+            if let Some(portal) = self.portal.as_mut() {
+                match portal.poll(cx) {
+                    Poll::Ready(result) => {
+                        let event = result.context("Failed to login to portal")?;
+                        self.handle_portal_event(event);
 
-                    continue;
+                        continue;
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            if let Some(local_config) = self.local_config.as_mut() {
+                match local_config.poll(cx) {
+                    Poll::Ready(messages) => {
+                        for msg in messages {
+                            self.handle_ingress_message(msg);
+                        }
+
+                        continue;
+                    }
+                    Poll::Pending => {}
                 }
-                Poll::Pending => {}
             }
 
             return Poll::Pending;
@@ -214,25 +265,28 @@ impl Eventloop {
                 conn_id: client,
                 candidates,
             } => {
-                self.portal.send(
-                    PHOENIX_TOPIC,
-                    EgressMessages::BroadcastIceCandidates(ClientsIceCandidates {
+                if self.portal.is_none() {
+                    // Without a portal, the operator has to hand these to the client.
+                    tracing::info!(%client, ?candidates, "New ICE candidates");
+                }
+
+                self.send_to_portal(EgressMessages::BroadcastIceCandidates(
+                    ClientsIceCandidates {
                         client_ids: vec![client],
                         candidates,
-                    }),
-                );
+                    },
+                ));
             }
             firezone_tunnel::GatewayEvent::RemovedIceCandidates {
                 conn_id: client,
                 candidates,
             } => {
-                self.portal.send(
-                    PHOENIX_TOPIC,
-                    EgressMessages::BroadcastInvalidatedIceCandidates(ClientsIceCandidates {
+                self.send_to_portal(EgressMessages::BroadcastInvalidatedIceCandidates(
+                    ClientsIceCandidates {
                         client_ids: vec![client],
                         candidates,
-                    }),
-                );
+                    },
+                ));
             }
             firezone_tunnel::GatewayEvent::ResolveDns(setup_nat) => {
                 if self
@@ -246,15 +300,31 @@ impl Eventloop {
                     tracing::warn!("Too many dns resolution requests, dropping existing one");
                 };
             }
+            firezone_tunnel::GatewayEvent::RefreshDns(refresh_nat) => {
+                if self
+                    .resolve_tasks
+                    .try_push(
+                        self.resolve(refresh_nat.domain().clone()),
+                        ResolveTrigger::RefreshNat(refresh_nat),
+                    )
+                    .is_err()
+                {
+                    tracing::warn!("Too many dns resolution requests, dropping existing one");
+                };
+            }
+            firezone_tunnel::GatewayEvent::FlowLog(record) => {
+                let Some(flow_log) = self.flow_log.as_mut() else {
+                    return;
+                };
+
+                flow_log.export(record);
+            }
         }
     }
 
-    fn handle_portal_event(&mut self, event: phoenix_channel::Event<IngressMessages, ()>) {
-        match event {
-            phoenix_channel::Event::InboundMessage {
-                msg: IngressMessages::AuthorizeFlow(msg),
-                ..
-            } => {
+    fn handle_ingress_message(&mut self, msg: IngressMessages) {
+        match msg {
+            IngressMessages::AuthorizeFlow(msg) => {
                 if let Err(snownet::NoTurnServers {}) = self.tunnel.state_mut().authorize_flow(
                     msg.client.id,
                     PublicKey::from(msg.client.public_key.0),
@@ -271,23 +341,15 @@ impl Eventloop {
                 ) {
                     tracing::debug!("Failed to authorise flow: No TURN servers available");
 
-                    // Re-connecting to the portal means we will receive another `init` and thus new TURN servers.
-                    self.portal
-                        .connect(PublicKeyParam(self.tunnel.public_key().to_bytes()));
+                    self.reconnect_portal();
                     return;
                 };
 
-                self.portal.send(
-                    PHOENIX_TOPIC,
-                    EgressMessages::FlowAuthorized {
-                        reference: msg.reference,
-                    },
-                );
+                self.send_to_portal(EgressMessages::FlowAuthorized {
+                    reference: msg.reference,
+                });
             }
-            phoenix_channel::Event::InboundMessage {
-                msg: IngressMessages::RequestConnection(req),
-                ..
-            } => {
+            IngressMessages::RequestConnection(req) => {
                 let Some(domain) = req.client.payload.domain.as_ref().map(|r| r.name.clone())
                 else {
                     self.accept_connection(Ok(vec![]), req);
@@ -302,10 +364,7 @@ impl Eventloop {
                     tracing::warn!("Too many connections requests, dropping existing one");
                 };
             }
-            phoenix_channel::Event::InboundMessage {
-                msg: IngressMessages::AllowAccess(req),
-                ..
-            } => {
+            IngressMessages::AllowAccess(req) => {
                 let Some(domain) = req.payload.as_ref().map(|r| r.name.clone()) else {
                     self.allow_access(Ok(vec![]), req);
                     return;
@@ -319,28 +378,20 @@ impl Eventloop {
                     tracing::warn!("Too many allow access requests, dropping existing one");
                 };
             }
-            phoenix_channel::Event::InboundMessage {
-                msg:
-                    IngressMessages::IceCandidates(ClientIceCandidates {
-                        client_id,
-                        candidates,
-                    }),
-                ..
-            } => {
+            IngressMessages::IceCandidates(ClientIceCandidates {
+                client_id,
+                candidates,
+            }) => {
                 for candidate in candidates {
                     self.tunnel
                         .state_mut()
                         .add_ice_candidate(client_id, candidate, Instant::now());
                 }
             }
-            phoenix_channel::Event::InboundMessage {
-                msg:
-                    IngressMessages::InvalidateIceCandidates(ClientIceCandidates {
-                        client_id,
-                        candidates,
-                    }),
-                ..
-            } => {
+            IngressMessages::InvalidateIceCandidates(ClientIceCandidates {
+                client_id,
+                candidates,
+            }) => {
                 for candidate in candidates {
                     self.tunnel.state_mut().remove_ice_candidate(
                         client_id,
@@ -349,34 +400,23 @@ impl Eventloop {
                     );
                 }
             }
-            phoenix_channel::Event::InboundMessage {
-                msg:
-                    IngressMessages::RejectAccess(RejectAccess {
-                        client_id,
-                        resource_id,
-                    }),
-                ..
-            } => {
+            IngressMessages::RejectAccess(RejectAccess {
+                client_id,
+                resource_id,
+            }) => {
                 self.tunnel
                     .state_mut()
                     .remove_access(&client_id, &resource_id);
             }
-            phoenix_channel::Event::InboundMessage {
-                msg:
-                    IngressMessages::RelaysPresence(RelaysPresence {
-                        disconnected_ids,
-                        connected,
-                    }),
-                ..
-            } => self.tunnel.state_mut().update_relays(
+            IngressMessages::RelaysPresence(RelaysPresence {
+                disconnected_ids,
+                connected,
+            }) => self.tunnel.state_mut().update_relays(
                 BTreeSet::from_iter(disconnected_ids),
                 firezone_tunnel::turn(&connected),
                 Instant::now(),
             ),
-            phoenix_channel::Event::InboundMessage {
-                msg: IngressMessages::Init(init),
-                ..
-            } => {
+            IngressMessages::Init(init) => {
                 self.tunnel.state_mut().update_relays(
                     BTreeSet::default(),
                     firezone_tunnel::turn(&init.relays),
@@ -412,14 +452,17 @@ impl Eventloop {
                     tracing::warn!("Too many 'Update TUN device' tasks");
                 };
             }
-            phoenix_channel::Event::InboundMessage {
-                msg: IngressMessages::ResourceUpdated(resource_description),
-                ..
-            } => {
+            IngressMessages::ResourceUpdated(resource_description) => {
                 self.tunnel
                     .state_mut()
                     .update_resource(resource_description);
             }
+        }
+    }
+
+    fn handle_portal_event(&mut self, event: phoenix_channel::Event<IngressMessages, ()>) {
+        match event {
+            phoenix_channel::Event::InboundMessage { msg, .. } => self.handle_ingress_message(msg),
             phoenix_channel::Event::ErrorResponse { topic, req_id, res } => {
                 tracing::warn!(%topic, %req_id, "Request failed: {res:?}");
             }
@@ -437,6 +480,25 @@ impl Eventloop {
         }
     }
 
+    fn send_to_portal(&mut self, msg: EgressMessages) {
+        let Some(portal) = self.portal.as_mut() else {
+            tracing::debug!(?msg, "Not connected to a portal; discarding message");
+            return;
+        };
+
+        portal.send(PHOENIX_TOPIC, msg);
+    }
+
+    fn reconnect_portal(&mut self) {
+        let Some(portal) = self.portal.as_mut() else {
+            tracing::warn!("No TURN servers available; check the relays in the local config");
+            return;
+        };
+
+        // Re-connecting to the portal means we will receive another `init` and thus new TURN servers.
+        portal.connect(PublicKeyParam(self.tunnel.public_key().to_bytes()));
+    }
+
     pub fn accept_connection(
         &mut self,
         result: Result<Vec<IpAddr>, Arc<anyhow::Error>>,
@@ -464,9 +526,7 @@ impl Eventloop {
             Err(snownet::NoTurnServers {}) => {
                 tracing::debug!("Failed to accept new connection: No TURN servers available");
 
-                // Re-connecting to the portal means we will receive another `init` and thus new TURN servers.
-                self.portal
-                    .connect(PublicKeyParam(self.tunnel.public_key().to_bytes()));
+                self.reconnect_portal();
 
                 return;
             }
@@ -484,6 +544,7 @@ impl Eventloop {
                 .payload
                 .domain
                 .map(|r| DnsResourceNatEntry::new(r, addresses)),
+            Instant::now(),
         ) {
             let client = req.client.id;
 
@@ -492,15 +553,12 @@ impl Eventloop {
             return;
         }
 
-        self.portal.send(
-            PHOENIX_TOPIC,
-            EgressMessages::ConnectionReady(ConnectionReady {
-                reference: req.reference,
-                gateway_payload: GatewayResponse::ConnectionAccepted(ConnectionAccepted {
-                    ice_parameters: answer,
-                }),
+        self.send_to_portal(EgressMessages::ConnectionReady(ConnectionReady {
+            reference: req.reference,
+            gateway_payload: GatewayResponse::ConnectionAccepted(ConnectionAccepted {
+                ice_parameters: answer,
             }),
-        );
+        }));
     }
 
     pub fn allow_access(
@@ -528,24 +586,81 @@ impl Eventloop {
             req.expires_at,
             req.resource,
             req.payload.map(|r| DnsResourceNatEntry::new(r, addresses)),
+            Instant::now(),
         ) {
             tracing::warn!(client = %req.client_id, "Allow access request failed: {e:#}");
         };
     }
 
+    /// Resolves the given domain, either from our cache or via DNS.
+    ///
+    /// The TTL of the returned [`ResolvedIps`] is the time remaining until the cache entry expires.
     fn resolve(
         &self,
         domain: DomainName,
-    ) -> impl Future<Output = Result<Vec<IpAddr>, Arc<anyhow::Error>>> + use<> {
-        let do_resolve = resolve(domain.clone());
+    ) -> impl Future<Output = Result<ResolvedIps, Arc<anyhow::Error>>> + use<> {
+        let upstream = self
+            .resolve_via_upstreams
+            .then(|| self.tunnel.resolve(domain.clone()))
+            .flatten();
         let cache = self.dns_cache.clone();
 
-        async move { cache.try_get_with(domain, do_resolve).await }
+        async move {
+            let cached = cache
+                .try_get_with(domain.clone(), async move {
+                    let resolved = match upstream {
+                        Some(upstream) => upstream.await,
+                        None => resolve(domain).await, // No upstreams configured or we don't know the fastest one yet.
+                    }?;
+
+                    anyhow::Ok(CachedIps {
+                        resolved,
+                        resolved_at: Instant::now(),
+                    })
+                })
+                .await?;
+
+            Ok(ResolvedIps {
+                ips: cached.resolved.ips.clone(),
+                ttl: cached.remaining_ttl(Instant::now()),
+            })
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedIps {
+    resolved: ResolvedIps,
+    resolved_at: Instant,
+}
+
+impl CachedIps {
+    fn ttl(&self) -> Duration {
+        self.resolved.ttl.clamp(MIN_DNS_TTL, MAX_DNS_TTL)
+    }
+
+    fn remaining_ttl(&self, now: Instant) -> Duration {
+        self.ttl()
+            .saturating_sub(now.duration_since(self.resolved_at))
+    }
+}
+
+/// Expires cached DNS responses according to the TTL of their records.
+struct RecordTtl;
+
+impl moka::Expiry<DomainName, CachedIps> for RecordTtl {
+    fn expire_after_create(
+        &self,
+        _: &DomainName,
+        cached: &CachedIps,
+        _: std::time::Instant,
+    ) -> Option<Duration> {
+        Some(cached.ttl())
     }
 }
 
-async fn resolve(domain: DomainName) -> Result<Vec<IpAddr>> {
-    tracing::debug!(%domain, "Resolving DNS");
+async fn resolve(domain: DomainName) -> Result<ResolvedIps> {
+    tracing::debug!(%domain, "Resolving DNS via system resolver");
 
     let dname = domain.to_string();
 
@@ -555,7 +670,10 @@ async fn resolve(domain: DomainName) -> Result<Vec<IpAddr>> {
         .context("DNS resolution task failed")?
         .context("DNS resolution failed")?;
 
-    Ok(addresses)
+    Ok(ResolvedIps {
+        ips: addresses,
+        ttl: DEFAULT_DNS_TTL,
+    })
 }
 
 #[cfg(target_os = "windows")]
@@ -0,0 +1,145 @@
+use anyhow::{Context as _, Result};
+use firezone_bin_shared::json_lines::JsonLinesWriter;
+use firezone_telemetry::otel;
+use firezone_tunnel::FlowRecord;
+use opentelemetry::logs::{AnyValue, LogRecord as _, Logger as _, LoggerProvider as _, Severity};
+use opentelemetry_sdk::logs::{SdkLogger, SdkLoggerProvider};
+use std::path::PathBuf;
+
+/// Rotate the flow log once it exceeds this size.
+const MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+/// How many rotated flow logs we keep around in addition to the active one.
+const MAX_ROTATED_FILES: usize = 10;
+
+const FILE_NAME: &str = "flows.jsonl";
+
+/// Exports [`FlowRecord`]s to a rotating JSON-lines file and / or an OTLP collector.
+///
+/// Neither blocks the caller: The file is written on a dedicated thread and the OTLP exporter batches records in the background.
+pub struct FlowLogExporter {
+    file: Option<JsonLinesWriter<FlowRecord>>,
+    otlp: Option<(SdkLoggerProvider, SdkLogger)>,
+}
+
+impl FlowLogExporter {
+    pub fn new(
+        dir: Option<PathBuf>,
+        otlp_grpc_endpoint: Option<String>,
+        firezone_id: String,
+    ) -> Result<Self> {
+        let file = dir
+            .map(|dir| {
+                JsonLinesWriter::new(
+                    "flow log",
+                    dir.join(FILE_NAME),
+                    MAX_FILE_SIZE,
+                    MAX_ROTATED_FILES,
+                )
+            })
+            .transpose()?;
+        let otlp = otlp_grpc_endpoint
+            .map(|endpoint| otlp_logger(endpoint, firezone_id))
+            .transpose()?;
+
+        Ok(Self { file, otlp })
+    }
+
+    pub fn export(&mut self, record: FlowRecord) {
+        if let Some((_, logger)) = self.otlp.as_ref() {
+            emit(logger, &record);
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            if let Err(e) = file.write(record) {
+                tracing::warn!("Failed to write flow log: {e:#}");
+
+                self.file = None;
+            }
+        }
+    }
+}
+
+impl Drop for FlowLogExporter {
+    fn drop(&mut self) {
+        if let Some((provider, _)) = self.otlp.take() {
+            if let Err(e) = provider.shutdown() {
+                tracing::debug!("Failed to shutdown OTLP flow log exporter: {e}");
+            }
+        }
+    }
+}
+
+fn otlp_logger(endpoint: String, firezone_id: String) -> Result<(SdkLoggerProvider, SdkLogger)> {
+    use opentelemetry_otlp::WithExportConfig as _;
+
+    let exporter = opentelemetry_otlp::LogExporter::builder()
+        .with_tonic()
+        .with_endpoint(otlp_endpoint_url(endpoint))
+        .build()
+        .context("Failed to build OTLP log exporter")?;
+
+    let provider = SdkLoggerProvider::builder()
+        .with_resource(otel::default_resource_with([
+            otel::attr::service_name!(),
+            otel::attr::service_version!(),
+            otel::attr::service_instance_id(firezone_id),
+        ]))
+        .with_batch_exporter(exporter)
+        .build();
+    let logger = provider.logger("flow-log");
+
+    Ok((provider, logger))
+}
+
+/// Endpoints without a scheme are assumed to be plaintext for backwards-compatibility.
+fn otlp_endpoint_url(endpoint: String) -> String {
+    if endpoint.contains("://") {
+        return endpoint;
+    }
+
+    format!("http://{endpoint}")
+}
+
+fn emit(logger: &SdkLogger, record: &FlowRecord) {
+    let mut log = logger.create_log_record();
+
+    log.set_event_name("flow");
+    log.set_severity_number(Severity::Info);
+    log.set_timestamp(record.end.into());
+
+    match serde_json::to_string(record) {
+        Ok(body) => log.set_body(AnyValue::from(body)),
+        Err(e) => {
+            tracing::debug!("Failed to serialize flow record: {e}");
+            return;
+        }
+    }
+
+    log.add_attribute("client.id", record.client_id.to_string());
+    if let Some(resource_id) = record.resource_id {
+        log.add_attribute("resource.id", resource_id.to_string());
+    }
+
+    logger.emit(log);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn otlp_endpoint_defaults_to_http() {
+        assert_eq!(
+            otlp_endpoint_url("collector:4317".to_owned()),
+            "http://collector:4317"
+        );
+    }
+
+    #[test]
+    fn otlp_endpoint_keeps_scheme() {
+        assert_eq!(
+            otlp_endpoint_url("https://collector:4317".to_owned()),
+            "https://collector:4317"
+        );
+    }
+}
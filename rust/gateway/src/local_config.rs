@@ -0,0 +1,299 @@
+//! A declarative config file that drives the gateway without a portal.
+//!
+//! The file describes the gateway's interface, the relays, the resources and which clients may access them.
+//! It is translated into the same [`IngressMessages`] the portal would send us.
+use anyhow::{Context as _, Result, bail};
+use connlib_model::{ClientId, RelayId, ResourceId};
+use firezone_tunnel::messages::gateway::{
+    AuthorizeFlow, Client, ClientIceCandidates, Config, IngressMessages, InitGateway, RejectAccess,
+    ResourceDescription,
+};
+use firezone_tunnel::messages::{IceCredentials, Interface, Relay, RelaysPresence};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::task::{Context, Poll};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocalConfig {
+    interface: Interface,
+    #[serde(default)]
+    relays: Vec<Relay>,
+    #[serde(default)]
+    resources: Vec<ResourceDescription>,
+    #[serde(default)]
+    clients: Vec<LocalClient>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LocalClient {
+    #[serde(flatten)]
+    client: Client,
+    /// The ICE credentials of the client.
+    ice_credentials: IceCredentials,
+    /// The ICE credentials we use for the connection to this client.
+    gateway_ice_credentials: IceCredentials,
+    /// The ICE candidates of the client, in SDP format.
+    #[serde(default)]
+    candidates: Vec<String>,
+    /// The resources this client is allowed to access.
+    resources: BTreeSet<ResourceId>,
+}
+
+impl LocalConfig {
+    pub fn parse(content: &str) -> Result<Self> {
+        let config = toml::from_str::<Self>(content)?;
+
+        let resources = config
+            .resources
+            .iter()
+            .map(|r| r.id())
+            .collect::<BTreeSet<_>>();
+
+        for client in &config.clients {
+            if let Some(unknown) = client.resources.difference(&resources).next() {
+                bail!(
+                    "Client {} references unknown resource {unknown}",
+                    client.client.id
+                );
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Computes the messages that transition the gateway from the `previous` config to this one.
+    fn messages(&self, previous: Option<&LocalConfig>) -> Vec<IngressMessages> {
+        let mut messages = Vec::new();
+
+        match previous {
+            Some(previous) if previous.interface == self.interface => {
+                let relays = self.relay_ids();
+
+                messages.push(IngressMessages::RelaysPresence(RelaysPresence {
+                    disconnected_ids: previous.relay_ids().difference(&relays).copied().collect(),
+                    connected: self.relays.clone(),
+                }));
+            }
+            Some(_) | None => {
+                messages.push(IngressMessages::Init(InitGateway {
+                    interface: self.interface.clone(),
+                    config: Config {
+                        ipv4_masquerade_enabled: true,
+                        ipv6_masquerade_enabled: true,
+                    },
+                    relays: self.relays.clone(),
+                }));
+            }
+        }
+
+        if let Some(previous) = previous {
+            let access = self.access();
+
+            for (client_id, resource_id) in previous.access() {
+                if access.contains(&(client_id, resource_id)) {
+                    continue;
+                }
+
+                messages.push(IngressMessages::RejectAccess(RejectAccess {
+                    client_id,
+                    resource_id,
+                }));
+            }
+
+            for resource in &self.resources {
+                messages.push(IngressMessages::ResourceUpdated(resource.clone()));
+            }
+        }
+
+        let resources = self
+            .resources
+            .iter()
+            .map(|r| (r.id(), r))
+            .collect::<BTreeMap<_, _>>();
+
+        for client in &self.clients {
+            for resource_id in &client.resources {
+                let resource = resources[resource_id];
+
+                messages.push(IngressMessages::AuthorizeFlow(AuthorizeFlow {
+                    reference: "local".to_owned(),
+                    resource: resource.clone(),
+                    gateway_ice_credentials: client.gateway_ice_credentials.clone(),
+                    client: client.client.clone(),
+                    client_ice_credentials: client.ice_credentials.clone(),
+                    expires_at: None,
+                }));
+            }
+
+            if !client.candidates.is_empty() {
+                messages.push(IngressMessages::IceCandidates(ClientIceCandidates {
+                    client_id: client.client.id,
+                    candidates: client.candidates.clone(),
+                }));
+            }
+        }
+
+        messages
+    }
+
+    fn relay_ids(&self) -> BTreeSet<RelayId> {
+        self.relays
+            .iter()
+            .map(|r| match r {
+                Relay::Stun(stun) => stun.id,
+                Relay::Turn(turn) => turn.id,
+            })
+            .collect()
+    }
+
+    fn access(&self) -> BTreeSet<(ClientId, ResourceId)> {
+        self.clients
+            .iter()
+            .flat_map(|c| c.resources.iter().map(|r| (c.client.id, *r)))
+            .collect()
+    }
+}
+
+/// Feeds a [`LocalConfig`] into the event-loop and reloads it on `SIGHUP`.
+pub struct LocalConfigSource {
+    path: PathBuf,
+    current: LocalConfig,
+    initial: Option<Vec<IngressMessages>>,
+
+    #[cfg(unix)]
+    sighup: tokio::signal::unix::Signal,
+}
+
+impl LocalConfigSource {
+    pub fn new(path: PathBuf) -> Result<Self> {
+        let current = read(&path)?;
+        let initial = current.messages(None);
+
+        Ok(Self {
+            path,
+            current,
+            initial: Some(initial),
+            #[cfg(unix)]
+            sighup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+                .context("Failed to listen for SIGHUP")?,
+        })
+    }
+
+    pub fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Vec<IngressMessages>> {
+        if let Some(initial) = self.initial.take() {
+            return Poll::Ready(initial);
+        }
+
+        #[cfg(unix)]
+        while let Poll::Ready(Some(())) = self.sighup.poll_recv(cx) {
+            match read(&self.path) {
+                Ok(new) => {
+                    tracing::info!(path = %self.path.display(), "Reloaded local config");
+
+                    let messages = new.messages(Some(&self.current));
+                    self.current = new;
+
+                    return Poll::Ready(messages);
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to reload local config; keeping the current one: {e:#}")
+                }
+            }
+        }
+
+        #[cfg(not(unix))]
+        let _ = cx;
+
+        Poll::Pending
+    }
+}
+
+fn read(path: &Path) -> Result<LocalConfig> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read local config from {}", path.display()))?;
+
+    LocalConfig::parse(&content)
+        .with_context(|| format!("Failed to parse local config at {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [interface]
+        ipv4 = "100.64.0.1"
+        ipv6 = "fd00:2021:1111::1"
+
+        [[relays]]
+        type = "turn"
+        id = "c9cb8892-e355-41e6-a882-b6d6c38beb66"
+        expires_at = 1686629954
+        addr = "172.28.0.101:3478"
+        username = "1719367575:ZQHcVGkdnfgGmcP1"
+        password = "ZWYiBeFHOJyYq0mcwAXjRpcuXIJJpzWlOXVdxwttrWg"
+
+        [[resources]]
+        type = "cidr"
+        id = "73037362-715d-4a83-a749-f18eadd970e6"
+        address = "172.172.0.0/16"
+        name = "Lab network"
+        filters = [{ protocol = "tcp", port_range_start = 22, port_range_end = 22 }]
+
+        [[clients]]
+        id = "3b9a4d36-e2a1-45d8-b0c4-1a4ff1f5e3d1"
+        public_key = "6Y0eKMY8UfRn2N4U9iPmK1nKNCt2FpmIqVGmbvHSkyM="
+        preshared_key = "bvUcB/gx7mMHz2+lHnZUdhlj4H8TuHfTjRYPRM1wvXk="
+        ipv4 = "100.64.0.2"
+        ipv6 = "fd00:2021:1111::2"
+        ice_credentials = { username = "client", password = "client-password" }
+        gateway_ice_credentials = { username = "gateway", password = "gateway-password" }
+        resources = ["73037362-715d-4a83-a749-f18eadd970e6"]
+    "#;
+
+    #[test]
+    fn initial_config_authorizes_flows() {
+        let config = LocalConfig::parse(CONFIG).unwrap();
+
+        let messages = config.messages(None);
+
+        assert!(matches!(messages[0], IngressMessages::Init(_)));
+        assert!(matches!(messages[1], IngressMessages::AuthorizeFlow(_)));
+        assert_eq!(messages.len(), 2);
+    }
+
+    #[test]
+    fn rejects_unknown_resource() {
+        let config = CONFIG.replace(
+            r#"resources = ["73037362-715d-4a83-a749-f18eadd970e6"]"#,
+            r#"resources = ["03000143-e25e-45c7-aafb-144990e57dcd"]"#,
+        );
+
+        assert!(LocalConfig::parse(&config).is_err());
+    }
+
+    #[test]
+    fn reload_rejects_removed_access() {
+        let previous = LocalConfig::parse(CONFIG).unwrap();
+        let current = LocalConfig::parse(&CONFIG.replace(
+            r#"resources = ["73037362-715d-4a83-a749-f18eadd970e6"]"#,
+            "resources = []",
+        ))
+        .unwrap();
+
+        let messages = current.messages(Some(&previous));
+
+        assert!(
+            messages
+                .iter()
+                .any(|m| matches!(m, IngressMessages::RejectAccess(_)))
+        );
+        assert!(
+            !messages
+                .iter()
+                .any(|m| matches!(m, IngressMessages::AuthorizeFlow(_)))
+        );
+    }
+}
@@ -3,6 +3,8 @@
 static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
 use crate::eventloop::{Eventloop, PHOENIX_TOPIC};
+use crate::flow_log::FlowLogExporter;
+use crate::local_config::LocalConfigSource;
 use anyhow::{Context, Result};
 use backoff::ExponentialBackoffBuilder;
 use clap::Parser;
@@ -13,6 +15,7 @@ use firezone_bin_shared::{
 
 use firezone_telemetry::{Telemetry, otel};
 use firezone_tunnel::GatewayTunnel;
+use firezone_tunnel::messages::gateway::{RateLimit, RateLimitAction};
 use ip_packet::IpPacket;
 use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
 use phoenix_channel::LoginUrl;
@@ -21,8 +24,9 @@ use phoenix_channel::get_user_agent;
 use futures::{TryFutureExt, future};
 use phoenix_channel::PhoenixChannel;
 use secrecy::Secret;
+use std::net::IpAddr;
 use std::sync::Arc;
-use std::{collections::BTreeSet, path::Path};
+use std::{collections::BTreeSet, path::Path, path::PathBuf};
 use std::{fmt, pin::pin};
 use std::{process::ExitCode, str::FromStr};
 use tokio::io::AsyncWriteExt;
@@ -33,6 +37,8 @@ use url::Url;
 use uuid::Uuid;
 
 mod eventloop;
+mod flow_log;
+mod local_config;
 
 const ID_PATH: &str = "/var/lib/firezone/gateway_id";
 
@@ -113,52 +119,88 @@ async fn try_main(cli: Cli) -> Result<ExitCode> {
         .context("Couldn't read FIREZONE_ID or write it to disk: Please provide it through the env variable or provide rw access to /var/lib/firezone/")?;
     Telemetry::set_firezone_id(firezone_id.clone());
 
-    if cli.metrics {
-        let exporter = opentelemetry_stdout::MetricExporter::default();
-        let reader = PeriodicReader::builder(exporter).build();
-        let provider = SdkMeterProvider::builder()
-            .with_reader(reader)
-            .with_resource(otel::default_resource_with([
+    if cli.metrics || cli.health_check.metrics_addr.is_some() {
+        let mut meter_provider =
+            SdkMeterProvider::builder().with_resource(otel::default_resource_with([
                 otel::attr::service_name!(),
                 otel::attr::service_version!(),
                 otel::attr::service_instance_id(firezone_id.clone()),
-            ]))
-            .build();
+            ]));
 
-        opentelemetry::global::set_meter_provider(provider);
+        if cli.metrics {
+            let exporter = opentelemetry_stdout::MetricExporter::default();
+            meter_provider = meter_provider.with_reader(PeriodicReader::builder(exporter).build());
+        }
+
+        if let Some(addr) = cli.health_check.metrics_addr {
+            let (registry, exporter) = http_health_check::prometheus_registry()?;
+            meter_provider = meter_provider.with_reader(exporter);
+
+            tokio::spawn(http_health_check::serve(addr, || true, Some(registry)));
+        }
+
+        opentelemetry::global::set_meter_provider(meter_provider.build());
     }
 
-    let login = LoginUrl::gateway(cli.api_url, &cli.token, firezone_id, cli.firezone_name)
-        .context("Failed to construct URL for logging into portal")?;
+    let flow_log = if cli.flow_log_dir.is_some() || cli.flow_log_otlp_grpc_endpoint.is_some() {
+        Some(
+            FlowLogExporter::new(
+                cli.flow_log_dir,
+                cli.flow_log_otlp_grpc_endpoint,
+                firezone_id.clone(),
+            )
+            .context("Failed to set up flow logs")?,
+        )
+    } else {
+        None
+    };
 
-    let resolv_conf = resolv_conf::Config::parse(
-        std::fs::read_to_string("/etc/resolv.conf").context("Failed to read /etc/resolv.conf")?,
-    )
-    .context("Failed to parse /etc/resolv.conf")?;
-    let nameservers = resolv_conf
-        .nameservers
-        .into_iter()
-        .map(|ip| ip.into())
-        .collect::<BTreeSet<_>>();
+    let (portal, local_config) = match (cli.local_config, cli.token) {
+        (Some(path), _) => {
+            tracing::info!(path = %path.display(), "Using local config instead of the portal");
+
+            let local_config =
+                LocalConfigSource::new(path).context("Failed to load local config")?;
+
+            (None, Some(local_config))
+        }
+        (None, Some(token)) => {
+            let login = LoginUrl::gateway(cli.api_url, &token, firezone_id, cli.firezone_name)
+                .context("Failed to construct URL for logging into portal")?;
+            let portal = PhoenixChannel::disconnected(
+                Secret::new(login),
+                get_user_agent(None, env!("CARGO_PKG_VERSION")),
+                PHOENIX_TOPIC,
+                (),
+                || {
+                    ExponentialBackoffBuilder::default()
+                        .with_max_elapsed_time(None)
+                        .build()
+                },
+                Arc::new(tcp_socket_factory),
+            )
+            .context("Failed to resolve portal URL")?;
+
+            (Some(portal), None)
+        }
+        (None, None) => unreachable!("clap enforces either a token or a local config"),
+    };
+
+    // DNS resources are only resolved via explicitly configured upstreams.
+    // Otherwise, the system resolver honours `/etc/hosts`, search domains and `nsswitch.conf`.
+    let resolve_via_upstreams = !cli.dns_upstreams.is_empty();
+    let nameservers = if cli.dns_upstreams.is_empty() {
+        system_nameservers()?
+    } else {
+        BTreeSet::from_iter(cli.dns_upstreams)
+    };
+    tracing::debug!(?nameservers, "Using upstream nameservers");
 
     let mut tunnel = GatewayTunnel::new(
         Arc::new(tcp_socket_factory),
         Arc::new(udp_socket_factory),
         nameservers,
     );
-    let portal = PhoenixChannel::disconnected(
-        Secret::new(login),
-        get_user_agent(None, env!("CARGO_PKG_VERSION")),
-        PHOENIX_TOPIC,
-        (),
-        || {
-            ExponentialBackoffBuilder::default()
-                .with_max_elapsed_time(None)
-                .build()
-        },
-        Arc::new(tcp_socket_factory),
-    )
-    .context("Failed to resolve portal URL")?;
 
     let mut tun_device_manager = TunDeviceManager::new(ip_packet::MAX_IP_SIZE, cli.tun_threads.0)
         .context("Failed to create TUN device manager")?;
@@ -166,6 +208,30 @@ async fn try_main(cli: Cli) -> Result<ExitCode> {
         .make_tun()
         .context("Failed to create TUN device")?;
 
+    if flow_log.is_some() {
+        tunnel.state_mut().enable_flow_logs();
+    }
+
+    if cli.dnssec_validation {
+        tunnel.state_mut().enable_dnssec_validation();
+    }
+
+    if !cli.dnssec_trust_anchors.is_empty() {
+        tunnel.set_dnssec_trust_anchors(cli.dnssec_trust_anchors);
+    }
+
+    tunnel
+        .state_mut()
+        .set_client_rate_limit(cli.client_rate_limit.map(|bytes_per_second| RateLimit {
+            bytes_per_second,
+            burst_bytes: cli.client_rate_limit_burst,
+            action: if cli.client_rate_limit_mark_ecn {
+                RateLimitAction::MarkEcn
+            } else {
+                RateLimitAction::Drop
+            },
+        }));
+
     if cli.validate_checksums {
         tunnel.set_tun(ValidateChecksumAdapter::wrap(tun));
     } else {
@@ -173,7 +239,14 @@ async fn try_main(cli: Cli) -> Result<ExitCode> {
     }
 
     let task = tokio::spawn(future::poll_fn({
-        let mut eventloop = Eventloop::new(tunnel, portal, tun_device_manager);
+        let mut eventloop = Eventloop::new(
+            tunnel,
+            portal,
+            local_config,
+            tun_device_manager,
+            flow_log,
+            resolve_via_upstreams,
+        );
 
         move |cx| eventloop.poll(cx)
     }))
@@ -183,6 +256,7 @@ async fn try_main(cli: Cli) -> Result<ExitCode> {
     tokio::spawn(http_health_check::serve(
         cli.health_check.health_check_addr,
         || true,
+        None,
     ));
 
     match future::try_select(task, ctrl_c)
@@ -202,6 +276,19 @@ async fn try_main(cli: Cli) -> Result<ExitCode> {
     }
 }
 
+fn system_nameservers() -> Result<BTreeSet<IpAddr>> {
+    let resolv_conf = resolv_conf::Config::parse(
+        std::fs::read_to_string("/etc/resolv.conf").context("Failed to read /etc/resolv.conf")?,
+    )
+    .context("Failed to parse /etc/resolv.conf")?;
+
+    Ok(resolv_conf
+        .nameservers
+        .into_iter()
+        .map(|ip| ip.into())
+        .collect())
+}
+
 async fn get_firezone_id(env_id: Option<String>) -> Result<String> {
     if let Some(id) = env_id {
         if !id.is_empty() {
@@ -235,12 +322,18 @@ struct Cli {
     )]
     api_url: Url,
     /// Token generated by the portal to authorize websocket connection.
-    #[arg(env = "FIREZONE_TOKEN")]
-    token: Secret<String>,
+    #[arg(env = "FIREZONE_TOKEN", required_unless_present = "local_config")]
+    token: Option<Secret<String>>,
     /// Friendly name to display in the UI
     #[arg(short = 'n', long, env = "FIREZONE_NAME")]
     firezone_name: Option<String>,
 
+    /// Drive the gateway from this TOML file instead of connecting to the portal.
+    ///
+    /// The file is reloaded on SIGHUP.
+    #[arg(long, env = "FIREZONE_LOCAL_CONFIG", conflicts_with = "token")]
+    local_config: Option<PathBuf>,
+
     /// Disable sentry.io crash-reporting agent.
     #[arg(long, env = "FIREZONE_NO_TELEMETRY", default_value_t = false)]
     no_telemetry: bool,
@@ -272,6 +365,71 @@ struct Cli {
         default_value_t = false
     )]
     validate_checksums: bool,
+
+    /// Write a log of all flows between clients and resources to this directory.
+    ///
+    /// The log is written as JSON lines and rotated once it reaches 100 MB.
+    #[arg(long, env = "FIREZONE_FLOW_LOG_DIR")]
+    flow_log_dir: Option<PathBuf>,
+
+    /// Export the flow log to this OTLP collector via gRPC.
+    ///
+    /// Use an `https://` URL to connect via TLS. Without a scheme, we connect via plaintext HTTP.
+    #[arg(long, hide = true, env = "FIREZONE_FLOW_LOG_OTLP_GRPC_ENDPOINT")]
+    flow_log_otlp_grpc_endpoint: Option<String>,
+
+    /// Limit the traffic of each client to this many bytes per second, in each direction.
+    #[arg(long, env = "FIREZONE_CLIENT_RATE_LIMIT")]
+    client_rate_limit: Option<u64>,
+
+    /// How many bytes a client may send in a single burst before the rate limit kicks in.
+    ///
+    /// Defaults to one second worth of traffic.
+    #[arg(
+        long,
+        env = "FIREZONE_CLIENT_RATE_LIMIT_BURST",
+        requires = "client_rate_limit"
+    )]
+    client_rate_limit_burst: Option<u64>,
+
+    /// Mark packets exceeding the rate limit with ECN "congestion experienced" instead of dropping them.
+    ///
+    /// Packets of transports that are not ECN-capable are always dropped.
+    /// Marking is capped at one additional burst; packets beyond that are dropped too.
+    #[arg(
+        long,
+        env = "FIREZONE_CLIENT_RATE_LIMIT_MARK_ECN",
+        default_value_t = false,
+        requires = "client_rate_limit"
+    )]
+    client_rate_limit_mark_ecn: bool,
+
+    /// Validate the DNS responses for names outside of DNS resources with DNSSEC.
+    ///
+    /// Responses that fail validation are answered with SERVFAIL.
+    #[arg(long, env = "FIREZONE_DNSSEC_VALIDATION", default_value_t = false)]
+    dnssec_validation: bool,
+
+    /// The DS records of the root zone to trust for DNSSEC validation, e.g. `20326 8 2 E06D44B8...`.
+    ///
+    /// Defaults to the root trust anchors published by IANA.
+    #[arg(
+        long = "dnssec-trust-anchor",
+        env = "FIREZONE_DNSSEC_TRUST_ANCHORS",
+        value_delimiter = ','
+    )]
+    dnssec_trust_anchors: Vec<dns_types::dnssec::Ds>,
+
+    /// Resolve DNS resources and the DNS queries of clients via these nameservers.
+    ///
+    /// If unset, DNS resources are resolved via the system resolver (`getaddrinfo`)
+    /// and the DNS queries of clients are forwarded to the nameservers in `/etc/resolv.conf`.
+    #[arg(
+        long = "dns-upstream",
+        env = "FIREZONE_DNS_UPSTREAMS",
+        value_delimiter = ','
+    )]
+    dns_upstreams: Vec<IpAddr>,
 }
 
 impl Cli {
@@ -407,6 +407,14 @@ impl<I: GuiIntegration> Controller<I> {
             token: token.expose_secret().clone(),
         })
         .await?;
+        self.send_ipc(&service::ClientMsg::SetDnsOverrides(
+            self.advanced_settings.dns_overrides.clone(),
+        ))
+        .await?;
+        self.send_ipc(&service::ClientMsg::SetDnsQueryLog(
+            self.advanced_settings.dns_query_log,
+        ))
+        .await?;
 
         // Change the status after we begin connecting
         self.status = Status::WaitingForPortal {
@@ -473,6 +481,14 @@ impl<I: GuiIntegration> Controller<I> {
                     directives: self.advanced_settings.log_filter.clone(),
                 })
                 .await?;
+                self.send_ipc(&service::ClientMsg::SetDnsOverrides(
+                    self.advanced_settings.dns_overrides.clone(),
+                ))
+                .await?;
+                self.send_ipc(&service::ClientMsg::SetDnsQueryLog(
+                    self.advanced_settings.dns_query_log,
+                ))
+                .await?;
 
                 tracing::debug!("Applied new settings. Log level will take effect immediately.");
 
@@ -3,9 +3,11 @@ use anyhow::{Context as _, Result, bail};
 use atomicwrites::{AtomicFile, OverwriteBehavior};
 use backoff::ExponentialBackoffBuilder;
 use client_shared::ConnlibMsg;
-use connlib_model::{ResourceId, ResourceView};
+use connlib_model::{DnsQueryLogEntry, ResourceId, ResourceView};
 use firezone_bin_shared::{
-    DnsControlMethod, DnsController, TunDeviceManager, device_id, device_info, known_dirs,
+    DnsControlMethod, DnsController, TunDeviceManager, device_id, device_info,
+    dns_query_log::DnsQueryLogWriter,
+    known_dirs,
     platform::{tcp_socket_factory, udp_socket_factory},
     signals,
 };
@@ -56,6 +58,10 @@ pub enum ClientMsg {
     },
     Reset,
     SetDns(Vec<IpAddr>),
+    /// The user's DNS overrides, in the format of [`client_shared::DnsOverrides`].
+    SetDnsOverrides(String),
+    /// Whether to log all DNS queries to a file in the Tunnel service's log dir.
+    SetDnsQueryLog(bool),
     SetDisabledResources(BTreeSet<ResourceId>),
     StartTelemetry {
         environment: String,
@@ -179,12 +185,15 @@ struct Handler<'a> {
 
 struct Session {
     cb_rx: mpsc::Receiver<ConnlibMsg>,
+    dns_query_rx: mpsc::Receiver<DnsQueryLogEntry>,
     connlib: client_shared::Session,
+    dns_query_log: Option<DnsQueryLogWriter>,
 }
 
 enum Event {
     Callback(ConnlibMsg),
     CallbackChannelClosed,
+    DnsQuery(DnsQueryLogEntry),
     Ipc(ClientMsg),
     IpcDisconnected,
     IpcError(anyhow::Error),
@@ -251,6 +260,12 @@ impl<'a> Handler<'a> {
                         continue;
                     }
                 }
+                Event::DnsQuery(entry) => {
+                    if let Err(error) = self.handle_dns_query(entry) {
+                        tracing::error!("Error while writing DNS query log: {error:#}");
+                        continue;
+                    }
+                }
                 Event::CallbackChannelClosed => {
                     tracing::error!("Impossible - Callback channel closed");
                     break HandlerOk::Err;
@@ -310,6 +325,12 @@ impl<'a> Handler<'a> {
                     None => Event::CallbackChannelClosed,
                 });
             }
+            if let Poll::Ready(option) = session.dns_query_rx.poll_recv(cx) {
+                return Poll::Ready(match option {
+                    Some(x) => Event::DnsQuery(x),
+                    None => Event::CallbackChannelClosed,
+                });
+            }
         }
         Poll::Pending
     }
@@ -356,6 +377,24 @@ impl<'a> Handler<'a> {
         Ok(())
     }
 
+    fn handle_dns_query(&mut self, entry: DnsQueryLogEntry) -> Result<()> {
+        let Some(session) = self.session.as_mut() else {
+            return Ok(());
+        };
+        let Some(writer) = session.dns_query_log.as_mut() else {
+            return Ok(());
+        };
+
+        if let Err(e) = writer.write(entry) {
+            session.connlib.set_dns_query_log(false);
+            session.dns_query_log = None;
+
+            return Err(e.context("Disabling DNS query log"));
+        }
+
+        Ok(())
+    }
+
     async fn handle_ipc_msg(&mut self, msg: ClientMsg) -> Result<()> {
         match msg {
             ClientMsg::ClearLogs => {
@@ -414,6 +453,36 @@ impl<'a> Handler<'a> {
                 tracing::debug!(?resolvers);
                 session.connlib.set_dns(resolvers);
             }
+            ClientMsg::SetDnsOverrides(overrides) => {
+                let Some(session) = self.session.as_ref() else {
+                    // The GUI sends the overrides again after signing in.
+                    tracing::debug!("Cannot set DNS overrides if we're signed out");
+                    return Ok(());
+                };
+
+                match overrides.parse() {
+                    Ok(overrides) => session.connlib.set_dns_overrides(overrides),
+                    Err(e) => tracing::warn!("Ignoring invalid DNS overrides: {e:#}"),
+                }
+            }
+            ClientMsg::SetDnsQueryLog(enabled) => {
+                let Some(session) = self.session.as_mut() else {
+                    // The GUI sends this setting again after signing in.
+                    tracing::debug!("Cannot set DNS query log if we're signed out");
+                    return Ok(());
+                };
+
+                session.dns_query_log = if enabled {
+                    let path = known_dirs::tunnel_service_logs()
+                        .context("Can't compute logs dir")?
+                        .join("dns-queries.jsonl");
+
+                    Some(DnsQueryLogWriter::new(path)?)
+                } else {
+                    None
+                };
+                session.connlib.set_dns_query_log(enabled);
+            }
             ClientMsg::SetDisabledResources(disabled_resources) => {
                 let Some(session) = self.session.as_ref() else {
                     // At this point, the GUI has already saved the disabled Resources to disk, so it'll be correct on the next sign-in anyway.
@@ -469,7 +538,7 @@ impl<'a> Handler<'a> {
         .context("Failed to create `LoginUrl`")?;
 
         self.last_connlib_start_instant = Some(Instant::now());
-        let (callbacks, cb_rx) = client_shared::ChannelCallbackHandler::new();
+        let (callbacks, cb_rx, dns_query_rx) = client_shared::ChannelCallbackHandler::new();
 
         // Synchronous DNS resolution here
         let portal = PhoenixChannel::disconnected(
@@ -507,7 +576,12 @@ impl<'a> Handler<'a> {
         };
         connlib.set_tun(tun);
 
-        let session = Session { cb_rx, connlib };
+        let session = Session {
+            cb_rx,
+            dns_query_rx,
+            connlib,
+            dns_query_log: None,
+        };
         self.session = Some(session);
 
         Ok(())
@@ -3,6 +3,7 @@
 
 use crate::gui::Managed;
 use anyhow::{Context as _, Result};
+use client_shared::DnsOverrides;
 use connlib_model::ResourceId;
 use firezone_bin_shared::known_dirs;
 use serde::{Deserialize, Serialize};
@@ -40,6 +41,11 @@ pub(crate) async fn reset_advanced_settings(
 
 /// Saves the settings to disk and then tells `Controller` to apply them in-memory
 async fn apply_inner(ctlr_tx: &CtlrTx, settings: AdvancedSettings) -> Result<()> {
+    settings
+        .dns_overrides
+        .parse::<DnsOverrides>()
+        .context("Invalid DNS overrides")?;
+
     save(&settings).await?;
     // TODO: Errors aren't handled here. But there isn't much that can go wrong
     // since it's just applying a new `Settings` object in memory.
@@ -76,6 +82,12 @@ pub struct AdvancedSettings {
     #[serde(default)]
     pub internet_resource_enabled: Option<bool>,
     pub log_filter: String,
+    /// Local DNS records and forwarding rules, see [`DnsOverrides`].
+    #[serde(default)]
+    pub dns_overrides: String,
+    /// Whether the tunnel service writes a log of all DNS queries alongside its logs.
+    #[serde(default)]
+    pub dns_query_log: bool,
 }
 
 #[cfg(debug_assertions)]
@@ -100,6 +112,8 @@ impl Default for AdvancedSettings {
             favorite_resources: Default::default(),
             internet_resource_enabled: Default::default(),
             log_filter: defaults::LOG_FILTER.to_string(),
+            dns_overrides: Default::default(),
+            dns_query_log: false,
         }
     }
 }
@@ -158,5 +172,7 @@ mod tests {
         assert_eq!(actual.auth_base_url.to_string(), "https://example.com/");
         assert_eq!(actual.api_url.to_string(), "wss://example.com/");
         assert_eq!(actual.log_filter, "info");
+        assert!(actual.dns_overrides.is_empty());
+        assert!(!actual.dns_query_log);
     }
 }
@@ -6,6 +6,8 @@ interface Settings {
   auth_base_url: string;
   api_url: string;
   log_filter: string;
+  dns_overrides: string;
+  dns_query_log: boolean;
 }
 
 interface FileCount {
@@ -22,6 +24,12 @@ const apiUrlInput = <HTMLInputElement>document.getElementById("api-url-input");
 const logFilterInput = <HTMLInputElement>(
   document.getElementById("log-filter-input")
 );
+const dnsOverridesInput = <HTMLTextAreaElement>(
+  document.getElementById("dns-overrides-input")
+);
+const dnsQueryLogInput = <HTMLInputElement>(
+  document.getElementById("dns-query-log-input")
+);
 const logCountOutput = <HTMLParagraphElement>(
   document.getElementById("log-count-output")
 );
@@ -46,6 +54,8 @@ function lockAdvancedSettingsForm() {
   authBaseUrlInput.disabled = true;
   apiUrlInput.disabled = true;
   logFilterInput.disabled = true;
+  dnsOverridesInput.disabled = true;
+  dnsQueryLogInput.disabled = true;
   resetAdvancedSettingsBtn.disabled = true;
   applyAdvancedSettingsBtn.disabled = true;
 
@@ -57,6 +67,8 @@ function unlockAdvancedSettingsForm() {
   authBaseUrlInput.disabled = false;
   apiUrlInput.disabled = false;
   logFilterInput.disabled = false;
+  dnsOverridesInput.disabled = false;
+  dnsQueryLogInput.disabled = false;
   resetAdvancedSettingsBtn.disabled = false;
   applyAdvancedSettingsBtn.disabled = false;
 
@@ -84,6 +96,8 @@ async function applyAdvancedSettings() {
         auth_base_url: authBaseUrlInput.value,
         api_url: apiUrlInput.value,
         log_filter: logFilterInput.value,
+        dns_overrides: dnsOverridesInput.value,
+        dns_query_log: dnsQueryLogInput.checked,
       },
     });
   } catch (e) {
@@ -102,6 +116,8 @@ async function resetAdvancedSettings() {
     authBaseUrlInput.value = settings.auth_base_url;
     apiUrlInput.value = settings.api_url;
     logFilterInput.value = settings.log_filter;
+    dnsOverridesInput.value = settings.dns_overrides;
+    dnsQueryLogInput.checked = settings.dns_query_log;
   } catch (e) {
     console.error(e);
   } finally {
@@ -118,6 +134,8 @@ async function getAdvancedSettings() {
     authBaseUrlInput.value = settings.auth_base_url;
     apiUrlInput.value = settings.api_url;
     logFilterInput.value = settings.log_filter;
+    dnsOverridesInput.value = settings.dns_overrides;
+    dnsQueryLogInput.checked = settings.dns_query_log;
   } catch (e) {
     console.error(e);
   } finally {
@@ -0,0 +1,116 @@
+//! Queries and steers a running headless Client via its control socket.
+//!
+//! The headless Client only listens on the control socket if started with `--control-socket`.
+
+use anyhow::Result;
+use clap::Parser;
+
+#[path = "../control/protocol.rs"]
+mod protocol;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// Path to the control socket of the headless Client.
+    #[arg(
+        long,
+        env = "FIREZONE_CONTROL_SOCKET",
+        default_value = "/run/dev.firezone.client/control.sock"
+    )]
+    socket: std::path::PathBuf,
+
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(clap::Subcommand)]
+enum Cmd {
+    /// Show the state of the tunnel.
+    Status,
+    /// List all Resources.
+    Resources,
+    /// List the connections to Gateways, including whether they are direct or relayed.
+    Connections,
+    /// Disable a Resource.
+    DisableResource { id: connlib_model::ResourceId },
+    /// Re-enable a previously disabled Resource.
+    EnableResource { id: connlib_model::ResourceId },
+    /// Override the upstream DNS servers. Pass no servers to use the system's resolvers again.
+    SetDns { servers: Vec<std::net::IpAddr> },
+    /// Reconnect to the portal and rebind all sockets.
+    Reset,
+    /// Change the log filter, e.g. `debug,firezone_tunnel=trace`.
+    LogFilter { directives: String },
+}
+
+impl From<Cmd> for protocol::Request {
+    fn from(cmd: Cmd) -> Self {
+        match cmd {
+            Cmd::Status => Self::Status,
+            Cmd::Resources => Self::Resources,
+            Cmd::Connections => Self::Connections,
+            Cmd::DisableResource { id } => Self::DisableResource { id },
+            Cmd::EnableResource { id } => Self::EnableResource { id },
+            Cmd::SetDns { servers } => Self::SetDns { servers },
+            Cmd::Reset => Self::Reset,
+            Cmd::LogFilter { directives } => Self::LogFilter { directives },
+        }
+    }
+}
+
+#[expect(clippy::print_stdout, reason = "Printing the response is our purpose")]
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+
+    let response = rt.block_on(platform::request(&cli.socket, cli.command.into()))?;
+
+    println!("{}", serde_json::to_string_pretty(&response)?);
+
+    if let protocol::Response::Error { message } = response {
+        anyhow::bail!(message)
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::protocol::{Request, Response};
+    use anyhow::{Context as _, Result};
+    use firezone_bin_shared::ipc_codec::{Decoder, Encoder};
+    use futures::{SinkExt as _, StreamExt as _};
+    use std::path::Path;
+    use tokio::net::UnixStream;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    pub(crate) async fn request(socket: &Path, request: Request) -> Result<Response> {
+        let stream = UnixStream::connect(socket)
+            .await
+            .with_context(|| format!("Couldn't connect to `{}`", socket.display()))?;
+
+        let (rx, tx) = tokio::io::split(stream);
+        let mut rx = FramedRead::new(rx, Decoder::<Response>::default());
+        let mut tx = FramedWrite::new(tx, Encoder::<Request>::default());
+
+        tx.send(&request).await?;
+
+        rx.next()
+            .await
+            .context("Headless Client closed the connection")?
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    use super::protocol::{Request, Response};
+    use anyhow::Result;
+    use std::path::Path;
+
+    pub(crate) async fn request(_: &Path, _: Request) -> Result<Response> {
+        anyhow::bail!("The control socket is only supported on Unix")
+    }
+}
@@ -0,0 +1,270 @@
+//! A local control socket for querying and steering the headless Client at runtime.
+//!
+//! Requests and responses are JSON, framed the same way as the GUI Client's IPC.
+
+pub(crate) mod protocol;
+
+pub(crate) use protocol::{Request, Response, Status, TunnelStatus};
+
+use anyhow::Result;
+use connlib_model::{ResourceId, ResourceStatus, ResourceView};
+use firezone_bin_shared::DnsController;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use tokio::sync::{mpsc, oneshot};
+
+/// A [`Request`] received on the control socket, together with the means to answer it.
+pub(crate) struct Command {
+    pub(crate) request: Request,
+    responder: oneshot::Sender<Response>,
+}
+
+impl Command {
+    pub(crate) fn respond(self, response: Response) {
+        let _ = self.responder.send(response);
+    }
+}
+
+/// The state of the headless Client that is exposed via the control socket.
+#[derive(Default)]
+pub(crate) struct State {
+    pub(crate) resources: Vec<ResourceView>,
+    pub(crate) tunnel: Option<TunnelStatus>,
+    pub(crate) disabled_resources: BTreeSet<ResourceId>,
+    pub(crate) dns_override: Option<Vec<IpAddr>>,
+}
+
+impl State {
+    pub(crate) fn status(&self) -> Status {
+        Status {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            tunnel: self.tunnel.clone(),
+            num_resources: self.resources.len(),
+            disabled_resources: self.disabled_resources.clone(),
+            connected_sites: self
+                .resources
+                .iter()
+                .filter(|r| r.status() == ResourceStatus::Online)
+                .flat_map(|r| r.sites().iter().cloned())
+                .collect(),
+            dns_override: self.dns_override.clone(),
+        }
+    }
+
+    /// The upstream DNS servers connlib should use.
+    pub(crate) fn upstream_dns(&self, dns_controller: &DnsController) -> Vec<IpAddr> {
+        self.dns_override
+            .clone()
+            .unwrap_or_else(|| dns_controller.system_resolvers())
+    }
+
+    /// Updates the list of Resources, forgetting about disabled Resources that no longer exist.
+    ///
+    /// Returns the new set of disabled resources if it changed.
+    pub(crate) fn update_resources(
+        &mut self,
+        resources: Vec<ResourceView>,
+    ) -> Option<BTreeSet<ResourceId>> {
+        self.resources = resources;
+
+        let num_disabled = self.disabled_resources.len();
+        self.disabled_resources
+            .retain(|id| self.resources.iter().any(|r| r.id() == *id));
+
+        (self.disabled_resources.len() != num_disabled).then(|| self.disabled_resources.clone())
+    }
+
+    /// Marks the given resource as disabled or enabled.
+    ///
+    /// Returns the new set of disabled resources.
+    pub(crate) fn set_resource_disabled(
+        &mut self,
+        id: ResourceId,
+        disabled: bool,
+    ) -> Result<BTreeSet<ResourceId>> {
+        anyhow::ensure!(
+            self.resources.iter().any(|r| r.id() == id),
+            "Unknown resource {id}"
+        );
+
+        if disabled {
+            self.disabled_resources.insert(id);
+        } else {
+            self.disabled_resources.remove(&id);
+        }
+
+        Ok(self.disabled_resources.clone())
+    }
+}
+
+/// Listens on the Unix socket at `path` and forwards all requests as [`Command`]s.
+#[cfg(target_os = "linux")]
+pub(crate) fn listen(path: PathBuf, commands: mpsc::Sender<Command>) -> Result<()> {
+    let server = unix::Server::bind(path)?;
+
+    tokio::spawn(server.serve(commands));
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn listen(_: PathBuf, _: mpsc::Sender<Command>) -> Result<()> {
+    anyhow::bail!("The control socket is only supported on Linux")
+}
+
+#[cfg(target_os = "linux")]
+mod unix {
+    use super::{Command, Request, Response};
+    use anyhow::{Context as _, Result};
+    use firezone_bin_shared::ipc_codec::{Decoder, Encoder};
+    use futures::{SinkExt as _, StreamExt as _};
+    use std::os::unix::fs::{DirBuilderExt as _, PermissionsExt as _};
+    use std::path::PathBuf;
+    use tokio::net::{UnixListener, UnixStream};
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    pub(super) struct Server {
+        listener: UnixListener,
+        path: PathBuf,
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                tracing::debug!(path = %self.path.display(), "Failed to delete control socket: {e}");
+            }
+        }
+    }
+
+    impl Server {
+        pub(super) fn bind(path: PathBuf) -> Result<Self> {
+            // Remove the socket if a previous run left it there
+            std::fs::remove_file(&path).ok();
+
+            let dir = path
+                .parent()
+                .context("Control socket path should have a parent")?;
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o750)
+                .create(dir)
+                .context("Failed to create socket parent directory")?;
+
+            // The socket is only restricted to 0o660 after binding it, so the directory needs to keep others out in the meantime.
+            let dir_mode = std::fs::metadata(dir)
+                .context("Failed to read socket parent directory")?
+                .permissions()
+                .mode();
+            if dir_mode & 0o007 != 0 {
+                tracing::warn!(dir = %dir.display(), mode = %format_args!("{dir_mode:o}"), "Control socket directory is accessible to others");
+            }
+
+            let listener = UnixListener::bind(&path)
+                .with_context(|| format!("Couldn't bind control socket `{}`", path.display()))?;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o660))
+                .context("Failed to set permissions on control socket")?;
+
+            tracing::info!(path = %path.display(), "Listening on control socket");
+
+            Ok(Self { listener, path })
+        }
+
+        pub(super) async fn serve(self, commands: mpsc::Sender<Command>) {
+            loop {
+                let stream = match self.listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        tracing::warn!("Failed to accept control connection: {e}");
+                        continue;
+                    }
+                };
+
+                let commands = commands.clone();
+
+                tokio::spawn(async move {
+                    if let Err(e) = handle_client(stream, commands).await {
+                        tracing::debug!("Control connection failed: {e:#}");
+                    }
+                });
+            }
+        }
+    }
+
+    async fn handle_client(stream: UnixStream, commands: mpsc::Sender<Command>) -> Result<()> {
+        let (rx, tx) = tokio::io::split(stream);
+        let mut rx = FramedRead::new(rx, Decoder::<Request>::default());
+        let mut tx = FramedWrite::new(tx, Encoder::<Response>::default());
+
+        while let Some(request) = rx.next().await {
+            let request = request?;
+            let (responder, response) = oneshot::channel();
+
+            commands
+                .send(Command { request, responder })
+                .await
+                .context("Headless Client is shutting down")?;
+            let response = response.await.context("Request was not answered")?;
+
+            tx.send(&response).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_serialization() {
+        let request = Request::SetDns {
+            servers: vec![IpAddr::from([1, 1, 1, 1])],
+        };
+
+        let json = serde_json::to_string(&request).unwrap();
+
+        assert_eq!(json, r#"{"command":"set-dns","servers":["1.1.1.1"]}"#);
+        assert_eq!(serde_json::from_str::<Request>(&json).unwrap(), request);
+    }
+
+    #[test]
+    fn cannot_disable_unknown_resource() {
+        let mut state = State::default();
+
+        assert!(
+            state
+                .set_resource_disabled(ResourceId::from_u128(1), true)
+                .is_err()
+        );
+        assert!(state.disabled_resources.is_empty());
+    }
+
+    #[test]
+    fn forgets_disabled_resources_that_no_longer_exist() {
+        let mut state = State::default();
+        state.update_resources(vec![internet_resource(1), internet_resource(2)]);
+        state
+            .set_resource_disabled(ResourceId::from_u128(1), true)
+            .unwrap();
+        state
+            .set_resource_disabled(ResourceId::from_u128(2), true)
+            .unwrap();
+
+        let disabled = state.update_resources(vec![internet_resource(2)]);
+
+        assert_eq!(disabled, Some(BTreeSet::from([ResourceId::from_u128(2)])));
+        assert_eq!(state.update_resources(vec![internet_resource(2)]), None);
+    }
+
+    fn internet_resource(id: u128) -> ResourceView {
+        ResourceView::Internet(connlib_model::InternetResourceView {
+            name: "Internet Resource".to_owned(),
+            id: ResourceId::from_u128(id),
+            sites: Vec::new(),
+            status: ResourceStatus::Unknown,
+        })
+    }
+}
@@ -0,0 +1,64 @@
+//! Messages exchanged over the headless Client's control socket.
+//!
+//! Shared between the headless Client and `firezone-ctl`.
+
+use connlib_model::{ConnectionView, ResourceId, ResourceView, Site};
+use ip_network::{Ipv4Network, Ipv6Network};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Status,
+    Resources,
+    Connections,
+    DisableResource {
+        id: ResourceId,
+    },
+    EnableResource {
+        id: ResourceId,
+    },
+    /// Overrides the upstream DNS servers; an empty list goes back to the system's resolvers.
+    SetDns {
+        servers: Vec<IpAddr>,
+    },
+    Reset,
+    LogFilter {
+        directives: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+pub enum Response {
+    Ok,
+    Status(Status),
+    Resources { resources: Vec<ResourceView> },
+    Connections { connections: Vec<ConnectionView> },
+    Error { message: String },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub version: String,
+    /// The current config of the TUN device, `None` until the tunnel is ready.
+    pub tunnel: Option<TunnelStatus>,
+    pub num_resources: usize,
+    pub disabled_resources: BTreeSet<ResourceId>,
+    /// Sites for which we are connected to a Gateway.
+    pub connected_sites: BTreeSet<Site>,
+    /// The upstream DNS servers set via [`Request::SetDns`], if any.
+    pub dns_override: Option<Vec<IpAddr>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TunnelStatus {
+    pub ipv4: Ipv4Addr,
+    pub ipv6: Ipv6Addr,
+    pub dns: Vec<IpAddr>,
+    pub search_domain: Option<String>,
+    pub ipv4_routes: Vec<Ipv4Network>,
+    pub ipv6_routes: Vec<Ipv6Network>,
+}
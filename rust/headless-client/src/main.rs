@@ -5,14 +5,15 @@
 use anyhow::{Context as _, Result, anyhow};
 use backoff::ExponentialBackoffBuilder;
 use clap::Parser;
-use client_shared::{ChannelCallbackHandler, ConnlibMsg, Session};
+use client_shared::{ChannelCallbackHandler, ConnlibMsg, DnsOverrides, Session};
 use firezone_bin_shared::{
     DnsControlMethod, DnsController, TOKEN_ENV_KEY, TunDeviceManager, device_id, device_info,
-    new_dns_notifier, new_network_notifier,
+    dns_query_log::DnsQueryLogWriter,
+    http_health_check, new_dns_notifier, new_network_notifier,
     platform::{tcp_socket_factory, udp_socket_factory},
     signals,
 };
-use firezone_logging::telemetry_span;
+use firezone_logging::{FilterReloadHandle, telemetry_span};
 use firezone_telemetry::Telemetry;
 use firezone_telemetry::otel;
 use futures::StreamExt as _;
@@ -22,12 +23,16 @@ use phoenix_channel::get_user_agent;
 use phoenix_channel::{DeviceInfo, LoginUrl};
 use secrecy::{Secret, SecretString};
 use std::{
+    net::SocketAddr,
     path::{Path, PathBuf},
     sync::Arc,
 };
+use tokio::sync::mpsc;
 use tokio::time::Instant;
 use tokio_stream::wrappers::ReceiverStream;
 
+mod control;
+
 #[cfg(target_os = "linux")]
 #[path = "linux.rs"]
 mod platform;
@@ -110,6 +115,28 @@ struct Cli {
     #[arg(long, env = "FIREZONE_METRICS", default_value_t = false)]
     metrics: bool,
 
+    /// Serve internal metrics in the Prometheus format at `http://<metrics_addr>/metrics`.
+    #[arg(long, env = "FIREZONE_METRICS_ADDR")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Listen for `firezone-ctl` commands on this Unix socket.
+    ///
+    /// `firezone-ctl` looks for it at `/run/dev.firezone.client/control.sock` by default.
+    #[arg(long, env = "FIREZONE_CONTROL_SOCKET")]
+    control_socket: Option<PathBuf>,
+
+    /// A file with local DNS records and forwarding rules, e.g. `10.0.0.10 db.lab.local` or `forward *.lab.local 10.0.0.53`.
+    ///
+    /// Only applies to domains that aren't DNS resources. The file is re-read on SIGHUP.
+    #[arg(long, env = "FIREZONE_DNS_OVERRIDES")]
+    dns_overrides: Option<PathBuf>,
+
+    /// Log every DNS query to this file, one JSON object per line.
+    ///
+    /// The file is rotated to `<FILE>.1` once it grows beyond 10 MiB.
+    #[arg(long, env = "FIREZONE_DNS_QUERY_LOG")]
+    dns_query_log: Option<PathBuf>,
+
     /// A filesystem path where the token can be found
     // Apparently passing secrets through stdin is the most secure method, but
     // until anyone asks for it, env vars are okay and files on disk are slightly better.
@@ -163,7 +190,8 @@ fn main() -> Result<()> {
         .as_deref()
         .map(|dir| firezone_logging::file::layer(dir, "firezone-headless-client"))
         .unzip();
-    firezone_logging::setup_global_subscriber(layer).context("Failed to set up logging")?;
+    let log_filter_reloader =
+        firezone_logging::setup_global_subscriber(layer).context("Failed to set up logging")?;
 
     // Deactivate DNS control before starting telemetry or connecting to the portal,
     // in case a previous run of Firezone left DNS control on and messed anything up.
@@ -216,32 +244,52 @@ fn main() -> Result<()> {
         },
     )?;
 
+    let dns_overrides = cli
+        .dns_overrides
+        .as_deref()
+        .map(read_dns_overrides)
+        .transpose()?;
+
     if cli.check {
         tracing::info!("Check passed");
         return Ok(());
     }
 
-    let (callbacks, cb_rx) = ChannelCallbackHandler::new();
+    let (callbacks, cb_rx, mut dns_query_rx) = ChannelCallbackHandler::new();
 
     // The name matches that in `ipc_service.rs`
     let mut last_connlib_start_instant = Some(Instant::now());
 
     rt.block_on(async {
-        if cli.metrics {
-            let exporter = opentelemetry_stdout::MetricExporter::default();
-            let reader = PeriodicReader::builder(exporter).build();
-            let provider = SdkMeterProvider::builder()
-                .with_reader(reader)
-                .with_resource(otel::default_resource_with([
+        if cli.metrics || cli.metrics_addr.is_some() {
+            let mut provider =
+                SdkMeterProvider::builder().with_resource(otel::default_resource_with([
                     otel::attr::service_name!(),
                     otel::attr::service_version!(),
                     otel::attr::service_instance_id(firezone_id),
-                ]))
-                .build();
+                ]));
+
+            if cli.metrics {
+                let exporter = opentelemetry_stdout::MetricExporter::default();
+                provider = provider.with_reader(PeriodicReader::builder(exporter).build());
+            }
 
-            opentelemetry::global::set_meter_provider(provider);
+            if let Some(addr) = cli.metrics_addr {
+                let (registry, exporter) = http_health_check::prometheus_registry()?;
+                provider = provider.with_reader(exporter);
+
+                tokio::spawn(http_health_check::serve(addr, || true, Some(registry)));
+            }
+
+            opentelemetry::global::set_meter_provider(provider.build());
         }
 
+        let (control_tx, mut control_rx) = mpsc::channel(10);
+        if let Some(path) = cli.control_socket.clone() {
+            control::listen(path, control_tx)?;
+        }
+        let mut control_state = control::State::default();
+
         let connect_span = telemetry_span!("connect_to_firezone").entered();
 
         // The Headless Client will bail out here if there's no Internet, because `PhoenixChannel` will try to
@@ -290,6 +338,17 @@ fn main() -> Result<()> {
         };
         session.set_tun(tun);
         session.set_dns(dns_controller.system_resolvers());
+        if let Some(overrides) = dns_overrides {
+            session.set_dns_overrides(overrides);
+        }
+        let mut dns_query_log = cli
+            .dns_query_log
+            .clone()
+            .map(DnsQueryLogWriter::new)
+            .transpose()?;
+        if dns_query_log.is_some() {
+            session.set_dns_query_log(true);
+        }
 
         drop(connect_span);
 
@@ -301,6 +360,12 @@ fn main() -> Result<()> {
                 },
                 () = hangup.recv() => {
                     tracing::info!("Caught SIGHUP");
+                    if let Some(path) = cli.dns_overrides.as_deref() {
+                        match read_dns_overrides(path) {
+                            Ok(overrides) => session.set_dns_overrides(overrides),
+                            Err(e) => tracing::warn!("Keeping previous DNS overrides: {e:#}"),
+                        }
+                    }
                     session.reset();
                     continue;
                 },
@@ -309,7 +374,32 @@ fn main() -> Result<()> {
                     // If the DNS control method is not `systemd-resolved`
                     // then we'll use polling here, so no point logging every 5 seconds that we're checking the DNS
                     tracing::trace!("DNS change, notifying Session");
-                    session.set_dns(dns_controller.system_resolvers());
+                    session.set_dns(control_state.upstream_dns(&dns_controller));
+                    continue;
+                },
+                Some(command) = control_rx.recv() => {
+                    let response = handle_control_request(
+                        &command.request,
+                        &mut control_state,
+                        &session,
+                        &dns_controller,
+                        &log_filter_reloader,
+                    )
+                    .await;
+                    command.respond(response);
+                    continue;
+                },
+                Some(entry) = dns_query_rx.recv() => {
+                    let Some(writer) = dns_query_log.as_mut() else {
+                        continue;
+                    };
+
+                    if let Err(e) = writer.write(entry) {
+                        tracing::warn!(path = %writer.path().display(), "Disabling DNS query log: {e:#}");
+
+                        session.set_dns_query_log(false);
+                        dns_query_log = None;
+                    }
                     continue;
                 },
                 result = network_notifier.notified() => {
@@ -327,9 +417,12 @@ fn main() -> Result<()> {
                     error_msg,
                     is_authentication_error: _,
                 } => break Err(anyhow!(error_msg).context("Firezone disconnected")),
-                ConnlibMsg::OnUpdateResources(_) => {
+                ConnlibMsg::OnUpdateResources(resources) => {
                     // On every Resources update, flush DNS to mitigate <https://github.com/firezone/firezone/issues/5052>
                     dns_controller.flush()?;
+                    if let Some(disabled) = control_state.update_resources(resources) {
+                        session.set_disabled_resources(disabled);
+                    }
                 }
                 ConnlibMsg::OnSetInterfaceConfig {
                     ipv4,
@@ -339,6 +432,15 @@ fn main() -> Result<()> {
                     ipv4_routes,
                     ipv6_routes,
                 } => {
+                    control_state.tunnel = Some(control::TunnelStatus {
+                        ipv4,
+                        ipv6,
+                        dns: dns.clone(),
+                        search_domain: search_domain.as_ref().map(|d| d.to_string()),
+                        ipv4_routes: ipv4_routes.clone(),
+                        ipv6_routes: ipv6_routes.clone(),
+                    });
+
                     tun_device.set_ips(ipv4, ipv6).await?;
                     tun_device.set_routes(ipv4_routes, ipv6_routes).await?;
 
@@ -367,6 +469,70 @@ fn main() -> Result<()> {
     })
 }
 
+async fn handle_control_request(
+    request: &control::Request,
+    state: &mut control::State,
+    session: &Session,
+    dns_controller: &DnsController,
+    log_filter_reloader: &FilterReloadHandle,
+) -> control::Response {
+    use control::{Request, Response};
+
+    let result = match request {
+        Request::Status => return Response::Status(state.status()),
+        Request::Resources => {
+            return Response::Resources {
+                resources: state.resources.clone(),
+            };
+        }
+        Request::Connections => {
+            return match session.connections().await {
+                Ok(connections) => Response::Connections { connections },
+                Err(e) => Response::Error {
+                    message: format!("{e:#}"),
+                },
+            };
+        }
+        Request::DisableResource { id } => state
+            .set_resource_disabled(*id, true)
+            .map(|disabled| session.set_disabled_resources(disabled)),
+        Request::EnableResource { id } => state
+            .set_resource_disabled(*id, false)
+            .map(|disabled| session.set_disabled_resources(disabled)),
+        Request::SetDns { servers } => {
+            state.dns_override = (!servers.is_empty()).then(|| servers.clone());
+            session.set_dns(state.upstream_dns(dns_controller));
+
+            Ok(())
+        }
+        Request::Reset => {
+            tracing::info!("Resetting Session on request of control socket");
+            session.reset();
+
+            Ok(())
+        }
+        Request::LogFilter { directives } => log_filter_reloader.reload(directives),
+    };
+
+    match result {
+        Ok(()) => Response::Ok,
+        Err(e) => Response::Error {
+            message: format!("{e:#}"),
+        },
+    }
+}
+
+fn read_dns_overrides(path: &Path) -> Result<DnsOverrides> {
+    let overrides = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read DNS overrides from `{}`", path.display()))?
+        .parse()
+        .with_context(|| format!("Failed to parse DNS overrides in `{}`", path.display()))?;
+
+    tracing::info!(path = %path.display(), "Loaded DNS overrides");
+
+    Ok(overrides)
+}
+
 /// Read the token from disk if it was not in the environment
 ///
 /// # Returns
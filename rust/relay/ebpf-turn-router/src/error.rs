@@ -12,6 +12,7 @@ pub enum Error {
     BadChannelDataLength,
     NoEntry(SupportedChannel),
     UnsupportedChannel(UnsupportedChannel),
+    QuotaExceeded,
     XdpLoadBytesFailed(i64),
     XdpAdjustHeadFailed(i64),
     XdpStoreBytesFailed(i64),
@@ -71,6 +72,7 @@ impl aya_log_ebpf::WriteToBuf for Error {
             Error::UnsupportedChannel(UnsupportedChannel::ChanToUdp64) => {
                 "Relaying channel IPv6 to UDPv4 is not supported".write(buf)
             }
+            Error::QuotaExceeded => "Channel exceeded its byte limit".write(buf),
             Error::XdpLoadBytesFailed(ret) => {
                 let mut written = 0;
 
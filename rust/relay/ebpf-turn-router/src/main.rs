@@ -34,6 +34,7 @@ mod eth;
 mod ip4;
 mod ip6;
 mod move_headers;
+mod quota;
 mod ref_mut_at;
 mod stats;
 mod udp;
@@ -90,7 +91,8 @@ pub fn handle_turn(ctx: XdpContext) -> u32 {
         | Error::Ipv4PacketWithOptions
         | Error::NoMacAddress
         | Error::UnsupportedChannel(_)
-        | Error::NoEntry(_) => {
+        | Error::NoEntry(_)
+        | Error::QuotaExceeded => {
             debug!(&ctx, "Passing packet to userspace: {}", e);
 
             xdp_action::XDP_PASS
@@ -183,6 +185,8 @@ fn try_handle_ipv4_channel_data_to_udp(
         Error::NoEntry(SupportedChannel::ChanToUdp44)
     })?;
 
+    quota::try_consume(port_and_peer.allocation_port(), cd.number(), cd.length())?;
+
     let new_src = ipv4.dst(); // The IP we received the packet on will be the new source IP.
     let new_dst = port_and_peer.peer_ip();
     let new_ipv4_total_len = ipv4.total_len() - CdHdr::LEN as u16;
@@ -223,6 +227,8 @@ fn try_handle_ipv4_udp_to_channel_data(
         Error::NoEntry(SupportedChannel::UdpToChan44)
     })?;
 
+    quota::try_consume(udp.dst(), client_and_channel.channel(), udp.payload_len())?;
+
     let new_src = ipv4.dst(); // The IP we received the packet on will be the new source IP.
     let new_dst = client_and_channel.client_ip();
     let new_ipv4_total_len = ipv4.total_len() + CdHdr::LEN as u16;
@@ -314,6 +320,8 @@ fn try_handle_ipv6_udp_to_channel_data(
         Error::NoEntry(SupportedChannel::UdpToChan66)
     })?;
 
+    quota::try_consume(udp.dst(), client_and_channel.channel(), udp.payload_len())?;
+
     let new_src = ipv6.dst(); // The IP we received the packet on will be the new source IP.
     let new_dst = client_and_channel.client_ip();
     let new_ipv6_total_len = ipv6.payload_len() + CdHdr::LEN as u16;
@@ -367,6 +375,8 @@ fn try_handle_ipv6_channel_data_to_udp(
         Error::NoEntry(SupportedChannel::ChanToUdp66)
     })?;
 
+    quota::try_consume(port_and_peer.allocation_port(), cd.number(), cd.length())?;
+
     let new_src = ipv6.dst(); // The IP we received the packet on will be the new source IP.
     let new_dst = port_and_peer.peer_ip();
     let new_ipv6_payload_len = ipv6.payload_len() - CdHdr::LEN as u16;
@@ -432,4 +442,20 @@ mod tests {
             "Total map size = {total_map_size_mb} MB"
         );
     }
+
+    #[test]
+    fn quota_hashmaps_are_less_than_3_mb() {
+        let datatypes =
+            core::mem::size_of::<ebpf_shared::AllocationAndChannel>() + core::mem::size_of::<u64>();
+
+        let map_size = datatypes as f32 * NUM_ENTRIES as f32 * HASH_MAP_OVERHEAD;
+
+        let total_map_size = map_size * 2_f32; // Relayed bytes and byte limits.
+        let total_map_size_mb = total_map_size / 1024_f32 / 1024_f32;
+
+        assert!(
+            total_map_size_mb < 3_f32,
+            "Total map size = {total_map_size_mb} MB"
+        );
+    }
 }
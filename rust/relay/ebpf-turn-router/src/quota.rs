@@ -0,0 +1,49 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use aya_ebpf::{macros::map, maps::HashMap};
+use ebpf_shared::AllocationAndChannel;
+
+use crate::{NUM_ENTRIES, error::Error};
+
+/// How many bytes we relayed for each channel.
+///
+/// Entries are created and removed by userspace but only ever updated by us.
+#[map]
+static CHANNEL_RELAYED_BYTES: HashMap<AllocationAndChannel, u64> =
+    HashMap::with_max_entries(NUM_ENTRIES, 0);
+
+/// How many bytes we may relay for each channel before handing its traffic to userspace.
+///
+/// Only ever written by userspace.
+#[map]
+static CHANNEL_BYTE_LIMITS: HashMap<AllocationAndChannel, u64> =
+    HashMap::with_max_entries(NUM_ENTRIES, 0);
+
+/// Accounts for `num_bytes` relayed on the given channel.
+///
+/// Fails if that would exceed the byte limit of the channel.
+/// The packet then needs to go to userspace which enforces the actual quotas.
+#[inline(always)]
+pub fn try_consume(allocation_port: u16, channel: u16, num_bytes: u16) -> Result<(), Error> {
+    let key = AllocationAndChannel::new(allocation_port, channel);
+
+    let Some(relayed_bytes) = CHANNEL_RELAYED_BYTES.get_ptr_mut(&key) else {
+        return Ok(()); // Channels without counters are not subject to any quota.
+    };
+    let byte_limit = unsafe { CHANNEL_BYTE_LIMITS.get(&key) }
+        .copied()
+        .unwrap_or(u64::MAX);
+
+    // SAFETY: The pointer comes from the map and is thus valid and aligned.
+    // Other CPUs may update the counter concurrently, which is why we only access it atomically.
+    let relayed_bytes = unsafe { AtomicU64::from_ptr(relayed_bytes) };
+    let num_bytes = u64::from(num_bytes);
+
+    if relayed_bytes.load(Ordering::Relaxed) + num_bytes > byte_limit {
+        return Err(Error::QuotaExceeded);
+    }
+
+    relayed_bytes.fetch_add(num_bytes, Ordering::Relaxed);
+
+    Ok(())
+}
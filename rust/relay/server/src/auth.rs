@@ -18,6 +18,9 @@
 //! As such, a TURN client can never create a set of credentials themselves because they are missing the `relay_secret`.
 //! In addition, a relay can validate such a username and password combination without having to store any state other than the `relay_secret`.
 //!
+//! In standalone mode, i.e. without a portal connection, the `relay_secret` is instead read from a file (see [`parse_secrets`]).
+//! The file may list additional secrets that are still accepted, which allows for rotating the secret without invalidating existing credentials.
+//!
 //! All STUN messages other than `BINDING` requests MUST be authenticated by the client.
 //!
 //! ## Server authentication
@@ -251,6 +254,19 @@ pub fn generate_password(
     BASE64_STANDARD_NO_PAD.encode(array.as_slice())
 }
 
+/// Parses the contents of a file containing one relay secret per line.
+///
+/// Empty lines and lines starting with `#` are ignored.
+/// The first secret is the one that new credentials should be generated with.
+pub fn parse_secrets(contents: &str) -> Vec<SecretString> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| SecretString::from(line.to_owned()))
+        .collect()
+}
+
 pub(crate) fn systemtime_from_unix(seconds: u64) -> SystemTime {
     SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
 }
@@ -286,6 +302,21 @@ mod tests {
         assert_eq!(password, "6xUIoZ+QvxKhRasLifwfRkMXl+ETLJUsFkHlXjlHAkg")
     }
 
+    #[test]
+    fn parse_secrets_skips_comments_and_empty_lines() {
+        let secrets = parse_secrets(&format!(
+            "# Current secret\n{RELAY_SECRET_1}\n\n  {RELAY_SECRET_2}  \n"
+        ));
+
+        assert_eq!(
+            secrets
+                .iter()
+                .map(|s| s.expose_secret().as_str())
+                .collect::<Vec<_>>(),
+            vec![RELAY_SECRET_1, RELAY_SECRET_2]
+        );
+    }
+
     #[test]
     fn smoke() {
         let message_integrity = message_integrity(
@@ -1,22 +1,33 @@
-use axum::Router;
 use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::routing::post;
+use axum::{Json, Router};
 use firezone_logging::FilterReloadHandle;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
 
-/// Runs an HTTP server that responds to `POST /log_filter?directives=` and sets the given directives as the new log-filter.
+/// Runs an HTTP server for controlling the relay at runtime.
+///
+/// - `POST /log_filter?directives=` sets the given directives as the new log-filter.
+/// - `POST /drain?deadline_secs=` stops accepting new allocations and optionally frees the remaining ones after the given number of seconds.
+/// - `GET /drain` reports how many allocations and channels are still active.
+///
+/// Requests that need access to the relay's state are forwarded to the event-loop via `requests`.
 pub async fn serve(
     addr: impl Into<SocketAddr>,
     filter_reload_handle: FilterReloadHandle,
+    requests: mpsc::Sender<Request>,
 ) -> std::io::Result<()> {
     let addr = addr.into();
 
     let service = Router::new()
         .route("/log_filter", post(set_log_filter))
+        .route("/drain", post(drain).get(drain_status))
         .with_state(AppState {
             handle: Arc::new(filter_reload_handle),
+            requests,
         })
         .into_make_service();
 
@@ -25,7 +36,30 @@ pub async fn serve(
     Ok(())
 }
 
-async fn set_log_filter(Query(params): Query<QueryParams>, state: State<AppState>) -> StatusCode {
+/// A request to the relay's event-loop.
+#[derive(Debug)]
+pub enum Request {
+    /// Stop accepting new allocations and free the remaining ones after the deadline, if any.
+    Drain {
+        deadline: Option<Duration>,
+        reply: oneshot::Sender<DrainStatus>,
+    },
+    DrainStatus {
+        reply: oneshot::Sender<DrainStatus>,
+    },
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct DrainStatus {
+    pub draining: bool,
+    pub allocations: usize,
+    pub channels: usize,
+}
+
+async fn set_log_filter(
+    Query(params): Query<LogFilterParams>,
+    state: State<AppState>,
+) -> StatusCode {
     let directives = params.directives;
 
     match state.handle.reload(&directives) {
@@ -42,12 +76,50 @@ async fn set_log_filter(Query(params): Query<QueryParams>, state: State<AppState
     }
 }
 
+async fn drain(
+    Query(params): Query<DrainParams>,
+    state: State<AppState>,
+) -> Result<Json<DrainStatus>, StatusCode> {
+    let deadline = params.deadline_secs.map(Duration::from_secs);
+
+    state
+        .request(|reply| Request::Drain { deadline, reply })
+        .await
+}
+
+async fn drain_status(state: State<AppState>) -> Result<Json<DrainStatus>, StatusCode> {
+    state.request(|reply| Request::DrainStatus { reply }).await
+}
+
 #[derive(Clone)]
 struct AppState {
     handle: Arc<FilterReloadHandle>,
+    requests: mpsc::Sender<Request>,
+}
+
+impl AppState {
+    async fn request(
+        &self,
+        make_request: impl FnOnce(oneshot::Sender<DrainStatus>) -> Request,
+    ) -> Result<Json<DrainStatus>, StatusCode> {
+        let (reply, rx) = oneshot::channel();
+
+        self.requests
+            .send(make_request(reply))
+            .await
+            .map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+        let status = rx.await.map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+
+        Ok(Json(status))
+    }
 }
 
 #[derive(serde::Deserialize)]
-struct QueryParams {
+struct LogFilterParams {
     directives: String,
 }
+
+#[derive(serde::Deserialize)]
+struct DrainParams {
+    deadline_secs: Option<u64>,
+}
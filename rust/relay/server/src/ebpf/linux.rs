@@ -1,4 +1,4 @@
-use std::net::SocketAddr;
+use std::{collections::BTreeMap, net::SocketAddr};
 
 use anyhow::{Context as _, Result};
 use aya::{
@@ -9,7 +9,8 @@ use aya::{
 use aya_log::EbpfLogger;
 use bytes::BytesMut;
 use ebpf_shared::{
-    ClientAndChannelV4, ClientAndChannelV6, Config, PortAndPeerV4, PortAndPeerV6, StatsEvent,
+    AllocationAndChannel, ClientAndChannelV4, ClientAndChannelV6, Config, PortAndPeerV4,
+    PortAndPeerV6, StatsEvent,
 };
 use stun_codec::rfc5766::attributes::ChannelNumber;
 
@@ -27,6 +28,9 @@ pub struct Program {
 
     #[expect(dead_code, reason = "We are just keeping it alive.")]
     stats: AsyncPerfEventArray<MapData>,
+
+    /// The relayed bytes of each channel as of the last [`Program::sync_channel_counters`], indexed by allocation port and channel number.
+    last_relayed_bytes: BTreeMap<(u16, u16), u64>,
 }
 
 impl Program {
@@ -108,7 +112,11 @@ impl Program {
 
         tracing::info!("eBPF TURN router loaded and attached to interface {interface}");
 
-        Ok(Self { ebpf, stats })
+        Ok(Self {
+            ebpf,
+            stats,
+            last_relayed_bytes: Default::default(),
+        })
     }
 
     pub fn add_channel_binding(
@@ -164,6 +172,16 @@ impl Program {
             }
         }
 
+        let allocation_and_channel =
+            AllocationAndChannel::new(allocation_port.value(), channel_number.value());
+
+        self.channel_relayed_bytes_map_mut()?
+            .insert(allocation_and_channel, 0, 0)?;
+        self.channel_byte_limits_map_mut()?
+            .insert(allocation_and_channel, u64::MAX, 0)?;
+        self.last_relayed_bytes
+            .insert((allocation_port.value(), channel_number.value()), 0);
+
         Ok(())
     }
 
@@ -212,6 +230,98 @@ impl Program {
             }
         }
 
+        let allocation_and_channel =
+            AllocationAndChannel::new(allocation_port.value(), channel_number.value());
+
+        self.channel_relayed_bytes_map_mut()?
+            .remove(&allocation_and_channel)?;
+        self.channel_byte_limits_map_mut()?
+            .remove(&allocation_and_channel)?;
+        self.last_relayed_bytes
+            .remove(&(allocation_port.value(), channel_number.value()));
+
+        Ok(())
+    }
+
+    /// Limits how many more bytes the eBPF program may relay on the given channel.
+    ///
+    /// Once the limit is reached, the channel's traffic is passed to userspace.
+    pub fn set_channel_byte_limit(
+        &mut self,
+        allocation_port: AllocationPort,
+        channel_number: ChannelNumber,
+        num_bytes: u64,
+    ) -> Result<()> {
+        let allocation_and_channel =
+            AllocationAndChannel::new(allocation_port.value(), channel_number.value());
+
+        let relayed_bytes = self
+            .channel_relayed_bytes_map_mut()?
+            .get(&allocation_and_channel, 0)?;
+
+        self.channel_byte_limits_map_mut()?.insert(
+            allocation_and_channel,
+            relayed_bytes.saturating_add(num_bytes),
+            0,
+        )?;
+
+        Ok(())
+    }
+
+    /// Reads how many bytes the eBPF program relayed on each channel since the last call.
+    ///
+    /// `on_relayed` is called for every channel with its allocation and the number of bytes relayed since the last sync.
+    pub fn sync_channel_counters(
+        &mut self,
+        mut on_relayed: impl FnMut(AllocationPort, u64),
+    ) -> Result<()> {
+        let counters = self
+            .channel_relayed_bytes_map_mut()?
+            .iter()
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to read channel counters")?;
+
+        for (allocation_and_channel, relayed_bytes) in counters {
+            let allocation_port = allocation_and_channel.allocation_port();
+            let last_relayed_bytes = self
+                .last_relayed_bytes
+                .insert(
+                    (allocation_port, allocation_and_channel.channel()),
+                    relayed_bytes,
+                )
+                .unwrap_or_default();
+
+            on_relayed(
+                AllocationPort::new(allocation_port),
+                relayed_bytes.saturating_sub(last_relayed_bytes),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Allows every channel to relay `budget(allocation)` more bytes than it had relayed as of the last [`Program::sync_channel_counters`].
+    pub fn set_channel_byte_limits(
+        &mut self,
+        budget: impl Fn(AllocationPort) -> u64,
+    ) -> Result<()> {
+        let limits = self
+            .last_relayed_bytes
+            .iter()
+            .map(|(&(allocation_port, channel), &relayed_bytes)| {
+                (
+                    AllocationAndChannel::new(allocation_port, channel),
+                    relayed_bytes.saturating_add(budget(AllocationPort::new(allocation_port))),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut byte_limits = self.channel_byte_limits_map_mut()?;
+
+        for (allocation_and_channel, limit) in limits {
+            byte_limits.insert(allocation_and_channel, limit, 0)?;
+        }
+
         Ok(())
     }
 
@@ -269,6 +379,18 @@ impl Program {
         self.hash_map_mut("UDP_TO_CHAN_64")
     }
 
+    fn channel_relayed_bytes_map_mut(
+        &mut self,
+    ) -> Result<HashMap<&mut MapData, AllocationAndChannel, u64>> {
+        self.hash_map_mut("CHANNEL_RELAYED_BYTES")
+    }
+
+    fn channel_byte_limits_map_mut(
+        &mut self,
+    ) -> Result<HashMap<&mut MapData, AllocationAndChannel, u64>> {
+        self.hash_map_mut("CHANNEL_BYTE_LIMITS")
+    }
+
     fn config_array_mut(&mut self) -> Result<Array<&mut MapData, Config>> {
         self.array_mut("CONFIG")
     }
@@ -36,6 +36,23 @@ impl Program {
         Ok(())
     }
 
+    pub fn set_channel_byte_limit(
+        &mut self,
+        _: AllocationPort,
+        _: ChannelNumber,
+        _: u64,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn sync_channel_counters(&mut self, _: impl FnMut(AllocationPort, u64)) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn set_channel_byte_limits(&mut self, _: impl Fn(AllocationPort) -> u64) -> Result<()> {
+        Ok(())
+    }
+
     pub fn set_config(&mut self, _: Config) -> Result<()> {
         Ok(())
     }
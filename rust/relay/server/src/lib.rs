@@ -11,11 +11,12 @@ pub mod ebpf;
 #[allow(clippy::unwrap_used)]
 pub mod proptest;
 pub mod sockets;
+pub mod stream;
 
 pub use net_ext::IpAddrExt;
 pub use server::{
     Allocate, AllocationPort, Attribute, Binding, ChannelBind, ChannelData, ClientMessage, Command,
-    CreatePermission, Refresh, Server,
+    CreatePermission, Quota, Refresh, SendIndication, Server,
 };
 pub use sleep::Sleep;
 use stun_codec::rfc5389::attributes::Software;
@@ -105,20 +106,50 @@ impl From<(Option<Ipv4Addr>, Option<Ipv6Addr>)> for IpStack {
 /// From the [spec](https://www.rfc-editor.org/rfc/rfc8656#section-2-4.4):
 ///
 /// > A STUN client that implements this specification.
+///
+/// TCP and UDP have separate port spaces, so a client connected via a stream may have the same address as an unrelated client using UDP.
+/// The transport is therefore part of the client's identity.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub struct ClientSocket {
+    addr: SocketAddr,
+    transport: ClientTransport,
+}
+
+/// How a client is connected to us.
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
-pub struct ClientSocket(SocketAddr);
+pub enum ClientTransport {
+    Udp,
+    /// TCP or TLS, see [`stream`].
+    Stream,
+}
 
 impl ClientSocket {
+    /// A client sending us datagrams on our UDP port.
     pub fn new(addr: SocketAddr) -> Self {
-        Self(addr)
+        Self {
+            addr,
+            transport: ClientTransport::Udp,
+        }
+    }
+
+    /// A client connected to us via a TCP or TLS stream.
+    pub fn new_stream(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            transport: ClientTransport::Stream,
+        }
     }
 
     pub fn into_socket(self) -> SocketAddr {
-        self.0
+        self.addr
+    }
+
+    pub fn transport(&self) -> ClientTransport {
+        self.transport
     }
 
     pub fn family(&self) -> AddressFamily {
-        match self.0 {
+        match self.addr {
             SocketAddr::V4(_) => AddressFamily::V4,
             SocketAddr::V6(_) => AddressFamily::V6,
         }
@@ -127,7 +158,10 @@ impl ClientSocket {
 
 impl fmt::Display for ClientSocket {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.0.fmt(f)
+        match self.transport {
+            ClientTransport::Udp => self.addr.fmt(f),
+            ClientTransport::Stream => write!(f, "{} (stream)", self.addr),
+        }
     }
 }
 
@@ -11,23 +11,27 @@ use ebpf_shared::Config;
 use firezone_bin_shared::http_health_check;
 use firezone_logging::{FilterReloadHandle, err_with_src, sentry_layer};
 use firezone_relay::sockets::Sockets;
+use firezone_relay::stream::Streams;
 use firezone_relay::{
-    AddressFamily, AllocationPort, ChannelData, ClientSocket, Command, IpStack, PeerSocket, Server,
-    Sleep, VERSION, control_endpoint, ebpf, sockets,
+    AddressFamily, AllocationPort, ChannelData, ClientSocket, ClientTransport, Command, IpStack,
+    PeerSocket, Quota, Server, Sleep, VERSION, auth, control_endpoint, ebpf, sockets, stream,
 };
 use firezone_telemetry::{RELAY_DSN, Telemetry};
 use futures::{FutureExt, future};
+use opentelemetry_prometheus::PrometheusExporter;
 use phoenix_channel::{Event, LoginUrl, NoParams, PhoenixChannel};
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
 use secrecy::{ExposeSecret, Secret, SecretString};
 use std::borrow::Cow;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Poll, ready};
 use std::time::{Duration, Instant};
 use stun_codec::rfc5766::attributes::ChannelNumber;
+use tokio_rustls::TlsAcceptor;
 use tracing::Subscriber;
 use tracing_core::Dispatch;
 use tracing_stackdriver::CloudTraceConfiguration;
@@ -38,6 +42,9 @@ const STATS_LOG_INTERVAL: Duration = Duration::from_secs(10);
 
 const MAX_PARTITION_TIME: Duration = Duration::from_secs(60 * 15);
 
+/// How often we account for the traffic relayed by the eBPF program against the quotas.
+const QUOTA_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Parser, Debug)]
 struct Args {
     /// The public (i.e. internet-reachable) IPv4 address of the relay server.
@@ -47,8 +54,21 @@ struct Args {
     #[arg(long, env)]
     public_ip6_addr: Option<Ipv6Addr>,
     /// The port to listen on for STUN messages.
+    ///
+    /// We also accept TURN over TCP connections on this port.
     #[arg(long, env, hide = true, default_value = "3478")]
     listen_port: u16,
+    /// The port to listen on for TURN over TLS connections.
+    ///
+    /// Only used if `--tls-cert` and `--tls-key` are set.
+    #[arg(long, env, default_value = "443")]
+    tls_listen_port: u16,
+    /// Path to a PEM-encoded certificate chain to use for TURN over TLS.
+    #[arg(long, env, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// Path to the PEM-encoded private key of the certificate used for TURN over TLS.
+    #[arg(long, env, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
     // See https://www.rfc-editor.org/rfc/rfc8656.html#name-allocations
     /// The lowest port used for TURN allocations.
     #[arg(long, env, hide = true, default_value = "49152")]
@@ -56,11 +76,22 @@ struct Args {
     /// The highest port used for TURN allocations.
     #[arg(long, env, hide = true, default_value = "65535")]
     highest_port: u16,
-    #[arg(long, env = "FIREZONE_API_URL")]
-    api_url: Url,
+    #[arg(
+        long,
+        env = "FIREZONE_API_URL",
+        required_unless_present = "auth_secret_file"
+    )]
+    api_url: Option<Url>,
     /// Token generated by the portal to authorize websocket connection.
-    #[arg(env = "FIREZONE_TOKEN")]
-    token: SecretString,
+    #[arg(env = "FIREZONE_TOKEN", required_unless_present = "auth_secret_file")]
+    token: Option<SecretString>,
+    /// Run in standalone mode without a portal connection, authenticating clients with the secrets in this file.
+    ///
+    /// The file contains one secret per line. Credentials are generated with the first one.
+    /// All others are still accepted, which allows for rotating secrets.
+    /// Send SIGHUP to re-read the file without restarting the relay.
+    #[arg(long, env, conflicts_with_all = ["api_url", "token"])]
+    auth_secret_file: Option<PathBuf>,
     /// Used as the human name for this Relay to display in the portal. If not provided,
     /// the system hostname is used by default.
     #[arg(env = "FIREZONE_NAME")]
@@ -92,6 +123,21 @@ struct Args {
     #[arg(long, env, hide = true)]
     ebpf_offloading: Option<String>,
 
+    /// The maximum number of bytes per second relayed for a single allocation.
+    #[arg(long, env)]
+    allocation_max_bytes_per_second: Option<u64>,
+    /// The maximum number of bytes relayed for a single allocation over its lifetime.
+    #[arg(long, env)]
+    allocation_max_bytes: Option<u64>,
+    /// The maximum number of bytes per second relayed across all allocations of a single username.
+    #[arg(long, env)]
+    username_max_bytes_per_second: Option<u64>,
+    /// The maximum number of bytes relayed across all allocations of a single username until it expires.
+    ///
+    /// Once reached, new allocations for this username are rejected.
+    #[arg(long, env)]
+    username_max_bytes: Option<u64>,
+
     #[command(flatten)]
     health_check: http_health_check::HealthCheckArgs,
 
@@ -119,12 +165,8 @@ fn main() {
     let args = Args::parse();
 
     let mut telemetry = Telemetry::default();
-    if args.telemetry {
-        telemetry.start(
-            args.api_url.as_str(),
-            VERSION.unwrap_or("unknown"),
-            RELAY_DSN,
-        );
+    if let (true, Some(api_url)) = (args.telemetry, args.api_url.as_ref()) {
+        telemetry.start(api_url.as_str(), VERSION.unwrap_or("unknown"), RELAY_DSN);
     }
 
     let runtime = tokio::runtime::Builder::new_current_thread()
@@ -144,7 +186,15 @@ fn main() {
 }
 
 async fn try_main(args: Args) -> Result<()> {
-    let filter_reload_handle = setup_tracing(&args)?;
+    let (metrics_registry, prometheus_exporter) = match args.health_check.metrics_addr {
+        Some(_) => {
+            let (registry, exporter) = http_health_check::prometheus_registry()?;
+
+            (Some(registry), Some(exporter))
+        }
+        None => (None, None),
+    };
+    let filter_reload_handle = setup_tracing(&args, prometheus_exporter)?;
 
     let mut ebpf = args
         .ebpf_offloading
@@ -172,53 +222,116 @@ async fn try_main(args: Args) -> Result<()> {
         }
     };
 
+    let tls = match (args.tls_cert.as_ref(), args.tls_key.as_ref()) {
+        (Some(cert), Some(key)) => {
+            let cert = std::fs::read(cert).with_context(|| {
+                format!("Failed to read TLS certificate from {}", cert.display())
+            })?;
+            let key = std::fs::read(key).with_context(|| {
+                format!("Failed to read TLS private key from {}", key.display())
+            })?;
+
+            Some((args.tls_listen_port, stream::tls_acceptor(&cert, &key)?))
+        }
+        _ => None,
+    };
+
+    let auth_secrets = args
+        .auth_secret_file
+        .as_deref()
+        .map(read_auth_secrets)
+        .transpose()?
+        .unwrap_or_default();
+
     let server = Server::new(
         public_addr,
         make_rng(args.rng_seed),
         args.listen_port,
         args.lowest_port..=args.highest_port,
-    );
+    )
+    .with_quotas(
+        Quota {
+            bytes_per_second: args.allocation_max_bytes_per_second,
+            total_bytes: args.allocation_max_bytes,
+        },
+        Quota {
+            bytes_per_second: args.username_max_bytes_per_second,
+            total_bytes: args.username_max_bytes,
+        },
+    )
+    .with_auth_secrets(auth_secrets);
 
     let last_heartbeat_sent = Arc::new(Mutex::new(Option::<Instant>::None));
 
     tokio::spawn(http_health_check::serve(
         args.health_check.health_check_addr,
         make_is_healthy(last_heartbeat_sent.clone()),
+        None,
     ));
 
+    if let Some((addr, registry)) = args.health_check.metrics_addr.zip(metrics_registry) {
+        tokio::spawn(http_health_check::serve(addr, || true, Some(registry)));
+    }
+
+    let (control_requests_tx, control_requests_rx) = tokio::sync::mpsc::channel(10);
+
     tokio::spawn(control_endpoint::serve(
         args.control_endpoint,
         filter_reload_handle,
+        control_requests_tx,
     ));
 
-    let login = LoginUrl::relay(
-        args.api_url.clone(),
-        &args.token,
-        args.name.clone(),
-        args.listen_port,
-        args.public_ip4_addr,
-        args.public_ip6_addr,
-    )?;
+    let channel = if args.auth_secret_file.is_some() {
+        tracing::info!(target: "relay", "Running in standalone mode without a portal connection");
+
+        None
+    } else {
+        let api_url = args.api_url.clone().context("Missing portal API URL")?;
+        let token = args.token.as_ref().context("Missing portal token")?;
+
+        let login = LoginUrl::relay(
+            api_url,
+            token,
+            args.name.clone(),
+            args.listen_port,
+            args.public_ip4_addr,
+            args.public_ip6_addr,
+        )?;
+
+        let mut channel = PhoenixChannel::disconnected(
+            Secret::new(login),
+            format!("relay/{}", env!("CARGO_PKG_VERSION")),
+            "relay",
+            JoinMessage {
+                stamp_secret: server.auth_secret().expose_secret().to_string(),
+            },
+            || {
+                ExponentialBackoffBuilder::default()
+                    .with_max_elapsed_time(Some(MAX_PARTITION_TIME))
+                    .build()
+            },
+            Arc::new(socket_factory::tcp),
+        )?;
+        channel.connect(NoParams);
+
+        Some(channel)
+    };
 
-    let mut channel = PhoenixChannel::disconnected(
-        Secret::new(login),
-        format!("relay/{}", env!("CARGO_PKG_VERSION")),
-        "relay",
-        JoinMessage {
-            stamp_secret: server.auth_secret().expose_secret().to_string(),
-        },
-        || {
-            ExponentialBackoffBuilder::default()
-                .with_max_elapsed_time(Some(MAX_PARTITION_TIME))
-                .build()
-        },
-        Arc::new(socket_factory::tcp),
+    let mut eventloop = Eventloop::new(
+        server,
+        ebpf,
+        channel,
+        control_requests_rx,
+        public_addr,
+        tls,
+        last_heartbeat_sent,
+        args.auth_secret_file.clone(),
     )?;
-    channel.connect(NoParams);
-
-    let mut eventloop = Eventloop::new(server, ebpf, channel, public_addr, last_heartbeat_sent)?;
 
-    tracing::info!(target: "relay", "Listening for incoming traffic on UDP port {0}", args.listen_port);
+    tracing::info!(target: "relay", "Listening for incoming traffic on UDP and TCP port {0}", args.listen_port);
+    if args.tls_cert.is_some() {
+        tracing::info!(target: "relay", "Listening for incoming traffic on TLS port {0}", args.tls_listen_port);
+    }
 
     future::poll_fn(|cx| eventloop.poll(cx))
         .await
@@ -229,6 +342,19 @@ async fn try_main(args: Args) -> Result<()> {
     Ok(())
 }
 
+fn read_auth_secrets(path: &Path) -> Result<Vec<SecretString>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read auth secrets from `{}`", path.display()))?;
+
+    let secrets = auth::parse_secrets(&contents);
+
+    if secrets.is_empty() {
+        bail!("`{}` does not contain any auth secrets", path.display());
+    }
+
+    Ok(secrets)
+}
+
 /// Sets up our tracing infrastructure.
 ///
 /// See [`log_layer`] for details on the base log layer.
@@ -236,7 +362,14 @@ async fn try_main(args: Args) -> Result<()> {
 /// ## Integration with OTLP
 ///
 /// If the user has specified [`TraceCollector::Otlp`], we will set up an OTLP-exporter that connects to an OTLP collector specified at `Args.otlp_grpc_endpoint`.
-fn setup_tracing(args: &Args) -> Result<FilterReloadHandle> {
+///
+/// ## Integration with Prometheus
+///
+/// If a [`PrometheusExporter`] is given, metrics are exported to it in addition to the OTLP collector if one is configured.
+fn setup_tracing(
+    args: &Args,
+    prometheus: Option<PrometheusExporter>,
+) -> Result<FilterReloadHandle> {
     use opentelemetry::{global, trace::TracerProvider as _};
     use opentelemetry_otlp::WithExportConfig;
 
@@ -250,6 +383,15 @@ fn setup_tracing(args: &Args) -> Result<FilterReloadHandle> {
 
     let (dispatch, reload_handle) = match args.otlp_grpc_endpoint.clone() {
         None => {
+            if let Some(prometheus) = prometheus {
+                let meter_provider = opentelemetry_sdk::metrics::SdkMeterProvider::builder()
+                    .with_resource(make_otel_metadata())
+                    .with_reader(prometheus)
+                    .build();
+
+                global::set_meter_provider(meter_provider);
+            }
+
             let (filter, reload_handle) = firezone_logging::try_filter(&directives)?;
 
             let dispatch: Dispatch = tracing_subscriber::registry()
@@ -287,12 +429,15 @@ fn setup_tracing(args: &Args) -> Result<FilterReloadHandle> {
                 .build()
                 .context("Failed to build OTLP metric exporter")?;
 
-            let meter_provider = opentelemetry_sdk::metrics::SdkMeterProvider::builder()
+            let mut meter_provider = opentelemetry_sdk::metrics::SdkMeterProvider::builder()
                 .with_resource(metadata)
-                .with_periodic_exporter(exporter)
-                .build();
+                .with_periodic_exporter(exporter);
 
-            global::set_meter_provider(meter_provider);
+            if let Some(prometheus) = prometheus {
+                meter_provider = meter_provider.with_reader(prometheus);
+            }
+
+            global::set_meter_provider(meter_provider.build());
 
             tracing::trace!(target: "relay", "Successfully initialized metric provider on tokio runtime");
 
@@ -371,9 +516,11 @@ const MAX_UDP_SIZE: usize = 65536;
 
 struct Eventloop<R> {
     sockets: Sockets,
+    streams: Streams,
 
     server: Server<R>,
     channel: Option<PhoenixChannel<JoinMessage, IngressMessage, (), NoParams>>,
+    control_requests: tokio::sync::mpsc::Receiver<control_endpoint::Request>,
     sleep: Sleep,
 
     ebpf: Option<ebpf::Program>,
@@ -382,9 +529,17 @@ struct Eventloop<R> {
     sigterm: tokio::signal::unix::Signal,
     shutting_down: bool,
 
+    /// Re-read on SIGHUP.
+    auth_secret_file: Option<PathBuf>,
+    #[cfg(unix)]
+    sighup: tokio::signal::unix::Signal,
+
     stats_log_interval: tokio::time::Interval,
     last_num_bytes_relayed: u64,
 
+    /// Only set if the eBPF program is loaded and quotas are configured.
+    quota_sync_interval: Option<tokio::time::Interval>,
+
     last_heartbeat_sent: Arc<Mutex<Option<Instant>>>,
 
     buffer: [u8; MAX_UDP_SIZE],
@@ -397,46 +552,70 @@ where
     fn new(
         server: Server<R>,
         ebpf: Option<ebpf::Program>,
-        channel: PhoenixChannel<JoinMessage, IngressMessage, (), NoParams>,
+        channel: Option<PhoenixChannel<JoinMessage, IngressMessage, (), NoParams>>,
+        control_requests: tokio::sync::mpsc::Receiver<control_endpoint::Request>,
         public_address: IpStack,
+        tls: Option<(u16, TlsAcceptor)>,
         last_heartbeat_sent: Arc<Mutex<Option<Instant>>>,
+        auth_secret_file: Option<PathBuf>,
     ) -> Result<Self> {
         let mut sockets = Sockets::new();
+        let mut streams = Streams::new();
 
-        if public_address.as_v4().is_some() {
+        let families = [
+            public_address.as_v4().map(|_| AddressFamily::V4),
+            public_address.as_v6().map(|_| AddressFamily::V6),
+        ];
+
+        for family in families.into_iter().flatten() {
             sockets
-                .bind(server.listen_port(), AddressFamily::V4)
+                .bind(server.listen_port(), family)
                 .with_context(|| {
                     format!(
-                        "Failed to bind to port {0} on IPv4 interfaces",
+                        "Failed to bind to port {0} on {family} interfaces",
                         server.listen_port()
                     )
                 })?;
-        }
-        if public_address.as_v6().is_some() {
-            sockets
-                .bind(server.listen_port(), AddressFamily::V6)
+            streams
+                .listen_tcp(server.listen_port(), family)
                 .with_context(|| {
                     format!(
-                        "Failed to bind to port {0} on IPv6 interfaces",
+                        "Failed to listen on TCP port {0} on {family} interfaces",
                         server.listen_port()
                     )
                 })?;
+
+            if let Some((port, acceptor)) = tls.clone() {
+                streams
+                    .listen_tls(port, family, acceptor)
+                    .with_context(|| {
+                        format!("Failed to listen on TLS port {port} on {family} interfaces")
+                    })?;
+            }
         }
 
+        let quota_sync_interval = (ebpf.is_some() && server.has_quotas())
+            .then(|| tokio::time::interval(QUOTA_SYNC_INTERVAL));
+
         Ok(Self {
             server,
-            channel: Some(channel),
+            channel,
+            control_requests,
             sleep: Sleep::default(),
             stats_log_interval: tokio::time::interval(STATS_LOG_INTERVAL),
             last_num_bytes_relayed: 0,
+            quota_sync_interval,
             sockets,
+            streams,
             ebpf,
             buffer: [0u8; MAX_UDP_SIZE],
             last_heartbeat_sent,
             #[cfg(unix)]
             sigterm: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?,
             shutting_down: false,
+            auth_secret_file,
+            #[cfg(unix)]
+            sighup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?,
         })
     }
 
@@ -454,9 +633,11 @@ where
             if let Some(next_command) = self.server.next_command() {
                 match next_command {
                     Command::SendMessage { payload, recipient } => {
-                        if let Err(e) = self.sockets.try_send(
+                        if let Err(e) = send_to_client(
+                            &mut self.sockets,
+                            &mut self.streams,
                             self.server.listen_port(),
-                            recipient.into_socket(),
+                            recipient,
                             Cow::Owned(payload),
                         ) {
                             tracing::warn!(target: "relay", %recipient, "Failed to send message: {}", err_with_src(&e));
@@ -512,6 +693,19 @@ where
                             tracing::debug!(target: "relay", %client, "Failed to delete channel binding in eBPF map: {e:#}");
                         }
                     }
+                    Command::ForwardToPeer {
+                        payload,
+                        peer,
+                        allocation_port,
+                    } => {
+                        if let Err(e) = self.sockets.try_send(
+                            allocation_port.value(),
+                            peer.into_socket(),
+                            Cow::Owned(payload),
+                        ) {
+                            tracing::warn!(target: "relay", %peer, "Failed to relay data to peer: {}", err_with_src(&e));
+                        }
+                    }
                 }
 
                 ready = true;
@@ -575,6 +769,7 @@ where
                         packet,
                         PeerSocket::new(from),
                         AllocationPort::new(port),
+                        Instant::now(),
                     ) {
                         let total_length = ChannelData::encode_header_to_slice(
                             channel,
@@ -582,9 +777,11 @@ where
                             header,
                         );
 
-                        if let Err(e) = self.sockets.try_send(
-                            self.server.listen_port(), // Packets coming in from peers always go out on the TURN port
-                            client.into_socket(),
+                        if let Err(e) = send_to_client(
+                            &mut self.sockets,
+                            &mut self.streams,
+                            self.server.listen_port(), // Packets coming in from peers always go out on the TURN port or the client's stream.
+                            client,
                             Cow::Borrowed(&self.buffer[..total_length]),
                         ) {
                             tracing::warn!(target: "relay", %client, "Failed to relay data to client: {}", err_with_src(&e));
@@ -602,20 +799,57 @@ where
                 Poll::Pending => {}
             }
 
-            // Priority 3: Check when we need to next be woken. This needs to happen after all state modifications.
+            // Priority 3: Read from our stream connections.
+            match self.streams.poll_recv(cx) {
+                Poll::Ready(stream::Received::Frame { from, packet }) => {
+                    let client = ClientSocket::new_stream(from);
+                    let relay = self
+                        .server
+                        .handle_client_input(&packet, client, Instant::now());
+
+                    if self.server.has_allocation(client) {
+                        self.streams.authenticate(from);
+                    }
+
+                    if let Some((port, peer)) = relay {
+                        let payload = ChannelData::parse(&packet)
+                            .expect("valid ChannelData if we should relay it")
+                            .data();
+
+                        if let Err(e) = self.sockets.try_send(
+                            port.value(),
+                            peer.into_socket(),
+                            Cow::Borrowed(payload),
+                        ) {
+                            tracing::warn!(target: "relay", %peer, "Failed to relay data to peer: {}", err_with_src(&e));
+                        }
+                    }
+
+                    ready = true;
+                }
+                Poll::Ready(stream::Received::Disconnected(client)) => {
+                    self.server
+                        .handle_client_disconnected(ClientSocket::new_stream(client));
+
+                    ready = true;
+                }
+                Poll::Pending => {}
+            }
+
+            // Priority 4: Check when we need to next be woken. This needs to happen after all state modifications.
             if let Some(timeout) = self.server.poll_timeout() {
                 Pin::new(&mut self.sleep).reset(timeout);
                 // Purposely no `ready = true` because we just change the state of `sleep` and we poll it below.
             }
 
-            // Priority 4: Handle time-sensitive tasks:
+            // Priority 5: Handle time-sensitive tasks:
             if let Poll::Ready(deadline) = self.sleep.poll_unpin(cx) {
                 self.server.handle_timeout(deadline);
 
                 ready = true;
             }
 
-            // Priority 5: Handle portal messages
+            // Priority 6: Handle portal messages
             match self.channel.as_mut().map(|c| c.poll(cx)) {
                 Some(Poll::Ready(result)) => {
                     let event = result.context("Portal connection failed")?;
@@ -653,6 +887,25 @@ where
                 Poll::Ready(None) | Poll::Pending => {}
             }
 
+            #[cfg(unix)]
+            match self.sighup.poll_recv(cx) {
+                Poll::Ready(Some(())) => {
+                    self.reload_auth_secrets();
+
+                    ready = true;
+                }
+                Poll::Ready(None) | Poll::Pending => {}
+            }
+
+            match self.control_requests.poll_recv(cx) {
+                Poll::Ready(Some(request)) => {
+                    self.handle_control_request(request);
+
+                    ready = true;
+                }
+                Poll::Ready(None) | Poll::Pending => {}
+            }
+
             if self.stats_log_interval.poll_tick(cx).is_ready() {
                 let num_allocations = self.server.num_allocations();
                 let num_channels = self.server.num_active_channels();
@@ -668,12 +921,72 @@ where
                 ready = true;
             }
 
+            if let (Some(interval), Some(ebpf)) =
+                (self.quota_sync_interval.as_mut(), self.ebpf.as_mut())
+            {
+                if interval.poll_tick(cx).is_ready() {
+                    let server = &mut self.server;
+
+                    if let Err(e) = ebpf.sync_channel_counters(|allocation, num_bytes| {
+                        server.handle_offloaded_traffic(allocation, num_bytes)
+                    }) {
+                        tracing::warn!("Failed to sync eBPF channel counters: {e:#}");
+                    }
+
+                    let budgets = server.channel_offload_budgets(Instant::now());
+
+                    if let Err(e) = ebpf.set_channel_byte_limits(|allocation| {
+                        budgets.get(&allocation).copied().unwrap_or_default()
+                    }) {
+                        tracing::warn!("Failed to set eBPF channel byte limits: {e:#}");
+                    }
+
+                    ready = true;
+                }
+            }
+
             if !ready {
                 break Poll::Pending;
             }
         }
     }
 
+    fn handle_control_request(&mut self, request: control_endpoint::Request) {
+        let reply = match request {
+            control_endpoint::Request::Drain { deadline, reply } => {
+                self.server
+                    .drain(deadline.map(|deadline| Instant::now() + deadline));
+
+                reply
+            }
+            control_endpoint::Request::DrainStatus { reply } => reply,
+        };
+
+        let _ = reply.send(control_endpoint::DrainStatus {
+            draining: self.server.is_draining(),
+            allocations: self.server.num_allocations(),
+            channels: self.server.num_active_channels(),
+        });
+    }
+
+    fn reload_auth_secrets(&mut self) {
+        let Some(path) = self.auth_secret_file.as_deref() else {
+            tracing::debug!(target: "relay", "Ignoring SIGHUP: No auth secret file configured");
+            return;
+        };
+
+        match read_auth_secrets(path) {
+            Ok(secrets) => {
+                tracing::info!(target: "relay", num_secrets = %secrets.len(), "Reloaded auth secrets");
+
+                self.server.set_auth_secrets(secrets);
+            }
+            Err(e) => {
+                tracing::warn!(target: "relay", "Failed to reload auth secrets, keeping the current ones: {e:#}");
+            }
+        }
+    }
+
     fn create_channel_binding_in_ebpf_map(
         &mut self,
         client: ClientSocket,
@@ -685,8 +998,17 @@ where
             return Ok(()); // ebPF program not loaded ...
         };
 
+        if client.transport() == ClientTransport::Stream {
+            return Ok(()); // The eBPF program can only relay to clients connected via UDP.
+        }
+
         ebpf.add_channel_binding(client, channel_number, peer, allocation_port)?;
 
+        // New channels only get a budget with the next sync, once it can be split across all channels of the allocation.
+        if self.server.has_quotas() {
+            ebpf.set_channel_byte_limit(allocation_port, channel_number, 0)?;
+        }
+
         Ok(())
     }
 
@@ -701,6 +1023,10 @@ where
             return Ok(()); // ebPF program not loaded ...
         };
 
+        if client.transport() == ClientTransport::Stream {
+            return Ok(()); // The eBPF program can only relay to clients connected via UDP.
+        }
+
         ebpf.remove_channel_binding(client, channel_number, peer, allocation_port)?;
 
         Ok(())
@@ -738,6 +1064,20 @@ where
     }
 }
 
+/// Sends a message to a client, either via its stream connection or as a UDP datagram from our TURN port.
+fn send_to_client(
+    sockets: &mut Sockets,
+    streams: &mut Streams,
+    listen_port: u16,
+    client: ClientSocket,
+    msg: Cow<'_, [u8]>,
+) -> std::io::Result<()> {
+    match client.transport() {
+        ClientTransport::Udp => sockets.try_send(listen_port, client.into_socket(), msg),
+        ClientTransport::Stream => streams.try_send(client.into_socket(), &msg),
+    }
+}
+
 fn fmt_human_throughput(mut throughput: f64) -> String {
     let units = ["B/s", "kB/s", "MB/s", "GB/s", "TB/s"];
 
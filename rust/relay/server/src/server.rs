@@ -1,13 +1,19 @@
 mod channel_data;
 mod client_message;
+mod quota;
 
 pub use crate::server::channel_data::ChannelData;
 pub use crate::server::client_message::{
-    Allocate, Binding, ChannelBind, ClientMessage, CreatePermission, Refresh,
+    Allocate, Binding, ChannelBind, ClientMessage, CreatePermission, Refresh, SendIndication,
 };
+pub use crate::server::quota::Quota;
 
-use crate::auth::{self, AuthenticatedMessage, FIREZONE, MessageIntegrityExt, Nonces};
+use crate::auth::{
+    self, AuthenticatedMessage, FIREZONE, MessageIntegrityExt, Nonces, split_username,
+    systemtime_from_unix,
+};
 use crate::net_ext::IpAddrExt;
+use crate::server::quota::Usage;
 use crate::{ClientSocket, IpStack, PeerSocket, SOFTWARE};
 use anyhow::Result;
 use bytecodec::EncodeExt;
@@ -21,6 +27,7 @@ use secrecy::SecretString;
 use smallvec::SmallVec;
 use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::hash::Hash;
+use std::iter;
 use std::net::{IpAddr, SocketAddr};
 use std::ops::RangeInclusive;
 use std::time::{Duration, Instant, SystemTime};
@@ -30,10 +37,12 @@ use stun_codec::rfc5389::attributes::{
 use stun_codec::rfc5389::errors::{BadRequest, ServerError, StaleNonce, Unauthorized};
 use stun_codec::rfc5389::methods::BINDING;
 use stun_codec::rfc5766::attributes::{
-    ChannelNumber, Lifetime, RequestedTransport, XorPeerAddress, XorRelayAddress,
+    ChannelNumber, Data, Lifetime, RequestedTransport, XorPeerAddress, XorRelayAddress,
+};
+use stun_codec::rfc5766::errors::{
+    AllocationMismatch, AllocationQuotaReached, InsufficientCapacity,
 };
-use stun_codec::rfc5766::errors::{AllocationMismatch, InsufficientCapacity};
-use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, REFRESH};
+use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, DATA, REFRESH};
 use stun_codec::rfc8656::attributes::{
     AdditionalAddressFamily, AddressFamily, RequestedAddressFamily,
 };
@@ -70,11 +79,28 @@ pub struct Server<R> {
     /// Channel numbers are unique between clients and peers, thus indexed by both.
     channel_numbers_by_client_and_peer: HashMap<(ClientSocket, PeerSocket), ChannelNumber>,
 
+    /// The expiry of each permission, indexed by the allocation and the peer's IP.
+    ///
+    /// Permissions are only needed for send and data indications, channels are sufficient for relaying channel data.
+    permissions: HashMap<(AllocationPort, IpAddr), Instant>,
+
+    allocation_quota: Quota,
+    username_quota: Quota,
+    /// How much data we relayed for each username, across all of its allocations.
+    usage_by_username: HashMap<String, UsernameUsage>,
+
+    /// Whether we reject new allocations because the relay is being drained.
+    draining: bool,
+    /// When to free all remaining allocations of a drained relay.
+    drain_deadline: Option<Instant>,
+
     pending_commands: VecDeque<Command>,
 
     rng: R,
 
     auth_secret: SecretString,
+    /// Secrets that we still accept credentials for, e.g. while rotating `auth_secret`.
+    previous_auth_secrets: Vec<SecretString>,
 
     nonces: Nonces,
 
@@ -121,6 +147,12 @@ pub enum Command {
         peer: PeerSocket,
         allocation_port: AllocationPort,
     },
+    /// Send the payload of a send indication to the given [`PeerSocket`] from the [`AllocationPort`].
+    ForwardToPeer {
+        payload: Vec<u8>,
+        peer: PeerSocket,
+        allocation_port: AllocationPort,
+    },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
@@ -155,6 +187,11 @@ const CHANNEL_BINDING_DURATION: Duration = Duration::from_secs(600);
 /// See <https://www.rfc-editor.org/rfc/rfc8656#section-12-14>.
 const CHANNEL_REBIND_TIMEOUT: Duration = Duration::from_secs(300);
 
+/// The duration of a permission.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc8656#name-permissions-2>.
+const PERMISSION_LIFETIME: Duration = Duration::from_secs(300);
+
 impl<R> Server<R>
 where
     R: Rng,
@@ -199,8 +236,15 @@ where
             ports,
             channels_by_client_and_number: Default::default(),
             channel_numbers_by_client_and_peer: Default::default(),
+            permissions: Default::default(),
+            allocation_quota: Quota::default(),
+            username_quota: Quota::default(),
+            usage_by_username: Default::default(),
+            draining: false,
+            drain_deadline: None,
             pending_commands: Default::default(),
             auth_secret: SecretString::from(hex::encode(rng.r#gen::<[u8; 32]>())),
+            previous_auth_secrets: Vec::new(),
             rng,
             nonces: Default::default(),
             allocations_up_down_counter,
@@ -211,6 +255,61 @@ where
         }
     }
 
+    /// Limits how much data is relayed per allocation and per username.
+    ///
+    /// Data exceeding the rate limit is dropped.
+    /// Once a username has relayed its total volume, it can no longer make new allocations.
+    pub fn with_quotas(mut self, per_allocation: Quota, per_username: Quota) -> Self {
+        self.allocation_quota = per_allocation;
+        self.username_quota = per_username;
+
+        self
+    }
+
+    pub fn has_quotas(&self) -> bool {
+        !self.allocation_quota.is_unlimited() || !self.username_quota.is_unlimited()
+    }
+
+    /// Stops accepting new allocations.
+    ///
+    /// Existing allocations and their channels keep working until they expire; refreshes cannot extend them any further.
+    /// If a `deadline` is given, all allocations still around at that point are freed.
+    pub fn drain(&mut self, deadline: Option<Instant>) {
+        tracing::info!(target: "relay", active_allocations = %self.num_allocations(), ?deadline, "Draining relay");
+
+        self.draining = true;
+        self.drain_deadline = deadline;
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    /// Authenticates clients with the given secrets instead of a randomly generated one.
+    ///
+    /// The first secret is used for all new credentials, the others are only accepted to allow for rotating secrets.
+    /// If `secrets` is empty, the randomly generated secret is kept.
+    pub fn with_auth_secrets(mut self, secrets: impl IntoIterator<Item = SecretString>) -> Self {
+        self.set_auth_secrets(secrets);
+
+        self
+    }
+
+    /// Replaces the secrets we authenticate clients with, e.g. after the secrets file changed.
+    ///
+    /// Existing allocations are kept; their credentials are only accepted if derived from one of the new secrets.
+    /// If `secrets` is empty, the current secrets are kept.
+    pub fn set_auth_secrets(&mut self, secrets: impl IntoIterator<Item = SecretString>) {
+        let mut secrets = secrets.into_iter();
+
+        let Some(current) = secrets.next() else {
+            return;
+        };
+
+        self.auth_secret = current;
+        self.previous_auth_secrets = secrets.collect();
+    }
+
     pub fn auth_secret(&self) -> &SecretString {
         &self.auth_secret
     }
@@ -246,6 +345,10 @@ where
         self.allocations.len()
     }
 
+    pub fn has_allocation(&self, client: ClientSocket) -> bool {
+        self.allocations.contains_key(&client)
+    }
+
     pub fn num_active_channels(&self) -> usize {
         self.channels_by_client_and_number
             .iter()
@@ -311,14 +414,18 @@ where
                 self.handle_channel_bind_request(request, sender, now)
             }
             ClientMessage::CreatePermission(request) => {
-                self.handle_create_permission_request(request, sender)
+                self.handle_create_permission_request(request, sender, now)
             }
             ClientMessage::Binding(request) => {
                 self.handle_binding_request(request, sender);
                 return None;
             }
             ClientMessage::ChannelData(msg) => {
-                return self.handle_channel_data_message(msg, sender);
+                return self.handle_channel_data_message(msg, sender, now);
+            }
+            ClientMessage::SendIndication(indication) => {
+                self.handle_send_indication(indication, sender, now);
+                return None;
             }
         };
 
@@ -341,8 +448,11 @@ where
 
         let message = match message.username() {
             Some(username) => {
-                match AuthenticatedMessage::new(&self.auth_secret, username.name(), error_response)
-                {
+                match AuthenticatedMessage::new(
+                    self.auth_secret_for(&message),
+                    username.name(),
+                    error_response,
+                ) {
                     Ok(message) => message,
                     Err(e) => {
                         tracing::warn!(target: "relay", "Failed to create error response: {}", err_with_src(&e));
@@ -368,30 +478,38 @@ where
     ///
     /// - [`Some`] if there is an active channel on this allocation for this peer.
     ///   In that case, you should create a [`ChannelData`] message with the returned channel number and send it to the [`ClientSocket`].
+    /// - [`None`] otherwise.
+    ///   If the client has a permission for this peer but no channel, the data is relayed in a data indication via [`Command::SendMessage`].
     #[tracing::instrument(level = "debug", skip_all, fields(%sender, %allocation, recipient, channel))]
     pub fn handle_peer_traffic(
         &mut self,
         msg: &[u8],
         sender: PeerSocket,
         allocation: AllocationPort,
+        now: Instant,
     ) -> Option<(ClientSocket, ChannelNumber)> {
         let Some((client, channel_number)) = self
             .channel_and_client_by_port_and_peer
             .get(&(allocation, sender))
+            .copied()
         else {
-            tracing::debug!(target: "relay", "no channel");
+            self.send_data_indication(msg, sender, allocation, now);
 
             return None;
         };
 
         Span::current().record("recipient", field::display(&client));
 
+        if !self.consume_quota(client, msg.len(), now) {
+            return None;
+        }
+
         self.data_relayed_counter.add(msg.len() as u64, &[]);
         self.data_relayed += msg.len() as u64;
 
         tracing::trace!(target: "wire", num_bytes = %msg.len());
 
-        Some((*client, *channel_number))
+        Some((client, channel_number))
     }
 
     /// An allocation failed.
@@ -400,6 +518,92 @@ where
         self.delete_allocation(allocation)
     }
 
+    /// A client connected via a stream transport (TCP or TLS) closed its connection.
+    ///
+    /// With a stream transport, the lifetime of the allocation is bound to the connection it was made on.
+    /// Any data relayed from peers could not be delivered anymore so we free the allocation right away instead of waiting for it to expire.
+    #[tracing::instrument(level = "debug", skip(self), fields(%client))]
+    pub fn handle_client_disconnected(&mut self, client: ClientSocket) {
+        let Some(allocation) = self.allocations.get(&client) else {
+            return;
+        };
+
+        self.delete_allocation(allocation.port)
+    }
+
+    /// Accounts for data of an allocation that was relayed without going through the [`Server`], e.g. by the eBPF kernel.
+    pub fn handle_offloaded_traffic(&mut self, allocation: AllocationPort, num_bytes: u64) {
+        let Some(allocation) = self
+            .clients_by_allocation
+            .get(&allocation)
+            .and_then(|client| self.allocations.get_mut(client))
+        else {
+            return;
+        };
+
+        allocation.usage.consume(num_bytes);
+
+        if let Some(username) = self.usage_by_username.get_mut(&allocation.username) {
+            username.usage.consume(num_bytes);
+        }
+    }
+
+    /// How many bytes each channel may relay without going through the [`Server`] right now, indexed by allocation.
+    ///
+    /// The budgets of an allocation and of its username are split evenly across their bound channels so that all channels together cannot exceed the quotas.
+    /// Once a channel's budget is used up, its traffic must be handed to the [`Server`] again so it can enforce the quotas.
+    pub fn channel_offload_budgets(&mut self, now: Instant) -> BTreeMap<AllocationPort, u64> {
+        let mut channels_by_allocation = BTreeMap::<AllocationPort, u64>::new();
+        let mut channels_by_username = BTreeMap::<String, u64>::new();
+
+        for channel in self.channels_by_client_and_number.values() {
+            if !channel.bound {
+                continue;
+            }
+
+            let Some(allocation) = self
+                .clients_by_allocation
+                .get(&channel.allocation)
+                .and_then(|client| self.allocations.get(client))
+            else {
+                continue;
+            };
+
+            *channels_by_allocation
+                .entry(channel.allocation)
+                .or_default() += 1;
+            *channels_by_username
+                .entry(allocation.username.clone())
+                .or_default() += 1;
+        }
+
+        let mut budgets = BTreeMap::new();
+
+        for (port, num_channels) in channels_by_allocation {
+            let Some(allocation) = self
+                .clients_by_allocation
+                .get(&port)
+                .and_then(|client| self.allocations.get_mut(client))
+            else {
+                continue;
+            };
+
+            let allocation_budget =
+                allocation.usage.budget(&self.allocation_quota, now) / num_channels;
+            let username_budget = self
+                .usage_by_username
+                .get_mut(&allocation.username)
+                .zip(channels_by_username.get(&allocation.username))
+                .map_or(u64::MAX, |(u, num_channels)| {
+                    u.usage.budget(&self.username_quota, now) / num_channels
+                });
+
+            budgets.insert(port, allocation_budget.min(username_budget));
+        }
+
+        budgets
+    }
+
     /// Return the next command to be executed.
     pub fn next_command(&mut self) -> Option<Command> {
         self.pending_commands.pop_front()
@@ -415,23 +619,48 @@ where
             }
         });
         let allocation_expiries = self.allocations.values().map(|a| a.expires_at);
+        let permission_expiries = self.permissions.values().copied();
 
         channel_expiries
             .chain(allocation_expiries)
+            .chain(permission_expiries)
+            .chain(self.drain_deadline)
             .fold(None, |current, next| earliest(current, Some(next)))
     }
 
     pub fn handle_timeout(&mut self, now: Instant) {
+        let drain_deadline_passed = self.drain_deadline.is_some_and(|deadline| deadline <= now);
+
+        if drain_deadline_passed {
+            tracing::info!(target: "relay", active_allocations = %self.num_allocations(), "Drain deadline passed, freeing all remaining allocations");
+
+            self.drain_deadline = None;
+        }
+
         let expired_allocations = self
             .allocations
             .values()
-            .filter_map(|a| a.is_expired(now).then_some(a.port))
+            .filter_map(|a| (drain_deadline_passed || a.is_expired(now)).then_some(a.port))
             .collect::<Vec<_>>();
 
         for id in expired_allocations {
             self.delete_allocation(id);
         }
 
+        let now_system = SystemTime::now(); // This is impure but we don't need to control this in our tests.
+        self.usage_by_username
+            .retain(|_, usage| usage.expires_at > now_system || usage.num_allocations > 0);
+
+        self.permissions.retain(|(allocation, peer), expiry| {
+            if *expiry > now {
+                return true;
+            }
+
+            tracing::info!(target: "relay", %allocation, %peer, "Permission is now expired");
+
+            false
+        });
+
         for ((client, number), channel) in self
             .channels_by_client_and_number
             .iter_mut()
@@ -491,7 +720,7 @@ where
         sender: ClientSocket,
         now: Instant,
     ) -> Result<(), Message<Attribute>> {
-        let username = self.verify_auth(request)?;
+        let (username, auth_secret) = self.verify_auth(request)?;
 
         if let Some(allocation) = self.allocations.get(&sender) {
             Span::current().record("allocation", display(&allocation.port));
@@ -502,6 +731,14 @@ where
             return Err(error_response);
         }
 
+        if self.draining {
+            let (error_response, msg) = make_error_response(InsufficientCapacity, request);
+
+            tracing::info!(target: "relay", "{msg}: Relay is draining");
+
+            return Err(error_response);
+        }
+
         let max_available_ports = self.max_available_ports() as usize;
         if self.clients_by_allocation.len() == max_available_ports {
             let (error_response, msg) = make_error_response(InsufficientCapacity, request);
@@ -511,6 +748,18 @@ where
             return Err(error_response);
         }
 
+        if self
+            .usage_by_username
+            .get(username.name())
+            .is_some_and(|u| u.usage.has_exhausted(&self.username_quota))
+        {
+            let (error_response, msg) = make_error_response(AllocationQuotaReached, request);
+
+            tracing::info!(target: "relay", username = %username.name(), "{msg}: Username has relayed its total volume");
+
+            return Err(error_response);
+        }
+
         let requested_protocol = request.requested_transport().protocol();
         if requested_protocol != UDP_TRANSPORT {
             let (error_response, msg) = make_error_response(BadRequest, request);
@@ -539,6 +788,7 @@ where
         let allocation = self.create_new_allocation(
             now,
             &effective_lifetime,
+            username.name(),
             first_relay_address,
             maybe_second_relay_addr,
         );
@@ -571,7 +821,7 @@ where
                 family: second_relay_addr.family(),
             });
         }
-        self.authenticate_and_send(username.name(), request, message, sender);
+        self.authenticate_and_send(&auth_secret, username.name(), request, message, sender);
 
         Span::current().record("allocation", display(&allocation.port));
 
@@ -592,6 +842,16 @@ where
             )
         }
 
+        self.usage_by_username
+            .entry(username.name().to_owned())
+            .or_insert_with(|| UsernameUsage {
+                usage: Usage::new(&self.username_quota, now),
+                expires_at: split_username(username.name())
+                    .map(|(expiry, _)| systemtime_from_unix(expiry))
+                    .unwrap_or_else(|_| SystemTime::now()), // We only get here if the username authenticated, so it must be valid.
+                num_allocations: 0,
+            })
+            .num_allocations += 1;
         self.clients_by_allocation.insert(allocation.port, sender);
         self.allocations.insert(sender, allocation);
         self.allocations_up_down_counter.add(1, &[]);
@@ -609,7 +869,7 @@ where
         sender: ClientSocket,
         now: Instant,
     ) -> Result<(), Message<Attribute>> {
-        let username = self.verify_auth(request)?;
+        let (username, auth_secret) = self.verify_auth(request)?;
 
         // TODO: Verify that this is the correct error code.
         let Some(allocation) = self.allocations.get_mut(&sender) else {
@@ -628,6 +888,7 @@ where
 
             self.delete_allocation(port);
             self.authenticate_and_send(
+                &auth_secret,
                 username.name(),
                 request,
                 refresh_success_response(effective_lifetime, request.transaction_id()),
@@ -637,11 +898,21 @@ where
             return Ok(());
         }
 
-        allocation.expires_at = now + effective_lifetime.lifetime();
+        let mut expires_at = now + effective_lifetime.lifetime();
+
+        // A draining relay must not keep allocations around forever: refreshes may only shorten them.
+        if self.draining && allocation.expires_at < expires_at {
+            expires_at = allocation.expires_at;
+        }
 
-        tracing::info!(target: "relay", "Refreshed allocation");
+        allocation.expires_at = expires_at;
+        let effective_lifetime = Lifetime::new(expires_at.duration_since(now))
+            .expect("lifetime is at most the requested lifetime which is less than 0xFFFF_FFFF");
+
+        tracing::info!(target: "relay", lifetime = ?effective_lifetime.lifetime(), "Refreshed allocation");
 
         self.authenticate_and_send(
+            &auth_secret,
             username.name(),
             request,
             refresh_success_response(effective_lifetime, request.transaction_id()),
@@ -661,7 +932,7 @@ where
         sender: ClientSocket,
         now: Instant,
     ) -> Result<(), Message<Attribute>> {
-        let username = self.verify_auth(request)?;
+        let (username, auth_secret) = self.verify_auth(request)?;
 
         let Some(allocation) = self.allocations.get_mut(&sender) else {
             let (error_response, msg) = make_error_response(AllocationMismatch, request);
@@ -728,6 +999,7 @@ where
             tracing::info!(target: "relay", "Refreshed channel binding");
 
             self.authenticate_and_send(
+                &auth_secret,
                 username.name(),
                 request,
                 channel_bind_success_response(request.transaction_id()),
@@ -745,6 +1017,7 @@ where
         let port = allocation.port;
         self.create_channel_binding(sender, requested_channel, peer_address, port, now);
         self.authenticate_and_send(
+            &auth_secret,
             username.name(),
             request,
             channel_bind_success_response(request.transaction_id()),
@@ -760,17 +1033,54 @@ where
     ///
     /// See <https://www.rfc-editor.org/rfc/rfc8656#name-receiving-a-createpermissio> for details.
     ///
-    /// This TURN server implementation does not support relaying data other than through channels.
-    /// Thus, creating a permission is a no-op that always succeeds.
-    #[tracing::instrument(level = "info", skip_all, fields(software = request.software().map(|s| field::display(s.description())), tid = %format_args!("{:X}", request.transaction_id().as_bytes().hex()), %sender))]
+    /// Permissions are only checked for send and data indications.
+    /// Channel data is relayed based on the channel binding alone.
+    #[tracing::instrument(level = "info", skip_all, fields(allocation, software = request.software().map(|s| field::display(s.description())), tid = %format_args!("{:X}", request.transaction_id().as_bytes().hex()), %sender))]
     fn handle_create_permission_request(
         &mut self,
         request: &CreatePermission,
         sender: ClientSocket,
+        now: Instant,
     ) -> Result<(), Message<Attribute>> {
-        let username = self.verify_auth(request)?;
+        let (username, auth_secret) = self.verify_auth(request)?;
+
+        let Some(allocation) = self.allocations.get(&sender) else {
+            let (error_response, msg) = make_error_response(AllocationMismatch, request);
+
+            tracing::info!(target: "relay", "{msg}: Sender doesn't have an allocation");
+
+            return Err(error_response);
+        };
+
+        Span::current().record("allocation", display(&allocation.port));
+
+        let peers = request
+            .xor_peer_addresses()
+            .iter()
+            .map(|a| PeerSocket(a.address()))
+            .collect::<Vec<_>>();
+
+        // Either all or none of the permissions are installed.
+        if let Some(peer) = peers.iter().find(|p| !allocation.can_relay_to(**p)) {
+            let (error_response, msg) = make_error_response(PeerAddressFamilyMismatch, request);
+
+            tracing::warn!(target: "relay", %peer, "{msg}: Allocation cannot relay to peer");
+
+            return Err(error_response);
+        }
+
+        let port = allocation.port;
+
+        for peer in peers {
+            // Creating an existing permission refreshes it.
+            self.permissions
+                .insert((port, peer.0.ip()), now + PERMISSION_LIFETIME);
+
+            tracing::info!(target: "relay", %peer, "Installed permission");
+        }
 
         self.authenticate_and_send(
+            &auth_secret,
             username.name(),
             request,
             create_permission_success_response(request.transaction_id()),
@@ -780,11 +1090,116 @@ where
         Ok(())
     }
 
+    /// Handle a TURN send indication.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc8656#name-receiving-a-send-indication> for details.
+    ///
+    /// Indications are never answered, thus any invalid indication is silently discarded.
+    #[tracing::instrument(level = "debug", skip_all, fields(allocation, recipient, %sender))]
+    fn handle_send_indication(
+        &mut self,
+        indication: &SendIndication,
+        sender: ClientSocket,
+        now: Instant,
+    ) {
+        let Some(allocation) = self.allocations.get(&sender) else {
+            tracing::debug!(target: "relay", "Sender doesn't have an allocation, discarding send indication");
+            return;
+        };
+
+        let port = allocation.port;
+        let peer = PeerSocket(indication.xor_peer_address().address());
+        let data = indication.data();
+
+        Span::current().record("allocation", field::display(&port));
+        Span::current().record("recipient", field::display(&peer));
+
+        if !self.permissions.contains_key(&(port, peer.0.ip())) {
+            tracing::debug!(target: "relay", "No permission for peer, discarding send indication");
+            return;
+        }
+
+        if !self.consume_quota(sender, data.len(), now) {
+            return;
+        }
+
+        tracing::trace!(target: "wire", num_bytes = %data.len());
+
+        self.data_relayed_counter.add(data.len() as u64, &[]);
+        self.data_relayed += data.len() as u64;
+
+        self.pending_commands.push_back(Command::ForwardToPeer {
+            payload: data.to_vec(),
+            peer,
+            allocation_port: port,
+        });
+    }
+
+    /// Relays data from a peer without a channel in a data indication, if the client has a permission for the peer.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc8656#name-receiving-an-ip-packet-on-a> for details.
+    fn send_data_indication(
+        &mut self,
+        msg: &[u8],
+        sender: PeerSocket,
+        allocation: AllocationPort,
+        now: Instant,
+    ) {
+        if !self.permissions.contains_key(&(allocation, sender.0.ip())) {
+            tracing::debug!(target: "relay", "no channel or permission");
+
+            return;
+        }
+
+        let Some(client) = self.clients_by_allocation.get(&allocation).copied() else {
+            debug_assert!(
+                false,
+                "permissions are deleted together with their allocation"
+            );
+            return;
+        };
+
+        Span::current().record("recipient", field::display(&client));
+
+        if !self.consume_quota(client, msg.len(), now) {
+            return;
+        }
+
+        let data = match Data::new(msg.to_vec()) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::debug!(target: "relay", "Cannot relay data in a data indication: {e}");
+                return;
+            }
+        };
+
+        let mut message = Message::new(
+            MessageClass::Indication,
+            DATA,
+            TransactionId::new(self.rng.r#gen()),
+        );
+        message.add_attribute(SOFTWARE.clone());
+        message.add_attribute(XorPeerAddress::new(sender.0));
+        message.add_attribute(data);
+
+        self.data_relayed_counter.add(msg.len() as u64, &[]);
+        self.data_relayed += msg.len() as u64;
+
+        tracing::trace!(target: "wire", num_bytes = %msg.len());
+
+        // Indications are not authenticated, see <https://www.rfc-editor.org/rfc/rfc8656#name-send-and-data-methods>.
+        self.send_message(
+            AuthenticatedMessage::new_dangerous_unauthenticated(message),
+            client,
+        );
+    }
+
     #[tracing::instrument(level = "debug", skip_all, fields(allocation, recipient, channel, %sender))] // It is important that this is level `debug` otherwise performance is shit!
     fn handle_channel_data_message(
         &mut self,
         message: &ChannelData,
         sender: ClientSocket,
+        now: Instant,
     ) -> Option<(AllocationPort, PeerSocket)> {
         let channel_number = message.channel();
         let data = message.data();
@@ -805,22 +1220,70 @@ where
             return None;
         }
 
-        Span::current().record("allocation", field::display(&channel.allocation));
-        Span::current().record("recipient", field::display(&channel.peer_address));
+        let allocation = channel.allocation;
+        let peer = channel.peer_address;
+
+        Span::current().record("allocation", field::display(&allocation));
+        Span::current().record("recipient", field::display(&peer));
         Span::current().record("channel", field::display(&channel_number.value()));
 
+        if !self.consume_quota(sender, data.len(), now) {
+            return None;
+        }
+
         tracing::trace!(target: "wire", num_bytes = %data.len());
 
         self.data_relayed_counter.add(data.len() as u64, &[]);
         self.data_relayed += data.len() as u64;
 
-        Some((channel.allocation, channel.peer_address))
+        Some((allocation, peer))
+    }
+
+    /// Checks the quotas of the client's allocation and username, accounting for the data if it may be relayed.
+    ///
+    /// Returns `false` if the data must be dropped.
+    fn consume_quota(&mut self, client: ClientSocket, num_bytes: usize, now: Instant) -> bool {
+        if self.allocation_quota.is_unlimited() && self.username_quota.is_unlimited() {
+            return true;
+        }
+
+        let Some(allocation) = self.allocations.get_mut(&client) else {
+            return true;
+        };
+        let mut username = self.usage_by_username.get_mut(&allocation.username);
+
+        let num_bytes = num_bytes as u64;
+
+        if let Err(e) = allocation
+            .usage
+            .check(&self.allocation_quota, num_bytes, now)
+        {
+            tracing::debug!(target: "relay", %num_bytes, "Dropping data: Allocation {e}");
+            return false;
+        }
+
+        if let Some(Err(e)) = username
+            .as_mut()
+            .map(|u| u.usage.check(&self.username_quota, num_bytes, now))
+        {
+            tracing::debug!(target: "relay", %num_bytes, username = %allocation.username, "Dropping data: Username {e}");
+            return false;
+        }
+
+        allocation.usage.consume(num_bytes);
+
+        if let Some(username) = username {
+            username.usage.consume(num_bytes);
+        }
+
+        true
     }
 
+    /// Verifies the credentials of the request, returning the username and the secret they were verified with.
     fn verify_auth(
         &mut self,
         request: &(impl StunRequest + ProtectedRequest),
-    ) -> Result<Username, Message<Attribute>> {
+    ) -> Result<(Username, SecretString), Message<Attribute>> {
         let message_integrity = request.message_integrity().ok_or_else(|| {
             let (error_response, msg) = make_error_response(Unauthorized, request);
             tracing::warn!(target: "relay", "{msg}: Missing `MessageIntegrity` attribute");
@@ -857,8 +1320,22 @@ where
             error_response
         })?;
 
-        message_integrity
-            .verify(&self.auth_secret, username.name(), SystemTime::now()) // This is impure but we don't need to control this in our tests.
+        let now = SystemTime::now(); // This is impure but we don't need to control this in our tests.
+
+        let mut error = None;
+        let secret = self
+            .auth_secrets()
+            .find(
+                |secret| match message_integrity.verify(secret, username.name(), now) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        error.get_or_insert(e); // Report the error of the current secret.
+                        false
+                    }
+                },
+            )
+            .cloned()
+            .ok_or_else(|| error.expect("there is always at least one auth secret"))
             .map_err(|e| {
                 let (error_response, msg) = make_error_response(Unauthorized, request);
 
@@ -874,13 +1351,14 @@ where
                 error_response
             })?;
 
-        Ok(username.clone())
+        Ok((username.clone(), secret))
     }
 
     fn create_new_allocation(
         &mut self,
         now: Instant,
         lifetime: &Lifetime,
+        username: &str,
         first_relay_addr: IpAddr,
         second_relay_addr: Option<IpAddr>,
     ) -> Allocation {
@@ -902,6 +1380,8 @@ where
             expires_at: now + lifetime.lifetime(),
             first_relay_addr,
             second_relay_addr,
+            username: username.to_owned(),
+            usage: Usage::new(&self.allocation_quota, now),
         }
     }
 
@@ -951,18 +1431,40 @@ where
         debug_assert!(existing.is_none());
     }
 
+    /// All secrets we accept credentials for, starting with the current one.
+    fn auth_secrets(&self) -> impl Iterator<Item = &SecretString> {
+        iter::once(&self.auth_secret).chain(&self.previous_auth_secrets)
+    }
+
+    /// The secret that the credentials of `message` can be verified with.
+    ///
+    /// Falls back to the current secret if the message fails verification, e.g. because the credentials have expired.
+    fn auth_secret_for(&self, message: &ClientMessage<'_>) -> &SecretString {
+        let now = SystemTime::now(); // This is impure but we don't need to control this in our tests.
+
+        message
+            .username()
+            .zip(message.message_integrity())
+            .and_then(|(username, message_integrity)| {
+                self.auth_secrets().find(|secret| {
+                    message_integrity
+                        .verify(secret, username.name(), now)
+                        .is_ok()
+                })
+            })
+            .unwrap_or(&self.auth_secret)
+    }
+
     fn authenticate_and_send(
         &mut self,
+        auth_secret: &SecretString,
         username: &str,
-        request: &impl StunRequest,
+        request: &(impl StunRequest + ProtectedRequest),
         message: Message<Attribute>,
         recipient: ClientSocket,
     ) {
-        let authenticated_message = match AuthenticatedMessage::new(
-            &self.auth_secret,
-            username,
-            message,
-        ) {
+        let authenticated_message = match AuthenticatedMessage::new(auth_secret, username, message)
+        {
             Ok(message) => message,
             Err(e) => {
                 let (error_response, msg) = make_error_response(ServerError, request);
@@ -1036,6 +1538,13 @@ where
 
         let port = allocation.port;
 
+        if let Some(usage) = self.usage_by_username.get_mut(&allocation.username) {
+            usage.num_allocations = usage.num_allocations.saturating_sub(1);
+        }
+
+        self.permissions
+            .retain(|(allocation, _), _| *allocation != port);
+
         self.channels_by_client_and_number
             .retain(|(cs, number), c| {
                 if c.allocation != port {
@@ -1153,6 +1662,21 @@ struct Allocation {
 
     first_relay_addr: IpAddr,
     second_relay_addr: Option<IpAddr>,
+
+    /// The username that created this allocation.
+    username: String,
+    usage: Usage,
+}
+
+#[derive(Debug)]
+struct UsernameUsage {
+    usage: Usage,
+    /// When the credentials of this username expire.
+    ///
+    /// Once expired, the username cannot create new allocations and we can forget its usage as soon as all of its allocations are gone.
+    expires_at: SystemTime,
+    /// How many allocations this username currently has.
+    num_allocations: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -1358,6 +1882,7 @@ stun_codec::define_attribute_enums!(
         Lifetime,
         ChannelNumber,
         XorPeerAddress,
+        Data,
         Nonce,
         Realm,
         Username,
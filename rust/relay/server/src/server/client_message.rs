@@ -7,13 +7,14 @@ use bytecodec::DecodeExt;
 use secrecy::SecretString;
 use std::io;
 use std::time::Duration;
+use stun_codec::convert::TryAsRef;
 use stun_codec::rfc5389::attributes::{ErrorCode, MessageIntegrity, Nonce, Software, Username};
 use stun_codec::rfc5389::errors::BadRequest;
 use stun_codec::rfc5389::methods::BINDING;
 use stun_codec::rfc5766::attributes::{
-    ChannelNumber, Lifetime, RequestedTransport, XorPeerAddress,
+    ChannelNumber, Data, Lifetime, RequestedTransport, XorPeerAddress,
 };
-use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, REFRESH};
+use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, REFRESH, SEND};
 use stun_codec::rfc8656::attributes::{
     AdditionalAddressFamily, AddressFamily, RequestedAddressFamily,
 };
@@ -57,10 +58,18 @@ pub fn decode(input: &[u8]) -> Result<Result<ClientMessage, Message<Attribute>>,
                 (CHANNEL_BIND, Request) => {
                     Ok(ChannelBind::parse(&message).map(ClientMessage::ChannelBind))
                 }
-                (CREATE_PERMISSION, Request) => Ok(Ok(ClientMessage::CreatePermission(
-                    CreatePermission::parse(&message),
-                ))),
+                (CREATE_PERMISSION, Request) => {
+                    Ok(CreatePermission::parse(&message).map(ClientMessage::CreatePermission))
+                }
                 (_, Request) => Ok(Err(bad_request(&message))),
+                // Indications are never answered, not even with an error, so we can only drop malformed ones.
+                (SEND, Indication) => match SendIndication::parse(&message) {
+                    Some(indication) => Ok(Ok(ClientMessage::SendIndication(indication))),
+                    None => Err(Error::DecodeStun(bytecodec::Error::from(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "send indication is missing `XOR-PEER-ADDRESS` or `DATA` attribute",
+                    )))),
+                },
                 (method, class) => Err(Error::DecodeStun(bytecodec::Error::from(io::Error::new(
                     io::ErrorKind::Unsupported,
                     format!(
@@ -84,6 +93,7 @@ pub enum ClientMessage<'a> {
     Refresh(Refresh),
     ChannelBind(ChannelBind),
     CreatePermission(CreatePermission),
+    SendIndication(SendIndication),
 }
 
 impl ClientMessage<'_> {
@@ -94,19 +104,34 @@ impl ClientMessage<'_> {
             ClientMessage::Refresh(request) => Some(request.transaction_id),
             ClientMessage::ChannelBind(request) => Some(request.transaction_id),
             ClientMessage::CreatePermission(request) => Some(request.transaction_id),
+            ClientMessage::SendIndication(indication) => Some(indication.transaction_id),
             ClientMessage::ChannelData(_) => None,
         }
     }
 
     pub fn username(&self) -> Option<&Username> {
         match self {
-            ClientMessage::ChannelData(_) | ClientMessage::Binding(_) => None,
+            ClientMessage::ChannelData(_)
+            | ClientMessage::Binding(_)
+            | ClientMessage::SendIndication(_) => None,
             ClientMessage::Allocate(request) => request.username(),
             ClientMessage::Refresh(request) => request.username(),
             ClientMessage::ChannelBind(request) => request.username(),
             ClientMessage::CreatePermission(request) => request.username(),
         }
     }
+
+    pub fn message_integrity(&self) -> Option<&MessageIntegrity> {
+        match self {
+            ClientMessage::ChannelData(_)
+            | ClientMessage::Binding(_)
+            | ClientMessage::SendIndication(_) => None,
+            ClientMessage::Allocate(request) => request.message_integrity(),
+            ClientMessage::Refresh(request) => request.message_integrity(),
+            ClientMessage::ChannelBind(request) => request.message_integrity(),
+            ClientMessage::CreatePermission(request) => request.message_integrity(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -539,26 +564,72 @@ impl ChannelBind {
 pub struct CreatePermission {
     transaction_id: TransactionId,
     message_integrity: Option<MessageIntegrity>,
+    xor_peer_addresses: Vec<XorPeerAddress>,
     username: Option<Username>,
     nonce: Option<Nonce>,
     software: Option<Software>,
 }
 
 impl CreatePermission {
-    pub fn parse(message: &Message<Attribute>) -> Self {
+    pub fn new(
+        transaction_id: TransactionId,
+        xor_peer_address: XorPeerAddress,
+        username: Username,
+        relay_secret: &SecretString,
+        nonce: Uuid,
+    ) -> Result<Self> {
+        let nonce = Nonce::new(nonce.as_hyphenated().to_string()).context("Invalid nonce")?;
+
+        let mut message =
+            Message::<Attribute>::new(MessageClass::Request, CREATE_PERMISSION, transaction_id);
+        message.add_attribute(username.clone());
+        message.add_attribute(xor_peer_address.clone());
+        message.add_attribute(nonce.clone());
+
+        let (expiry, salt) = split_username(username.name())?;
+        let expiry_systemtime = systemtime_from_unix(expiry);
+
+        let password = generate_password(relay_secret, expiry_systemtime, salt);
+
+        let message_integrity =
+            MessageIntegrity::new_long_term_credential(&message, &username, &FIREZONE, &password)?;
+
+        Ok(Self {
+            transaction_id,
+            message_integrity: Some(message_integrity),
+            xor_peer_addresses: vec![xor_peer_address],
+            username: Some(username),
+            nonce: Some(nonce),
+            software: None,
+        })
+    }
+
+    pub fn parse(message: &Message<Attribute>) -> Result<Self, Message<Attribute>> {
         let transaction_id = message.transaction_id();
         let message_integrity = message.get_attribute::<MessageIntegrity>().cloned();
         let username = message.get_attribute::<Username>().cloned();
         let nonce = message.get_attribute::<Nonce>().cloned();
         let software = message.get_attribute::<Software>().cloned();
 
-        CreatePermission {
+        // A single request may install permissions for several peers, see <https://www.rfc-editor.org/rfc/rfc8656#name-the-createpermission-reques>.
+        let xor_peer_addresses = message
+            .attributes()
+            .filter_map(TryAsRef::<XorPeerAddress>::try_as_ref)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if xor_peer_addresses.is_empty() {
+            return Err(bad_request(message));
+        }
+
+        Ok(CreatePermission {
             transaction_id,
             message_integrity,
+            xor_peer_addresses,
             username,
             nonce,
             software,
-        }
+        })
     }
 
     pub fn transaction_id(&self) -> TransactionId {
@@ -569,6 +640,10 @@ impl CreatePermission {
         self.message_integrity.as_ref()
     }
 
+    pub fn xor_peer_addresses(&self) -> &[XorPeerAddress] {
+        &self.xor_peer_addresses
+    }
+
     pub fn username(&self) -> Option<&Username> {
         self.username.as_ref()
     }
@@ -582,6 +657,55 @@ impl CreatePermission {
     }
 }
 
+/// A TURN send indication.
+///
+/// Allows a client to relay data to a peer without binding a channel first.
+/// See <https://www.rfc-editor.org/rfc/rfc8656#name-send-and-data-methods>.
+#[derive(Debug)]
+pub struct SendIndication {
+    transaction_id: TransactionId,
+    xor_peer_address: XorPeerAddress,
+    data: Data,
+}
+
+impl SendIndication {
+    pub fn new(
+        transaction_id: TransactionId,
+        xor_peer_address: XorPeerAddress,
+        data: Data,
+    ) -> Self {
+        Self {
+            transaction_id,
+            xor_peer_address,
+            data,
+        }
+    }
+
+    pub fn parse(message: &Message<Attribute>) -> Option<Self> {
+        let transaction_id = message.transaction_id();
+        let xor_peer_address = message.get_attribute::<XorPeerAddress>()?.clone();
+        let data = message.get_attribute::<Data>()?.clone();
+
+        Some(SendIndication {
+            transaction_id,
+            xor_peer_address,
+            data,
+        })
+    }
+
+    pub fn transaction_id(&self) -> TransactionId {
+        self.transaction_id
+    }
+
+    pub fn xor_peer_address(&self) -> &XorPeerAddress {
+        &self.xor_peer_address
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.data.data()
+    }
+}
+
 /// Computes the effective lifetime of an allocation.
 fn compute_effective_lifetime(requested_lifetime: Option<&Lifetime>) -> Lifetime {
     let Some(requested) = requested_lifetime else {
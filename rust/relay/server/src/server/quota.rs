@@ -0,0 +1,192 @@
+use std::{fmt, time::Instant};
+
+/// Limits how much data the [`Server`](crate::Server) relays for a single allocation or username.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// How many bytes may be relayed per second on average.
+    ///
+    /// Bursts of up to one second worth of data are allowed.
+    pub bytes_per_second: Option<u64>,
+    /// How many bytes may be relayed in total.
+    pub total_bytes: Option<u64>,
+}
+
+impl Quota {
+    pub fn is_unlimited(&self) -> bool {
+        self.bytes_per_second.is_none() && self.total_bytes.is_none()
+    }
+}
+
+/// Why data could not be relayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exceeded {
+    Rate,
+    Total,
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exceeded::Rate => write!(f, "rate limit exceeded"),
+            Exceeded::Total => write!(f, "total volume exceeded"),
+        }
+    }
+}
+
+/// Tracks how much data has been relayed against a [`Quota`].
+///
+/// The rate limit is implemented as a token bucket that holds at most one second worth of data.
+#[derive(Debug, Clone)]
+pub struct Usage {
+    total_bytes: u64,
+    /// How many bytes may currently be relayed without exceeding the rate limit.
+    tokens: u64,
+    last_refill: Instant,
+}
+
+impl Usage {
+    pub fn new(quota: &Quota, now: Instant) -> Self {
+        Self {
+            total_bytes: 0,
+            tokens: quota.bytes_per_second.unwrap_or_default(),
+            last_refill: now,
+        }
+    }
+
+    /// Checks whether `bytes` may be relayed right now.
+    pub fn check(&mut self, quota: &Quota, bytes: u64, now: Instant) -> Result<(), Exceeded> {
+        if quota
+            .total_bytes
+            .is_some_and(|max| self.total_bytes.saturating_add(bytes) > max)
+        {
+            return Err(Exceeded::Total);
+        }
+
+        self.refill(quota, now);
+
+        if quota.bytes_per_second.is_some() && bytes > self.tokens {
+            return Err(Exceeded::Rate);
+        }
+
+        Ok(())
+    }
+
+    /// Accounts for `bytes` that have been relayed.
+    ///
+    /// This never fails because data relayed outside of the [`Server`](crate::Server) has already been sent.
+    pub fn consume(&mut self, bytes: u64) {
+        self.total_bytes = self.total_bytes.saturating_add(bytes);
+        self.tokens = self.tokens.saturating_sub(bytes);
+    }
+
+    /// How many bytes may be relayed right now.
+    pub fn budget(&mut self, quota: &Quota, now: Instant) -> u64 {
+        self.refill(quota, now);
+
+        let rate = quota.bytes_per_second.map_or(u64::MAX, |_| self.tokens);
+        let total = quota
+            .total_bytes
+            .map_or(u64::MAX, |max| max.saturating_sub(self.total_bytes));
+
+        rate.min(total)
+    }
+
+    /// Whether the total volume of the [`Quota`] has been used up.
+    pub fn has_exhausted(&self, quota: &Quota) -> bool {
+        quota.total_bytes.is_some_and(|max| self.total_bytes >= max)
+    }
+
+    fn refill(&mut self, quota: &Quota, now: Instant) {
+        let Some(rate) = quota.bytes_per_second else {
+            return;
+        };
+
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refill = (rate as u128 * elapsed.as_nanos() / 1_000_000_000) as u64;
+
+        if refill == 0 {
+            return; // Don't update `last_refill`, otherwise frequent small packets would never accumulate any tokens.
+        }
+
+        self.tokens = self.tokens.saturating_add(refill).min(rate);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn unlimited_quota_admits_everything() {
+        let now = Instant::now();
+        let quota = Quota::default();
+        let mut usage = Usage::new(&quota, now);
+
+        usage.consume(u64::MAX);
+
+        assert_eq!(usage.check(&quota, 1_000_000, now), Ok(()));
+        assert_eq!(usage.budget(&quota, now), u64::MAX);
+    }
+
+    #[test]
+    fn rate_limit_refills_over_time() {
+        let now = Instant::now();
+        let quota = Quota {
+            bytes_per_second: Some(1000),
+            total_bytes: None,
+        };
+        let mut usage = Usage::new(&quota, now);
+
+        assert_eq!(usage.check(&quota, 1000, now), Ok(()));
+        usage.consume(1000);
+        assert_eq!(usage.check(&quota, 1, now), Err(Exceeded::Rate));
+
+        let now = now + Duration::from_millis(500);
+
+        assert_eq!(usage.budget(&quota, now), 500);
+        assert_eq!(usage.check(&quota, 500, now), Ok(()));
+        assert_eq!(usage.check(&quota, 501, now), Err(Exceeded::Rate));
+    }
+
+    #[test]
+    fn bursts_are_capped_at_one_second() {
+        let now = Instant::now();
+        let quota = Quota {
+            bytes_per_second: Some(1000),
+            total_bytes: None,
+        };
+        let mut usage = Usage::new(&quota, now);
+
+        let now = now + Duration::from_secs(60);
+
+        assert_eq!(usage.budget(&quota, now), 1000);
+        assert_eq!(usage.check(&quota, 1001, now), Err(Exceeded::Rate));
+    }
+
+    #[test]
+    fn total_volume_is_never_refilled() {
+        let now = Instant::now();
+        let quota = Quota {
+            bytes_per_second: None,
+            total_bytes: Some(1000),
+        };
+        let mut usage = Usage::new(&quota, now);
+
+        usage.consume(900);
+
+        assert_eq!(usage.budget(&quota, now), 100);
+        assert_eq!(usage.check(&quota, 101, now), Err(Exceeded::Total));
+        assert!(!usage.has_exhausted(&quota));
+
+        usage.consume(100);
+
+        let now = now + Duration::from_secs(60 * 60);
+
+        assert_eq!(usage.budget(&quota, now), 0);
+        assert_eq!(usage.check(&quota, 1, now), Err(Exceeded::Total));
+        assert!(usage.has_exhausted(&quota));
+    }
+}
@@ -0,0 +1,515 @@
+//! TURN over stream-based transports.
+//!
+//! Clients that cannot reach us via UDP (e.g. because a firewall drops all UDP traffic) may connect via TCP or TLS instead.
+//! The messages exchanged on such a connection are the same as over UDP: STUN messages and channel-data messages.
+//! Because a stream doesn't preserve message boundaries, we need to frame them ourselves:
+//!
+//! - STUN messages carry their own length in the header and are always a multiple of 4 bytes long.
+//! - Channel-data messages carry the length of their payload and must be padded to a multiple of 4 bytes (see <https://www.rfc-editor.org/rfc/rfc8656#section-12.5>).
+//!
+//! The allocations themselves are always UDP, i.e. peers are unaware of how the client is connected to us.
+
+use anyhow::{Context as _, Result, bail};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use stun_codec::rfc8656::attributes::AddressFamily;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
+    net::TcpListener,
+    sync::{OwnedSemaphorePermit, Semaphore, mpsc, oneshot},
+};
+use tokio_rustls::TlsAcceptor;
+
+/// The length of a STUN message header.
+const STUN_HEADER_LEN: usize = 20;
+
+/// The length of a channel-data message header.
+const CHANNEL_DATA_HEADER_LEN: usize = 4;
+
+/// How many frames we buffer for a single connection before we start dropping them.
+const MAX_PENDING_FRAMES: usize = 1024;
+
+/// How many stream connections we serve at most, across all listeners.
+///
+/// Each connection costs us a task and buffers, so we refuse new ones beyond this.
+const MAX_CONNECTIONS: usize = 10_000;
+
+/// How long a client has to complete the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a client has to make an allocation after connecting.
+const UNAUTHENTICATED_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// After how long without receiving a message we close a connection.
+///
+/// Clients send a BINDING request every 25s to keep their NAT bindings alive so this only affects dead connections.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A dynamic collection of client connections made via stream-based transports (TCP and TLS).
+///
+/// Internally, each connection is served by its own task.
+/// All connections share a single channel to deliver the frames they read to the foreground task.
+///
+/// Connections are closed if the client doesn't make an allocation within [`UNAUTHENTICATED_TIMEOUT`] (see [`Streams::authenticate`]) or is idle for [`IDLE_TIMEOUT`].
+pub struct Streams {
+    /// The connected clients, indexed by their remote address.
+    connections: HashMap<SocketAddr, Connection>,
+    /// Limits the number of concurrent connections to [`MAX_CONNECTIONS`].
+    connection_permits: Arc<Semaphore>,
+
+    event_tx: mpsc::Sender<Event>,
+    event_rx: mpsc::Receiver<Event>,
+}
+
+struct Connection {
+    outbound: mpsc::Sender<Vec<u8>>,
+    /// Present until the client is authenticated.
+    authenticated: Option<oneshot::Sender<()>>,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Streams {
+    pub fn new() -> Self {
+        let (event_tx, event_rx) = mpsc::channel(1_024);
+
+        Self {
+            connections: Default::default(),
+            connection_permits: Arc::new(Semaphore::new(MAX_CONNECTIONS)),
+            event_tx,
+            event_rx,
+        }
+    }
+
+    /// Listens for TURN over TCP connections on the given port and address family.
+    ///
+    /// Must be called within a Tokio runtime context.
+    pub fn listen_tcp(&mut self, port: u16, address_family: AddressFamily) -> Result<()> {
+        let listener = make_wildcard_listener(address_family, port)?;
+
+        tokio::spawn(accept_connections(
+            listener,
+            None,
+            self.connection_permits.clone(),
+            self.event_tx.clone(),
+        ));
+
+        Ok(())
+    }
+
+    /// Listens for TURN over TLS connections on the given port and address family.
+    ///
+    /// Must be called within a Tokio runtime context.
+    pub fn listen_tls(
+        &mut self,
+        port: u16,
+        address_family: AddressFamily,
+        acceptor: TlsAcceptor,
+    ) -> Result<()> {
+        let listener = make_wildcard_listener(address_family, port)?;
+
+        tokio::spawn(accept_connections(
+            listener,
+            Some(acceptor),
+            self.connection_permits.clone(),
+            self.event_tx.clone(),
+        ));
+
+        Ok(())
+    }
+
+    /// Marks the given client as authenticated, i.e. it made an allocation.
+    ///
+    /// Unauthenticated connections are closed after [`UNAUTHENTICATED_TIMEOUT`].
+    pub fn authenticate(&mut self, client: SocketAddr) {
+        let Some(authenticated) = self
+            .connections
+            .get_mut(&client)
+            .and_then(|c| c.authenticated.take())
+        else {
+            return;
+        };
+
+        let _ = authenticated.send(());
+    }
+
+    /// Queues a message to be sent to the given client.
+    ///
+    /// The message must be a STUN message or a channel-data message and will be padded as necessary.
+    /// In case the connection is congested, the message is dropped.
+    pub fn try_send(&mut self, dst: SocketAddr, msg: &[u8]) -> io::Result<()> {
+        let connection = self
+            .connections
+            .get(&dst)
+            .ok_or_else(|| not_connected(dst))?;
+
+        match connection.outbound.try_send(msg.to_vec()) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::debug!(target: "relay", %dst, "Dropping message because stream is congested");
+
+                Ok(())
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(not_connected(dst)),
+        }
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Received> {
+        loop {
+            match self.event_rx.poll_recv(cx) {
+                Poll::Ready(Some(Event::Connected {
+                    remote,
+                    outbound,
+                    authenticated,
+                })) => {
+                    tracing::debug!(target: "relay", %remote, "New stream connection");
+
+                    self.connections.insert(
+                        remote,
+                        Connection {
+                            outbound,
+                            authenticated: Some(authenticated),
+                        },
+                    );
+                    continue;
+                }
+                Poll::Ready(Some(Event::Frame { from, packet })) => {
+                    return Poll::Ready(Received::Frame { from, packet });
+                }
+                Poll::Ready(Some(Event::Disconnected(remote))) => {
+                    tracing::debug!(target: "relay", %remote, "Stream connection closed");
+
+                    self.connections.remove(&remote);
+
+                    return Poll::Ready(Received::Disconnected(remote));
+                }
+                Poll::Ready(None) => {
+                    unreachable!("we hold a sender ourselves so the channel is never closed")
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Something that happened on one of our stream connections.
+#[derive(Debug)]
+pub enum Received {
+    /// A client sent us a STUN or channel-data message.
+    Frame { from: SocketAddr, packet: Vec<u8> },
+    /// A client closed its connection.
+    Disconnected(SocketAddr),
+}
+
+enum Event {
+    Connected {
+        remote: SocketAddr,
+        outbound: mpsc::Sender<Vec<u8>>,
+        authenticated: oneshot::Sender<()>,
+    },
+    Frame {
+        from: SocketAddr,
+        packet: Vec<u8>,
+    },
+    Disconnected(SocketAddr),
+}
+
+/// Computes the length of the message starting with the given header, excluding any padding.
+///
+/// Returns `None` if the header is neither a STUN nor a channel-data message.
+pub fn message_len(header: [u8; 4]) -> Option<usize> {
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+
+    // The first two bits tell STUN messages (0b00) and channel-data messages (0b01) apart.
+    match header[0] >> 6 {
+        0b00 => Some(STUN_HEADER_LEN + length),
+        0b01 => Some(CHANNEL_DATA_HEADER_LEN + length),
+        _ => None,
+    }
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    connection_permits: Arc<Semaphore>,
+    event_tx: mpsc::Sender<Event>,
+) {
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(ok) => ok,
+            Err(e) => {
+                tracing::debug!(target: "relay", "Failed to accept stream connection: {e}");
+                continue;
+            }
+        };
+
+        let Ok(permit) = connection_permits.clone().try_acquire_owned() else {
+            tracing::debug!(target: "relay", %remote, "Refusing stream connection because we are at capacity");
+            continue; // Dropping the stream closes the connection.
+        };
+
+        if let Err(e) = stream.set_nodelay(true) {
+            tracing::debug!(target: "relay", %remote, "Failed to set TCP_NODELAY: {e}");
+        }
+
+        let event_tx = event_tx.clone();
+
+        match tls.clone() {
+            Some(acceptor) => {
+                tokio::spawn(async move {
+                    let stream = match tokio::time::timeout(
+                        TLS_HANDSHAKE_TIMEOUT,
+                        acceptor.accept(stream),
+                    )
+                    .await
+                    {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            tracing::debug!(target: "relay", %remote, "TLS handshake failed: {e}");
+                            return;
+                        }
+                        Err(_) => {
+                            tracing::debug!(target: "relay", %remote, "TLS handshake timed out");
+                            return;
+                        }
+                    };
+
+                    serve_connection(stream, remote, permit, event_tx).await;
+                });
+            }
+            None => {
+                tokio::spawn(serve_connection(stream, remote, permit, event_tx));
+            }
+        }
+    }
+}
+
+/// Serves a single connection until it is closed or times out.
+///
+/// The `permit` is held for as long as the connection is alive.
+async fn serve_connection<S>(
+    stream: S,
+    remote: SocketAddr,
+    _permit: OwnedSemaphorePermit,
+    event_tx: mpsc::Sender<Event>,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    let (outbound_tx, mut outbound_rx) = mpsc::channel(MAX_PENDING_FRAMES);
+    let (authenticated_tx, authenticated_rx) = oneshot::channel();
+
+    if event_tx
+        .send(Event::Connected {
+            remote,
+            outbound: outbound_tx,
+            authenticated: authenticated_tx,
+        })
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    let (mut reader, mut writer) = tokio::io::split(stream);
+
+    let result = tokio::select! {
+        result = read_frames(&mut reader, remote, &event_tx) => result,
+        result = write_frames(&mut writer, &mut outbound_rx) => result,
+        result = require_authentication(authenticated_rx) => result,
+    };
+
+    if let Err(e) = result {
+        tracing::debug!(target: "relay", %remote, "Stream connection failed: {e:#}");
+    }
+
+    let _ = event_tx.send(Event::Disconnected(remote)).await;
+}
+
+async fn read_frames(
+    reader: &mut (impl AsyncRead + Unpin),
+    remote: SocketAddr,
+    event_tx: &mpsc::Sender<Event>,
+) -> Result<()> {
+    loop {
+        let mut header = [0u8; 4];
+
+        match tokio::time::timeout(IDLE_TIMEOUT, reader.read_exact(&mut header)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()), // Client closed the connection.
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => bail!("Connection was idle for {IDLE_TIMEOUT:?}"),
+        }
+
+        let len = message_len(header)
+            .context("Stream is not a sequence of STUN or channel-data messages")?;
+
+        // The length is at most 65k + header, meaning we are okay to allocate here based on what the remote is sending.
+        let mut packet = vec![0u8; len.next_multiple_of(4)];
+        packet[..4].copy_from_slice(&header);
+        reader.read_exact(&mut packet[4..]).await?;
+        packet.truncate(len); // Strip the padding.
+
+        event_tx
+            .send(Event::Frame {
+                from: remote,
+                packet,
+            })
+            .await
+            .context("Event channel closed")?;
+    }
+}
+
+async fn write_frames(
+    writer: &mut (impl AsyncWrite + Unpin),
+    outbound_rx: &mut mpsc::Receiver<Vec<u8>>,
+) -> Result<()> {
+    while let Some(mut packet) = outbound_rx.recv().await {
+        packet.resize(packet.len().next_multiple_of(4), 0); // STUN messages are always aligned, only channel-data messages may need padding.
+
+        writer.write_all(&packet).await?;
+        writer.flush().await?;
+    }
+
+    Ok(())
+}
+
+/// Fails unless the connection is authenticated within [`UNAUTHENTICATED_TIMEOUT`], otherwise never completes.
+async fn require_authentication(authenticated: oneshot::Receiver<()>) -> Result<()> {
+    match tokio::time::timeout(UNAUTHENTICATED_TIMEOUT, authenticated).await {
+        Ok(Ok(())) => std::future::pending().await,
+        Ok(Err(_)) => bail!("Connection was replaced"),
+        Err(_) => bail!("Client did not make an allocation within {UNAUTHENTICATED_TIMEOUT:?}"),
+    }
+}
+
+fn not_connected(dst: SocketAddr) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotConnected,
+        format!("No stream connection to {dst}"),
+    )
+}
+
+/// Creates a [`TcpListener`] via the [socket2] library that is configured for our needs.
+///
+/// Like our UDP sockets, this sets the `IPV6_V6ONLY` flag to ensure we can listen on IP4 and IP6 addresses on the same port.
+fn make_wildcard_listener(family: AddressFamily, port: u16) -> Result<TcpListener> {
+    use socket2::*;
+
+    let domain = match family {
+        AddressFamily::V4 => Domain::IPV4,
+        AddressFamily::V6 => Domain::IPV6,
+    };
+    let address = match family {
+        AddressFamily::V4 => IpAddr::from(Ipv4Addr::UNSPECIFIED),
+        AddressFamily::V6 => IpAddr::from(Ipv6Addr::UNSPECIFIED),
+    };
+
+    let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
+    if family == AddressFamily::V6 {
+        socket.set_only_v6(true)?;
+    }
+
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SockAddr::from(SocketAddr::new(address, port)))?;
+    socket.listen(1024)?;
+
+    let listener = TcpListener::from_std(socket.into())?;
+
+    Ok(listener)
+}
+
+/// Builds a [`TlsAcceptor`] from a PEM-encoded certificate chain and private key.
+pub fn tls_acceptor(cert_chain_pem: &[u8], private_key_pem: &[u8]) -> Result<TlsAcceptor> {
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject as _};
+
+    let cert_chain = CertificateDer::pem_slice_iter(cert_chain_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse certificate chain")?;
+    let private_key =
+        PrivateKeyDer::from_pem_slice(private_key_pem).context("Failed to parse private key")?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, private_key)
+        .context("Invalid certificate or private key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stun_message_len_includes_header() {
+        let binding_request_header = [0x00, 0x01, 0x00, 0x08];
+
+        assert_eq!(message_len(binding_request_header), Some(28));
+    }
+
+    #[test]
+    fn channel_data_len_includes_header() {
+        let channel_data_header = [0x40, 0x00, 0x00, 0x05];
+
+        assert_eq!(message_len(channel_data_header), Some(9));
+    }
+
+    #[test]
+    fn rejects_unknown_message_type() {
+        let header = [0x80, 0x00, 0x00, 0x05];
+
+        assert_eq!(message_len(header), None);
+    }
+
+    #[tokio::test]
+    async fn reads_padded_channel_data_frames() {
+        let (event_tx, mut event_rx) = mpsc::channel(10);
+        let remote = SocketAddr::from((Ipv4Addr::LOCALHOST, 1234));
+
+        let mut stream: &[u8] = &[
+            // Channel-data with 3 bytes of padding.
+            0x40, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5, 0, 0, 0,
+            // Channel-data without padding.
+            0x40, 0x01, 0x00, 0x04, 6, 7, 8, 9,
+        ];
+
+        read_frames(&mut stream, remote, &event_tx).await.unwrap();
+
+        let Some(Event::Frame { packet, .. }) = event_rx.recv().await else {
+            panic!("expected frame")
+        };
+        assert_eq!(packet, [0x40, 0x00, 0x00, 0x05, 1, 2, 3, 4, 5]);
+
+        let Some(Event::Frame { packet, .. }) = event_rx.recv().await else {
+            panic!("expected frame")
+        };
+        assert_eq!(packet, [0x40, 0x01, 0x00, 0x04, 6, 7, 8, 9]);
+    }
+
+    #[tokio::test]
+    async fn pads_channel_data_frames() {
+        let (outbound_tx, mut outbound_rx) = mpsc::channel(10);
+        let mut written = Vec::new();
+
+        outbound_tx
+            .send(vec![0x40, 0x00, 0x00, 0x01, 1])
+            .await
+            .unwrap();
+        drop(outbound_tx);
+
+        write_frames(&mut written, &mut outbound_rx).await.unwrap();
+
+        assert_eq!(written, [0x40, 0x00, 0x00, 0x01, 1, 0, 0, 0]);
+    }
+}
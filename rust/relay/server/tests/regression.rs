@@ -4,10 +4,12 @@ use Output::{CreateAllocation, FreeAllocation};
 use bytecodec::{DecodeExt, EncodeExt};
 use firezone_relay::{
     AddressFamily, Allocate, AllocationPort, Attribute, Binding, ChannelBind, ChannelData,
-    ClientMessage, ClientSocket, Command, IpStack, PeerSocket, Refresh, SOFTWARE, Server,
+    ClientMessage, ClientSocket, Command, CreatePermission, IpStack, PeerSocket, Quota, Refresh,
+    SOFTWARE, SendIndication, Server,
 };
 use rand::rngs::mock::StepRng;
-use secrecy::SecretString;
+use secrecy::{ExposeSecret, SecretString};
+use std::collections::BTreeMap;
 use std::iter;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::time::{Duration, Instant, SystemTime};
@@ -16,8 +18,11 @@ use stun_codec::rfc5389::attributes::{
 };
 use stun_codec::rfc5389::errors::Unauthorized;
 use stun_codec::rfc5389::methods::BINDING;
-use stun_codec::rfc5766::attributes::{ChannelNumber, Lifetime, XorPeerAddress, XorRelayAddress};
-use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, REFRESH};
+use stun_codec::rfc5766::attributes::{
+    ChannelNumber, Data, Lifetime, XorPeerAddress, XorRelayAddress,
+};
+use stun_codec::rfc5766::errors::{AllocationQuotaReached, InsufficientCapacity};
+use stun_codec::rfc5766::methods::{ALLOCATE, CHANNEL_BIND, CREATE_PERMISSION, DATA, REFRESH};
 use stun_codec::{Message, MessageClass, MessageDecoder, MessageEncoder, TransactionId};
 use test_strategy::proptest;
 use uuid::Uuid;
@@ -304,6 +309,88 @@ fn when_receiving_lifetime_0_for_existing_allocation_then_delete(
     server.assert_commands(forward_time_to(first_wake + Duration::from_secs(1)), []);
 }
 
+#[proptest]
+fn when_stream_client_disconnects_then_delete_allocation(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] allocate_lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let first_wake = now + allocate_lifetime.lifetime();
+
+    server.assert_commands(
+        from_stream_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(allocate_lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            Output::SendMessage((
+                ClientSocket::new_stream(source.into()),
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &allocate_lifetime,
+                ),
+            )),
+        ],
+    );
+
+    server.assert_commands(
+        stream_client_disconnected(source),
+        [free_allocation(49152, AddressFamily::V4)],
+    );
+    assert_eq!(server.server.num_allocations(), 0);
+
+    // Assert that forwarding time does not produce an obsolete event.
+    server.assert_commands(forward_time_to(first_wake + Duration::from_secs(1)), []);
+}
+
+#[proptest]
+fn stream_and_udp_clients_with_same_address_are_distinct(
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    server.allocate(source, &username_salt, nonce, now);
+
+    assert!(
+        server
+            .server
+            .has_allocation(ClientSocket::new(source.into()))
+    );
+    assert!(
+        !server
+            .server
+            .has_allocation(ClientSocket::new_stream(source.into()))
+    );
+
+    server
+        .server
+        .handle_client_disconnected(ClientSocket::new_stream(source.into()));
+    assert_eq!(server.server.num_allocations(), 1);
+}
+
 #[proptest]
 fn freeing_allocation_clears_all_channels(
     #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
@@ -474,6 +561,7 @@ fn ping_pong_relay(
         peer_to_client_ping.as_slice(),
         PeerSocket::new(peer.into()),
         AllocationPort::new(49152),
+        now,
     );
 
     assert_eq!(
@@ -710,6 +798,7 @@ fn ping_pong_ip6_relay(
         peer_to_client_ping.as_slice(),
         PeerSocket::new(peer.into()),
         AllocationPort::new(49152),
+        now,
     );
 
     assert_eq!(
@@ -718,97 +807,1105 @@ fn ping_pong_ip6_relay(
     );
 }
 
-struct TestServer {
-    server: Server<StepRng>,
-}
+#[proptest]
+fn relays_send_and_data_indications_with_permission(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    create_permission_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] send_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    client_to_peer_ping: [u8; 32],
+    peer_to_client_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
 
-impl TestServer {
-    fn new(relay_public_addr: impl Into<IpStack>) -> Self {
-        Self {
-            server: Server::new(relay_public_addr, StepRng::new(0, 0), 3478, 49152..=65535),
-        }
-    }
+    let _ = env_logger::try_init();
 
-    fn with_nonce(mut self, nonce: Uuid) -> Self {
-        self.server.add_nonce(nonce);
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap(); // Lifetime longer than permission expiry
 
-        self
-    }
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
 
-    fn auth_secret(&self) -> &SecretString {
-        self.server.auth_secret()
-    }
+    let now = now + Duration::from_secs(1);
 
-    fn assert_commands<const N: usize>(&mut self, input: Input, output: [Output; N]) {
-        match input {
-            Input::Client(sender, message, now) => {
-                self.server.handle_client_message(message, sender, now);
-            }
-            Input::Time(now) => {
-                self.server.handle_timeout(now);
-            }
-        }
+    server.assert_commands(
+        from_client(
+            source,
+            CreatePermission::new(
+                create_permission_transaction_id,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            create_permission_response(create_permission_transaction_id),
+        )],
+    );
 
-        for expected_output in output {
-            let Some(actual_output) = self.server.next_command() else {
-                let msg = match expected_output {
-                    Output::SendMessage((recipient, msg)) => {
-                        format!("to send message {:?} to {recipient}", msg)
-                    }
-                    CreateAllocation(port, family) => {
-                        format!("to create allocation on port {port} for address family {family}")
-                    }
-                    FreeAllocation(port, family) => {
-                        format!("to free allocation on port {port} for address family {family}")
-                    }
-                    Output::CreateChannelBinding(client, channel, peer, port) => {
-                        format!(
-                            "to create a channel binding for channel {channel} from {client} to {peer} on allocation {port}"
-                        )
-                    }
-                    Output::DeleteChannelBinding(client, channel, peer, port) => {
-                        format!(
-                            "to remove a channel binding for channel {channel} from {client} to {peer} on allocation {port}"
-                        )
-                    }
-                };
+    assert_eq!(
+        server.server.poll_timeout(),
+        Some(now + Duration::from_secs(60 * 5))
+    );
 
-                panic!("No commands produced but expected {msg}");
-            };
+    server.assert_commands(
+        from_client(
+            source,
+            SendIndication::new(
+                send_transaction_id,
+                XorPeerAddress::new(peer.into()),
+                Data::new(client_to_peer_ping.to_vec()).unwrap(),
+            ),
+            now,
+        ),
+        [forward_to_peer(peer, 49152, client_to_peer_ping.to_vec())],
+    );
 
-            match (expected_output, actual_output) {
-                (
-                    Output::SendMessage((to, mut message)),
-                    Command::SendMessage { payload, recipient },
-                ) => {
-                    let sent_message = parse_message(&payload);
+    server.assert_commands(
+        from_peer(peer, 49152, &peer_to_client_ping, now),
+        [send_message(
+            source,
+            data_indication(peer, peer_to_client_ping.to_vec()),
+        )],
+    );
+}
 
-                    // In order to avoid simulating authentication, we copy the MessageIntegrity attribute.
-                    if let Some(mi) = sent_message.get_attribute::<MessageIntegrity>() {
-                        message.add_attribute(mi.clone());
-                    }
+#[proptest]
+fn discards_send_and_data_indications_without_permission(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] send_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    client_to_peer_ping: [u8; 32],
+    peer_to_client_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
 
-                    let expected_bytes = MessageEncoder::new()
-                        .encode_into_bytes(message.clone())
-                        .unwrap();
+    let _ = env_logger::try_init();
 
-                    if expected_bytes != payload {
-                        let expected_message = format!("{:?}", message);
-                        let actual_message = format!("{:?}", sent_message);
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap();
 
-                        difference::assert_diff!(&expected_message, &actual_message, "\n", 0);
-                    }
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
 
-                    assert_eq!(recipient, to);
-                }
-                (
-                    CreateAllocation(expected_port, expected_family),
-                    Command::CreateAllocation {
-                        port: actual_port,
-                        family: actual_family,
-                    },
-                ) => {
-                    assert_eq!(expected_port, actual_port);
+    server.assert_commands(
+        from_client(
+            source,
+            SendIndication::new(
+                send_transaction_id,
+                XorPeerAddress::new(peer.into()),
+                Data::new(client_to_peer_ping.to_vec()).unwrap(),
+            ),
+            now,
+        ),
+        [],
+    );
+    server.assert_commands(from_peer(peer, 49152, &peer_to_client_ping, now), []);
+}
+
+#[proptest]
+fn permission_expires_after_five_minutes(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    create_permission_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] send_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    client_to_peer_ping: [u8; 32],
+    peer_to_client_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap(); // Lifetime longer than permission expiry
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            CreatePermission::new(
+                create_permission_transaction_id,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            create_permission_response(create_permission_transaction_id),
+        )],
+    );
+
+    let allocation_expiry = now + lifetime.lifetime();
+    let now = now + Duration::from_secs(60 * 5 + 1);
+
+    server.assert_commands(forward_time_to(now), []);
+    assert_eq!(server.server.poll_timeout(), Some(allocation_expiry));
+
+    server.assert_commands(
+        from_client(
+            source,
+            SendIndication::new(
+                send_transaction_id,
+                XorPeerAddress::new(peer.into()),
+                Data::new(client_to_peer_ping.to_vec()).unwrap(),
+            ),
+            now,
+        ),
+        [],
+    );
+    server.assert_commands(from_peer(peer, 49152, &peer_to_client_ping, now), []);
+}
+
+#[proptest]
+fn drops_send_indications_exceeding_allocation_rate(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    create_permission_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] send_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    client_to_peer_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_quotas(
+            Quota {
+                bytes_per_second: Some(32),
+                total_bytes: None,
+            },
+            Quota::default(),
+        );
+    let secret = server.auth_secret().to_owned();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            CreatePermission::new(
+                create_permission_transaction_id,
+                XorPeerAddress::new(peer.into()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            create_permission_response(create_permission_transaction_id),
+        )],
+    );
+
+    let send_indication = || {
+        SendIndication::new(
+            send_transaction_id,
+            XorPeerAddress::new(peer.into()),
+            Data::new(client_to_peer_ping.to_vec()).unwrap(),
+        )
+    };
+
+    server.assert_commands(
+        from_client(source, send_indication(), now),
+        [forward_to_peer(peer, 49152, client_to_peer_ping.to_vec())],
+    );
+    server.assert_commands(from_client(source, send_indication(), now), []);
+
+    let now = now + Duration::from_secs(1);
+
+    server.assert_commands(
+        from_client(source, send_indication(), now),
+        [forward_to_peer(peer, 49152, client_to_peer_ping.to_vec())],
+    );
+}
+
+#[proptest]
+fn rejects_allocate_once_username_volume_is_exhausted(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    create_permission_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] send_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    second_allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    peer: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    client_to_peer_ping: [u8; 32],
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_quotas(
+            Quota::default(),
+            Quota {
+                bytes_per_second: None,
+                total_bytes: Some(32),
+            },
+        );
+    let secret = server.auth_secret().to_owned();
+    let username = valid_username(&username_salt);
+    let second_source = SocketAddrV4::new(*source.ip(), source.port().wrapping_add(1));
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                username.clone(),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            CreatePermission::new(
+                create_permission_transaction_id,
+                XorPeerAddress::new(peer.into()),
+                username.clone(),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            create_permission_response(create_permission_transaction_id),
+        )],
+    );
+    server.assert_commands(
+        from_client(
+            source,
+            SendIndication::new(
+                send_transaction_id,
+                XorPeerAddress::new(peer.into()),
+                Data::new(client_to_peer_ping.to_vec()).unwrap(),
+            ),
+            now,
+        ),
+        [forward_to_peer(peer, 49152, client_to_peer_ping.to_vec())],
+    );
+
+    server.assert_commands(
+        from_client(
+            second_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                second_allocate_transaction_id,
+                Some(lifetime.clone()),
+                username,
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            second_source,
+            allocation_quota_reached_response(second_allocate_transaction_id),
+        )],
+    );
+}
+
+#[proptest]
+fn offloaded_traffic_is_deducted_from_offload_budget(
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_quotas(
+            Quota {
+                bytes_per_second: None,
+                total_bytes: Some(1000),
+            },
+            Quota {
+                bytes_per_second: None,
+                total_bytes: Some(500),
+            },
+        );
+    server.allocate(source, &username_salt, nonce, now);
+    server.bind_channel(
+        source,
+        ChannelNumber::new(0x4000).unwrap(),
+        SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1000),
+        &username_salt,
+        nonce,
+        now,
+    );
+
+    let allocation = AllocationPort::new(49152);
+
+    assert_eq!(
+        server.server.channel_offload_budgets(now),
+        BTreeMap::from([(allocation, 500)])
+    );
+
+    server.server.handle_offloaded_traffic(allocation, 400);
+
+    assert_eq!(
+        server.server.channel_offload_budgets(now),
+        BTreeMap::from([(allocation, 100)])
+    );
+}
+
+#[proptest]
+fn offload_budget_is_split_across_channels_of_an_allocation(
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_quotas(
+            Quota {
+                bytes_per_second: None,
+                total_bytes: Some(1000),
+            },
+            Quota::default(),
+        );
+    server.allocate(source, &username_salt, nonce, now);
+    server.bind_channel(
+        source,
+        ChannelNumber::new(0x4000).unwrap(),
+        SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1000),
+        &username_salt,
+        nonce,
+        now,
+    );
+    server.bind_channel(
+        source,
+        ChannelNumber::new(0x4001).unwrap(),
+        SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 1000),
+        &username_salt,
+        nonce,
+        now,
+    );
+
+    let allocation = AllocationPort::new(49152);
+
+    assert_eq!(server.server.num_active_channels(), 2);
+    assert_eq!(
+        server.server.channel_offload_budgets(now),
+        BTreeMap::from([(allocation, 500)])
+    );
+
+    server.server.handle_offloaded_traffic(allocation, 600);
+
+    assert_eq!(
+        server.server.channel_offload_budgets(now),
+        BTreeMap::from([(allocation, 200)])
+    );
+}
+
+#[proptest]
+fn draining_rejects_new_allocations_but_keeps_existing_ones(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    second_allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let second_source = SocketAddrV4::new(*source.ip(), source.port().wrapping_add(1));
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.server.drain(None);
+
+    server.assert_commands(
+        from_client(
+            second_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                second_allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            second_source,
+            insufficient_capacity_response(second_allocate_transaction_id),
+        )],
+    );
+
+    assert!(server.server.is_draining());
+    assert_eq!(server.server.num_allocations(), 1);
+    assert_eq!(
+        server.server.poll_timeout(),
+        Some(now + lifetime.lifetime())
+    );
+}
+
+#[proptest]
+fn drain_deadline_frees_remaining_allocations(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(60 * 60)).unwrap(); // Lifetime longer than drain deadline
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    let deadline = now + Duration::from_secs(60);
+
+    server.server.drain(Some(deadline));
+
+    assert_eq!(server.server.poll_timeout(), Some(deadline));
+
+    server.assert_commands(
+        forward_time_to(deadline),
+        [free_allocation(49152, AddressFamily::V4)],
+    );
+
+    assert_eq!(server.server.num_allocations(), 0);
+    assert_eq!(server.server.poll_timeout(), None);
+}
+
+#[proptest]
+fn draining_does_not_extend_allocations_on_refresh(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())] refresh_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let mut server = TestServer::new(public_relay_addr).with_nonce(nonce);
+    let secret = server.auth_secret().to_owned();
+    let lifetime = Lifetime::new(Duration::from_secs(10 * 60)).unwrap();
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    server.server.drain(None);
+
+    let expires_at = now + lifetime.lifetime();
+    let now = now + Duration::from_secs(5 * 60);
+
+    server.assert_commands(
+        from_client(
+            source,
+            Refresh::new(
+                refresh_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            refresh_response(
+                refresh_transaction_id,
+                Lifetime::new(Duration::from_secs(5 * 60)).unwrap(),
+            ),
+        )],
+    );
+
+    assert_eq!(server.server.poll_timeout(), Some(expires_at));
+
+    server.assert_commands(
+        forward_time_to(expires_at),
+        [free_allocation(49152, AddressFamily::V4)],
+    );
+}
+
+#[proptest]
+fn accepts_credentials_of_previous_auth_secrets(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    second_allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let current_secret = SecretString::from("current".to_owned());
+    let previous_secret = SecretString::from("previous".to_owned());
+    let unknown_secret = SecretString::from("unknown".to_owned());
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_auth_secrets(vec![current_secret.clone(), previous_secret.clone()]);
+
+    assert_eq!(
+        server.auth_secret().expose_secret(),
+        current_secret.expose_secret()
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &previous_secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+
+    let second_source = SocketAddrV4::new(*source.ip(), source.port().wrapping_add(1));
+
+    server.assert_commands(
+        from_client(
+            second_source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                second_allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &unknown_secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            second_source,
+            unauthorized_allocate_response(second_allocate_transaction_id, Uuid::from_u128(0x0)),
+        )],
+    );
+}
+
+#[proptest]
+fn reloaded_auth_secrets_replace_previous_ones(
+    #[strategy(firezone_relay::proptest::transaction_id())] allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::transaction_id())]
+    second_allocate_transaction_id: TransactionId,
+    #[strategy(firezone_relay::proptest::allocation_lifetime())] lifetime: Lifetime,
+    #[strategy(firezone_relay::proptest::username_salt())] username_salt: String,
+    source: SocketAddrV4,
+    public_relay_addr: Ipv4Addr,
+    #[strategy(firezone_relay::proptest::nonce())] nonce: Uuid,
+) {
+    let now = Instant::now();
+
+    let _ = env_logger::try_init();
+
+    let old_secret = SecretString::from("old".to_owned());
+    let new_secret = SecretString::from("new".to_owned());
+
+    let mut server = TestServer::new(public_relay_addr)
+        .with_nonce(nonce)
+        .with_auth_secrets(vec![old_secret.clone()]);
+
+    server.server.set_auth_secrets(vec![new_secret.clone()]);
+
+    assert_eq!(
+        server.auth_secret().expose_secret(),
+        new_secret.expose_secret()
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &old_secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [send_message(
+            source,
+            unauthorized_allocate_response(allocate_transaction_id, Uuid::from_u128(0x0)),
+        )],
+    );
+
+    server.assert_commands(
+        from_client(
+            source,
+            Allocate::new_authenticated_udp_implicit_ip4(
+                second_allocate_transaction_id,
+                Some(lifetime.clone()),
+                valid_username(&username_salt),
+                &new_secret,
+                nonce,
+            )
+            .unwrap(),
+            now,
+        ),
+        [
+            create_allocation(49152, AddressFamily::V4),
+            send_message(
+                source,
+                allocate_response(
+                    second_allocate_transaction_id,
+                    public_relay_addr,
+                    49152,
+                    source,
+                    &lifetime,
+                ),
+            ),
+        ],
+    );
+}
+
+struct TestServer {
+    server: Server<StepRng>,
+}
+
+impl TestServer {
+    fn new(relay_public_addr: impl Into<IpStack>) -> Self {
+        Self {
+            server: Server::new(relay_public_addr, StepRng::new(0, 0), 3478, 49152..=65535),
+        }
+    }
+
+    fn with_nonce(mut self, nonce: Uuid) -> Self {
+        self.server.add_nonce(nonce);
+
+        self
+    }
+
+    fn with_auth_secrets(mut self, secrets: Vec<SecretString>) -> Self {
+        self.server = self.server.with_auth_secrets(secrets);
+
+        self
+    }
+
+    fn with_quotas(mut self, per_allocation: Quota, per_username: Quota) -> Self {
+        self.server = self.server.with_quotas(per_allocation, per_username);
+
+        self
+    }
+
+    fn auth_secret(&self) -> &SecretString {
+        self.server.auth_secret()
+    }
+
+    fn allocate(
+        &mut self,
+        source: impl Into<SocketAddr>,
+        username_salt: &str,
+        nonce: Uuid,
+        now: Instant,
+    ) {
+        let message = Allocate::new_authenticated_udp_implicit_ip4(
+            TransactionId::new([1; 12]),
+            None,
+            valid_username(username_salt),
+            self.auth_secret(),
+            nonce,
+        )
+        .unwrap();
+
+        self.server.handle_client_message(
+            ClientMessage::Allocate(message),
+            ClientSocket::new(source.into()),
+            now,
+        );
+    }
+
+    fn bind_channel(
+        &mut self,
+        source: impl Into<SocketAddr>,
+        channel: ChannelNumber,
+        peer: impl Into<SocketAddr>,
+        username_salt: &str,
+        nonce: Uuid,
+        now: Instant,
+    ) {
+        let message = ChannelBind::new(
+            TransactionId::new([2; 12]),
+            channel,
+            XorPeerAddress::new(peer.into()),
+            valid_username(username_salt),
+            self.auth_secret(),
+            nonce,
+        )
+        .unwrap();
+
+        self.server.handle_client_message(
+            ClientMessage::ChannelBind(message),
+            ClientSocket::new(source.into()),
+            now,
+        );
+    }
+
+    fn assert_commands<const N: usize>(&mut self, input: Input, output: [Output; N]) {
+        match input {
+            Input::Client(sender, message, now) => {
+                self.server.handle_client_message(message, sender, now);
+            }
+            Input::ClientDisconnected(client) => {
+                self.server.handle_client_disconnected(client);
+            }
+            Input::Peer(sender, allocation, payload, now) => {
+                let maybe_channel = self
+                    .server
+                    .handle_peer_traffic(payload, sender, allocation, now);
+
+                assert_eq!(
+                    maybe_channel, None,
+                    "peer traffic is expected to not be relayed via a channel"
+                );
+            }
+            Input::Time(now) => {
+                self.server.handle_timeout(now);
+            }
+        }
+
+        for expected_output in output {
+            let Some(actual_output) = self.server.next_command() else {
+                let msg = match expected_output {
+                    Output::SendMessage((recipient, msg)) => {
+                        format!("to send message {:?} to {recipient}", msg)
+                    }
+                    CreateAllocation(port, family) => {
+                        format!("to create allocation on port {port} for address family {family}")
+                    }
+                    FreeAllocation(port, family) => {
+                        format!("to free allocation on port {port} for address family {family}")
+                    }
+                    Output::CreateChannelBinding(client, channel, peer, port) => {
+                        format!(
+                            "to create a channel binding for channel {channel} from {client} to {peer} on allocation {port}"
+                        )
+                    }
+                    Output::DeleteChannelBinding(client, channel, peer, port) => {
+                        format!(
+                            "to remove a channel binding for channel {channel} from {client} to {peer} on allocation {port}"
+                        )
+                    }
+                    Output::ForwardToPeer(peer, port, _) => {
+                        format!("to forward data to {peer} from allocation {port}")
+                    }
+                };
+
+                panic!("No commands produced but expected {msg}");
+            };
+
+            match (expected_output, actual_output) {
+                (
+                    Output::SendMessage((to, mut message)),
+                    Command::SendMessage { payload, recipient },
+                ) => {
+                    let sent_message = parse_message(&payload);
+
+                    // In order to avoid simulating authentication, we copy the MessageIntegrity attribute.
+                    if let Some(mi) = sent_message.get_attribute::<MessageIntegrity>() {
+                        message.add_attribute(mi.clone());
+                    }
+
+                    let expected_bytes = MessageEncoder::new()
+                        .encode_into_bytes(message.clone())
+                        .unwrap();
+
+                    if expected_bytes != payload {
+                        let expected_message = format!("{:?}", message);
+                        let actual_message = format!("{:?}", sent_message);
+
+                        difference::assert_diff!(&expected_message, &actual_message, "\n", 0);
+                    }
+
+                    assert_eq!(recipient, to);
+                }
+                (
+                    CreateAllocation(expected_port, expected_family),
+                    Command::CreateAllocation {
+                        port: actual_port,
+                        family: actual_family,
+                    },
+                ) => {
+                    assert_eq!(expected_port, actual_port);
                     assert_eq!(expected_family, actual_family);
                 }
                 (
@@ -859,6 +1956,18 @@ impl TestServer {
                     assert_eq!(expected_peer, peer);
                     assert_eq!(expected_port, allocation_port);
                 }
+                (
+                    Output::ForwardToPeer(expected_peer, expected_port, expected_payload),
+                    Command::ForwardToPeer {
+                        payload,
+                        peer,
+                        allocation_port,
+                    },
+                ) => {
+                    assert_eq!(expected_peer, peer);
+                    assert_eq!(expected_port, allocation_port);
+                    assert_eq!(expected_payload, payload);
+                }
                 (expected, actual) => panic!("Unhandled combination: {expected:?} {actual:?}"),
             }
         }
@@ -926,6 +2035,24 @@ fn unauthorized_allocate_response(
     message
 }
 
+fn allocation_quota_reached_response(transaction_id: TransactionId) -> Message<Attribute> {
+    let mut message =
+        Message::<Attribute>::new(MessageClass::ErrorResponse, ALLOCATE, transaction_id);
+    message.add_attribute(SOFTWARE.clone());
+    message.add_attribute(ErrorCode::from(AllocationQuotaReached));
+
+    message
+}
+
+fn insufficient_capacity_response(transaction_id: TransactionId) -> Message<Attribute> {
+    let mut message =
+        Message::<Attribute>::new(MessageClass::ErrorResponse, ALLOCATE, transaction_id);
+    message.add_attribute(SOFTWARE.clone());
+    message.add_attribute(ErrorCode::from(InsufficientCapacity));
+
+    message
+}
+
 fn refresh_response(transaction_id: TransactionId, lifetime: Lifetime) -> Message<Attribute> {
     let mut message =
         Message::<Attribute>::new(MessageClass::SuccessResponse, REFRESH, transaction_id);
@@ -943,6 +2070,30 @@ fn channel_bind_response(transaction_id: TransactionId) -> Message<Attribute> {
     message
 }
 
+fn create_permission_response(transaction_id: TransactionId) -> Message<Attribute> {
+    let mut message = Message::<Attribute>::new(
+        MessageClass::SuccessResponse,
+        CREATE_PERMISSION,
+        transaction_id,
+    );
+    message.add_attribute(SOFTWARE.clone());
+
+    message
+}
+
+fn data_indication(peer: impl Into<SocketAddr>, data: Vec<u8>) -> Message<Attribute> {
+    let mut message = Message::<Attribute>::new(
+        MessageClass::Indication,
+        DATA,
+        TransactionId::new([0; 12]), // Our test server uses a `StepRng` that always returns 0.
+    );
+    message.add_attribute(SOFTWARE.clone());
+    message.add_attribute(XorPeerAddress::new(peer.into()));
+    message.add_attribute(Data::new(data).unwrap());
+
+    message
+}
+
 fn parse_message(message: &[u8]) -> Message<Attribute> {
     MessageDecoder::new()
         .decode_from_bytes(message)
@@ -952,6 +2103,8 @@ fn parse_message(message: &[u8]) -> Message<Attribute> {
 
 enum Input<'a> {
     Client(ClientSocket, ClientMessage<'a>, Instant),
+    ClientDisconnected(ClientSocket),
+    Peer(PeerSocket, AllocationPort, &'a [u8], Instant),
     Time(Instant),
 }
 
@@ -963,6 +2116,27 @@ fn from_client<'a>(
     Input::Client(ClientSocket::new(from.into()), message.into(), now)
 }
 
+fn from_stream_client<'a>(
+    from: impl Into<SocketAddr>,
+    message: impl Into<ClientMessage<'a>>,
+    now: Instant,
+) -> Input<'a> {
+    Input::Client(ClientSocket::new_stream(from.into()), message.into(), now)
+}
+
+fn stream_client_disconnected<'a>(client: impl Into<SocketAddr>) -> Input<'a> {
+    Input::ClientDisconnected(ClientSocket::new_stream(client.into()))
+}
+
+fn from_peer(from: impl Into<SocketAddr>, port: u16, payload: &[u8], now: Instant) -> Input<'_> {
+    Input::Peer(
+        PeerSocket::new(from.into()),
+        AllocationPort::new(port),
+        payload,
+        now,
+    )
+}
+
 fn forward_time_to<'a>(when: Instant) -> Input<'a> {
     Input::Time(when)
 }
@@ -974,6 +2148,7 @@ enum Output {
     FreeAllocation(AllocationPort, AddressFamily),
     CreateChannelBinding(ClientSocket, ChannelNumber, PeerSocket, AllocationPort),
     DeleteChannelBinding(ClientSocket, ChannelNumber, PeerSocket, AllocationPort),
+    ForwardToPeer(PeerSocket, AllocationPort, Vec<u8>),
 }
 
 fn create_allocation(port: u16, fam: AddressFamily) -> Output {
@@ -1015,3 +2190,11 @@ fn delete_channel_binding(
         AllocationPort::new(port),
     )
 }
+
+fn forward_to_peer(peer: impl Into<SocketAddr>, port: u16, payload: Vec<u8>) -> Output {
+    Output::ForwardToPeer(
+        PeerSocket::new(peer.into()),
+        AllocationPort::new(port),
+        payload,
+    )
+}
@@ -36,6 +36,10 @@ pub mod attr {
         KeyValue::new("network.transport", "udp")
     }
 
+    pub fn network_transport_tcp() -> KeyValue {
+        KeyValue::new("network.transport", "tcp")
+    }
+
     pub fn network_type_for_packet(p: &IpPacket) -> KeyValue {
         match p {
             IpPacket::Ipv4(_) => network_type_ipv4(),
@@ -78,6 +82,22 @@ pub mod attr {
         KeyValue::new("error.type", value)
     }
 
+    pub fn network_path_direct() -> KeyValue {
+        KeyValue::new("network.path", "direct")
+    }
+
+    pub fn network_path_relayed() -> KeyValue {
+        KeyValue::new("network.path", "relayed")
+    }
+
+    pub fn dns_cache_hit() -> KeyValue {
+        KeyValue::new("dns.cache.result", "hit")
+    }
+
+    pub fn dns_cache_miss() -> KeyValue {
+        KeyValue::new("dns.cache.result", "miss")
+    }
+
     #[cfg(test)]
     mod tests {
         use super::*;
@@ -95,7 +115,7 @@ pub mod attr {
 }
 
 pub mod metrics {
-    use opentelemetry::metrics::Counter;
+    use opentelemetry::metrics::{Counter, Gauge};
 
     pub fn network_packet_dropped() -> Counter<u64> {
         opentelemetry::global::meter("connlib")
@@ -104,6 +124,30 @@ pub mod metrics {
             .with_unit("{packet}")
             .build()
     }
+
+    pub fn connections() -> Gauge<u64> {
+        opentelemetry::global::meter("connlib")
+            .u64_gauge("connlib.connections")
+            .with_description("Number of established connections to other peers")
+            .with_unit("{connection}")
+            .build()
+    }
+
+    pub fn connection_stun_bytes() -> Gauge<u64> {
+        opentelemetry::global::meter("connlib")
+            .u64_gauge("connlib.connection.stun_bytes")
+            .with_description("Bytes sent as part of exchanging STUN messages with all peers")
+            .with_unit("By")
+            .build()
+    }
+
+    pub fn dns_cache_lookups() -> Counter<u64> {
+        opentelemetry::global::meter("connlib")
+            .u64_counter("connlib.dns.cache.lookups")
+            .with_description("Count of recursive DNS queries looked up in the DNS response cache")
+            .with_unit("{query}")
+            .build()
+    }
 }
 
 pub fn default_resource_with<const N: usize>(attributes: [KeyValue; N]) -> Resource {